@@ -0,0 +1,254 @@
+use std::env;
+use std::path::PathBuf;
+
+use jgboy_core::emulator::MODE;
+
+const USAGE: &str = "usage: sponGB [rom] [--model <dmg|dmg0|cgb>] [--link <rom>] [--host <port>] [--join <host:port>] [--link-local] [--force-load] [--info] [--dump-audio <frames>] [--test <script or dir>]
+       sponGB run <rom> [--model <dmg|dmg0|cgb>] [--frames <n>] [--inputs <file>] [--dump-frame <png>] [--dump-ram <file>] [--assert <file>]
+       sponGB diff <rom> --frames <n> --against <png> [--model <dmg|dmg0|cgb>] [--inputs <file>] [--out <png>]
+       sponGB statediff <rom> <state> <state> [--model <dmg|dmg0|cgb>]
+       sponGB selftest";
+
+pub struct Args {
+    pub rom: PathBuf,
+    pub link: Option<PathBuf>,  // a second game in the same window, on the other end of the link cable
+    pub host: Option<u16>,  // wait for another jgboy to --join, the link cable goes over the network
+    pub join: Option<String>,  // host:port of one started with --host
+    pub link_local: bool,  // spawn a second instance with pause/fast-forward kept in sync
+    pub link_child: bool,  // internal, this is the instance spawned by --link-local
+    pub dump_audio: Option<u32>,  // run this many frames without a window, writing every apu channel to a file
+    pub test: Option<PathBuf>,  // run test scripts without a window instead of a rom
+    pub force_load: bool,  // load roms with a bad header anyway, same as lenient_header = on
+    pub info: bool,  // print what the rom header says and exit
+    pub model: Option<MODE>,  // overrides the header and the config's model
+    pub run: Option<RunArgs>,  // `sponGB run`, everything else is left at the defaults
+    pub diff: Option<DiffArgs>,  // `sponGB diff`, the same
+    pub state_diff: Option<StateDiffArgs>,  // `sponGB statediff`, the same
+    pub selftest: bool  // `sponGB selftest`, prints the accuracy scorecard
+}
+
+// a scripted run without a window, for other projects' CI
+pub struct RunArgs {
+    pub rom: PathBuf,
+    pub model: Option<MODE>,  // overrides the header and the assertion file's model
+    pub frames: Option<u32>,  // at least up to the end of the inputs and the last assertion
+    pub inputs: Option<PathBuf>,  // a hex byte per frame like the macros, active low, directions in the high nibble
+    pub dump_frame: Option<PathBuf>,  // the last frame as a png
+    pub dump_ram: Option<PathBuf>,  // wram, all banks, then cart ram
+    pub assert: Option<PathBuf>  // [[expect]] entries like a test script, the exit status says whether they passed
+}
+
+// a headless run compared with a golden screenshot, for bug reports
+pub struct DiffArgs {
+    pub rom: PathBuf,
+    pub model: Option<MODE>,
+    pub frames: u32,
+    pub inputs: Option<PathBuf>,  // the same as for run
+    pub against: PathBuf,  // 160x144, 8 bit rgb or rgba
+    pub out: Option<PathBuf>  // the diff image, <against>.diff.png when left out
+}
+
+// what changed between two save states of one game
+pub struct StateDiffArgs {
+    pub rom: PathBuf,
+    pub model: Option<MODE>,  // the states' model when the header's isn't it
+    pub old: PathBuf,
+    pub new: PathBuf
+}
+
+fn frames_arg(it: &mut impl Iterator<Item = String>) -> Result<u32, String> {
+    it.next().and_then(|n| n.parse().ok()).ok_or_else(|| format!("--frames expects a frame count\n{}", USAGE))
+}
+
+fn path_arg(it: &mut impl Iterator<Item = String>, opt: &str) -> Result<PathBuf, String> {
+    it.next().map(PathBuf::from).ok_or_else(|| format!("{} expects a path\n{}", opt, USAGE))
+}
+
+fn model_arg(it: &mut impl Iterator<Item = String>) -> Result<MODE, String> {
+    match it.next().as_deref() {
+        Some("dmg") => Ok(MODE::DMG),
+        Some("dmg0") => Ok(MODE::DMG0),
+        Some("cgb") => Ok(MODE::CGB),
+        _ => Err(format!("--model expects dmg, dmg0 or cgb\n{}", USAGE))
+    }
+}
+
+impl RunArgs {
+    fn parse(mut it: impl Iterator<Item = String>) -> Result<RunArgs, String> {
+        let mut args = RunArgs {
+            rom: PathBuf::new(),
+            model: None,
+            frames: None,
+            inputs: None,
+            dump_frame: None,
+            dump_ram: None,
+            assert: None
+        };
+
+        let mut rom = None;
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--frames" => args.frames = Some(frames_arg(&mut it)?),
+                "--model" => args.model = Some(model_arg(&mut it)?),
+                "--inputs" => args.inputs = Some(path_arg(&mut it, "--inputs")?),
+                "--dump-frame" => args.dump_frame = Some(path_arg(&mut it, "--dump-frame")?),
+                "--dump-ram" => args.dump_ram = Some(path_arg(&mut it, "--dump-ram")?),
+                "--assert" => args.assert = Some(path_arg(&mut it, "--assert")?),
+                a if a.starts_with("--") => return Err(format!("unknown option `{}`\n{}", a, USAGE)),
+                a => {
+                    if rom.is_some() {
+                        return Err(USAGE.to_string())
+                    }
+                    rom = Some(PathBuf::from(a));
+                }
+            }
+        }
+
+        args.rom = rom.ok_or_else(|| format!("run expects a rom\n{}", USAGE))?;
+        Ok(args)
+    }
+}
+
+impl DiffArgs {
+    fn parse(mut it: impl Iterator<Item = String>) -> Result<DiffArgs, String> {
+        let (mut rom, mut model, mut frames, mut inputs, mut against, mut out) = (None, None, None, None, None, None);
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--frames" => frames = Some(frames_arg(&mut it)?),
+                "--model" => model = Some(model_arg(&mut it)?),
+                "--inputs" => inputs = Some(path_arg(&mut it, "--inputs")?),
+                "--against" => against = Some(path_arg(&mut it, "--against")?),
+                "--out" => out = Some(path_arg(&mut it, "--out")?),
+                a if a.starts_with("--") => return Err(format!("unknown option `{}`\n{}", a, USAGE)),
+                a => {
+                    if rom.is_some() {
+                        return Err(USAGE.to_string())
+                    }
+                    rom = Some(PathBuf::from(a));
+                }
+            }
+        }
+
+        Ok(DiffArgs {
+            rom: rom.ok_or_else(|| format!("diff expects a rom\n{}", USAGE))?,
+            model: model,
+            frames: frames.ok_or_else(|| format!("diff expects --frames\n{}", USAGE))?,
+            inputs: inputs,
+            against: against.ok_or_else(|| format!("diff expects --against\n{}", USAGE))?,
+            out: out
+        })
+    }
+}
+
+impl StateDiffArgs {
+    fn parse(mut it: impl Iterator<Item = String>) -> Result<StateDiffArgs, String> {
+        let (mut paths, mut model) = (vec![], None);
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--model" => model = Some(model_arg(&mut it)?),
+                a if a.starts_with("--") => return Err(format!("unknown option `{}`\n{}", a, USAGE)),
+                a => paths.push(PathBuf::from(a))
+            }
+        }
+
+        if paths.len() != 3 {
+            return Err(format!("statediff expects a rom and two states\n{}", USAGE))
+        }
+        let new = paths.pop().unwrap();
+        let old = paths.pop().unwrap();
+        Ok(StateDiffArgs {
+            rom: paths.pop().unwrap(),
+            model: model,
+            old: old,
+            new: new
+        })
+    }
+}
+
+impl Args {
+    pub fn parse() -> Result<Args, String> {
+        let mut args = Args {
+            rom: PathBuf::from("pksilver.gbc"),
+            link: None,
+            host: None,
+            join: None,
+            link_local: false,
+            link_child: false,
+            dump_audio: None,
+            test: None,
+            force_load: false,
+            info: false,
+            model: None,
+            run: None,
+            diff: None,
+            state_diff: None,
+            selftest: false
+        };
+
+        let mut rom = None;
+        let mut it = env::args().skip(1).peekable();
+        match it.peek().map(|a| a.as_str()) {
+            Some("run") => {
+                it.next();
+                args.run = Some(RunArgs::parse(it)?);
+                return Ok(args)
+            },
+            Some("diff") => {
+                it.next();
+                args.diff = Some(DiffArgs::parse(it)?);
+                return Ok(args)
+            },
+            Some("statediff") => {
+                it.next();
+                args.state_diff = Some(StateDiffArgs::parse(it)?);
+                return Ok(args)
+            },
+            Some("selftest") => {
+                it.next();
+                if let Some(a) = it.next() {
+                    return Err(format!("unexpected `{}`\n{}", a, USAGE))
+                }
+                args.selftest = true;
+                return Ok(args)
+            },
+            _ => ()
+        }
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--link" => args.link = Some(path_arg(&mut it, "--link")?),
+                "--host" => {
+                    args.host = Some(it.next().and_then(|p| p.parse().ok())
+                        .ok_or_else(|| format!("--host expects a port\n{}", USAGE))?);
+                },
+                "--join" => {
+                    args.join = Some(it.next().ok_or_else(|| format!("--join expects host:port\n{}", USAGE))?);
+                },
+                "--link-local" => args.link_local = true,
+                "--link-child" => args.link_child = true,
+                "--force-load" => args.force_load = true,
+                "--info" => args.info = true,
+                "--model" => args.model = Some(model_arg(&mut it)?),
+                "--dump-audio" => {
+                    args.dump_audio = Some(it.next().and_then(|n| n.parse().ok())
+                        .ok_or_else(|| format!("--dump-audio expects a frame count\n{}", USAGE))?);
+                },
+                "--test" => {
+                    args.test = Some(it.next().map(PathBuf::from)
+                        .ok_or_else(|| format!("--test expects a script or a directory of them\n{}", USAGE))?);
+                },
+                a if a.starts_with("--") => return Err(format!("unknown option `{}`\n{}", a, USAGE)),
+                a => {
+                    if rom.is_some() {
+                        return Err(USAGE.to_string())
+                    }
+                    rom = Some(PathBuf::from(a));
+                }
+            }
+        }
+
+        if let Some(rom) = rom {
+            args.rom = rom;
+        }
+        Ok(args)
+    }
+}
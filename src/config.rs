@@ -0,0 +1,432 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::emulator::{self, AccuracyPreset, AudioConfig, Resampler, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE, RtcSource, Loopback, DmgPalette, MODE, RamInit};
+use crate::frontend::{FrameSkipMode, MAX_SKIP, DEFAULT_REWIND_SECONDS, VideoSync, SyncMode, VideoFormat};
+
+pub const CONFIG_PATH: &str = "sponGB.cfg";
+
+// Plain `key = value` lines, `#` starts a comment. Unknown keys and bad values are reported and skipped.
+// A `<rom name>.cfg` next to the rom overrides the global file for that game.
+pub struct Config {
+    pub accuracy: AccuracyPreset,  // accuracy = fast | balanced | accurate
+    pub echo_ram: Option<bool>,    // echo_ram = on | off, the bus quirks below override the preset's
+    pub open_bus: Option<u8>,      // open_bus = <hex>, what E000-FDFF with echo_ram off and disabled cart ram read
+    pub data_bus: Option<bool>,    // data_bus = on | off, those reads get the last byte on the bus instead of open_bus
+    pub dma_conflicts: Option<bool>,  // dma_conflicts = on | off, cpu reads during OAM DMA see the copied byte
+    pub rtc: RtcSource,            // rtc = host | emulated | frozen
+    pub rtc_offset: u64,           // rtc_offset = [<days>d] [hh:mm[:ss]], where the cartridge clock starts
+    pub overclock: f32,            // overclock = 1 - 4, cpu speed multiplier, can break timing sensitive games
+    pub io_log: Option<Vec<u16>>,  // io_log = off | all | <hex addresses>, logs io register writes for the debugger
+    pub bootrom_dir: Option<PathBuf>,  // bootrom_dir = <path> with dmg_boot.bin / cgb_boot.bin, the free_bootrom feature fills in for missing ones
+    pub fast_boot: bool,           // fast_boot = on | off, runs the bootrom without showing it
+    pub dmg_palette: Option<DmgPalette>,  // dmg_palette = grayscale | up | up+a | ... | right+b, the CGB bootrom's picks for dmg games
+    pub state_hash_log: Option<PathBuf>,  // state_hash_log = <path>, per frame state hashes for bisecting regressions
+    pub play_time: u64,            // play_time = [<days>d] hh:mm:ss of emulated time, kept up to date in the game's .cfg
+    pub title_play_time: bool,     // title_play_time = on | off, shows play_time in the window title
+    pub lenient_header: bool,      // lenient_header = on | off, load roms with a bad checksum or size anyway
+    pub verify_global_checksum: bool,  // verify_global_checksum = on | off, treat a bad 0x14E-0x14F checksum like a bad header one
+    pub compat_db: Option<PathBuf>,  // compat_db = <path>, `<header hash> <note>` lines of known problems, shown at startup
+    pub link_loopback: Option<Loopback>,  // link_loopback = off | echo | <sent>:<reply> hex pairs, `*:<reply>` for the rest
+    pub sample_rate: u32,          // sample_rate = 8000 - 96000, Hz
+    pub audio_buffer: usize,       // audio_buffer = <frames>, per stream buffer, lower for less latency, higher if it crackles
+    pub resampler: Resampler,      // resampler = nearest | average | sinc, from cheapest to cleanest
+    pub channel_volume: [f32; 4],  // channel_volume = <square 1> <square 2> <wave> <noise>, 0 - 200 percent each
+    pub sync: SyncMode,            // sync = audio | video | uncapped, what paces the emulation, video stretches audio to keep up
+    pub vsync: bool,               // vsync = on | off, Shift+F9 switches it for the session
+    pub video_sync: VideoSync,     // video_sync = off | auto | <hz>, runs the game at the display's rate when within 0.5%
+    pub frame_skip: FrameSkipMode,  // frame_skip = auto | off | 2-4, draw 1 of n frames, auto when the host can't keep up
+    pub rumble_shake: bool,        // rumble_shake = on | off, shakes the screen while a rumble cartridge's motor runs
+    pub screenshot_window: bool,   // screenshot_size = native | window, F11 saves 160x144 or what the window shows
+    pub video_format: VideoFormat,  // video_format = apng | ffmpeg, what Ctrl+F11 records, ffmpeg adds sound
+    pub ffmpeg: PathBuf,           // ffmpeg = <path>, the program video_format = ffmpeg runs, found on PATH by default
+    pub bess_states: bool,         // bess_states = on | off, F5 writes uncompressed states other emulators can load
+    pub rewind: usize,             // rewind = off | <seconds>, how far back holding Backspace can go
+    pub camera_image: Option<PathBuf>,  // camera_image = <path>, a binary PGM the Pocket Camera sees instead of its test image
+    pub power_loss: Option<u32>,   // power_loss = off | <frames>, cart ram writes Shift+F8 drops when it pulls the power
+    pub model: Option<MODE>,       // model = auto | dmg | dmg0 | cgb, dmg0 is the first DMG revision's boot registers and quirks
+    pub ram_init: RamInit,         // ram_init = zero | ff | stripes | random | random:<seed>, what ram holds at power on
+    pub language: Option<PathBuf>,  // language = <path>, `key = text` lines replacing the English osd text, see lang/
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            accuracy: AccuracyPreset::Balanced,
+            echo_ram: None,
+            open_bus: None,
+            data_bus: None,
+            dma_conflicts: None,
+            rtc: RtcSource::Host,
+            rtc_offset: 0,
+            overclock: 1.,
+            io_log: None,
+            bootrom_dir: None,
+            fast_boot: false,
+            dmg_palette: None,
+            state_hash_log: None,
+            play_time: 0,
+            title_play_time: false,
+            lenient_header: false,
+            verify_global_checksum: false,
+            compat_db: None,
+            link_loopback: None,
+            sample_rate: AudioConfig::new().sample_rate,
+            audio_buffer: AudioConfig::new().buffer,
+            resampler: AudioConfig::new().resampler,
+            channel_volume: [1.; 4],
+            sync: SyncMode::Audio,
+            vsync: false,
+            video_sync: VideoSync::Off,
+            frame_skip: FrameSkipMode::Auto,
+            rumble_shake: true,
+            screenshot_window: false,
+            video_format: VideoFormat::Apng,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            bess_states: false,
+            rewind: DEFAULT_REWIND_SECONDS,
+            camera_image: None,
+            power_loss: None,
+            model: None,
+            ram_init: RamInit::Zero,
+            language: None
+        }
+    }
+
+    pub fn load(p: &Path) -> Config {
+        let mut config = Config::new();
+
+        if let Ok(data) = fs::read_to_string(p) {
+            config.apply(&data, p);
+        }
+        config
+    }
+
+    pub fn load_game(&mut self, rom: &Path) {
+        let p = rom.with_extension("cfg");
+        if let Ok(data) = fs::read_to_string(&p) {
+            self.apply(&data, &p);
+        }
+    }
+
+    pub fn audio(&self) -> AudioConfig {
+        AudioConfig {
+            sample_rate: self.sample_rate,
+            buffer: self.audio_buffer,
+            resampler: self.resampler
+        }
+    }
+
+    fn apply(&mut self, data: &str, p: &Path) {
+        for (n, line) in data.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let result = match line.find('=') {
+                Some(i) => self.set(line[.. i].trim(), line[i+1 ..].trim()),
+                None => Err("expected `key = value`".to_string())
+            };
+            if let Err(e) = result {
+                eprintln!("{}:{}: {}", p.display(), n + 1, e);
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, val: &str) -> Result<(), String> {
+        match key {
+            "accuracy" => {
+                self.accuracy = match val {
+                    "fast" => AccuracyPreset::Fast,
+                    "balanced" => AccuracyPreset::Balanced,
+                    "accurate" => AccuracyPreset::Accurate,
+                    _ => return Err(format!("unknown accuracy preset `{}`", val))
+                };
+            },
+            "echo_ram" => self.echo_ram = Some(parse_switch(key, val)?),
+            "open_bus" => {
+                self.open_bus = Some(u8::from_str_radix(val.trim_start_matches("0x"), 16).map_err(|_| format!("open_bus expects a hex byte, got `{}`", val))?);
+            },
+            "data_bus" => self.data_bus = Some(parse_switch(key, val)?),
+            "dma_conflicts" => self.dma_conflicts = Some(parse_switch(key, val)?),
+            "rtc" => {
+                self.rtc = match val {
+                    "host" => RtcSource::Host,
+                    "emulated" => RtcSource::Emulated,
+                    "frozen" => RtcSource::Frozen,
+                    _ => return Err(format!("unknown rtc source `{}`", val))
+                };
+            },
+            "rtc_offset" => {
+                self.rtc_offset = parse_time(val).ok_or_else(|| format!("invalid rtc offset `{}`", val))?;
+            },
+            "overclock" => {
+                self.overclock = match val.parse::<f32>() {
+                    Ok(v) if v >= 1. && v <= 4. => v,
+                    _ => return Err(format!("overclock must be between 1 and 4, got `{}`", val))
+                };
+            },
+            "io_log" => {
+                self.io_log = match val {
+                    "off" => None,
+                    "all" => Some(vec![]),
+                    _ => Some(val.split_whitespace()
+                        .map(|a| u16::from_str_radix(a.trim_start_matches("0x"), 16).ok().filter(|a| *a >= 0xFF00))
+                        .collect::<Option<Vec<u16>>>()
+                        .ok_or_else(|| format!("io_log expects off, all or FF00-FFFF addresses, got `{}`", val))?)
+                };
+            },
+            "bootrom_dir" => self.bootrom_dir = Some(PathBuf::from(val)),
+            "fast_boot" => self.fast_boot = parse_switch(key, val)?,
+            "dmg_palette" => {
+                self.dmg_palette = Some(emulator::dmg_palette(val).ok_or_else(|| format!("unknown dmg palette `{}`", val))?);
+            },
+            "state_hash_log" => self.state_hash_log = Some(PathBuf::from(val)),
+            "play_time" => {
+                self.play_time = parse_time(val).ok_or_else(|| format!("invalid play time `{}`", val))?;
+            },
+            "title_play_time" => self.title_play_time = parse_switch(key, val)?,
+            "lenient_header" => self.lenient_header = parse_switch(key, val)?,
+            "verify_global_checksum" => self.verify_global_checksum = parse_switch(key, val)?,
+            "compat_db" => self.compat_db = Some(PathBuf::from(val)),
+            "sample_rate" => {
+                self.sample_rate = match val.parse::<u32>() {
+                    Ok(hz) if hz >= MIN_SAMPLE_RATE && hz <= MAX_SAMPLE_RATE => hz,
+                    _ => return Err(format!("sample_rate must be between {} and {}, got `{}`", MIN_SAMPLE_RATE, MAX_SAMPLE_RATE, val))
+                };
+            },
+            "audio_buffer" => {
+                self.audio_buffer = match val.parse::<usize>() {
+                    Ok(n) if n >= 64 && n <= 65536 => n,
+                    _ => return Err(format!("audio_buffer must be between 64 and 65536 frames, got `{}`", val))
+                };
+            },
+            "resampler" => {
+                self.resampler = match val {
+                    "nearest" => Resampler::Nearest,
+                    "average" => Resampler::Average,
+                    "sinc" => Resampler::Sinc,
+                    _ => return Err(format!("resampler expects nearest, average or sinc, got `{}`", val))
+                };
+            },
+            "channel_volume" => {
+                let volumes = val.split_whitespace()
+                    .map(|v| v.parse::<f32>().ok().filter(|v| *v >= 0. && *v <= 200.).map(|v| v / 100.))
+                    .collect::<Option<Vec<f32>>>()
+                    .filter(|v| v.len() == 4)
+                    .ok_or_else(|| format!("channel_volume expects 4 percentages from 0 to 200, got `{}`", val))?;
+                self.channel_volume.copy_from_slice(&volumes);
+            },
+            "sync" => {
+                self.sync = match val {
+                    "audio" => SyncMode::Audio,
+                    "video" => SyncMode::Video,
+                    "uncapped" => SyncMode::Uncapped,
+                    _ => return Err(format!("sync expects audio, video or uncapped, got `{}`", val))
+                };
+            },
+            "vsync" => self.vsync = parse_switch(key, val)?,
+            "video_sync" => {
+                self.video_sync = match val {
+                    "off" => VideoSync::Off,
+                    "auto" => VideoSync::Auto,
+                    _ => match val.parse::<f32>() {
+                        Ok(hz) if hz > 0. => VideoSync::Rate(hz),
+                        _ => return Err(format!("video_sync expects off, auto or a refresh rate in Hz, got `{}`", val))
+                    }
+                };
+            },
+            "frame_skip" => {
+                self.frame_skip = match val {
+                    "auto" => FrameSkipMode::Auto,
+                    "off" => FrameSkipMode::Off,
+                    _ => match val.parse::<usize>() {
+                        Ok(n) if n >= 1 && n <= MAX_SKIP => FrameSkipMode::Fixed(n),
+                        _ => return Err(format!("frame_skip expects auto, off or 1-{}, got `{}`", MAX_SKIP, val))
+                    }
+                };
+            },
+            "rumble_shake" => self.rumble_shake = parse_switch(key, val)?,
+            "bess_states" => self.bess_states = parse_switch(key, val)?,
+            "screenshot_size" => {
+                self.screenshot_window = match val {
+                    "native" => false,
+                    "window" => true,
+                    _ => return Err(format!("screenshot_size expects native or window, got `{}`", val))
+                };
+            },
+            "video_format" => {
+                self.video_format = match val {
+                    "apng" => VideoFormat::Apng,
+                    "ffmpeg" => VideoFormat::Ffmpeg,
+                    _ => return Err(format!("video_format expects apng or ffmpeg, got `{}`", val))
+                };
+            },
+            "ffmpeg" => self.ffmpeg = PathBuf::from(val),
+            "rewind" => {
+                self.rewind = match val {
+                    "off" => 0,
+                    _ => val.parse::<usize>().map_err(|_| format!("rewind expects off or seconds, got `{}`", val))?
+                };
+            },
+            "camera_image" => self.camera_image = Some(PathBuf::from(val)),
+            "language" => self.language = Some(PathBuf::from(val)),
+            "power_loss" => {
+                self.power_loss = match val {
+                    "off" => None,
+                    _ => Some(val.parse::<u32>().map_err(|_| format!("power_loss expects off or frames, got `{}`", val))?)
+                };
+            },
+            "model" => {
+                self.model = match val {
+                    "auto" => None,
+                    "dmg" => Some(MODE::DMG),
+                    "dmg0" => Some(MODE::DMG0),
+                    "cgb" => Some(MODE::CGB),
+                    _ => return Err(format!("model expects auto, dmg, dmg0 or cgb, got `{}`", val))
+                };
+            },
+            "ram_init" => {
+                self.ram_init = match val {
+                    "zero" => RamInit::Zero,
+                    "ff" => RamInit::Ones,
+                    "stripes" => RamInit::Stripes,
+                    "random" => RamInit::Random(session_seed()),
+                    _ => match val.strip_prefix("random:").map(|s| s.parse::<u32>()) {
+                        Some(Ok(seed)) => RamInit::Random(seed),
+                        _ => return Err(format!("ram_init expects zero, ff, stripes, random or random:<seed>, got `{}`", val))
+                    }
+                };
+            },
+            "link_loopback" => {
+                self.link_loopback = match val {
+                    "off" => None,
+                    _ => Some(Loopback::parse(val).map_err(|e| format!("link_loopback {}, got `{}`", e, val))?)
+                };
+            },
+            _ => return Err(format!("unknown key `{}`", key))
+        }
+        Ok(())
+    }
+}
+
+// a new seed every session, restarts and reloads keep it and save states record it
+fn session_seed() -> u32 {
+    static SEED: AtomicU32 = AtomicU32::new(0);
+    if SEED.load(Ordering::Relaxed) == 0 {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos() ^ d.as_secs() as u32);
+        SEED.store(seed | 1, Ordering::Relaxed);
+    }
+    SEED.load(Ordering::Relaxed)
+}
+
+// Modification times of the config files, looked at once a second at most
+pub struct ConfigWatch {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    checked: Instant
+}
+
+fn modified(p: &Path) -> Option<SystemTime> {
+    fs::metadata(p).and_then(|m| m.modified()).ok()
+}
+
+impl ConfigWatch {
+    pub fn new(files: &[PathBuf]) -> ConfigWatch {
+        ConfigWatch {
+            files: files.iter().map(|p| (p.clone(), modified(p))).collect(),
+            checked: Instant::now()
+        }
+    }
+
+    // whether one of them was written, created or removed since the last call that said so
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed().as_secs() < 1 {
+            return false
+        }
+        self.checked = Instant::now();
+
+        let mut changed = false;
+        for (p, time) in self.files.iter_mut() {
+            let now = modified(p);
+            if now != *time {
+                *time = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn parse_switch(key: &str, val: &str) -> Result<bool, String> {
+    match val {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("{} expects on or off, got `{}`", key, val))
+    }
+}
+
+// note for the game from a compat_db file, `#` starts a comment
+pub fn known_issue(db: &Path, header_hash: u32) -> Option<String> {
+    let data = fs::read_to_string(db).ok()?;
+    data.lines()
+        .map(|l| l.split('#').next().unwrap().trim())
+        .filter_map(|l| {
+            let (hash, note) = l.split_at(l.find(char::is_whitespace)?);
+            Some((u32::from_str_radix(hash, 16).ok()?, note.trim()))
+        })
+        .find(|(hash, _)| *hash == header_hash)
+        .map(|(_, note)| note.to_string())
+}
+
+// rewrites the play_time line of the game's .cfg, the rest of the file is kept as is
+pub fn save_play_time(rom: &Path, seconds: u64) -> io::Result<()> {
+    let p = rom.with_extension("cfg");
+    let line = format!("play_time = {}", format_time(seconds));
+
+    let data = fs::read_to_string(&p).unwrap_or_default();
+    let mut lines: Vec<&str> = data.lines()
+        .filter(|l| l.split('=').next().unwrap().trim() != "play_time")
+        .collect();
+    lines.push(&line);
+
+    fs::write(&p, lines.join("\n") + "\n")
+}
+
+// seconds -> "12d 08:30:00", "08:30:00", readable by parse_time
+pub fn format_time(seconds: u64) -> String {
+    let hms = format!("{:02}:{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+    if seconds >= 86400 {
+        format!("{}d {}", seconds / 86400, hms)
+    } else {
+        hms
+    }
+}
+
+// "12d 08:30", "3d", "23:59:59" -> seconds
+fn parse_time(val: &str) -> Option<u64> {
+    let mut seconds = 0;
+    for part in val.split_whitespace() {
+        if let Some(days) = part.strip_suffix('d') {
+            seconds += days.parse::<u64>().ok()? * 86400;
+        } else {
+            let mut hms = [0u64; 3];
+            let mut n = 0;
+            for field in part.split(':') {
+                if n == 3 {
+                    return None
+                }
+                hms[n] = field.parse().ok()?;
+                n += 1;
+            }
+            if n < 2 || hms[0] > 23 || hms[1] > 59 || hms[2] > 59 {
+                return None
+            }
+            seconds += hms[0]*3600 + hms[1]*60 + hms[2];
+        }
+    }
+    Some(seconds)
+}
@@ -0,0 +1,62 @@
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AccuracyPreset {
+    Fast,
+    Balanced,
+    Accurate
+}
+
+// Expensive emulation features, toggled as a group by AccuracyPreset
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Accuracy {
+    pub per_cycle_memory: bool,  // tick the hardware on every memory access instead of after the whole instruction
+    pub fifo_renderer: bool,  // pixel FIFO in mode 3 instead of drawing whole scanlines at once
+    pub bus: BusQuirks
+}
+
+// Odd bus behaviour some games trip over, each one can be flipped from the config on top of the preset
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct BusQuirks {
+    pub echo_ram: bool,  // E000-FDFF mirrors C000-DDFF with the banked half following SVBK, off reads open_bus and drops writes
+    pub open_bus: u8,  // what unmapped cartridge bus reads get, E000-FDFF with echo_ram off and disabled cart ram
+    pub data_bus: bool,  // they get the last byte on the bus instead, usually the instruction's own operand
+    pub dma_conflicts: bool  // during OAM DMA, cpu reads on the bus the DMA reads from get the byte being copied and writes are lost
+}
+
+impl BusQuirks {
+    pub fn new() -> BusQuirks {
+        BusQuirks {
+            echo_ram: true,
+            open_bus: 0xFF,
+            data_bus: false,
+            dma_conflicts: false
+        }
+    }
+}
+
+impl Default for BusQuirks {
+    fn default() -> BusQuirks {
+        BusQuirks::new()
+    }
+}
+
+impl Accuracy {
+    pub fn new(preset: AccuracyPreset) -> Accuracy {
+        match preset {
+            AccuracyPreset::Fast => Accuracy {
+                per_cycle_memory: false,
+                fifo_renderer: false,
+                bus: BusQuirks::new()
+            },
+            AccuracyPreset::Balanced => Accuracy {
+                per_cycle_memory: true,
+                fifo_renderer: true,
+                bus: BusQuirks::new()
+            },
+            AccuracyPreset::Accurate => Accuracy {
+                per_cycle_memory: true,
+                fifo_renderer: true,
+                bus: BusQuirks { dma_conflicts: true, data_bus: true, ..BusQuirks::new() }
+            }
+        }
+    }
+}
@@ -1,10 +1,7 @@
-use crate::emulator::apu::{Square, Wave, Noise, DUTY_CYCLE};
+use alloc::vec::Vec;
 
-use raylib::prelude::*;
-
-const BUFFER_SIZE: usize = 8192;
-const SAMPLE_RATE: u32 = 48000;
-const SAMPLE_SIZE: u32 = 16;
+use crate::emulator::apu::{Square, Wave, Noise, Resample, DUTY_CYCLE};
+use crate::emulator::{AudioConfig, StateHasher, StateWriter, StateReader, MODE};
 
 pub struct Envelope {
     pub volume: u8,
@@ -56,6 +53,19 @@ impl Envelope {
             }
         }
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.volume, self.volume_init, self.add, self.period, self.timer]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.volume = r.read_u8()?;
+        self.volume_init = r.read_u8()?;
+        self.add = r.read_u8()?;
+        self.period = r.read_u8()?;
+        self.timer = r.read_u8()?;
+        Ok(())
+    }
 }
 
 pub struct LengthDuty {  // length counter and duty cycles
@@ -91,6 +101,17 @@ impl LengthDuty {
             *enable = false;
         }
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.duty, self.length]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.duty = r.read_u8()?&0xC0;
+        self.duty_table = DUTY_CYCLE[self.duty as usize >> 6];
+        self.length = r.read_u8()?;
+        Ok(())
+    }
 }
 
 pub struct ChannelVolume {
@@ -168,19 +189,18 @@ pub struct APU {
 
     clock: u16,
     frame_clock: u8,
-    sample_clock: u32,
-
-    stream: raylib::ffi::AudioStream,
-    _audio: RaylibAudio,
-    samples: [i16; BUFFER_SIZE],
+    output: Resample,
+    channel_volume: [f32; 4],  // listener's mixer, not the game's, 1.0 as the hardware plays it
+    channel_muted: [bool; 4],
+    gain: [i32; 4],  // of the two above, 256 is 1.0
+
+    pub samples: Vec<i16>,  // interleaved stereo, drained by the frontend
+    channel_dump: Option<[Vec<i16>; 4]>,  // each channel's own output at the sample rate, before panning and volume
+    recording: Option<Vec<i16>>,  // a copy of samples for a recording, the frontend drains the other one
 }
 
 impl APU {
-    pub fn new(rl_thread: &RaylibThread) -> APU {
-        let mut audio = RaylibAudio::init_audio_device();
-        let mut stream = AudioStream::init_audio_stream(rl_thread, SAMPLE_RATE, SAMPLE_SIZE, 2);
-        audio.play_audio_stream(&mut stream);
-
+    pub fn new() -> APU {
         let mut apu = APU {
             volume: ChannelVolume::new(),
             sch_output: ChannelOutput::new(),
@@ -192,11 +212,14 @@ impl APU {
 
             clock: 0,
             frame_clock: 0,
-            sample_clock: 0,
-
-            stream: stream.to_raw(),
-            _audio: audio,
-            samples: [0; BUFFER_SIZE],
+            output: Resample::new(AudioConfig::new()),
+            channel_volume: [1.; 4],
+            channel_muted: [false; 4],
+            gain: [256; 4],
+
+            samples: Vec::with_capacity(4096),
+            channel_dump: None,
+            recording: None,
         };
 
         // apu.write(0xFF10, 0x80);
@@ -221,6 +244,177 @@ impl APU {
         apu
     }
 
+    // debugger access
+
+    // 32 4 bit samples, upper nibble first
+    pub fn wave_ram(&self) -> &[u8; 16] {
+        &self.sc3.wave_data
+    }
+
+    pub fn set_wave_sample(&mut self, i: usize, val: u8) {
+        let byte = &mut self.sc3.wave_data[i/2];
+        if i & 1 == 0 {
+            *byte = (*byte&0x0F) | (val << 4);
+        } else {
+            *byte = (*byte&0xF0) | (val&0xF);
+        }
+    }
+
+    // sample channel 3 plays next and its output level (0 - mute, 1 - 100%, 2 - 50%, 3 - 25%)
+    pub fn wave_status(&self) -> (u8, u8) {
+        (self.sc3.position(), self.sc3.volume)
+    }
+
+    // the wave ram quirks differ between models, compat mode keeps the CGB ones
+    pub fn set_model(&mut self, mode: MODE) {
+        self.sc3.cgb = mode == MODE::CGB;
+    }
+
+    // FF76 and FF77, the 4 bit value each channel feeds its DAC, the lower numbered one in the low nibble
+    pub fn pcm12(&mut self) -> u8 {
+        let s1 = self.sc1.get_sample().max(0) as u8;
+        let s2 = self.sc2.get_sample().max(0) as u8;
+        s1 | s2 << 4
+    }
+
+    pub fn pcm34(&mut self) -> u8 {
+        let s3 = match self.sc3.volume {
+            0 => 0,
+            v => self.sc3.get_sample() as u8 >> (v - 1)
+        };
+        let s4 = self.sc4.get_sample().max(0) as u8;
+        s3 | s4 << 4
+    }
+
+    // back to power on, keeping the audio config, the channel mixer, the channel dump and samples not taken yet
+    pub fn reset(&mut self) {
+        let old = core::mem::take(self);
+        self.output = old.output;
+        self.output.clear();
+        self.channel_volume = old.channel_volume;
+        self.channel_muted = old.channel_muted;
+        self.gain = old.gain;
+        self.samples = old.samples;
+        self.sc3.cgb = old.sc3.cgb;
+        self.channel_dump = old.channel_dump.map(|_| Default::default());
+        self.recording = old.recording;
+    }
+
+    // sample rate and resampler of what goes into samples, the buffer length is the frontend's business
+    pub fn set_audio_config(&mut self, config: AudioConfig) {
+        if config != self.output.config {
+            self.output = Resample::new(config);
+        }
+    }
+
+    pub fn audio_config(&self) -> AudioConfig {
+        self.output.config
+    }
+
+    // Channels 0-3 are square 1, square 2, wave and noise. Only the mix in samples hears these,
+    // the registers, PCM12/PCM34 and the channel dump don't
+    pub fn set_channel_muted(&mut self, ch: usize, muted: bool) {
+        self.channel_muted[ch] = muted;
+        self.update_gain();
+    }
+
+    pub fn channel_muted(&self, ch: usize) -> bool {
+        self.channel_muted[ch]
+    }
+
+    // ch alone, or every channel again when it already was
+    pub fn solo_channel(&mut self, ch: usize) {
+        let solo = (0 .. 4).all(|c| self.channel_muted[c] == (c != ch));
+        for c in 0 .. 4 {
+            self.channel_muted[c] = !solo && c != ch;
+        }
+        self.update_gain();
+    }
+
+    // 0.0 - 2.0
+    pub fn set_channel_volume(&mut self, ch: usize, volume: f32) {
+        self.channel_volume[ch] = volume.clamp(0., 2.);
+        self.update_gain();
+    }
+
+    pub fn channel_volume(&self, ch: usize) -> f32 {
+        self.channel_volume[ch]
+    }
+
+    fn update_gain(&mut self) {
+        for c in 0 .. 4 {
+            self.gain[c] = if self.channel_muted[c] { 0 } else { (self.channel_volume[c] * 256.) as i32 };
+        }
+    }
+
+    // for audio regression tests, drained by the caller like samples
+    pub fn set_channel_dump(&mut self, on: bool) {
+        self.channel_dump = if on { Some(Default::default()) } else { None };
+    }
+
+    pub fn channel_dump(&mut self) -> Option<&mut [Vec<i16>; 4]> {
+        self.channel_dump.as_mut()
+    }
+
+    // a copy of the mix for CPU::start_audio_recording, written out and emptied every frame
+    pub fn set_recording(&mut self, on: bool) {
+        self.recording = if on { Some(Vec::new()) } else { None };
+    }
+
+    pub fn recording(&mut self) -> Option<&mut Vec<i16>> {
+        self.recording.as_mut()
+    }
+
+    // moves as many samples as fit into a frontend buffer, returns how many were written
+    pub fn drain_samples(&mut self, out: &mut [i16]) -> usize {
+        let n = out.len().min(self.samples.len());
+        out[.. n].copy_from_slice(&self.samples[.. n]);
+        self.samples.drain(.. n);
+        n
+    }
+
+    // queued samples aren't part of the state, a load drops them so what plays next comes from
+    // the loaded timeline only, rewind and rollback don't leave the old one's tail in the buffer.
+    // Neither is the resampler, states stay the same whatever the audio config
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.volume.data, self.sch_output.data, self.sch_control]);
+        self.sc1.save_state(w);
+        self.sc2.save_state(w);
+        self.sc3.save_state(w);
+        self.sc4.save_state(w);
+        w.write_u16(self.clock);
+        w.write_u8(self.frame_clock);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.volume.write(r.read_u8()?);
+        self.sch_output.write(r.read_u8()?);
+        self.sch_control = r.read_u8()?;
+        self.sc1.load_state(r)?;
+        self.sc2.load_state(r)?;
+        self.sc3.load_state(r)?;
+        self.sc4.load_state(r)?;
+        self.clock = r.read_u16()?;
+        if self.clock > 8192 {
+            return Err("save state has the frame sequencer past its step")
+        }
+        self.frame_clock = r.read_u8()?&0x7;
+        self.output.clear();
+        self.samples.clear();
+        if let Some(dump) = &mut self.channel_dump {
+            dump.iter_mut().for_each(|c| c.clear());
+        }
+        Ok(())
+    }
+
+    // everything a state holds, channel timers and the noise lfsr drift apart long before
+    // the registers show it
+    pub fn hash_state(&self, h: &mut StateHasher) {
+        let mut w = StateWriter::new();
+        self.save_state(&mut w);
+        h.write(&w.finish());
+    }
+
     pub fn read(&mut self, addr: u16) -> u8 {
         match addr {
             // sound channel 1
@@ -242,7 +436,7 @@ impl APU {
             0xFF1C => (self.sc3.volume << 5)| 0x9F,  // only 6 and 5 bit used
             0xFF1D => self.sc3.freq_lo,
             0xFF1E => self.sc3.freq_hi,
-            0xFF30 ..= 0xFF3F => self.sc3.wave_data[addr as usize - 0xFF30],
+            0xFF30 ..= 0xFF3F => self.sc3.ram_read(addr as usize - 0xFF30),
 
             // sound channel 4
             0xFF20 => self.sc4.length.read() | 0xC0,  // two MSb unused
@@ -280,7 +474,7 @@ impl APU {
             0xFF1C => self.sc3.volume = (val >> 5)&0x3,
             0xFF1D => self.sc3.freq_lo_write(val),
             0xFF1E => self.sc3.freq_hi_write(val),
-            0xFF30 ..= 0xFF3F => self.sc3.wave_data[addr as usize - 0xFF30] = val,
+            0xFF30 ..= 0xFF3F => self.sc3.ram_write(addr as usize - 0xFF30, val),
 
             // sound channel 4
             0xFF20 => self.sc4.length.write(val),
@@ -294,11 +488,40 @@ impl APU {
             0xFF26 => self.sch_control = (val&0x80) | (self.sch_control&0x7F),
 
             _ => {
+                #[cfg(feature = "std")]
                 println!("Write to weird APU address: {:x}, val: {:x}", addr, val);
             }
         }
     }
 
+    fn mix(&mut self) -> (i16, i16) {
+        let mut l = 0;
+        let mut r = 0;
+
+        let s1 = self.sc1.get_sample() as i32 * self.gain[0];
+        let s2 = self.sc2.get_sample() as i32 * self.gain[1];
+        let s3 = self.sc3.get_sample() as i32 * self.gain[2];
+        let s4 = self.sc4.get_sample() as i32 * self.gain[3];
+
+        if self.sch_control&0x80 != 0 {
+            if self.sch_output.left_sch1 { l += s1; }
+            if self.sch_output.left_sch2 { l += s2; }
+            if self.sch_output.left_sch3 { l += s3; }
+            if self.sch_output.left_sch4 { l += s4; }
+
+            l *= self.volume.left as i32;
+
+            if self.sch_output.right_sch1 { r += s1; }
+            if self.sch_output.right_sch2 { r += s2; }
+            if self.sch_output.right_sch3 { r += s3; }
+            if self.sch_output.right_sch4 { r += s4; }
+
+            r *= self.volume.right as i32;
+        }
+
+        ((l*4 / 256) as i16, (r*4 / 256) as i16)
+    }
+
     pub fn tick(&mut self){
         self.sc1.tick();
         self.sc2.tick();
@@ -325,52 +548,27 @@ impl APU {
             self.clock = 0;
         }
 
-        if self.sample_clock % 87 == 0 {  // 4194304 / 87 ~ 48000Hz aka sample rate
-            let mut l = 0;
-            let mut r = 0;
-            let pos = (self.sample_clock / 87) * 2;
-
-            let s1 = self.sc1.get_sample();
-            let s2 = self.sc2.get_sample();
-            let s3 = self.sc3.get_sample();
-            let s4 = self.sc4.get_sample();
-
-            if self.sch_control&0x80 != 0 {
-                if self.sch_output.left_sch1 { l += s1; }
-                if self.sch_output.left_sch2 { l += s2; }
-                if self.sch_output.left_sch3 { l += s3; }
-                if self.sch_output.left_sch4 { l += s4; }
-
-                l *= self.volume.left;
-
-                if self.sch_output.right_sch1 { r += s1; }
-                if self.sch_output.right_sch2 { r += s2; }
-                if self.sch_output.right_sch3 { r += s3; }
-                if self.sch_output.right_sch4 { r += s4; }
-
-                r *= self.volume.right;
-            }
-
-            self.samples[pos as usize] = l*4;
-            self.samples[pos as usize + 1] = r*4;
-
-            if pos == BUFFER_SIZE as u32 - 2 {
-                unsafe {
-                    while !raylib::ffi::IsAudioStreamProcessed(self.stream) {}
-                    raylib::ffi::UpdateAudioStream(
-                        self.stream,
-                        self.samples.as_ptr() as *const std::os::raw::c_void,
-                        BUFFER_SIZE as i32
-                    );
+        if self.clock & 3 == 0 {  // 1MiHz, the resampler brings it down to the sample rate
+            let (l, r) = self.mix();
+            if self.output.push(l, r, &mut self.samples) {
+                if let Some(rec) = &mut self.recording {
+                    rec.extend_from_slice(&self.samples[self.samples.len() - 2 ..]);
+                }
+                if let Some(dump) = &mut self.channel_dump {
+                    dump[0].push(self.sc1.get_sample());
+                    dump[1].push(self.sc2.get_sample());
+                    dump[2].push(self.sc3.get_sample());
+                    dump[3].push(self.sc4.get_sample());
                 }
-                self.sample_clock = 0;
-            } else {
-                self.sample_clock += 1;
             }
-        } else {        
-            self.sample_clock += 1;
         }
 
         self.clock += 1;
     }
+}
+
+impl Default for APU {
+    fn default() -> APU {
+        APU::new()
+    }
 }
\ No newline at end of file
@@ -1,13 +1,30 @@
+#[cfg(feature = "apu")]
 mod square;
+#[cfg(feature = "apu")]
 mod wave;
+#[cfg(feature = "apu")]
 mod noise;
+#[cfg(feature = "apu")]
+mod resample;
+#[cfg(feature = "apu")]
 mod apu;
+#[cfg(not(feature = "apu"))]
+mod null;
 
+#[cfg(feature = "apu")]
 pub use square::Square;
+#[cfg(feature = "apu")]
 pub use wave::Wave;
+#[cfg(feature = "apu")]
 pub use noise::Noise;
+#[cfg(feature = "apu")]
+pub use resample::Resample;
+#[cfg(feature = "apu")]
 pub use apu::*;
+#[cfg(not(feature = "apu"))]
+pub use null::*;
 
+#[cfg(feature = "apu")]
 pub const DUTY_CYCLE: [[i16; 8]; 4] = [
     [-1, -1, -1, -1, -1, -1, -1, 1],
     [1, -1, -1, -1, -1, -1, -1, 1],
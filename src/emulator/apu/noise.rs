@@ -1,4 +1,5 @@
 use crate::emulator::apu::{Envelope, LengthDuty};
+use crate::emulator::{StateWriter, StateReader};
 
 const DIVISOR_CODE: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
 
@@ -89,4 +90,27 @@ impl Noise {
         self.enable = true;
         self.lfsr = 0x7FFF;
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.length.save_state(w);
+        self.envelope.save_state(w);
+        w.write(&[self.counter_consecutive, self.enable as u8, self.clock_shift, self.width_mode, self.divisor]);
+        w.write_u16(self.timer);
+        w.write_u16(self.lfsr);
+        w.write_u16(self.output as u16);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.length.load_state(r)?;
+        self.envelope.load_state(r)?;
+        self.counter_consecutive = r.read_u8()?;
+        self.enable = r.read_bool()?;
+        self.clock_shift = r.read_u8()?&0xF;
+        self.width_mode = r.read_u8()?;
+        self.divisor = r.read_u8()?&0x7;
+        self.timer = r.read_u16()?;
+        self.lfsr = r.read_u16()?;
+        self.output = r.read_u16()? as i16;
+        Ok(())
+    }
 }
\ No newline at end of file
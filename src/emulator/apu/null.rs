@@ -0,0 +1,59 @@
+use alloc::vec::Vec;
+
+use crate::emulator::{AudioConfig, StateHasher, StateWriter, StateReader, MODE};
+
+// Stands in for the APU when built without the "apu" feature,
+// registers read as unmapped and no samples are made
+pub struct APU {
+    pub samples: Vec<i16>
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            samples: vec![]
+        }
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {}
+    pub fn set_model(&mut self, _mode: MODE) {}
+
+    pub fn tick(&mut self) {}
+
+    pub fn read(&mut self, _addr: u16) -> u8 { 0xFF }
+    pub fn write(&mut self, _addr: u16, _val: u8) {}
+    pub fn pcm12(&mut self) -> u8 { 0xFF }
+    pub fn pcm34(&mut self) -> u8 { 0xFF }
+
+    pub fn wave_ram(&self) -> &[u8; 16] { &[0; 16] }
+    pub fn set_wave_sample(&mut self, _i: usize, _val: u8) {}
+    pub fn wave_status(&self) -> (u8, u8) { (0, 0) }
+
+    pub fn set_audio_config(&mut self, _config: AudioConfig) {}
+    pub fn audio_config(&self) -> AudioConfig { AudioConfig::new() }
+
+    pub fn set_channel_muted(&mut self, _ch: usize, _muted: bool) {}
+    pub fn channel_muted(&self, _ch: usize) -> bool { false }
+    pub fn solo_channel(&mut self, _ch: usize) {}
+    pub fn set_channel_volume(&mut self, _ch: usize, _volume: f32) {}
+    pub fn channel_volume(&self, _ch: usize) -> f32 { 1. }
+
+    pub fn set_channel_dump(&mut self, _on: bool) {}
+    pub fn channel_dump(&mut self) -> Option<&mut [Vec<i16>; 4]> { None }
+
+    pub fn set_recording(&mut self, _on: bool) {}
+    pub fn recording(&mut self) -> Option<&mut Vec<i16>> { None }
+
+    pub fn drain_samples(&mut self, _out: &mut [i16]) -> usize { 0 }
+
+    pub fn hash_state(&self, _h: &mut StateHasher) {}
+    pub fn save_state(&self, _w: &mut StateWriter) {}
+    pub fn load_state(&mut self, _r: &mut StateReader) -> Result<(), &'static str> { Ok(()) }
+}
+
+impl Default for APU {
+    fn default() -> APU {
+        APU::new()
+    }
+}
@@ -0,0 +1,148 @@
+use alloc::vec::Vec;
+use core::f32::consts::PI;
+
+use crate::emulator::{AudioConfig, Resampler};
+
+pub const INPUT_RATE: u32 = 1048576;  // the APU mixes every 4 clocks
+const BOX: i32 = 4;  // inputs averaged into one before the sinc filter
+const MID_RATE: u32 = INPUT_RATE / BOX as u32;  // 262144Hz
+const TAPS: usize = 64;  // MID_RATE values around each sample, also how many are kept
+const PHASES: usize = 64;  // kernel rows from one MID_RATE value to the next, positions in between interpolate
+const CUTOFF: f32 = 20000.;  // Hz, or 45% of the sample rate when that's lower
+
+// sin(pi*x), core has no trig without std. Taylor on the first quarter wave, within 2e-6
+fn sin_pi(x: f32) -> f32 {
+    let sign = if x < 0. { -1. } else { 1. };
+    let x = x.abs() % 2.;
+    let (sign, x) = if x > 1. { (-sign, x - 1.) } else { (sign, x) };
+    let a = PI * if x > 0.5 { 1. - x } else { x };
+    let a2 = a*a;
+    sign * a * (1. - a2/6. * (1. - a2/20. * (1. - a2/42. * (1. - a2/72.))))
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 { 1. } else { sin_pi(x) / (PI * x) }
+}
+
+// Blackman windowed sinc, row p is for a sample p/PHASES of the way from the TAPS/2-th oldest
+// value to the next one. Rows sum to 1 so a constant level comes out unchanged
+fn kernel(rate: u32) -> Vec<f32> {
+    let fc = CUTOFF.min(rate as f32 * 0.45) / MID_RATE as f32;
+    let half = (TAPS/2) as f32;
+    let mut kernel = Vec::with_capacity((PHASES + 1) * TAPS);
+
+    for p in 0 ..= PHASES {
+        let t = p as f32 / PHASES as f32;
+        let row = kernel.len();
+        for k in 0 .. TAPS {
+            let x = t + half - 1. - k as f32;  // from value k to the sample
+            let z = x / half;
+            let window = 0.42 + 0.5*sin_pi(z + 0.5) + 0.08*sin_pi(2.*z + 0.5);
+            kernel.push(2. * fc * sinc(2. * fc * x) * window);
+        }
+        let sum: f32 = kernel[row ..].iter().sum();
+        kernel[row ..].iter_mut().for_each(|h| *h /= sum);
+    }
+    kernel
+}
+
+// Turns the APU's INPUT_RATE output into samples at the configured rate
+pub struct Resample {
+    pub config: AudioConfig,
+    phase: u32,  // counts up by the sample rate with every input, a sample is due when it passes the input's rate
+    acc: (i32, i32),  // inputs since the last sample, or since the last MID_RATE value for sinc
+    count: i32,
+    history: [[f32; 2]; TAPS],  // sinc, the last MID_RATE values as a ring
+    head: usize,  // the oldest one, written next
+    kernel: Vec<f32>  // sinc, (PHASES + 1) rows of TAPS
+}
+
+impl Resample {
+    pub fn new(config: AudioConfig) -> Resample {
+        Resample {
+            config,
+            phase: 0,
+            acc: (0, 0),
+            count: 0,
+            history: [[0.; 2]; TAPS],
+            head: 0,
+            kernel: if config.resampler == Resampler::Sinc { kernel(config.sample_rate) } else { vec![] }
+        }
+    }
+
+    // forgets the inputs so far, the next sample only depends on what comes after
+    pub fn clear(&mut self) {
+        self.phase = 0;
+        self.acc = (0, 0);
+        self.count = 0;
+        self.history = [[0.; 2]; TAPS];
+        self.head = 0;
+    }
+
+    fn due(&mut self, rate: u32) -> bool {
+        self.phase += self.config.sample_rate;
+        if self.phase < rate {
+            return false
+        }
+        self.phase -= rate;
+        true
+    }
+
+    // takes one input, true when it completed a sample in out
+    pub fn push(&mut self, l: i16, r: i16, out: &mut Vec<i16>) -> bool {
+        match self.config.resampler {
+            Resampler::Nearest => {
+                if !self.due(INPUT_RATE) {
+                    return false
+                }
+                out.push(l);
+                out.push(r);
+            },
+            Resampler::Average => {
+                self.acc.0 += l as i32;
+                self.acc.1 += r as i32;
+                self.count += 1;
+                if !self.due(INPUT_RATE) {
+                    return false
+                }
+                out.push((self.acc.0 / self.count) as i16);
+                out.push((self.acc.1 / self.count) as i16);
+                self.acc = (0, 0);
+                self.count = 0;
+            },
+            Resampler::Sinc => {
+                self.acc.0 += l as i32;
+                self.acc.1 += r as i32;
+                self.count += 1;
+                if self.count < BOX {
+                    return false
+                }
+                self.history[self.head] = [self.acc.0 as f32 / BOX as f32, self.acc.1 as f32 / BOX as f32];
+                self.head = (self.head + 1) % TAPS;
+                self.acc = (0, 0);
+                self.count = 0;
+                if !self.due(MID_RATE) {
+                    return false
+                }
+
+                // the sample fell this far before the newest value, it comes out about TAPS/2 values late
+                let before = self.phase as f32 / self.config.sample_rate as f32;
+                let pos = (1. - before) * PHASES as f32;
+                let p = (pos as usize).min(PHASES - 1);
+                let f = pos - p as f32;
+                let (a, b) = (&self.kernel[p*TAPS .. (p+1)*TAPS], &self.kernel[(p+1)*TAPS .. (p+2)*TAPS]);
+
+                let mut sum = [0.; 2];
+                for k in 0 .. TAPS {
+                    let h = a[k] + (b[k] - a[k]) * f;
+                    let v = self.history[(self.head + k) % TAPS];
+                    sum[0] += v[0] * h;
+                    sum[1] += v[1] * h;
+                }
+                out.push(sum[0] as i16);
+                out.push(sum[1] as i16);
+            }
+        }
+        true
+    }
+}
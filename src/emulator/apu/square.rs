@@ -1,4 +1,5 @@
 use crate::emulator::apu::{Envelope, LengthDuty};
+use crate::emulator::{StateWriter, StateReader};
 
 pub struct Sweep {
     period: u8,
@@ -44,6 +45,21 @@ impl Sweep {
 
         freq
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.period, self.negate, self.shift, self.timer, self.enabled as u8]);
+        w.write_u16(self.freq);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.period = r.read_u8()?;
+        self.negate = r.read_u8()?;
+        self.shift = r.read_u8()?;
+        self.timer = r.read_u8()?;
+        self.enabled = r.read_bool()?;
+        self.freq = r.read_u16()?;
+        Ok(())
+    }
 }
 
 pub struct Square {  // Tone
@@ -145,4 +161,26 @@ impl Square {
             }
         }
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.length_duty.save_state(w);
+        self.envelope.save_state(w);
+        w.write(&[self.freq_lo, self.freq_hi, self.duty_pos, self.counter_enabled, self.enabled as u8]);
+        w.write_u16(self.timer);
+        w.write_u16(self.freq);
+        self.sweep.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.length_duty.load_state(r)?;
+        self.envelope.load_state(r)?;
+        self.freq_lo = r.read_u8()?;
+        self.freq_hi = r.read_u8()?;
+        self.duty_pos = r.read_u8()? % 8;
+        self.counter_enabled = r.read_u8()?;
+        self.enabled = r.read_bool()?;
+        self.timer = r.read_u16()?;
+        self.freq = r.read_u16()?&0x7FF;
+        self.sweep.load_state(r)
+    }
 }
\ No newline at end of file
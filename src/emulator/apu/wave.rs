@@ -1,4 +1,5 @@
 use crate::emulator::apu::LengthDuty;
+use crate::emulator::{StateWriter, StateReader};
 
 pub struct Wave {  // Wave
     pub enable: bool,       // 0xFF1A NR30
@@ -11,6 +12,8 @@ pub struct Wave {  // Wave
     counter_enabled: u8,
     timer: u16,
     sample_pos: u8,
+    just_read: bool,  // the last clock moved to a new sample, on a DMG the cpu can reach wave ram only then
+    pub cgb: bool,
 }
 
 impl Wave {
@@ -25,7 +28,9 @@ impl Wave {
             freq: 0,
             counter_enabled: 0,
             sample_pos: 0,
-            timer: 0
+            timer: 0,
+            just_read: false,
+            cgb: false
         }
     }
 
@@ -47,12 +52,32 @@ impl Wave {
         if self.timer > 0 {
             self.timer -= 1;
         }
+        self.just_read = self.timer == 0;
         if self.timer == 0 {
             self.sample_pos = (self.sample_pos + 1) % 32;
             self.timer = (2048 - self.freq)*4;
         }
     }
 
+    // While it plays, the cpu gets the byte the channel is on whatever address it asks for. A CGB
+    // always sees it, a DMG only on the clock the channel reads it and gets FF otherwise
+    pub fn ram_read(&self, i: usize) -> u8 {
+        if !self.enable {
+            self.wave_data[i]
+        } else if self.cgb || self.just_read {
+            self.wave_data[self.sample_pos as usize/2]
+        } else { 0xFF }
+    }
+
+    // same as ram_read, writes a DMG can't reach are lost
+    pub fn ram_write(&mut self, i: usize, val: u8) {
+        if !self.enable {
+            self.wave_data[i] = val;
+        } else if self.cgb || self.just_read {
+            self.wave_data[self.sample_pos as usize/2] = val;
+        }
+    }
+
     pub fn get_sample(&mut self) -> i16 {
         if (self.counter_enabled != 0 && self.length.length > 0) || self.enable {
             let sample = self.wave_data[self.sample_pos as usize/2];
@@ -65,10 +90,49 @@ impl Wave {
         0
     }
 
+    pub fn position(&self) -> u8 {
+        self.sample_pos
+    }
+
+    // A DMG retriggered on the clock before the channel reads a byte corrupts the start of wave
+    // ram. A byte in the first 4 gets copied over byte 0, otherwise its aligned 4 over bytes 0-3
     pub fn trigger(&mut self) {
+        if !self.cgb && self.enable && self.timer == 1 {
+            let pos = ((self.sample_pos as usize + 1) % 32) / 2;
+            if pos < 4 {
+                self.wave_data[0] = self.wave_data[pos];
+            } else {
+                let block = pos & !3;
+                self.wave_data.copy_within(block .. block + 4, 0);
+            }
+        }
+
         self.length.length = 255;
         self.timer = (2048 - self.freq) * 4;
         self.sample_pos = 0;
         self.enable = true;
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.enable as u8, self.volume, self.freq_lo, self.freq_hi, self.counter_enabled, self.sample_pos, self.just_read as u8]);
+        self.length.save_state(w);
+        w.write(&self.wave_data);
+        w.write_u16(self.freq);
+        w.write_u16(self.timer);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.enable = r.read_bool()?;
+        self.volume = r.read_u8()?&0x3;
+        self.freq_lo = r.read_u8()?;
+        self.freq_hi = r.read_u8()?;
+        self.counter_enabled = r.read_u8()?;
+        self.sample_pos = r.read_u8()? % 32;
+        self.just_read = r.read_bool()?;
+        self.length.load_state(r)?;
+        r.read_into(&mut self.wave_data)?;
+        self.freq = r.read_u16()?&0x7FF;
+        self.timer = r.read_u16()?;
+        Ok(())
+    }
 }
\ No newline at end of file
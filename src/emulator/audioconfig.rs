@@ -0,0 +1,35 @@
+pub const MIN_SAMPLE_RATE: u32 = 8000;
+pub const MAX_SAMPLE_RATE: u32 = 96000;
+
+// How the APU's 1MiHz output is brought down to the sample rate
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Resampler {
+    Nearest,  // the output at each sample, cheapest, high notes alias into audible whistles
+    Average,  // box filter over each sample period, most aliasing gone
+    Sinc      // windowed sinc low pass, band limited to under half the sample rate
+}
+
+// The APU's output format, what the frontend opens the audio device with. A shorter buffer
+// means less latency and more crackle on a host that can't keep it fed
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct AudioConfig {
+    pub sample_rate: u32,  // MIN_SAMPLE_RATE - MAX_SAMPLE_RATE Hz
+    pub buffer: usize,  // stereo frames per stream buffer
+    pub resampler: Resampler
+}
+
+impl AudioConfig {
+    pub fn new() -> AudioConfig {
+        AudioConfig {
+            sample_rate: 48000,
+            buffer: 4096,
+            resampler: Resampler::Average
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig::new()
+    }
+}
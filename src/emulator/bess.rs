@@ -0,0 +1,309 @@
+use alloc::vec::Vec;
+
+use crate::emulator::{CPU, MODE, RTC_FOOTER_SIZE};
+
+// BESS (Best Effort Save State, https://github.com/LIJI32/SameBoy/blob/master/BESS.md), the block
+// format SameBoy and other emulators append to their own states. Ours are laid out as
+// [native state][raw memory buffers][blocks][u32 offset of the first block]["BESS"]
+// so we load our own part and everyone else reads the blocks.
+const FOOTER_MAGIC: &[u8; 4] = b"BESS";
+const CORE_SIZE: usize = 0xD0;
+const XOAM_SIZE: usize = 0x60;
+const INFO_SIZE: usize = 0x12;
+const NAME: &str = concat!("sponGB ", env!("CARGO_PKG_VERSION"));
+
+fn write_block(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos+1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> usize {
+    u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]) as usize
+}
+
+pub fn is_bess(data: &[u8]) -> bool {
+    data.len() >= 8 && &data[data.len() - 4 ..] == FOOTER_MAGIC
+}
+
+// id, contents
+type Block<'a> = (&'a [u8], &'a [u8]);
+
+// up to END, blocks we don't know are left for the caller to skip
+fn blocks(data: &[u8]) -> Result<Vec<Block<'_>>, &'static str> {
+    let end = data.len() - 8;
+    let mut pos = u32_at(data, end);
+    let mut out = vec![];
+
+    loop {
+        if end < 8 || pos > end - 8 {
+            return Err("BESS blocks run past the end of the state")
+        }
+        let id = &data[pos .. pos + 4];
+        let len = u32_at(data, pos + 4);
+        pos += 8;
+        if len > end - pos {
+            return Err("BESS block runs past the end of the state")
+        }
+        if id == b"END " {
+            return Ok(out)
+        }
+        out.push((id, &data[pos .. pos + len]));
+        pos += len;
+    }
+}
+
+fn core_block(data: &[u8]) -> Result<&[u8], &'static str> {
+    match blocks(data)?.iter().find(|(id, _)| *id == b"CORE") {
+        Some((_, core)) if core.len() >= CORE_SIZE => Ok(core),
+        Some(_) => Err("BESS CORE block is too short"),
+        None => Err("BESS state has no CORE block")
+    }
+}
+
+// size and offset pair from the CORE block, checked against the file
+fn buffer<'a>(data: &'a [u8], core: &[u8], pos: usize) -> Result<&'a [u8], &'static str> {
+    let size = u32_at(core, pos);
+    let offset = u32_at(core, pos + 4);
+    offset.checked_add(size).and_then(|end| data.get(offset .. end)).ok_or("BESS memory buffer is outside the state")
+}
+
+// the part of one of our states in front of the BESS data
+pub fn native_part(data: &[u8]) -> &[u8] {
+    if !is_bess(data) {
+        return data
+    }
+    match core_block(data) {
+        Ok(core) => &data[.. u32_at(core, 0x9C).min(data.len())],  // wram comes right after it
+        Err(_) => data
+    }
+}
+
+pub fn append(cpu: &mut CPU, mut out: Vec<u8>) -> Vec<u8> {
+    let cgb = cpu.memory.mode == MODE::CGB;
+    let mut buffers: Vec<(u32, u32)> = vec![];
+    {
+        let mut add = |out: &mut Vec<u8>, data: &[u8]| {
+            buffers.push((data.len() as u32, out.len() as u32));
+            out.extend_from_slice(data);
+        };
+        let wram_size = if cgb { cpu.memory.wram().len() } else { 0x2000 };
+        add(&mut out, &cpu.memory.wram()[.. wram_size]);
+        add(&mut out, &cpu.memory.vram[.. if cgb { cpu.memory.vram.len() } else { 0x2000 }]);
+        let ram = cpu.memory.cart.ram().to_vec();
+        add(&mut out, &ram);
+        add(&mut out, &cpu.memory.OAM);
+        add(&mut out, cpu.memory.hram());
+        let bg = if cgb { cpu.memory.ppu.palette_bytes(false).to_vec() } else { vec![] };
+        add(&mut out, &bg);
+        let obj = if cgb { cpu.memory.ppu.palette_bytes(true).to_vec() } else { vec![] };
+        add(&mut out, &obj);
+    }
+    let first_block = out.len() as u32;
+
+    write_block(&mut out, b"NAME", NAME.as_bytes());
+
+    let header = &cpu.memory.cart.header;
+    let mut info = vec![];
+    info.extend_from_slice(&header[0x34 .. 0x44]);  // title, 0x134-0x143
+    info.extend_from_slice(&header[0x4E .. 0x50]);  // global checksum
+    write_block(&mut out, b"INFO", &info);
+
+    let mut core = Vec::with_capacity(CORE_SIZE);
+    core.extend_from_slice(&1u16.to_le_bytes());
+    core.extend_from_slice(&1u16.to_le_bytes());
+    core.extend_from_slice(if cgb { b"CCE " } else { b"GDB " });
+    for r in [cpu.PC, *cpu.AF(), *cpu.BC(), *cpu.DE(), *cpu.HL(), cpu.SP].iter() {
+        core.extend_from_slice(&r.to_le_bytes());
+    }
+    let state = if cpu.memory.stopped() { 2 } else { cpu.halt as u8 };
+    core.extend_from_slice(&[cpu.IME as u8, cpu.memory.IER, state, 0]);
+    for addr in 0xFF00 ..= 0xFF7F {
+        core.push(cpu.memory.read(addr));
+    }
+    let io = core.len() - 0x80;
+    if cgb {  // BESS wants the write only ones as written
+        core[io + 0x51 .. io + 0x55].copy_from_slice(&cpu.memory.hdma_registers());
+    }
+    for (size, offset) in buffers.iter() {
+        core.extend_from_slice(&size.to_le_bytes());
+        core.extend_from_slice(&offset.to_le_bytes());
+    }
+    write_block(&mut out, b"CORE", &core);
+
+    write_block(&mut out, b"XOAM", &[0; XOAM_SIZE]);  // FEA0-FEFF isn't emulated
+
+    let mut mbc = vec![];
+    for (addr, val) in cpu.memory.cart.register_writes().iter() {
+        mbc.extend_from_slice(&addr.to_le_bytes());
+        mbc.push(*val);
+    }
+    if !mbc.is_empty() {
+        write_block(&mut out, b"MBC ", &mbc);
+    }
+    // other mappers' clocks don't use the MBC3 layout the RTC block has
+    if let (Some(footer), 0x0F ..= 0x10) = (cpu.memory.cart.rtc_footer(), cpu.memory.cart.header[0x47]) {
+        write_block(&mut out, b"RTC ", &footer);
+    }
+    write_block(&mut out, b"END ", &[]);
+
+    out.extend_from_slice(&first_block.to_le_bytes());
+    out.extend_from_slice(FOOTER_MAGIC);
+    out
+}
+
+// A state from another emulator. The machine is left as it was for anything the state
+// doesn't cover, the PPU restarts its line and unknown blocks are skipped
+pub fn load(cpu: &mut CPU, data: &[u8]) -> Result<(), &'static str> {
+    let blocks = blocks(data)?;
+    let core = core_block(data)?;
+
+    if u16_at(core, 0) != 1 {
+        return Err("BESS state is from an unsupported major version")
+    }
+    let mode = match core[4] {
+        b'C' => MODE::CGB,
+        b'G' | b'S' => MODE::DMG,
+        _ => return Err("BESS state is for an unknown model")
+    };
+    if (mode == MODE::CGB) != (cpu.memory.mode == MODE::CGB) {
+        return Err("save state is for the other model")
+    }
+    if let Some((_, info)) = blocks.iter().find(|(id, _)| *id == b"INFO") {
+        let header = &cpu.memory.cart.header;
+        if info.len() >= INFO_SIZE && (info[.. 0x10] != header[0x34 .. 0x44] || info[0x10 .. 0x12] != header[0x4E .. 0x50]) {
+            return Err("save state is for a different game")
+        }
+    }
+
+    let buffers = (0 .. 7).map(|i| buffer(data, core, 0x98 + i*8)).collect::<Result<Vec<_>, _>>()?;
+
+    // banking first, the io registers and cart ram depend on it
+    if let Some((_, mbc)) = blocks.iter().find(|(id, _)| *id == b"MBC ") {
+        for w in mbc.chunks_exact(3) {
+            let addr = u16_at(w, 0);
+            if addr < 0x8000 {
+                cpu.memory.write(addr, w[2]);
+            }
+        }
+    }
+
+    let copy = |to: &mut [u8], from: &[u8]| {
+        let len = to.len().min(from.len());
+        to[.. len].copy_from_slice(&from[.. len]);
+    };
+    copy(cpu.memory.wram_mut(), buffers[0]);
+    copy(&mut cpu.memory.vram, buffers[1]);
+    copy(cpu.memory.cart.ram_mut(), buffers[2]);
+    copy(&mut cpu.memory.OAM, buffers[3]);
+    copy(cpu.memory.hram_mut(), buffers[4]);
+    if mode == MODE::CGB {
+        cpu.memory.ppu.set_palette_bytes(false, buffers[5]);
+        cpu.memory.ppu.set_palette_bytes(true, buffers[6]);
+    }
+    cpu.memory.restore_io(&core[0x18 .. 0x98]);
+
+    cpu.PC = u16_at(core, 0x8);
+    *cpu.AF() = u16_at(core, 0xA)&0xFFF0;
+    *cpu.BC() = u16_at(core, 0xC);
+    *cpu.DE() = u16_at(core, 0xE);
+    *cpu.HL() = u16_at(core, 0x10);
+    cpu.SP = u16_at(core, 0x12);
+    cpu.IME = core[0x14] != 0;
+    cpu.memory.IER = core[0x15];
+    cpu.halt = core[0x16] == 1;
+    if core[0x16] == 2 {
+        cpu.memory.stop(false);
+    }
+    cpu.EI = false;
+
+    if let Some((_, rtc)) = blocks.iter().find(|(id, _)| *id == b"RTC ") {
+        if rtc.len() == RTC_FOOTER_SIZE && (0x0F ..= 0x10).contains(&cpu.memory.cart.header[0x47]) {
+            cpu.memory.cart.load_rtc_footer(rtc);
+        }
+    }
+    cpu.memory.apu.samples.clear();
+    #[cfg(feature = "debugger")]
+    cpu.memory.vram_dirty.iter_mut().for_each(|d| *d = true);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::savestate::tests::{cpu, run};
+
+    // the io registers in the CORE block, they start at 0x18
+    fn core_io(data: &mut [u8]) -> &mut [u8] {
+        let core = data.windows(4).rposition(|w| w == b"CORE").unwrap() + 8;
+        &mut data[core + 0x18 .. core + 0x98]
+    }
+
+    #[test]
+    fn append_and_load() {
+        let mut a = cpu();
+        run(&mut a, 3);
+        let data = a.save_state_bess();
+        assert!(is_bess(&data));
+
+        let mut b = cpu();
+        load(&mut b, &data).unwrap();
+        assert_eq!((b.PC, *b.AF(), *b.BC(), *b.DE(), *b.HL(), b.SP), (a.PC, *a.AF(), *a.BC(), *a.DE(), *a.HL(), a.SP));
+        assert_eq!(b.memory.wram(), a.memory.wram());
+        assert_eq!(b.memory.vram, a.memory.vram);
+        assert_eq!(b.memory.hram(), a.memory.hram());
+        assert_eq!(b.memory.read(0xFF07), a.memory.read(0xFF07));
+        assert_eq!(b.memory.ppu.ly(), a.memory.ppu.ly());
+        run(&mut b, 2);
+
+        // our own loader takes the native part in front
+        let mut c = cpu();
+        c.load_state(&data).unwrap();
+        assert_eq!(c.save_state(), a.save_state());
+    }
+
+    #[test]
+    fn lines_below_the_screen_load_as_vblank() {
+        let mut a = cpu();
+        run(&mut a, 3);
+        for stat in [0x82, 0x83] {
+            let mut data = a.save_state_bess();
+            let io = core_io(&mut data);
+            io[0x44] = 150;
+            io[0x41] = stat;
+
+            let mut b = cpu();
+            load(&mut b, &data).unwrap();
+            assert_eq!(b.memory.ppu.ly(), 150);
+            assert_eq!(b.memory.read(0xFF41)&0x3, 1);
+            run(&mut b, 2);
+        }
+    }
+
+    #[test]
+    fn rejects_broken_blocks() {
+        let mut a = cpu();
+        run(&mut a, 1);
+        let data = a.save_state_bess();
+        let before = a.save_state();
+
+        let mut bad = data.clone();
+        let end = bad.len() - 8;
+        bad[end .. end + 4].copy_from_slice(&(end as u32 - 4).to_le_bytes());
+        assert!(load(&mut a, &bad).is_err());
+
+        let mut bad = data.clone();
+        let core = bad.windows(4).rposition(|w| w == b"CORE").unwrap();
+        bad[core + 4 .. core + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(load(&mut a, &bad).is_err());
+
+        let mut bad = data;
+        bad[core + 12] = b'X';  // model
+        assert!(load(&mut a, &bad).is_err());
+        assert_eq!(a.save_state(), before);
+    }
+}
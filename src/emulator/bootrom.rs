@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+
+use crate::emulator::MODE;
+
+const NIBBLE: usize = 0xA0;  // subroutines, the main code has to end before them
+const FRAME: usize = 0xB0;
+const FINISH: usize = 0xC0;
+const UNMAP: usize = 0xFE;  // the FF50 write, the cpu carries on at 0x100 in the cartridge
+const CGB_PALETTE: usize = 0x210;  // past the cartridge header the CGB bootrom leaves visible
+
+// white to black for BG palette 0, little endian BGR555
+const PALETTE: [u8; 8] = [0xFF, 0x7F, 0xB5, 0x56, 0x4A, 0x29, 0x00, 0x00];
+
+// A replacement for the Nintendo bootroms, for players without a dump of their own. It clears vram,
+// doubles the cartridge's logo into tiles, scrolls it down, dings and leaves the registers the
+// way CPU::boot sets them up for the model. Unlike the real ones it doesn't lock up on a bad
+// logo or header checksum, and on a CGB the palette for a dmg game still comes from dmg_palette
+pub fn free_bootrom(mode: MODE) -> Vec<u8> {
+    let cgb = mode == MODE::CGB;
+    let mut rom = vec![0u8; if cgb { 0x900 } else { 0x100 }];
+
+    let mut main = vec![
+        0x31, 0xFE, 0xFF,  // ld sp, $FFFE
+        0xAF,  // xor a
+        0x21, 0xFF, 0x9F,  // ld hl, $9FFF
+        0x32,  // .clear: ld (hl-), a
+        0xCB, 0x7C,  // bit 7, h
+        0x20, 0xFB,  // jr nz, .clear
+
+        0x3E, 0x80, 0xE0, 0x26,  // NR52 = $80, sound on
+        0xE0, 0x11,  // NR11 = $80, 50% duty
+        0x3E, 0xF3, 0xE0, 0x12,  // NR12 = $F3
+        0xE0, 0x25,  // NR51 = $F3
+        0x3E, 0x77, 0xE0, 0x24,  // NR50 = $77
+        0x3E, 0xFC, 0xE0, 0x47  // BGP = $FC
+    ];
+    if cgb {
+        main.extend_from_slice(&[
+            0x3E, 0x80, 0xE0, 0x68,  // BCPS = $80, auto increment
+            0x21, CGB_PALETTE as u8, (CGB_PALETTE >> 8) as u8,  // ld hl, palette
+            0x06, 0x08,  // ld b, 8
+            0x2A,  // .palette: ld a, (hl+)
+            0xE0, 0x69,  // ldh (BCPD), a
+            0x05,  // dec b
+            0x20, 0xFA  // jr nz, .palette
+        ]);
+    }
+    main.extend_from_slice(&[
+        // every logo byte is 2 rows of 4 pixels, doubled both ways into 4 rows of a tile
+        0x11, 0x04, 0x01,  // ld de, $0104
+        0x21, 0x10, 0x80,  // ld hl, $8010
+        0x1A,  // .logo: ld a, (de)
+        0x4F,  // ld c, a
+        0xCD, NIBBLE as u8, 0x00,  // call nibble
+        0xCD, NIBBLE as u8, 0x00,  // call nibble
+        0x13,  // inc de
+        0x7B,  // ld a, e
+        0xFE, 0x34,  // cp $34
+        0x20, 0xF2,  // jr nz, .logo
+
+        // tiles 1-12 on map row 8, 13-24 below them
+        0x3E, 0x01,  // ld a, 1
+        0x21, 0x04, 0x99,  // ld hl, $9904
+        0x0E, 0x0C,  // .row: ld c, 12
+        0x22,  // .tile: ld (hl+), a
+        0x3C,  // inc a
+        0x0D,  // dec c
+        0x20, 0xFB,  // jr nz, .tile
+        0x2E, 0x24,  // ld l, $24
+        0xFE, 0x19,  // cp 25
+        0x20, 0xF3,  // jr nz, .row
+
+        // from above the screen down to SCY 0, a line a frame
+        0x3E, 0x64, 0xE0, 0x42,  // SCY = $64
+        0x3E, 0x91, 0xE0, 0x40,  // LCDC = $91
+        0x1E, 0x64,  // ld e, $64
+        0xCD, FRAME as u8, 0x00,  // .scroll: call frame
+        0x1D,  // dec e
+        0x7B,  // ld a, e
+        0xE0, 0x42,  // ldh (SCY), a
+        0x20, 0xF7,  // jr nz, .scroll
+
+        // the two notes, then a second with the logo still
+        0x3E, 0x83, 0xE0, 0x13, 0x3E, 0x87, 0xE0, 0x14,  // NR13/NR14, trigger
+        0x1E, 0x06,  // ld e, 6
+        0xCD, FRAME as u8, 0x00,  // .first: call frame
+        0x1D,  // dec e
+        0x20, 0xFA,  // jr nz, .first
+        0x3E, 0xC1, 0xE0, 0x13, 0x3E, 0x87, 0xE0, 0x14,
+        0x1E, 0x3C,  // ld e, 60
+        0xCD, FRAME as u8, 0x00,  // .second: call frame
+        0x1D,  // dec e
+        0x20, 0xFA,  // jr nz, .second
+        0xC3, FINISH as u8, 0x00  // jp finish
+    ]);
+    assert!(main.len() <= NIBBLE);
+    rom[.. main.len()].copy_from_slice(&main);
+
+    // the top 4 bits of c, each doubled into a, written to 2 rows of plane 0
+    rom[NIBBLE .. NIBBLE + 16].copy_from_slice(&[
+        0x06, 0x04,  // ld b, 4
+        0xCB, 0x21,  // .bit: sla c
+        0x17,  // rla
+        0x0F,  // rrca, the bit goes back to carry
+        0x07,  // rlca
+        0x17,  // rla
+        0x05,  // dec b
+        0x20, 0xF7,  // jr nz, .bit
+        0x22, 0x23, 0x22, 0x23,  // ld (hl+), a, inc hl, twice
+        0xC9  // ret
+    ]);
+
+    // waits for the start of the next vblank
+    rom[FRAME .. FRAME + 13].copy_from_slice(&[
+        0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA,  // until LY is 144
+        0xF0, 0x44, 0xFE, 0x90, 0x28, 0xFA,  // and past it, so the next call waits a whole frame
+        0xC9
+    ]);
+
+    let (af, bc, de, hl): (u16, u16, u16, u16) = match mode {
+        MODE::DMG => (0x01B0, 0x0013, 0x00D8, 0x014D),
+        MODE::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+        MODE::CGB => (0x1180, 0x0000, 0xFF56, 0x000D)
+    };
+    rom[FINISH .. FINISH + 14].copy_from_slice(&[
+        0x01, af as u8, (af >> 8) as u8,  // ld bc, af
+        0xC5,  // push bc
+        0xF1,  // pop af
+        0x01, bc as u8, (bc >> 8) as u8,
+        0x11, de as u8, (de >> 8) as u8,
+        0x21, hl as u8, (hl >> 8) as u8
+    ]);
+    rom[UNMAP .. UNMAP + 2].copy_from_slice(&[0xE0, 0x50]);  // nops up to here, then ldh ($50), a
+
+    if cgb {
+        rom[CGB_PALETTE .. CGB_PALETTE + PALETTE.len()].copy_from_slice(&PALETTE);
+    }
+    rom
+}
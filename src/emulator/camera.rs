@@ -0,0 +1,248 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::emulator::{StateWriter, StateReader};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+const REGISTERS: usize = 0x36;
+const IMAGE_START: usize = 0x100;  // the picture's tiles in ram bank 0
+const EXPOSURE_NORMAL: f32 = 0x800 as f32;  // exposure that passes a source pixel through unchanged at 20dB
+// 14dB + 1.5dB a step, as a factor against 20dB
+const GAIN: [f32; 32] = [
+    0.501, 0.596, 0.708, 0.841, 1.000, 1.189, 1.413, 1.679, 1.995, 2.371, 2.818, 3.350, 3.981, 4.732, 5.623, 6.683,
+    7.943, 9.441, 11.220, 13.335, 15.849, 18.836, 22.387, 26.607, 31.623, 37.584, 44.668, 53.088, 63.096, 74.989, 89.125, 105.925
+];
+const EDGE_RATIO: [f32; 8] = [0.5, 0.75, 1., 1.25, 2., 3., 4., 5.];
+
+// Where the Pocket Camera's pictures come from, the frontend can hand the cartridge its own
+pub trait CameraSource {
+    // CAMERA_WIDTH x CAMERA_HEIGHT, row by row, 0 black - 255 white
+    fn capture(&mut self, image: &mut [u8]);
+}
+
+// What the camera sees when nothing else is plugged in: a gradient with a few shapes on it,
+// enough for the exposure and contrast settings to show
+pub struct TestImage;
+
+impl CameraSource for TestImage {
+    fn capture(&mut self, image: &mut [u8]) {
+        for (i, p) in image.iter_mut().enumerate() {
+            let (x, y) = ((i % CAMERA_WIDTH) as i32, (i / CAMERA_WIDTH) as i32);
+            let (dx, dy) = (x - 64, y - 56);
+            *p = if dx*dx + dy*dy < 24*24 {
+                if (dx*dx + dy*dy) < 12*12 { 0x20 } else { 0xE0 }
+            } else if y >= 96 {
+                if (x / 8) % 2 == 0 { 0xFF } else { 0x00 }  // stripes for the edge enhancement
+            } else {
+                (x * 2) as u8
+            };
+        }
+    }
+}
+
+// A fixed picture, scaled to fit the sensor
+pub struct StillImage {
+    pixels: Vec<u8>
+}
+
+impl StillImage {
+    pub fn new(width: usize, height: usize, pixels: &[u8]) -> StillImage {
+        let mut scaled = vec![0; CAMERA_WIDTH*CAMERA_HEIGHT];
+        for (i, p) in scaled.iter_mut().enumerate() {
+            let x = (i % CAMERA_WIDTH) * width / CAMERA_WIDTH;
+            let y = (i / CAMERA_WIDTH) * height / CAMERA_HEIGHT;
+            *p = pixels[y*width + x];
+        }
+        StillImage { pixels: scaled }
+    }
+
+    // binary PGM (P5) with 8 bit samples, what most image tools can save grayscale as
+    pub fn from_pgm(data: &[u8]) -> Result<StillImage, &'static str> {
+        const BAD: &str = "not an 8 bit binary PGM image";
+        let mut fields = [0usize; 3];
+        let mut pos = 2;
+        if !data.starts_with(b"P5") {
+            return Err(BAD)
+        }
+        for f in fields.iter_mut() {
+            // whitespace and # comments between the header fields
+            loop {
+                match data.get(pos) {
+                    Some(b'#') => while data.get(pos).is_some_and(|c| *c != b'\n') { pos += 1 },
+                    Some(c) if c.is_ascii_whitespace() => pos += 1,
+                    Some(c) if c.is_ascii_digit() => break,
+                    _ => return Err(BAD)
+                }
+            }
+            while let Some(c) = data.get(pos).filter(|c| c.is_ascii_digit()) {
+                *f = f.saturating_mul(10).saturating_add((c - b'0') as usize);
+                pos += 1;
+            }
+        }
+
+        let [width, height, max] = fields;
+        let pixels = data.get(pos + 1 ..).ok_or(BAD)?;
+        if width == 0 || height == 0 || max == 0 || max > 255 || pixels.len() < width.saturating_mul(height) {
+            return Err(BAD)
+        }
+        let pixels: Vec<u8> = pixels[.. width*height].iter().map(|p| (*p as usize * 255 / max) as u8).collect();
+        Ok(StillImage::new(width, height, &pixels))
+    }
+}
+
+impl CameraSource for StillImage {
+    fn capture(&mut self, image: &mut [u8]) {
+        image.copy_from_slice(&self.pixels);
+    }
+}
+
+// The Pocket Camera's sensor and the registers at A000-A035 that drive it. A000 bit 0 starts a
+// capture and reads 1 until it's done, the picture then lands in ram bank 0 as 16x14 tiles.
+// A001-A005 set the exposure, gain, edge enhancement and inversion, A006-A035 are a 4x4 matrix
+// of 3 thresholds each that dither the result to 4 shades. The output offset voltage isn't modelled
+pub struct Camera {
+    regs: [u8; REGISTERS],
+    busy: u32,  // M-cycles left of the capture
+    source: Box<dyn CameraSource>,
+    image: Vec<u8>
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            regs: [0; REGISTERS],
+            busy: 0,
+            source: Box::new(TestImage),
+            image: vec![0; CAMERA_WIDTH*CAMERA_HEIGHT]
+        }
+    }
+
+    pub fn set_source(&mut self, source: Box<dyn CameraSource>) {
+        self.source = source;
+    }
+
+    pub fn busy(&self) -> bool {
+        self.busy > 0
+    }
+
+    // only A000 reads back, mirrored every 0x80
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr & 0x7F {
+            0 => self.regs[0] & 0x6 | self.busy() as u8,
+            _ => 0
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        let reg = (addr & 0x7F) as usize;
+        if reg >= REGISTERS {
+            return
+        }
+        if reg == 0 {
+            self.regs[0] = val & 0x7;
+            if val&0x1 != 0 && !self.busy() {
+                self.busy = self.capture_cycles();
+            } else if val&0x1 == 0 {
+                self.busy = 0;  // stopping early leaves the old picture
+            }
+        } else {
+            self.regs[reg] = val;
+        }
+    }
+
+    fn exposure(&self) -> u32 {
+        (self.regs[2] as u32) << 8 | self.regs[3] as u32
+    }
+
+    fn capture_cycles(&self) -> u32 {
+        let n = self.regs[1] & 0x80 != 0;
+        32446 + if n { 0 } else { 512 } + 16*self.exposure()
+    }
+
+    // every M-cycle, true when a capture finished and ram bank 0 should get the picture
+    pub fn tick(&mut self) -> bool {
+        if self.busy == 0 {
+            return false
+        }
+        self.busy -= 1;
+        if self.busy == 0 {
+            self.regs[0] &= !0x1;
+            return true
+        }
+        false
+    }
+
+    // sensor output for a pixel, 0 - 255 before dithering
+    fn sensor(&self, x: i32, y: i32) -> f32 {
+        let x = x.max(0).min(CAMERA_WIDTH as i32 - 1) as usize;
+        let y = y.max(0).min(CAMERA_HEIGHT as i32 - 1) as usize;
+        let v = self.image[y*CAMERA_WIDTH + x] as f32 * self.exposure() as f32 / EXPOSURE_NORMAL;
+        (v * GAIN[(self.regs[1] & 0x1F) as usize]).min(255.)
+    }
+
+    fn pixel(&self, x: i32, y: i32) -> u8 {
+        let ratio = EDGE_RATIO[(self.regs[4] >> 4 & 0x7) as usize];
+        let v = self.sensor(x, y);
+        let horizontal = 2.*v - self.sensor(x - 1, y) - self.sensor(x + 1, y);
+        let vertical = 2.*v - self.sensor(x, y - 1) - self.sensor(x, y + 1);
+        let mut v = match self.regs[1] >> 5 & 0x3 {
+            1 => v + ratio * horizontal,
+            2 => v + ratio * vertical,
+            3 => v + ratio * (horizontal + vertical),
+            _ => v
+        }.clamp(0., 255.) as u8;
+        if self.regs[4] & 0x8 != 0 {
+            v = 255 - v;
+        }
+
+        let m = 6 + 3 * ((y as usize & 3) * 4 + (x as usize & 3));
+        match v {
+            v if v < self.regs[m] => 3,
+            v if v < self.regs[m + 1] => 2,
+            v if v < self.regs[m + 2] => 1,
+            _ => 0
+        }
+    }
+
+    // takes the picture and writes it to ram bank 0 as tiles, left to right then down
+    pub fn develop(&mut self, ram: &mut [u8]) {
+        self.source.capture(&mut self.image);
+        if ram.len() < IMAGE_START + CAMERA_WIDTH*CAMERA_HEIGHT/4 {
+            return
+        }
+
+        for y in 0 .. CAMERA_HEIGHT {
+            for tx in 0 .. CAMERA_WIDTH/8 {
+                let (mut lo, mut hi) = (0u8, 0u8);
+                for x in tx*8 .. tx*8 + 8 {
+                    let c = self.pixel(x as i32, y as i32);
+                    lo = lo << 1 | c & 1;
+                    hi = hi << 1 | c >> 1;
+                }
+                let tile = (y / 8) * (CAMERA_WIDTH/8) + tx;
+                let pos = IMAGE_START + tile*16 + (y % 8)*2;
+                ram[pos] = lo;
+                ram[pos + 1] = hi;
+            }
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.regs);
+        w.write_u32(self.busy);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        r.read_into(&mut self.regs)?;
+        self.regs[0] &= 0x7;
+        self.busy = r.read_u32()?;
+        Ok(())
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new()
+    }
+}
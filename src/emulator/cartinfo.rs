@@ -0,0 +1,183 @@
+use core::fmt;
+
+use alloc::string::String;
+
+use crate::emulator::mbc;
+use crate::emulator::compat::cart_type_name;
+
+// 0x14B old licensee codes, 0x33 means the new code at 0x144 is used instead
+const OLD_LICENSEES: [(u8, &str); 12] = [
+    (0x00, "none"), (0x01, "Nintendo"), (0x08, "Capcom"), (0x13, "Electronic Arts"),
+    (0x18, "Hudson Soft"), (0x31, "Nintendo"), (0x34, "Konami"), (0x41, "Ubisoft"),
+    (0x52, "Activision"), (0x69, "Electronic Arts"), (0xA4, "Konami"), (0xAF, "Namco")
+];
+
+// 0x144-0x145 new licensee codes, two ascii characters
+const NEW_LICENSEES: [(&str, &str); 12] = [
+    ("00", "none"), ("01", "Nintendo"), ("08", "Capcom"), ("13", "Electronic Arts"),
+    ("18", "Hudson Soft"), ("34", "Konami"), ("41", "Ubisoft"), ("52", "Activision"),
+    ("69", "Electronic Arts"), ("78", "THQ"), ("A4", "Konami"), ("AF", "Namco")
+];
+
+// 0x104-0x133, the bootrom locks up when it doesn't match
+const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    Japan,
+    Overseas
+}
+
+// Everything the header at 0x100-0x14F says about the game, read once when the rom is loaded
+#[derive(Clone, Debug)]
+pub struct CartridgeInfo {
+    pub title: String,
+    pub manufacturer: String,  // 0x13F-0x142 on newer carts, empty when those bytes are part of the title
+    pub licensee: String,  // hex old code, or the two character new one
+    pub publisher: Option<&'static str>,  // name for the licensee code, only the common ones are known
+    pub cgb_flag: u8,  // 0x143, 0x80 - runs on both models, 0xC0 - CGB only
+    pub sgb: bool,
+    pub cart_type: u8,  // 0x147
+    pub mapper: &'static str,
+    pub rom_size: Option<usize>,  // None for a size code that doesn't exist
+    pub ram_size: Option<usize>,
+    pub region: Region,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_ok: bool,
+    pub global_checksum: u16,  // the boot rom doesn't check this one, plenty of dumps get it wrong
+    pub global_checksum_ok: bool
+}
+
+impl CartridgeInfo {
+    // from the rom as loaded, before a lenient load patches anything
+    pub fn new(rom: &[u8]) -> CartridgeInfo {
+        let h = |addr: usize| rom.get(addr).copied().unwrap_or(0);
+
+        // old carts have 16 characters of title, CGB ones give the last to the flag and often 4 to the code
+        let cgb = h(0x143)&0x80 != 0;
+        let manufacturer: String = (0x13F ..= 0x142).map(|a| h(a) as char).collect();
+        let manufacturer = if cgb && manufacturer.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+            manufacturer
+        } else {
+            String::new()
+        };
+        let title_len = if !manufacturer.is_empty() { 0x0B } else if cgb { 0x0F } else { 0x10 };
+        let title = (0x134 .. 0x134 + title_len).map(h).take_while(|b| *b != 0)
+            .filter(|b| *b >= 0x20 && *b < 0x7F).map(|b| b as char).collect();
+
+        let (licensee, publisher) = if h(0x14B) == 0x33 {
+            let code: String = [h(0x144) as char, h(0x145) as char].iter().collect();
+            let publisher = NEW_LICENSEES.iter().find(|l| l.0 == code).map(|l| l.1);
+            (code, publisher)
+        } else {
+            (format!("{:02X}", h(0x14B)), OLD_LICENSEES.iter().find(|l| l.0 == h(0x14B)).map(|l| l.1))
+        };
+
+        let header_checksum = (0x134 ..= 0x14C).fold(0u8, |sum, a| sum.wrapping_sub(h(a)).wrapping_sub(1));
+        let global_checksum = (h(0x14E) as u16) << 8 | h(0x14F) as u16;
+        let sum = rom.iter().enumerate()
+            .filter(|(i, _)| *i != 0x14E && *i != 0x14F)
+            .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16));
+
+        CartridgeInfo {
+            title,
+            manufacturer,
+            licensee,
+            publisher,
+            cgb_flag: h(0x143),
+            sgb: h(0x146) == 0x03 && h(0x14B) == 0x33,  // the SGB ignores the flag on old licensee codes
+            cart_type: h(0x147),
+            mapper: cart_type_name(h(0x147)).unwrap_or("unknown"),
+            rom_size: mbc::rom_size(h(0x148)).ok(),
+            ram_size: mbc::ram_size(h(0x149)).ok(),
+            region: if h(0x14A) == 0 { Region::Japan } else { Region::Overseas },
+            version: h(0x14C),
+            header_checksum: h(0x14D),
+            header_checksum_ok: header_checksum == h(0x14D),
+            global_checksum,
+            global_checksum_ok: sum == global_checksum
+        }
+    }
+
+    pub fn cgb_only(&self) -> bool {
+        self.cgb_flag == 0xC0
+    }
+
+    // runs in color on a CGB, either only there or on both
+    pub fn cgb(&self) -> bool {
+        self.cgb_flag&0x80 != 0
+    }
+}
+
+// Which of the rom's own checks hold, from the rom as loaded. Only a bad header checksum stops a strict
+// load by default, the global one when Cartridge::verify_global_checksum is set
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RomIntegrity {
+    pub header_checksum: bool,
+    pub global_checksum: bool,
+    pub logo: bool,
+    pub size: bool  // the file is as big as 0x148 says
+}
+
+impl RomIntegrity {
+    pub fn new(rom: &[u8]) -> RomIntegrity {
+        let info = CartridgeInfo::new(rom);
+        RomIntegrity {
+            header_checksum: info.header_checksum_ok,
+            global_checksum: info.global_checksum_ok,
+            logo: rom.get(0x104 ..= 0x133).is_some_and(|l| l == NINTENDO_LOGO),
+            size: info.rom_size == Some(rom.len())
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.header_checksum && self.global_checksum && self.logo && self.size
+    }
+}
+
+impl Default for RomIntegrity {
+    fn default() -> RomIntegrity {
+        RomIntegrity::new(&[])
+    }
+}
+
+impl fmt::Display for RomIntegrity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ok = |b: bool| if b { "ok" } else { "bad" };
+        write!(f, "header checksum {}, global checksum {}, logo {}, size {}",
+            ok(self.header_checksum), ok(self.global_checksum), ok(self.logo), ok(self.size))
+    }
+}
+
+impl Default for CartridgeInfo {
+    fn default() -> CartridgeInfo {
+        CartridgeInfo::new(&[])
+    }
+}
+
+impl fmt::Display for CartridgeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = |s: Option<usize>| s.map_or(String::from("invalid"), |s| format!("{} KB", s / 1024));
+        let ok = |b: bool| if b { "ok" } else { "bad" };
+        let model = if self.cgb_only() { "CGB only" } else if self.cgb() { "DMG and CGB" } else { "DMG" };
+
+        writeln!(f, "{}", self.title)?;
+        if !self.manufacturer.is_empty() {
+            writeln!(f, "  code      {}", self.manufacturer)?;
+        }
+        writeln!(f, "  licensee  {} ({})", self.licensee, self.publisher.unwrap_or("unknown"))?;
+        writeln!(f, "  model     {}{}", model, if self.sgb { ", SGB" } else { "" })?;
+        writeln!(f, "  mapper    {} ({:02X})", self.mapper, self.cart_type)?;
+        writeln!(f, "  rom       {}", size(self.rom_size))?;
+        writeln!(f, "  ram       {}", size(self.ram_size))?;
+        writeln!(f, "  region    {:?}", self.region)?;
+        writeln!(f, "  version   {}", self.version)?;
+        writeln!(f, "  header    {:02X} {}", self.header_checksum, ok(self.header_checksum_ok))?;
+        write!(f, "  global    {:04X} {}", self.global_checksum, ok(self.global_checksum_ok))
+    }
+}
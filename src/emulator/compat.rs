@@ -0,0 +1,122 @@
+use core::fmt;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::emulator::Cartridge;
+
+// 0x147 cartridge type -> name, emulated, rtc, battery, rumble
+const CART_TYPES: [(u8, &str, bool, bool, bool, bool); 28] = [
+    (0x00, "ROM ONLY", true, false, false, false),
+    (0x01, "MBC1", true, false, false, false),
+    (0x02, "MBC1+RAM", true, false, false, false),
+    (0x03, "MBC1+RAM+BATTERY", true, false, true, false),
+    (0x05, "MBC2", true, false, false, false),
+    (0x06, "MBC2+BATTERY", true, false, true, false),
+    (0x08, "ROM+RAM", false, false, false, false),
+    (0x09, "ROM+RAM+BATTERY", false, false, true, false),
+    (0x0B, "MMM01", false, false, false, false),
+    (0x0C, "MMM01+RAM", false, false, false, false),
+    (0x0D, "MMM01+RAM+BATTERY", false, false, true, false),
+    (0x0F, "MBC3+TIMER+BATTERY", true, true, true, false),
+    (0x10, "MBC3+TIMER+RAM+BATTERY", true, true, true, false),
+    (0x11, "MBC3", true, false, false, false),
+    (0x12, "MBC3+RAM", true, false, false, false),
+    (0x13, "MBC3+RAM+BATTERY", true, false, true, false),
+    (0x19, "MBC5", true, false, false, false),
+    (0x1A, "MBC5+RAM", true, false, false, false),
+    (0x1B, "MBC5+RAM+BATTERY", true, false, true, false),
+    (0x1C, "MBC5+RUMBLE", true, false, false, true),
+    (0x1D, "MBC5+RUMBLE+RAM", true, false, false, true),
+    (0x1E, "MBC5+RUMBLE+RAM+BATTERY", true, false, true, true),
+    (0x20, "MBC6", true, false, true, false),
+    (0x22, "MBC7+SENSOR+RUMBLE+RAM+BATTERY", true, false, true, true),
+    (0xFC, "POCKET CAMERA", true, false, true, false),
+    (0xFD, "BANDAI TAMA5", false, false, true, false),
+    (0xFE, "HuC3", true, true, true, false),
+    (0xFF, "HuC1+RAM+BATTERY", true, false, true, false),
+];
+
+pub(crate) fn cart_type_name(kind: u8) -> Option<&'static str> {
+    CART_TYPES.iter().find(|t| t.0 == kind).map(|t| t.1)
+}
+
+// What the header says the cartridge needs and how much of it is emulated
+pub struct CompatReport {
+    pub title: String,
+    pub header_hash: u32,
+    pub mapper: &'static str,
+    pub mapper_supported: bool,
+    pub rtc: bool,
+    pub battery: bool,
+    pub rumble: bool,  // shown as screen shake, games run fine without it
+    pub sgb: bool,  // SGB borders and palettes are not emulated
+    pub cgb_flag: u8,
+    pub header_warnings: Vec<&'static str>,
+    pub known_issue: Option<String>  // from the frontend's database, keyed by header hash
+}
+
+impl CompatReport {
+    pub fn new(cart: &Cartridge) -> CompatReport {
+        let kind = cart.header[0x47];
+        let (mapper, supported, rtc, battery, rumble) = match CART_TYPES.iter().find(|t| t.0 == kind) {
+            Some(t) => (t.1, t.2, t.3, t.4, t.5),
+            None => ("unknown", false, false, false, false)
+        };
+
+        CompatReport {
+            title: cart.title.clone(),
+            header_hash: cart.header_hash,
+            mapper,
+            mapper_supported: supported,
+            rtc,
+            battery,
+            rumble,
+            sgb: cart.header[0x46] == 0x03,
+            cgb_flag: cart.cgb_flag,
+            header_warnings: cart.warnings.clone(),
+            known_issue: None
+        }
+    }
+
+    // reasons the game may misbehave, short enough for the OSD
+    pub fn problems(&self) -> Vec<String> {
+        let mut p = Vec::new();
+        if !self.mapper_supported {
+            p.push(format!("{} mapper is not supported", self.mapper));
+        }
+        if self.sgb && self.cgb_flag&0x80 == 0 {
+            p.push(String::from("SGB features are not emulated"));
+        }
+        for w in self.header_warnings.iter() {
+            p.push(format!("header: {}", w));
+        }
+        if let Some(issue) = &self.known_issue {
+            p.push(format!("known issue: {}", issue));
+        }
+        p
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let cgb = match self.cgb_flag {
+            0xC0 => "CGB only",
+            0x80 => "DMG and CGB",
+            _ => "DMG"
+        };
+
+        writeln!(f, "Compatibility report for {} ({:08x})", self.title, self.header_hash)?;
+        writeln!(f, "  mapper   {}, {}", self.mapper, if self.mapper_supported { "supported" } else { "not supported" })?;
+        writeln!(f, "  rtc      {}", yes_no(self.rtc))?;
+        writeln!(f, "  battery  {}", yes_no(self.battery))?;
+        writeln!(f, "  rumble   {}", yes_no(self.rumble))?;
+        writeln!(f, "  sgb      {}", yes_no(self.sgb))?;
+        writeln!(f, "  model    {}", cgb)?;
+        for w in self.header_warnings.iter() {
+            writeln!(f, "  header   {}", w)?;
+        }
+        write!(f, "  known    {}", self.known_issue.as_deref().unwrap_or("no issues"))
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::vec::Vec;
+
+// LZ4 block format (https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md) with a greedy
+// single probe matcher. Save states are mostly zeroed or repeated memory, that's plenty for them
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 12;
+const MAX_OFFSET: usize = 0xFFFF;
+const END_LITERALS: usize = 5;  // the block has to end in literals
+const MATCH_LIMIT: usize = 12;  // and no match can start closer than this to the end
+const CORRUPT: &str = "compressed data is corrupt";
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]])
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_length(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 255 {
+        out.push(255);
+        n -= 255;
+    }
+    out.push(n as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], m: Option<(usize, usize)>) {
+    let match_len = m.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, _)) = m {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4);
+    let mut table = vec![0usize; 1 << HASH_BITS];  // position + 1 of the last sequence with that hash
+    let limit = data.len().saturating_sub(MATCH_LIMIT);
+    let match_end = data.len().saturating_sub(END_LITERALS);
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    while pos < limit {
+        let seq = u32_at(data, pos);
+        let h = hash(seq);
+        let candidate = table[h];
+        table[h] = pos + 1;
+        if candidate == 0 || pos - (candidate - 1) > MAX_OFFSET || u32_at(data, candidate - 1) != seq {
+            pos += 1;
+            continue;
+        }
+
+        let from = candidate - 1;
+        let mut len = MIN_MATCH;
+        while pos + len < match_end && data[from + len] == data[pos + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &data[anchor .. pos], Some((pos - from, len)));
+        pos += len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &data[anchor ..], None);
+    out
+}
+
+fn read_length(data: &[u8], pos: &mut usize) -> Result<usize, &'static str> {
+    let mut n = 0;
+    loop {
+        let b = *data.get(*pos).ok_or(CORRUPT)?;
+        *pos += 1;
+        n += b as usize;
+        if b != 255 {
+            return Ok(n)
+        }
+    }
+}
+
+// len is the size compress was given, anything that doesn't decode to exactly that is an error
+pub fn decompress(data: &[u8], len: usize) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::with_capacity(len.min(data.len().saturating_mul(255)));
+    let mut pos = 0;
+
+    loop {
+        let token = *data.get(pos).ok_or(CORRUPT)?;
+        pos += 1;
+
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += read_length(data, &mut pos)?;
+        }
+        let end = pos.checked_add(literals).ok_or(CORRUPT)?;
+        if out.len() + literals > len {
+            return Err(CORRUPT)
+        }
+        out.extend_from_slice(data.get(pos .. end).ok_or(CORRUPT)?);
+        pos = end;
+        if pos == data.len() {
+            break;
+        }
+
+        let offset = match data.get(pos .. pos + 2) {
+            Some(b) => u16::from_le_bytes([b[0], b[1]]) as usize,
+            None => return Err(CORRUPT)
+        };
+        pos += 2;
+        let mut match_len = (token & 0xF) as usize + MIN_MATCH;
+        if token & 0xF == 15 {
+            match_len += read_length(data, &mut pos)?;
+        }
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return Err(CORRUPT)
+        }
+        // byte by byte, matches can overlap what they're copying
+        let start = out.len() - offset;
+        for i in 0 .. match_len {
+            out.push(out[start + i]);
+        }
+    }
+
+    if out.len() != len {
+        return Err(CORRUPT)
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, the same bytes every run
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 0x2545F491u32;
+        (0 .. len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        }).collect()
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut mixed = vec![0; 0x4000];
+        mixed.extend(noise(300));
+        mixed.extend((0 .. 0x3000).map(|i| (i / 7) as u8));
+        mixed.extend(noise(20));
+        vec![vec![], vec![7], noise(12), noise(13), vec![0; 0x10000], noise(0x1000), b"abcabcabcabcabcabcabcabc".to_vec(), mixed]
+    }
+
+    #[test]
+    fn round_trip() {
+        for data in samples() {
+            let packed = compress(&data);
+            assert_eq!(decompress(&packed, data.len()).unwrap(), data, "{} bytes", data.len());
+        }
+        assert!(compress(&[0; 0x10000]).len() < 0x200);
+    }
+
+    #[test]
+    fn rejects_the_wrong_length() {
+        let data = noise(100);
+        let packed = compress(&data);
+        assert_eq!(decompress(&packed, 99), Err(CORRUPT));
+        assert_eq!(decompress(&packed, 101), Err(CORRUPT));
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        assert_eq!(decompress(&[], 0), Err(CORRUPT));
+        assert_eq!(decompress(&[0x40, 1, 2], 4), Err(CORRUPT));  // literals past the end
+        assert_eq!(decompress(&[0x10, 1, 0, 0], 5), Err(CORRUPT));  // offset 0
+        assert_eq!(decompress(&[0x10, 1, 2, 0], 5), Err(CORRUPT));  // offset before the start
+        assert_eq!(decompress(&[0xF0, 255, 255], 1000), Err(CORRUPT));  // length runs off the end
+
+        // anything else either fails or comes out the right size, it never panics
+        let mut small = vec![0; 600];
+        small.extend(noise(100));
+        small.extend(b"abcabcabcabcabcabcabcabc");
+        for data in [small, noise(13), vec![]] {
+            let packed = compress(&data);
+            for i in 0 .. packed.len() {
+                for bit in 0 .. 8 {
+                    let mut bad = packed.clone();
+                    bad[i] ^= 1 << bit;
+                    if let Ok(out) = decompress(&bad, data.len()) {
+                        assert_eq!(out.len(), data.len());
+                    }
+                }
+                assert!(decompress(&packed[.. i], data.len()).is_err() || data.is_empty());
+            }
+        }
+    }
+}
@@ -1,6 +1,19 @@
 #![allow(non_snake_case)]
 
-use crate::emulator::{Memory, execute, PUSH};
+#[cfg(feature = "rcheevos")]
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[cfg(feature = "std")]
+use crate::emulator::{png, WavWriter};
+use crate::emulator::{bess, Memory, Event, Accuracy, AccuracyPreset, PPU_MODE, MODE, StateHasher, StateWriter, StateReader, STATE_MAGIC, STATE_VERSION, COMPRESSED_MAGIC, compress_state, decompress_state, execute};
+
+const FRAME_CYCLES: f32 = 70224.;
+const BOOT_STEPS: u32 = 10_000_000;  // well over the 2-3 seconds a bootrom takes
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -22,6 +35,13 @@ pub enum Flag {
     C = 16   // carry flag
 }
 
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RunState {
+    FrameDone,
+    Breakpoint,
+    Watchpoint(u16)  // address written by the last instruction
+}
+
 pub struct CPU {
     // registers
     reg_af: Register,
@@ -36,6 +56,20 @@ pub struct CPU {
 
     pub memory: Memory,
     pub halt: bool,
+    pub(crate) halt_bug: bool,  // HALT with IME off and an interrupt pending, the next opcode byte is read twice
+    accuracy: Accuracy,
+    overclock_cycles: u32,  // extra cycles given to the cpu at the start of every vblank
+    frozen: bool,  // rest of the hardware isn't ticked while overclocked
+
+    #[cfg(feature = "debugger")]
+    pub breakpoints: Vec<u16>,
+    #[cfg(feature = "debugger")]
+    pub resuming: bool,  // don't stop on the breakpoint at current PC
+
+    #[cfg(feature = "rcheevos")]
+    pub frame_callback: Option<Box<dyn FnMut(&mut Memory)>>,  // called once per emulated frame, for rc_runtime_do_frame
+    #[cfg(feature = "std")]
+    audio_recording: Option<WavWriter>,
 
     subins: u8  // subinstruction memory access counter
 }
@@ -55,6 +89,20 @@ impl CPU {
 
             memory: Memory::new(),
             halt: false,
+            halt_bug: false,
+            accuracy: Accuracy::new(AccuracyPreset::Balanced),
+            overclock_cycles: 0,
+            frozen: false,
+
+            #[cfg(feature = "debugger")]
+            breakpoints: vec![],
+            #[cfg(feature = "debugger")]
+            resuming: false,
+
+            #[cfg(feature = "rcheevos")]
+            frame_callback: None,
+            #[cfg(feature = "std")]
+            audio_recording: None,
 
             subins: 0
         }
@@ -125,22 +173,245 @@ impl CPU {
         }
     }
 
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+        self.memory.ppu.scanline_renderer = !accuracy.fifo_renderer;
+        self.memory.bus = accuracy.bus;
+    }
+
+    // starts the bootrom if one is loaded, otherwise sets up the registers the bootrom would leave behind
+    pub fn boot(&mut self) {
+        if self.memory.cart.bootrom_enable {
+            self.PC = 0;
+            return;
+        }
+
+        self.PC = 0x100;
+        self.SP = 0xFFFE;
+        match self.memory.mode {
+            MODE::DMG0 => {
+                *self.AF() = 0x0100;
+                *self.BC() = 0xFF13;
+                *self.DE() = 0x00C1;
+                *self.HL() = 0x8403;
+            },
+            MODE::DMG => {
+                *self.AF() = 0x01B0;
+                *self.BC() = 0x0013;
+                *self.DE() = 0x00D8;
+                *self.HL() = 0x014D;
+            },
+            MODE::CGB => {  // A = 0x11 tells games they run on a CGB
+                *self.AF() = 0x1180;
+                *self.BC() = 0x0000;
+                *self.DE() = 0xFF56;
+                *self.HL() = 0x000D;
+                if self.memory.cart.gb_cart_type != MODE::CGB {
+                    *self.DE() = 0x0008;
+                    *self.HL() = 0x007C;
+                    self.memory.enter_compat_mode();
+                }
+            }
+        }
+    }
+
+    // Runs the bootrom at full speed until it unmaps itself with a write to FF50, its frames and sound
+    // are thrown away. False if it didn't get there, a bad logo stops the bootrom for good
+    pub fn run_bootrom(&mut self) -> bool {
+        for _ in 0 .. BOOT_STEPS {
+            if !self.memory.cart.bootrom_enable {
+                break;
+            }
+            self.step();
+        }
+        self.memory.ppu.frame_ready = false;
+        self.memory.apu.samples.clear();
+
+        !self.memory.cart.bootrom_enable
+    }
+
+    // see statehash::SUBSYSTEMS for the order
+    pub fn state_hashes(&mut self) -> [u64; 8] {
+        let mut h = StateHasher::new();
+        h.write_u16(*self.AF());
+        h.write_u16(*self.BC());
+        h.write_u16(*self.DE());
+        h.write_u16(*self.HL());
+        h.write_u16(self.SP);
+        h.write_u16(self.PC);
+        h.write(&[self.IME as u8, self.EI as u8, self.halt as u8, self.halt_bug as u8]);
+
+        let m = self.memory.state_hashes();
+        [h.finish(), m[0], m[1], m[2], m[3], m[4], m[5], m[6]]
+    }
+
+    // Everything needed to pick the game up again later, see savestate.rs for the layout.
+    // Only loads into the same cartridge running as the same model
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write(&STATE_MAGIC);
+        w.write_u16(STATE_VERSION);
+        w.write_u32(self.memory.cart.header_hash);
+        w.write_u8(self.memory.mode as u8);
+
+        for r in [*self.AF(), *self.BC(), *self.DE(), *self.HL(), self.SP, self.PC].iter() {
+            w.write_u16(*r);
+        }
+        w.write(&[self.IME as u8, self.EI as u8, self.halt as u8, self.halt_bug as u8]);
+        self.memory.save_state(&mut w);
+        w.finish()
+    }
+
+    // save_state with BESS blocks after it, other emulators can load the result and we load
+    // theirs through load_state
+    pub fn save_state_bess(&mut self) -> Vec<u8> {
+        let state = self.save_state();
+        bess::append(self, state)
+    }
+
+    // save_state packed down to a few kB, for files that only we read
+    pub fn save_state_compressed(&mut self) -> Vec<u8> {
+        compress_state(&self.save_state())
+    }
+
+    // a state that fails to load halfway leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let backup = self.save_state();
+        let result = self.load_state_unchecked(data);
+        if result.is_err() {
+            self.load_state_unchecked(&backup).expect("restoring the state from before a failed load");
+        }
+        self.memory.cart.settle_ram();
+        result
+    }
+
+    fn load_state_unchecked(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        if data.starts_with(&COMPRESSED_MAGIC) {
+            return self.load_state_unchecked(&decompress_state(data)?)
+        }
+        if !data.starts_with(&STATE_MAGIC) {
+            return if bess::is_bess(data) { bess::load(self, data) } else { Err("not a save state") }
+        }
+
+        let mut r = StateReader::new(bess::native_part(data));
+        r.read(4)?;
+        if r.read_u16()? != STATE_VERSION {
+            return Err("save state is from an unsupported version")
+        }
+        if r.read_u32()? != self.memory.cart.header_hash {
+            return Err("save state is for a different game")
+        }
+        if r.read_u8()? != self.memory.mode as u8 {
+            return Err("save state is for the other model")
+        }
+
+        *self.AF() = r.read_u16()? & 0xFFF0;
+        *self.BC() = r.read_u16()?;
+        *self.DE() = r.read_u16()?;
+        *self.HL() = r.read_u16()?;
+        self.SP = r.read_u16()?;
+        self.PC = r.read_u16()?;
+        self.IME = r.read_bool()?;
+        self.EI = r.read_bool()?;
+        self.halt = r.read_bool()?;
+        self.halt_bug = r.read_bool()?;
+        self.memory.load_state(&mut r)?;
+        r.finish()?;
+
+        self.subins = 0;
+        #[cfg(feature = "debugger")]
+        { self.resuming = true; }
+        Ok(())
+    }
+
+    // Writes the APU's mix to a WAV file at the audio config's rate until stop_audio_recording,
+    // a frame at a time. A recording already running gets what's pending and is finished by its drop
+    #[cfg(feature = "std")]
+    pub fn start_audio_recording(&mut self, p: &Path) -> io::Result<()> {
+        self.write_audio_recording();
+        let rate = self.memory.apu.audio_config().sample_rate;
+        self.audio_recording = Some(WavWriter::create(p, rate)?);
+        self.memory.apu.set_recording(true);
+        Ok(())
+    }
+
+    // the length of what was recorded in stereo frames, None if nothing was
+    #[cfg(feature = "std")]
+    pub fn stop_audio_recording(&mut self) -> io::Result<Option<u32>> {
+        self.write_audio_recording();
+        self.memory.apu.set_recording(false);
+        match self.audio_recording.take() {
+            Some(wav) => wav.finish().map(Some),
+            None => Ok(None)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn recording_audio(&self) -> bool {
+        self.audio_recording.is_some()
+    }
+
+    #[cfg(feature = "std")]
+    fn write_audio_recording(&mut self) {
+        if let (Some(wav), Some(samples)) = (&mut self.audio_recording, self.memory.apu.recording()) {
+            wav.write(samples);
+            samples.clear();
+        }
+    }
+
+    // The frame buffer as a PNG, scaled to width x height without smoothing, 160x144 leaves it as is.
+    // Between frames that's the last one finished, mid frame the lines above LY are the new one's
+    #[cfg(feature = "std")]
+    pub fn screenshot(&self, p: &Path, width: u32, height: u32) -> io::Result<()> {
+        let frame = &self.memory.ppu.frame;
+        if (width, height) == (160, 144) {
+            return png::write_rgb(p, 160, 144, frame)
+        }
+
+        let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0 .. height {
+            let row = (y * 144 / height) as usize * 160;
+            for x in 0 .. width {
+                let i = (row + (x * 160 / width) as usize) * 3;
+                rgb.extend_from_slice(&frame[i .. i+3]);
+            }
+        }
+        png::write_rgb(p, width, height, &rgb)
+    }
+
+    // factor 1.0 - normal speed, 2.0 - the cpu gets twice the cycles of a frame
+    pub fn set_overclock(&mut self, factor: f32) {
+        self.overclock_cycles = ((factor - 1.).max(0.) * FRAME_CYCLES) as u32;
+    }
+
     pub fn read(&mut self, addr: u16) -> u8 {
+        #[cfg(feature = "debugger")]
+        if addr >= 0xFF00 {
+            self.memory.io_touched[addr as usize&0xFF] = true;
+        }
         let a = self.memory.read(addr);
 
-        self.subins += 1;
-        for _ in 0..4 {
-            self.memory.tick();
+        if self.accuracy.per_cycle_memory && !self.frozen {
+            self.subins += 1;
+            for _ in 0..4 {
+                self.memory.tick();
+            }
         }
         a
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
+        #[cfg(feature = "debugger")]
+        if addr >= 0xFF00 {
+            self.memory.io_touched[addr as usize&0xFF] = true;
+        }
         self.memory.write(addr, val);
 
-        self.subins += 1;
-        for _ in 0..4 {
-            self.memory.tick();
+        if self.accuracy.per_cycle_memory && !self.frozen {
+            self.subins += 1;
+            for _ in 0..4 {
+                self.memory.tick();
+            }
         }
     }
 
@@ -204,42 +475,55 @@ impl CPU {
         };
     }
 
-    fn handle_interrupts(&mut self) -> bool {
-        let interrupts = self.memory.IF & self.memory.IER;
-        if interrupts & 0b00011111 != 0 {
-            if self.halt && !self.IME { self.halt = false; return false; }
-            PUSH(self, self.PC);
-            if interrupts & 0b00000001 != 0 {  // V-Blank
-                self.memory.IF &= 0b11111110;
-                self.PC = 0x0040;
-                return true;
-            } else if interrupts & 0b00000010 != 0 {  // LCD STAT
-                self.memory.IF &= 0b11111101;
-                self.PC = 0x0048;
-                return true;
-            } else if interrupts & 0b00000100 != 0 {  // Timer
-                self.memory.IF &= 0b11111011;
-                self.PC = 0x0050;
-                return true;
-            } else if interrupts & 0b00001000 != 0 {  // Serial
-                self.memory.IF &= 0b11110111;
-                self.PC = 0x0058;
-                return true;
-            } else if interrupts & 0b00010000 != 0 {  // Joypad
-                self.memory.IF &= 0b11101111;
-                self.PC = 0x0060;
-                return true;
+    // a machine cycle without a memory access, ticked in place like read and write do
+    fn idle(&mut self) {
+        if self.accuracy.per_cycle_memory && !self.frozen {
+            self.subins += 1;
+            for _ in 0..4 {
+                self.memory.tick();
             }
         }
-        false
+    }
+
+    // Dispatch takes 5 machine cycles, one more when it wakes the cpu from halt: 2 idle ones, PC
+    // pushed high byte first, then the jump. The interrupt is picked after the high byte, so a push
+    // onto IE (SP at 0000) can cancel it and the cpu jumps to 0000 instead. Returns 0 for no dispatch
+    fn handle_interrupts(&mut self) -> u8 {
+        if self.memory.IF & self.memory.IER & 0b00011111 == 0 {
+            return 0
+        }
+        if self.halt && !self.IME { self.halt = false; return 0; }
+
+        let wake = self.halt as u8;
+        for _ in 0 .. 2 + wake {
+            self.idle();
+        }
+        let sp = self.SP.wrapping_sub(1);
+        self.write(sp, (self.PC >> 8) as u8);
+        let interrupts = self.memory.IF & self.memory.IER & 0b00011111;
+        self.write(sp.wrapping_sub(1), self.PC as u8);
+        self.SP = sp.wrapping_sub(1);
+
+        if interrupts == 0 {
+            self.PC = 0x0000;
+        } else {
+            // V-Blank, LCD STAT, Timer, Serial, Joypad, the lowest bit goes first
+            let irq = interrupts.trailing_zeros() as u16;
+            #[cfg(feature = "debugger")]
+            self.memory.irq_latency.serviced(irq as usize);
+            self.memory.IF &= !(1 << irq);
+            self.PC = 0x0040 + irq * 8;
+        }
+        5 + wake
     }
 
     pub fn tick(&mut self) -> u8 {
         if self.IME || self.halt {
-            if self.handle_interrupts() {
+            let cycles = self.handle_interrupts();
+            if cycles != 0 {
                 self.IME = false;
                 self.halt = false;
-                return 5;
+                return cycles;
             }
         }
 
@@ -249,33 +533,180 @@ impl CPU {
         }
 
         if !self.halt {
+            #[cfg(feature = "debugger")]
+            if self.memory.io_log.enabled {
+                self.memory.pc = self.PC;
+            }
             let inst = self.load_u8();
+            if self.halt_bug {  // PC didn't move past the byte
+                self.halt_bug = false;
+                self.PC = self.PC.wrapping_sub(1);
+            }
             execute(self, inst)
         } else { 1 }
     }
 
-    pub fn run(&mut self) {
-        let mut cycles_left = 0;
+    // STOP as Pan Docs tabulates it. With a button held it halts or does nothing, otherwise DIV is
+    // reset and the clock stops, after a speed switch only for the switch's pause. The byte after
+    // it is skipped unless an interrupt is pending
+    pub(crate) fn stop(&mut self) -> u8 {
+        let pending = self.memory.IF & self.memory.IER & 0x1F != 0;
+        if !pending {
+            self.PC = self.PC.wrapping_add(1);
+        }
+        if self.memory.joypad_held() {
+            self.halt = !pending;
+            return 1
+        }
+        let switched = self.memory.switch_speed();
+        self.memory.stop(switched);
+        1
+    }
 
-        if self.memory.cart.bootrom_enable {
-            self.PC = 0;
+    // executes one instruction (or one halted cycle) and ticks the rest of the hardware along
+    pub fn step(&mut self) {
+        if self.memory.dma_stall() || self.memory.stopped() {
+            for _ in 0 .. 4 {
+                self.memory.tick();
+            }
+            return;
+        }
+        if self.halt && !self.EI && self.memory.IER&0x1F == 0x01 && self.memory.IF&0x01 == 0 {
+            self.skip_halt();
+            return;
+        }
+
+        let cycles = (self.tick() - self.subins) as u16 * 4;
+        self.subins = 0;
+
+        for _ in 0 .. cycles {
+            self.memory.tick();
+        }
+    }
+
+    // Halted with only vblank enabled, nothing but the ppu can wake the cpu. Hblank and most of
+    // every vblank line go by in one Memory::tick_idle, the rest machine cycle by machine cycle
+    // like a halted step, so the state comes out the same. Stops where run_frame has to look
+    // (frame end, vblank entry for the overclock, watchpoints), at most a frame
+    fn skip_halt(&mut self) {
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES as u32 {
+            if self.memory.IF & self.memory.IER & 0x1F != 0 {
+                break;
+            }
+
+            // nothing to look at until the ppu's mode or line changes
+            let idle = self.memory.tick_idle(FRAME_CYCLES as u32 - cycles);
+            if idle > 0 {
+                cycles += idle;
+                continue;
+            }
+
+            let mode = self.memory.ppu.mode;
+            for _ in 0 .. 4 {
+                self.memory.tick();
+            }
+            cycles += 4;
+
+            if self.memory.ppu.frame_ready || (mode != PPU_MODE::VBLANK && self.memory.ppu.mode == PPU_MODE::VBLANK) {
+                break;
+            }
+            #[cfg(feature = "debugger")]
+            if self.memory.watch_hit.is_some() {
+                break;
+            }
+        }
+    }
+
+    // timers, ppu and apu are stopped, so the game sees a longer vblank. Stops early when the cpu halts
+    fn run_overclocked(&mut self) {
+        self.frozen = true;
+
+        let mut cycles = 0;
+        while cycles < self.overclock_cycles && !self.halt && !self.memory.stopped() {
+            cycles += self.tick() as u32 * 4;
+            self.subins = 0;
+        }
+
+        self.frozen = false;
+    }
+
+    pub fn run_frame(&mut self) -> RunState {
+        #[cfg(feature = "debugger")]
+        {
+            self.memory.watch_hit = None;
         }
 
         loop {
-            if cycles_left > 0 {
-                cycles_left -= 1;
-            } else {
-                cycles_left = (self.tick() - self.subins)*4;
-                self.subins = 0;
-                
-                if cycles_left > 0 {
-                    cycles_left -= 1;
-                }
-                if cycles_left == 0 {
-                    continue
-                }
+            if let Some(state) = self.run_step(true) {
+                return state;
             }
-            self.memory.tick();
         }
     }
+
+    // Runs a frame with another instance on the end of the link cable (see LinkCable), keeping it
+    // within an instruction of this one so transfers clocked by either side arrive in time.
+    // The other instance's breakpoints and watchpoints are ignored, true when it finished a frame too
+    pub fn run_frame_linked(&mut self, other: &mut CPU) -> (RunState, bool) {
+        #[cfg(feature = "debugger")]
+        {
+            self.memory.watch_hit = None;
+        }
+
+        let start = (self.memory.clock, other.memory.clock);
+        let mut other_frame = false;
+        loop {
+            while other.memory.clock - start.1 < self.memory.clock - start.0 {
+                other_frame |= other.run_step(false) == Some(RunState::FrameDone);
+            }
+            if let Some(state) = self.run_step(true) {
+                return (state, other_frame);
+            }
+        }
+    }
+
+    // an instruction of run_frame, Some when it has to stop
+    #[cfg_attr(not(feature = "debugger"), allow(unused_variables))]
+    fn run_step(&mut self, breakpoints: bool) -> Option<RunState> {
+        #[cfg(feature = "debugger")]
+        {
+            if breakpoints && !self.resuming && !self.breakpoints.is_empty() && !self.halt && self.breakpoints.contains(&self.PC) {
+                self.memory.events.push(Event::Breakpoint(self.PC));
+                return Some(RunState::Breakpoint);
+            }
+            self.resuming = false;
+        }
+
+        let mode = self.memory.ppu.mode;
+        self.step();
+        if self.overclock_cycles > 0 && mode != PPU_MODE::VBLANK && self.memory.ppu.mode == PPU_MODE::VBLANK {
+            self.run_overclocked();
+        }
+
+        #[cfg(feature = "debugger")]
+        if let Some(addr) = self.memory.watch_hit.take() {
+            if breakpoints {
+                return Some(RunState::Watchpoint(addr));
+            }
+        }
+        if self.memory.ppu.frame_ready {
+            self.memory.ppu.frame_ready = false;
+            self.memory.cart.power_loss_frame();
+            self.memory.events.push(Event::FrameComplete);
+            #[cfg(feature = "std")]
+            self.write_audio_recording();
+            #[cfg(feature = "rcheevos")]
+            if let Some(callback) = &mut self.frame_callback {
+                callback(&mut self.memory);
+            }
+            return Some(RunState::FrameDone);
+        }
+        None
+    }
 }
+
+impl Default for CPU {
+    fn default() -> CPU {
+        CPU::new()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,194 @@
+use crate::emulator::{StateWriter, StateReader};
+
+pub const EEPROM_SIZE: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,       // waiting for a start bit
+    Command,    // shifting in opcode and address
+    Read,       // shifting a word out, then the next one
+    Write(u8),  // shifting in a word for this address
+    WriteAll
+}
+
+// 93LC56 serial EEPROM of MBC7 carts, 128 16-bit words driven bit by bit through A080:
+// bit 7 chip select, bit 6 clock, bit 1 data in, bit 0 data out. A command is a start bit,
+// 2 opcode bits and 8 address bits (the top one unused) clocked in on rising edges.
+// Writes finish at once, data out reads 1 (ready) afterwards.
+// Words are kept little endian, the byte order other emulators save them in
+pub struct Eeprom {
+    pub data: [u8; EEPROM_SIZE],
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+    write_enabled: bool,
+    state: State,
+    shift: u16,
+    bits: u8,
+    addr: u8  // next word of a sequential read
+}
+
+impl Eeprom {
+    pub fn new() -> Eeprom {
+        Eeprom {
+            data: [0xFF; EEPROM_SIZE],
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            write_enabled: false,
+            state: State::Idle,
+            shift: 0,
+            bits: 0,
+            addr: 0
+        }
+    }
+
+    fn word(&self, addr: u8) -> u16 {
+        let i = (addr as usize & 0x7F) * 2;
+        u16::from_le_bytes([self.data[i], self.data[i+1]])
+    }
+
+    fn set_word(&mut self, addr: u8, val: u16) {
+        if self.write_enabled {
+            let i = (addr as usize & 0x7F) * 2;
+            self.data[i .. i+2].copy_from_slice(&val.to_le_bytes());
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.dout as u8
+    }
+
+    pub fn write(&mut self, val: u8) {
+        let cs = val&0x80 != 0;
+        let clk = val&0x40 != 0;
+        let di = val&0x02 != 0;
+        let rising = clk && !self.clk;
+        self.clk = clk;
+        self.di = di;
+
+        if !cs {
+            self.cs = false;
+            self.state = State::Idle;
+            return
+        }
+        if !self.cs {
+            self.cs = true;
+            self.state = State::Idle;
+        }
+        if rising {
+            self.clock(di);
+        }
+    }
+
+    fn clock(&mut self, di: bool) {
+        match self.state {
+            State::Idle => if di {
+                self.state = State::Command;
+                self.shift = 0;
+                self.bits = 0;
+            },
+            State::Command => {
+                self.shift = self.shift << 1 | di as u16;
+                self.bits += 1;
+                if self.bits >= 10 {
+                    self.command(self.shift);
+                }
+            },
+            State::Read => {
+                if self.bits == 0 {
+                    self.shift = self.word(self.addr);
+                    self.addr = self.addr.wrapping_add(1);
+                    self.bits = 16;
+                }
+                self.dout = self.shift&0x8000 != 0;
+                self.shift <<= 1;
+                self.bits -= 1;
+            },
+            State::Write(_) | State::WriteAll => {
+                self.shift = self.shift << 1 | di as u16;
+                self.bits += 1;
+                if self.bits >= 16 {
+                    match self.state {
+                        State::Write(addr) => self.set_word(addr, self.shift),
+                        _ => for addr in 0 .. 0x80 {
+                            self.set_word(addr, self.shift);
+                        }
+                    }
+                    self.state = State::Idle;
+                    self.dout = true;
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, cmd: u16) {
+        let addr = cmd as u8 & 0x7F;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = State::Idle;
+        match cmd >> 8 {
+            0b10 => {  // READ, a dummy 0 before the data
+                self.addr = addr;
+                self.dout = false;
+                self.state = State::Read;
+            },
+            0b01 => self.state = State::Write(addr),
+            0b11 => {  // ERASE
+                self.set_word(addr, 0xFFFF);
+                self.dout = true;
+            },
+            _ => match cmd >> 6 & 0x3 {
+                0b11 => self.write_enabled = true,  // EWEN
+                0b00 => self.write_enabled = false,  // EWDS
+                0b10 => for addr in 0 .. 0x80 {  // ERAL
+                    self.set_word(addr, 0xFFFF);
+                },
+                _ => self.state = State::WriteAll  // WRAL
+            }
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.cs as u8, self.clk as u8, self.di as u8, self.dout as u8, self.write_enabled as u8]);
+        let (state, addr) = match self.state {
+            State::Idle => (0, 0),
+            State::Command => (1, 0),
+            State::Read => (2, 0),
+            State::Write(a) => (3, a),
+            State::WriteAll => (4, 0)
+        };
+        w.write(&[state, addr]);
+        w.write_u16(self.shift);
+        w.write(&[self.bits, self.addr]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.cs = r.read_bool()?;
+        self.clk = r.read_bool()?;
+        self.di = r.read_bool()?;
+        self.dout = r.read_bool()?;
+        self.write_enabled = r.read_bool()?;
+        let (state, addr) = (r.read_u8()?, r.read_u8()?);
+        self.state = match state {
+            0 => State::Idle,
+            1 => State::Command,
+            2 => State::Read,
+            3 => State::Write(addr),
+            4 => State::WriteAll,
+            _ => return Err("save state has an invalid EEPROM state")
+        };
+        self.shift = r.read_u16()?;
+        self.bits = r.read_u8()?.min(16);
+        self.addr = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Default for Eeprom {
+    fn default() -> Eeprom {
+        Eeprom::new()
+    }
+}
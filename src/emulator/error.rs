@@ -0,0 +1,54 @@
+use core::fmt;
+
+use crate::emulator::MODE;
+
+// Why a rom or bootrom couldn't be loaded, for the frontend to show instead of aborting
+#[derive(Debug)]
+pub enum EmulatorError {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    RomTooSmall,
+    InvalidChecksum,
+    InvalidGlobalChecksum,
+    InvalidRomSize(u8),  // header byte 0x148
+    InvalidRamSize(u8),  // header byte 0x149
+    RomSizeMismatch { header: usize, actual: usize },
+    RomTooBig(&'static str),  // for the named mapper
+    RamTooBig(&'static str),
+    UnsupportedMapper(u8),  // header byte 0x147
+    InvalidBootrom(usize),  // its length
+    BootromMismatch(MODE),  // the bootrom's model, which can't start the cartridge as loaded
+    NoCgbSupport  // a CGB only rom or the CGB bootrom, built without the "cgb" feature
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            EmulatorError::Io(e) => write!(f, "{}", e),
+            EmulatorError::RomTooSmall => write!(f, "ROM too small"),
+            EmulatorError::InvalidChecksum => write!(f, "Invalid ROM header checksum"),
+            EmulatorError::InvalidGlobalChecksum => write!(f, "Invalid ROM global checksum"),
+            EmulatorError::InvalidRomSize(v) => write!(f, "Invalid ROM size {:02X} in the header", v),
+            EmulatorError::InvalidRamSize(v) => write!(f, "Invalid RAM size {:02X} in the header", v),
+            EmulatorError::RomSizeMismatch { header, actual } => write!(f, "header rom size {} != rom size {}", header, actual),
+            EmulatorError::RomTooBig(mbc) => write!(f, "Rom size too big for {}", mbc),
+            EmulatorError::RamTooBig(mbc) => write!(f, "header ram size too big for {}", mbc),
+            EmulatorError::UnsupportedMapper(v) => write!(f, "{:02X} - unsupported cartridge type", v),
+            EmulatorError::InvalidBootrom(len) => write!(f, "Invalid bootrom, {} bytes", len),
+            EmulatorError::BootromMismatch(MODE::CGB) => write!(f, "CGB bootrom, but the cartridge is set to run as a DMG"),
+            EmulatorError::BootromMismatch(_) => write!(f, "DMG bootrom, but the cartridge is CGB only"),
+            EmulatorError::NoCgbSupport => write!(f, "CGB only, but built without cgb support")
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EmulatorError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for EmulatorError {
+    fn from(e: std::io::Error) -> EmulatorError {
+        EmulatorError::Io(e)
+    }
+}
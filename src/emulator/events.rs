@@ -0,0 +1,105 @@
+use alloc::vec::{Vec, Drain};
+
+const MAX_QUEUED: usize = 4096;  // events past this are dropped until the queue is drained
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    FrameComplete,
+    VBlank,  // the ppu entered mode 1, before the interrupt is serviced
+    SerialByte { sent: u8, received: u8 },
+    Breakpoint(u16),
+    CartRamDirty,  // the game wrote to cart ram, once until the queue is drained
+    Rumble(bool),  // the cartridge's motor turned on or off
+    PrintJob,  // the serial device finished a print, see SerialDevice::take_print_job
+    JoypadRead(u8)  // the game read FF00, with what it saw. Can be many a frame
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EventKind {
+    FrameComplete,
+    VBlank,
+    SerialByte,
+    Breakpoint,
+    CartRamDirty,
+    Rumble,
+    PrintJob,
+    JoypadRead
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::FrameComplete => EventKind::FrameComplete,
+            Event::VBlank => EventKind::VBlank,
+            Event::SerialByte { .. } => EventKind::SerialByte,
+            Event::Breakpoint(_) => EventKind::Breakpoint,
+            Event::CartRamDirty => EventKind::CartRamDirty,
+            Event::Rumble(_) => EventKind::Rumble,
+            Event::PrintJob => EventKind::PrintJob,
+            Event::JoypadRead(_) => EventKind::JoypadRead
+        }
+    }
+}
+
+// What the core tells the frontend about, queued while emulating and drained by it between frames.
+// Only subscribed kinds are queued, the rest cost a bit test where they happen
+pub struct EventBus {
+    subscribed: u32,  // bit per EventKind
+    queue: Vec<Event>,
+    ram_dirty: bool  // a CartRamDirty is already queued
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus {
+            subscribed: 0,
+            queue: vec![],
+            ram_dirty: false
+        }
+    }
+
+    pub fn subscribe(&mut self, kind: EventKind) {
+        self.subscribed |= 1 << kind as u32;
+    }
+
+    pub fn unsubscribe(&mut self, kind: EventKind) {
+        self.subscribed &= !(1 << kind as u32);
+        self.queue.retain(|e| e.kind() != kind);
+        if kind == EventKind::CartRamDirty {
+            self.ram_dirty = false;
+        }
+    }
+
+    #[inline]
+    pub fn subscribed(&self, kind: EventKind) -> bool {
+        self.subscribed & 1 << kind as u32 != 0
+    }
+
+    #[inline]
+    pub fn push(&mut self, event: Event) {
+        if !self.subscribed(event.kind()) {
+            return
+        }
+        if event == Event::CartRamDirty {
+            if self.ram_dirty {
+                return
+            }
+            self.ram_dirty = true;
+        }
+        if self.queue.len() < MAX_QUEUED {
+            self.queue.push(event);
+        }
+    }
+
+    // oldest first
+    pub fn drain(&mut self) -> Drain<'_, Event> {
+        self.ram_dirty = false;
+        self.queue.drain(..)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus::new()
+    }
+}
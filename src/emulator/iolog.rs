@@ -0,0 +1,85 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Clone, Copy, Default)]
+pub struct IoWrite {
+    pub addr: u16,
+    pub val: u8,
+    pub pc: u16,  // instruction that did the write
+    pub frame: u32,
+    pub ly: u8
+}
+
+// Ring buffer of writes to FF00-FFFF, filtered by register
+pub struct IoLog {
+    pub enabled: bool,
+    filter: [bool; 0x100],  // indexed by the low byte of the address
+    entries: Vec<IoWrite>,
+    pos: usize,
+    len: usize
+}
+
+impl IoLog {
+    pub fn new(capacity: usize) -> IoLog {
+        IoLog {
+            enabled: false,
+            filter: [true; 0x100],
+            entries: vec![IoWrite::default(); capacity],
+            pos: 0,
+            len: 0
+        }
+    }
+
+    // empty set logs every register
+    pub fn set_filter(&mut self, addresses: &[u16]) {
+        self.filter = [addresses.is_empty(); 0x100];
+        for addr in addresses.iter() {
+            self.filter[*addr as usize&0xFF] = true;
+        }
+    }
+
+    #[inline]
+    pub fn logs(&self, addr: u16) -> bool {
+        self.enabled && addr >= 0xFF00 && self.filter[addr as usize&0xFF]
+    }
+
+    pub fn push(&mut self, entry: IoWrite) {
+        self.entries[self.pos] = entry;
+        self.pos = (self.pos + 1) % self.entries.len();
+        self.len = (self.len + 1).min(self.entries.len());
+    }
+
+    pub fn clear(&mut self) {
+        self.pos = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // oldest first
+    pub fn iter(&self) -> impl Iterator<Item=&IoWrite> {
+        let start = (self.pos + self.entries.len() - self.len) % self.entries.len();
+        self.entries[start ..].iter().chain(self.entries[.. start].iter()).take(self.len)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn dump(&self, p: &Path) -> io::Result<()> {
+        let mut file = File::create(p)?;
+        writeln!(file, "frame   LY  PC    addr  val")?;
+        for e in self.iter() {
+            writeln!(file, "{:<7} {:<3} {:04X}  {:04X}  {:02X}", e.frame, e.ly, e.pc, e.addr, e.val)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+// How much of a register the emulator implements
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Support {
+    Full,
+    Partial,  // reads and writes work, some hardware behaviour is missing
+    Missing   // reads 0xFF, writes are dropped
+}
+
+impl Support {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Support::Full => "full",
+            Support::Partial => "partial",
+            Support::Missing => "missing"
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IoRegister {
+    pub addr: u16,
+    pub len: u16,  // 1 except for wave ram
+    pub name: &'static str,
+    pub read_mask: u8,  // bits that always read back as 1 on hardware
+    pub write: &'static str,
+    pub support: Support,
+    pub cgb: bool  // only mapped in CGB mode
+}
+
+const fn reg(addr: u16, name: &'static str, read_mask: u8, write: &'static str, support: Support, cgb: bool) -> IoRegister {
+    IoRegister { addr, len: 1, name, read_mask, write, support, cgb }
+}
+
+use Support::*;
+
+// Everything in FF00-FF7F and FFFF that does something on some model, in address order
+pub static IO_REGISTERS: [IoRegister; 63] = [
+    reg(0xFF00, "P1", 0xC0, "bits 4-5 select the button row", Full, false),
+    reg(0xFF01, "SB", 0x00, "serial data, replaced by the peer's byte", Full, false),
+    reg(0xFF02, "SC", 0x7E, "bit 7 starts a transfer, bit 0 on the own clock, bit 1 the CGB fast one", Full, false),
+    reg(0xFF04, "DIV", 0x00, "any write resets the divider", Full, false),
+    reg(0xFF05, "TIMA", 0x00, "counter, reload delay emulated", Full, false),
+    reg(0xFF06, "TMA", 0x00, "timer reload value", Full, false),
+    reg(0xFF07, "TAC", 0xF8, "timer enable and clock select", Full, false),
+    reg(0xFF0F, "IF", 0xE0, "interrupt requests", Full, false),
+    reg(0xFF10, "NR10", 0x80, "square 1 sweep", Full, false),
+    reg(0xFF11, "NR11", 0x3F, "square 1 duty and length", Full, false),
+    reg(0xFF12, "NR12", 0x00, "square 1 envelope", Full, false),
+    reg(0xFF13, "NR13", 0xFF, "square 1 period low, write only", Full, false),
+    reg(0xFF14, "NR14", 0xBF, "square 1 period high, length enable, trigger", Full, false),
+    reg(0xFF16, "NR21", 0x3F, "square 2 duty and length", Full, false),
+    reg(0xFF17, "NR22", 0x00, "square 2 envelope", Full, false),
+    reg(0xFF18, "NR23", 0xFF, "square 2 period low, write only", Full, false),
+    reg(0xFF19, "NR24", 0xBF, "square 2 period high, length enable, trigger", Full, false),
+    reg(0xFF1A, "NR30", 0x7F, "wave DAC enable", Full, false),
+    reg(0xFF1B, "NR31", 0xFF, "wave length, write only", Full, false),
+    reg(0xFF1C, "NR32", 0x9F, "wave output level", Full, false),
+    reg(0xFF1D, "NR33", 0xFF, "wave period low, write only", Full, false),
+    reg(0xFF1E, "NR34", 0xBF, "wave period high, length enable, trigger", Full, false),
+    reg(0xFF20, "NR41", 0xFF, "noise length, write only", Full, false),
+    reg(0xFF21, "NR42", 0x00, "noise envelope", Full, false),
+    reg(0xFF22, "NR43", 0x00, "noise frequency and width", Full, false),
+    reg(0xFF23, "NR44", 0xBF, "noise length enable, trigger", Full, false),
+    reg(0xFF24, "NR50", 0x00, "master volume", Full, false),
+    reg(0xFF25, "NR51", 0x00, "channel panning", Full, false),
+    reg(0xFF26, "NR52", 0x70, "bit 7 powers the APU, channel bits are read only", Full, false),
+    IoRegister { addr: 0xFF30, len: 16, name: "WAVE", read_mask: 0x00, write: "wave pattern ram, only the playing byte while channel 3 is on", support: Full, cgb: false },
+    reg(0xFF40, "LCDC", 0x00, "LCD control", Full, false),
+    reg(0xFF41, "STAT", 0x80, "interrupt selects, mode and LYC bits are read only", Full, false),
+    reg(0xFF42, "SCY", 0x00, "background scroll y", Full, false),
+    reg(0xFF43, "SCX", 0x00, "background scroll x", Full, false),
+    reg(0xFF44, "LY", 0x00, "read only", Full, false),
+    reg(0xFF45, "LYC", 0x00, "LY compare", Full, false),
+    reg(0xFF46, "DMA", 0x00, "starts an OAM DMA from XX00", Full, false),
+    reg(0xFF47, "BGP", 0x00, "DMG background palette", Full, false),
+    reg(0xFF48, "OBP0", 0x00, "DMG sprite palette 0", Full, false),
+    reg(0xFF49, "OBP1", 0x00, "DMG sprite palette 1", Full, false),
+    reg(0xFF4A, "WY", 0x00, "window y", Full, false),
+    reg(0xFF4B, "WX", 0x00, "window x + 7", Full, false),
+    reg(0xFF4D, "KEY1", 0x7E, "bit 0 arms a speed switch for STOP, bit 7 is the current speed", Partial, true),
+    reg(0xFF4F, "VBK", 0xFE, "VRAM bank", Full, true),
+    reg(0xFF50, "BANK", 0xFF, "any write unmaps the bootrom", Full, false),
+    reg(0xFF51, "HDMA1", 0xFF, "DMA source high", Full, true),
+    reg(0xFF52, "HDMA2", 0xFF, "DMA source low", Full, true),
+    reg(0xFF53, "HDMA3", 0xFF, "DMA destination high", Full, true),
+    reg(0xFF54, "HDMA4", 0xFF, "DMA destination low", Full, true),
+    reg(0xFF55, "HDMA5", 0x00, "starts general or hblank DMA, no CPU stall timing", Partial, true),
+    reg(0xFF56, "RP", 0x3C, "infrared port is not emulated", Missing, true),
+    reg(0xFF68, "BCPS", 0x40, "background palette index, auto increment", Full, true),
+    reg(0xFF69, "BCPD", 0x00, "background palette data", Full, true),
+    reg(0xFF6A, "OCPS", 0x40, "sprite palette index, auto increment", Full, true),
+    reg(0xFF6B, "OCPD", 0x00, "sprite palette data", Full, true),
+    reg(0xFF6C, "OPRI", 0xFE, "sprite priority by OAM or x position", Full, true),
+    reg(0xFF70, "SVBK", 0xF8, "WRAM bank, 0 selects 1", Full, true),
+    reg(0xFF72, "FF72", 0x00, "undocumented scratch byte", Full, true),
+    reg(0xFF73, "FF73", 0x00, "undocumented scratch byte", Full, true),
+    reg(0xFF74, "FF74", 0x00, "undocumented scratch byte", Full, true),
+    reg(0xFF75, "FF75", 0x8F, "undocumented, bits 4-6 writable", Full, true),
+    reg(0xFF76, "PCM12", 0x00, "channel 1 and 2 output, read only", Full, true),
+    reg(0xFF77, "PCM34", 0x00, "channel 3 and 4 output, read only", Full, true),
+];
+
+pub static IE_REGISTER: IoRegister = reg(0xFFFF, "IE", 0x00, "interrupt enables", Full, false);
+
+// Register covering addr, if any
+pub fn io_register(addr: u16) -> Option<&'static IoRegister> {
+    if addr == IE_REGISTER.addr {
+        return Some(&IE_REGISTER)
+    }
+    IO_REGISTERS.iter().find(|r| addr >= r.addr && addr < r.addr + r.len)
+}
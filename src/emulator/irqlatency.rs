@@ -0,0 +1,103 @@
+use alloc::collections::VecDeque;
+
+pub const IRQ_NAMES: [&str; 5] = ["VBlank", "STAT", "Timer", "Serial", "Joypad"];
+const HISTORY: usize = 256;  // latencies kept per interrupt for the graph
+const DISPATCH_CYCLES: u32 = 20;  // 5 machine cycles from dispatch to the handler's first fetch
+
+#[derive(Clone, Copy, Default)]
+pub struct IrqStats {
+    pub count: u64,
+    pub last: u32,
+    pub min: u32,
+    pub max: u32,
+    total: u64
+}
+
+impl IrqStats {
+    pub fn average(&self) -> f32 {
+        if self.count == 0 { 0. } else { self.total as f32 / self.count as f32 }
+    }
+}
+
+// Cycles from an IF bit being set to the first instruction of its handler, per interrupt.
+// The cpu only looks at IF between instructions, so this includes the wait for the running
+// instruction, a halt wakeup or a stretch with IME off
+pub struct IrqLatency {
+    pub enabled: bool,
+    cycles: u64,
+    requested: [Option<u64>; 5],  // cycle the pending IF bit was first seen set
+    stats: [IrqStats; 5],
+    history: [VecDeque<u32>; 5]  // oldest first
+}
+
+impl IrqLatency {
+    pub fn new() -> IrqLatency {
+        IrqLatency {
+            enabled: false,
+            cycles: 0,
+            requested: [None; 5],
+            stats: [IrqStats::default(); 5],
+            history: Default::default()
+        }
+    }
+
+    // requests seen before a pause in the measurement would count the pause
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.requested = [None; 5];
+    }
+
+    // once per cycle with the IF register after the hardware had its turn
+    #[inline]
+    pub fn tick(&mut self, flags: u8) {
+        self.cycles += 1;
+        for (i, requested) in self.requested.iter_mut().enumerate() {
+            if flags & (1 << i) == 0 {
+                *requested = None;  // acknowledged by a write to IF without being serviced
+            } else if requested.is_none() {
+                *requested = Some(self.cycles);
+            }
+        }
+    }
+
+    // the cpu is jumping to the handler of interrupt i
+    pub fn serviced(&mut self, i: usize) {
+        let at = match self.requested[i].take() {
+            Some(at) => at,
+            None => return
+        };
+        let latency = (self.cycles - at) as u32 + DISPATCH_CYCLES;
+
+        let s = &mut self.stats[i];
+        s.min = if s.count == 0 { latency } else { s.min.min(latency) };
+        s.max = s.max.max(latency);
+        s.last = latency;
+        s.total += latency as u64;
+        s.count += 1;
+
+        let h = &mut self.history[i];
+        if h.len() == HISTORY {
+            h.pop_front();
+        }
+        h.push_back(latency);
+    }
+
+    pub fn stats(&self, i: usize) -> &IrqStats {
+        &self.stats[i]
+    }
+
+    pub fn history(&self, i: usize) -> &VecDeque<u32> {
+        &self.history[i]
+    }
+
+    pub fn clear(&mut self) {
+        self.stats = [IrqStats::default(); 5];
+        self.history.iter_mut().for_each(|h| h.clear());
+    }
+}
+
+impl Default for IrqLatency {
+    fn default() -> IrqLatency {
+        IrqLatency::new()
+    }
+}
@@ -1,24 +1,76 @@
 #![allow(non_camel_case_types)]
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::emulator::{RtcClock, RtcSource, Mbc3Rtc, Huc3Rtc, RTC_FOOTER_SIZE, StateWriter, StateReader, Eeprom, EEPROM_SIZE, Camera, CameraSource, EmulatorError};
+
 pub trait MemoryBankController {
     fn read_rom(&mut self, addr: u16) -> u8;
     fn write_rom(&mut self, addr: u16, val: u8);
     fn read_ram(&mut self, addr: u16) -> u8;
     fn write_ram(&mut self, addr: u16, val: u8);
+
+    // false when ram is disabled or the cart has none, reads then return 0xFF and writes are ignored
+    fn ram_enabled(&self) -> bool { false }
+
+    // called every cycle
+    fn tick(&mut self) {}
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    // battery backed ram, persisted between sessions
+    fn battery(&self) -> bool { false }
+    fn ram(&self) -> &[u8] { &[] }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut [] }
+    fn load_ram(&mut self, _data: &[u8]) {}
+
+    // every 8KB ram bank whatever is mapped or enabled, for the debugger. Read only, 0xFF past the end
+    fn ram_banks(&self) -> usize { self.ram().len().div_ceil(0x2000) }
+    fn peek_ram(&self, bank: usize, offset: u16) -> u8 {
+        self.ram().get(bank*0x2000 + (offset as usize&0x1FFF)).copied().unwrap_or(0xFF)
+    }
+
+    // clock state saved after the ram, see Mbc3Rtc::footer
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_SIZE]> { None }
+    fn load_rtc_footer(&mut self, _data: &[u8]) {}
+
+    // flash chip contents, persisted next to the save, see MBC6
+    fn flash(&self) -> &[u8] { &[] }
+    fn load_flash(&mut self, _data: &[u8]) {}
+
+    // accelerometer input, see MBC7::set_tilt
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // where a camera cartridge's pictures come from
+    fn set_camera_source(&mut self, _source: Box<dyn CameraSource>) {}
+
+    // M-cycles the rumble motor was on since the last call
+    fn take_rumble_cycles(&mut self) -> u32 { 0 }
+    // whether the motor is on right now
+    fn rumble(&self) -> bool { false }
+
+    // writes that take a freshly reset controller to the current banking state, for BESS states
+    fn register_writes(&self) -> Vec<(u16, u8)> { vec![] }
+
+    // banking registers and ram for save states, the rom is expected to be the same
+    fn save_state(&self, _w: &mut StateWriter) {}
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), &'static str> { Ok(()) }
 }
 
-fn rom_size(val: u8) -> Result<usize, &'static str> {
+pub(crate) fn rom_size(val: u8) -> Result<usize, EmulatorError> {
     if val < 0x09 {
         return Ok((32768) << val)
     }
-    Err(&"Invalid ROM size")
+    Err(EmulatorError::InvalidRomSize(val))
 }
 
-fn rom_banks(val: u8) -> u16 {
-    2 << val as u16
+// bank numbers past the end of the rom wrap around, the register bits above the rom's size
+// aren't wired to anything. Applied on reads so the registers keep what the game wrote
+fn bank_mask(size: usize, bank_size: usize) -> usize {
+    (size / bank_size).max(1).next_power_of_two() - 1
 }
 
-fn ram_size(val: u8) -> Result<usize, &'static str> {
+pub(crate) fn ram_size(val: u8) -> Result<usize, EmulatorError> {
     match val {
         0x00 => Ok(0),
         0x01 => Ok(2048),    // 2kB
@@ -26,8 +78,38 @@ fn ram_size(val: u8) -> Result<usize, &'static str> {
         0x03 => Ok(32768),   // 32kB  - 4 banks
         0x04 => Ok(131072),  // 128kB - 16 banks
         0x05 => Ok(65536),   // 64kB  - 8 banks
-        _ => Err(&"Invalid RAM size")
+        _ => Err(EmulatorError::InvalidRamSize(val))
+    }
+}
+
+// some carts have a battery but report no ram, give them a single bank to persist
+fn battery_ram_size(size: usize, battery: bool) -> usize {
+    if battery && size == 0 {
+        0x2000
+    } else {
+        size
+    }
+}
+
+// saves from other emulators or older dumps can be smaller or bigger than the header ram size,
+// grow the ram up to the controller's limit and copy what fits
+fn load_battery_ram(ram: &mut Vec<u8>, data: &[u8], max: usize) {
+    let len = data.len().min(max);
+    if len > ram.len() {
+        ram.resize(len, 0);
+    }
+    let len = len.min(ram.len());
+    ram[.. len].copy_from_slice(&data[.. len]);
+}
+
+// save states keep the ram at whatever size it had grown to, see load_battery_ram
+fn load_state_ram(ram: &mut Vec<u8>, data: &[u8], max: usize) -> Result<(), &'static str> {
+    if data.len() > max {
+        return Err("save state cartridge ram is too big for this cartridge")
     }
+    ram.clear();
+    ram.extend_from_slice(data);
+    Ok(())
 }
 
 
@@ -83,177 +165,178 @@ pub struct MBC1 {
     rom: Vec<u8>,
     ram: Vec<u8>,
     ram_enabled: bool,
-    bank: u8,
+    bank1: u8,  // 2000-3FFF, lower 5 bits of the rom bank
+    bank2: u8,  // 4000-5FFF, upper 2 bits of the rom bank or the ram bank
     banking_mode: bool,  // false -> rom, true -> ram
-    bitmask: u8,
     battery: bool,
-    rom_banks: u8
+    rom_mask: usize,
+    ram_mask: usize
 }
 
 impl MBC1 {
     const MAX_ROM_SIZE: usize = 2*1024*1024;  // 2MB (in bytes)
     const MAX_RAM_SIZE: usize = 32*1024;      // 32kB (in bytes)
 
-    fn gen_bitmask(val: u8) -> u8 {
-        match rom_banks(val) {
-            2 =>   0b00000001,
-            4 =>   0b00000011,
-            8 =>   0b00000111,
-            16 =>  0b00001111,
-            32 =>  0b00011111,
-            64 =>  0b00011111,
-            128 => 0b00011111,
-            v => panic!("Unexpected MBC1 rom bank value {} from {}", v, val)
-        }
-    }
-
-    pub fn new(data: Vec<u8>) -> Result<Box<MBC1>, &'static str> {
+    pub fn new(data: Vec<u8>) -> Result<Box<MBC1>, EmulatorError> {
         let ram_s = ram_size(data[0x149])?;
         let rom_s = rom_size(data[0x148])?;
         let bat = data[0x147] == 0x03;
-        let bitmask = MBC1::gen_bitmask(data[0x148]);
+        let ram_s = battery_ram_size(ram_s, bat);
 
         if ram_s > MBC1::MAX_RAM_SIZE {
-            return Err(&"header ram size too big for MBC1")
+            return Err(EmulatorError::RamTooBig("MBC1"))
         }
         if rom_s != data.len() {
-            return Err(&"header rom size != rom size")
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
         }
         if data.len() > MBC1::MAX_ROM_SIZE {
-            return Err(&"Rom size too big for MBC1")
+            return Err(EmulatorError::RomTooBig("MBC1"))
         }
 
         Ok(Box::new(MBC1 {
-            rom_banks: rom_banks(data[0x148]) as u8,
+            rom_mask: bank_mask(data.len(), 0x4000),
             rom: data,
             ram: vec![0; ram_s],
             ram_enabled: false,
-            bank: 1,
+            bank1: 1,
+            bank2: 0,
             banking_mode: false,
-            bitmask: bitmask,
-            battery: bat
+            battery: bat,
+            ram_mask: MBC1::gen_ram_mask(ram_s)
         }))
     }
+
+    fn gen_ram_mask(size: usize) -> usize {
+        if size > 0x2000 {
+            size / 0x2000 - 1
+        } else {
+            0
+        }
+    }
+
+    // in mode 1 bank2 also switches the 0000-3FFF area on 1MB+ roms and the ram bank on 32kB ram
+    #[inline]
+    fn ram_addr(&self, addr: u16) -> usize {
+        let bank = if self.banking_mode {
+            self.bank2 as usize & self.ram_mask
+        } else { 0 };
+
+        (addr as usize&0x1FFF) + bank*0x2000
+    }
 }
 
 impl MemoryBankController for MBC1 {
     fn read_rom(&mut self, addr: u16) -> u8 {
         match addr {
             0x0000 ..= 0x3FFF => {
-                if self.banking_mode {
-                    let bank = match self.rom_banks {
-                        64 => self.bank as usize & 0x20,
-                        128 => self.bank as usize & 0x60,
-                        _ => 0
-                    };
-
-                    self.rom[addr as usize + bank as usize*0x4000]
-                } else {
-                    self.rom[addr as usize]
-                }
+                let bank = if self.banking_mode {
+                    (self.bank2 as usize) << 5 & self.rom_mask
+                } else { 0 };
+
+                self.rom[addr as usize + bank*0x4000]
             },
             0x4000 ..= 0x7FFF => {
-                let bank = match self.rom_banks {
-                    64 => self.bank as usize & 0b00111111,
-                    128 => self.bank as usize & 0b01111111,
-                    _ => self.bank as usize & 0b00011111
-                };
-                
-                self.rom[(addr as usize&0x3FFF) + 0x4000*bank]
+                let bank = ((self.bank2 as usize) << 5 | self.bank1 as usize) & self.rom_mask;
+
+                self.rom[(addr as usize&0x3FFF) + bank*0x4000]
             },
-            _ => panic!()
+            _ => 0xFF
         }
     }
 
-    fn write_rom(&mut self, addr: u16, mut val: u8){
+    fn write_rom(&mut self, addr: u16, val: u8){
         match addr {
             0x0000 ..= 0x1FFF => {
                 self.ram_enabled = val&0xF == 0xA;
             },
             0x2000 ..= 0x3FFF => {
-                let bef = val&0x1F;
-                val &= self.bitmask;
-                if val == 0 {
-                    val = (bef<=val) as u8
+                // zero check happens on the full 5 bits, before masking by rom size
+                self.bank1 = val&0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
                 }
-                self.bank = (self.bank&0x60) | val;
             },
             0x4000 ..= 0x5FFF => {
-                val = val.rotate_right(3);
-                self.bank = val&0x60 | self.bank&0x1F;
+                self.bank2 = val&0x3;
             },
             0x6000 ..= 0x7FFF => {
                 self.banking_mode = val&0x1 == 1;
             },
-            _ => panic!()
+            _ => ()
         }
     }
 
-    fn read_ram(&mut self, addr: u16) -> u8 {
-        if self.ram_enabled {
-            let bank = if self.banking_mode {
-                let b = match self.ram.len() / 0x2000 {
-                    2 => self.bank&0x20,
-                    4 => self.bank&0x60,
-                    _ => 0
-                };
-                b as usize >> 5
-            } else { 0 };
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled && !self.ram.is_empty()
+    }
 
-            self.ram[addr as usize + bank*0x2000]
-        } else { 0xFF }
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        let addr = self.ram_addr(addr) % self.ram.len();
+        self.ram[addr]
     }
 
     fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled && self.ram.len() > 0 {
-            let bank = if self.banking_mode {
-                let b = match self.ram.len() / 0x2000 {
-                    2 => self.bank&0x20,
-                    4 => self.bank&0x60,
-                    _ => 0
-                };
-                b as usize >> 5
-            } else { 0 };
+        let addr = self.ram_addr(addr) % self.ram.len();
+        self.ram[addr] = val;
+    }
 
-            self.ram[addr as usize + bank*0x2000] = val;
-        }
+    fn battery(&self) -> bool { self.battery }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        load_battery_ram(&mut self.ram, data, MBC1::MAX_RAM_SIZE);
+        self.ram_mask = MBC1::gen_ram_mask(self.ram.len());
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, if self.ram_enabled { 0xA } else { 0 }), (0x2000, self.bank1), (0x4000, self.bank2), (0x6000, self.banking_mode as u8)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.ram_enabled as u8, self.bank1, self.bank2, self.banking_mode as u8]);
+        w.write_bytes(&self.ram);
+    }
+
+    // through write_rom, so the values get masked the same as the game's writes
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        let mut regs = [0; 4];
+        r.read_into(&mut regs)?;
+        self.write_rom(0x0000, if regs[0] != 0 { 0xA } else { 0 });
+        self.write_rom(0x2000, regs[1]);
+        self.write_rom(0x4000, regs[2]);
+        self.write_rom(0x6000, regs[3]);
+        load_state_ram(&mut self.ram, r.read_bytes()?, MBC1::MAX_RAM_SIZE)?;
+        self.ram_mask = MBC1::gen_ram_mask(self.ram.len());
+        Ok(())
     }
 }
 
 
 pub struct MBC2 {
     rom: Vec<u8>,
-    ram: Vec<u8>,
+    ram: Vec<u8>,  // 512 x 4 bits, only the lower nibble is stored
     ram_enabled: bool,
     bank: usize,
-    bitmask: u8
+    rom_mask: usize,
+    battery: bool
 }
 
 impl MBC2 {
-    fn gen_bitmask(val: u8) -> u8 {
-        match rom_banks(val) {
-            2 =>   0b00000001,
-            4 =>   0b00000011,
-            8 =>   0b00000111,
-            16 =>  0b00001111,
-            v => panic!("Unexpected MBC2 rom bank value {} from {}", v, val)
-        }
-    }
-
-    pub fn new(data: Vec<u8>) -> Result<Box<MBC2>, &'static str> {
+    pub fn new(data: Vec<u8>) -> Result<Box<MBC2>, EmulatorError> {
         let rom_s = rom_size(data[0x148])?;
-        let bitmask = MBC2::gen_bitmask(data[0x148]);
+        let bat = data[0x147] == 0x06;
         if rom_s != data.len() {
-            return Err(&"header rom size != rom size")
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
         }
 
         Ok(Box::new(
             MBC2 {
+                rom_mask: bank_mask(data.len(), 0x4000),
                 rom: data,
                 ram: vec![0; 512],
                 ram_enabled: false,
                 bank: 1,
-                bitmask: bitmask
+                battery: bat
             }
         ))
     }
@@ -266,40 +349,65 @@ impl MemoryBankController for MBC2 {
                 self.rom[addr as usize]
             },
             0x4000 ..= 0x7FFF => {
-                self.rom[(addr&0x3FFF) as usize + self.bank*0x4000]
+                self.rom[(addr&0x3FFF) as usize + (self.bank & self.rom_mask)*0x4000]
             },
-            _ => panic!()
+            _ => 0xFF
         }
     }
 
-    fn write_rom(&mut self, addr: u16, mut val: u8){
+    fn write_rom(&mut self, addr: u16, val: u8){
         if addr <= 0x3FFF { 
             if addr&0x0100 == 0 {
                 self.ram_enabled = val&0xF == 0xA;
             } else {
-                let bef = val&0xF;
-                val &= self.bitmask;
-                if val == 0 {
-                    val = (bef<=val) as u8
-                }
-                self.bank = val as usize;
+                // zero check on all 4 bits, a bank past the rom size can still land on bank 0
+                self.bank = (val&0xF).max(1) as usize;
             }
         }
     }
 
-    fn read_ram(&mut self, addr: u16) -> u8 {
-        if self.ram_enabled {
-            return self.ram[addr as usize&0x01FF]
-        }
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
 
-        0xFF
+    // upper nibble is open bus, the 512 bytes echo through the whole A000-BFFF range
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize&0x01FF] | 0xF0
     }
 
     fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled {
-            self.ram[addr as usize&0x01FF] = val&0xF | 0xF0
+        self.ram[addr as usize&0x01FF] = val&0xF
+    }
+
+    fn battery(&self) -> bool { self.battery }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn peek_ram(&self, _bank: usize, offset: u16) -> u8 {
+        self.ram[offset as usize&0x01FF] | 0xF0
+    }
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        for (r, d) in self.ram.iter_mut().zip(data[.. len].iter()) {
+            *r = d&0xF;
         }
     }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, if self.ram_enabled { 0xA } else { 0 }), (0x0100, self.bank as u8)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.ram_enabled as u8, self.bank as u8]);
+        w.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        let mut regs = [0; 2];
+        r.read_into(&mut regs)?;
+        self.write_rom(0x0000, if regs[0] != 0 { 0xA } else { 0 });
+        self.write_rom(0x0100, regs[1]);
+        r.read_bytes_into(&mut self.ram)
+    }
 }
 
 
@@ -309,8 +417,8 @@ pub struct MBC3 {
     ram_enabled: bool,
     bank: u8,
     ram_bank: u8,
-    bitmask: u8,
-    rtc: bool,
+    rom_mask: usize,
+    rtc: Option<Mbc3Rtc>,
     battery: bool,
 }
 
@@ -318,58 +426,59 @@ impl MBC3 {
     const MAX_ROM_SIZE: usize = 2*1024*1024;  // 2MB (in bytes)
     const MAX_RAM_SIZE: usize = 32*1024;      // 32kB (in bytes)
 
-    fn gen_bitmask(val: u8) -> u8 {
-        match rom_banks(val) {
-            2 =>   0b00000001,
-            4 =>   0b00000011,
-            8 =>   0b00000111,
-            16 =>  0b00001111,
-            32 =>  0b00011111,
-            64 =>  0b00111111,
-            128 => 0b01111111,
-            v => panic!("Unexpected MBC3 rom bank value {} from {}", v, val)
-        }
-    }
-
-    pub fn new(data: Vec<u8>) -> Result<Box<MBC3>, &'static str> {
+    pub fn new(data: Vec<u8>) -> Result<Box<MBC3>, EmulatorError> {
         let ram_s = ram_size(data[0x149])?;
         let rom_s = rom_size(data[0x148])?;
-        let bat = data[0x147] == 0x03;
-        let bitmask = MBC3::gen_bitmask(data[0x148]);
+        let bat = data[0x147] == 0x0F || data[0x147] == 0x10 || data[0x147] == 0x13;
+        let ram_s = battery_ram_size(ram_s, bat);
+        let rtc = data[0x147] == 0x0F || data[0x147] == 0x10;
+        let rtc = if rtc { Some(Mbc3Rtc::new(RtcClock::new(RtcSource::Host, 0))) } else { None };
 
         if ram_s > MBC3::MAX_RAM_SIZE {
-            return Err(&"header ram size too big for MBC3")
+            return Err(EmulatorError::RamTooBig("MBC3"))
         }
         if rom_s != data.len() {
-            return Err(&"header rom size != rom size")
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
         }
         if data.len() > MBC3::MAX_ROM_SIZE {
-            return Err(&"Rom is bigger than MBC3 max rom size")
+            return Err(EmulatorError::RomTooBig("MBC3"))
         }
 
         Ok(Box::new(MBC3 {
+            rom_mask: bank_mask(data.len(), 0x4000),
             rom: data,
             ram: vec![0; ram_s],
             ram_enabled: false,
             bank: 1,
             ram_bank: 0,
-            bitmask: bitmask,
             battery: bat,
-            rtc: false
+            rtc
         }))
     }
 }
 
 impl MemoryBankController for MBC3 {
+    fn tick(&mut self) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick();
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
     fn read_rom(&mut self, addr: u16) -> u8 {
         match addr {
             0x0000 ..= 0x3FFF => {
                 self.rom[addr as usize]
             },
             0x4000 ..= 0x7FFF => {
-                self.rom[(addr as usize & 0x3FFF) + 0x4000*self.bank as usize]
+                self.rom[(addr as usize & 0x3FFF) + 0x4000*(self.bank as usize & self.rom_mask)]
             },
-            _ => panic!()
+            _ => 0xFF
         }
     }
 
@@ -379,50 +488,89 @@ impl MemoryBankController for MBC3 {
                 self.ram_enabled = val&0xF == 0xA;
             },
             0x2000 ..= 0x3FFF => {
-                let bef = val&0x7F;
-                val &= self.bitmask;
-                if val == 0 {
-                    val = (bef<=val) as u8
-                }
-                self.bank = val;
+                self.bank = (val&0x7F).max(1);
             },
             0x4000 ..= 0x5FFF => {
                 val &= 0xF;
-                if val > 0x7 && val < 0xD && self.rtc {
+                if val > 0x7 && val < 0xD && self.rtc.is_some() {
                     self.ram_bank = val;
                 } else {
                     self.ram_bank = val&0b11;
                 }
             },
             0x6000 ..= 0x7FFF => {
-                
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
             },
-            _ => panic!()
+            _ => ()
         }
     }
 
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled && (self.ram_bank > 0x7 || !self.ram.is_empty())
+    }
+
     fn read_ram(&mut self, addr: u16) -> u8 {
-        if self.ram_enabled {
-            if self.ram_bank < 0x4 {
-                return self.ram[addr as usize + self.ram_bank as usize*0x2000]
-            } else {
-                match self.ram_bank {
-                    0x8 => 1,  // seconds 0x00-0x3B 59
-                    0x9 => 1,  // minutes 0x00-0x3B 59
-                    0xA => 1,  // hours   0x00-0x17 23
-                    0xB => 1,  // lower 8 bits of days counter
-                    0xC => 0b01000000, 
-                    a => panic!("MBC3 read_ram wrong rtc register address: {:x}", a)
-                }
+        if self.ram_bank < 0x4 {
+            let addr = (addr as usize + self.ram_bank as usize*0x2000) % self.ram.len();
+            self.ram[addr]
+        } else {
+            match &self.rtc {
+                Some(rtc) => rtc.read(self.ram_bank),
+                None => 0xFF
             }
-        } else { 0x0 }
+        }
     }
 
     fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled && self.ram.len() > 0 && self.ram_bank < 0x4 {
-            if self.ram_bank < 0x4 {
-                self.ram[addr as usize + self.ram_bank as usize*0x2000] = val;
-            }
+        if self.ram_bank < 0x4 {
+            let addr = (addr as usize + self.ram_bank as usize*0x2000) % self.ram.len();
+            self.ram[addr] = val;
+        } else if let Some(rtc) = &mut self.rtc {
+            rtc.write(self.ram_bank, val);
+        }
+    }
+
+    fn battery(&self) -> bool { self.battery }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        load_battery_ram(&mut self.ram, data, MBC3::MAX_RAM_SIZE)
+    }
+
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_SIZE]> {
+        self.rtc.as_ref().map(|rtc| rtc.footer())
+    }
+
+    fn load_rtc_footer(&mut self, data: &[u8]) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_footer(data);
+        }
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, if self.ram_enabled { 0xA } else { 0 }), (0x2000, self.bank), (0x4000, self.ram_bank)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.ram_enabled as u8, self.bank, self.ram_bank]);
+        w.write_bytes(&self.ram);
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        let mut regs = [0; 3];
+        r.read_into(&mut regs)?;
+        self.write_rom(0x0000, if regs[0] != 0 { 0xA } else { 0 });
+        self.write_rom(0x2000, regs[1]);
+        self.write_rom(0x4000, regs[2]);
+        load_state_ram(&mut self.ram, r.read_bytes()?, MBC3::MAX_RAM_SIZE)?;
+        match &mut self.rtc {
+            Some(rtc) => rtc.load_state(r),
+            None => Ok(())
         }
     }
 }
@@ -435,37 +583,27 @@ pub struct MBC5 {
     bank: u16,
     ram_bank: u8,
     battery: bool,
+    rumble: bool,  // bit 3 of the ram bank drives a motor instead of selecting a bank
+    rumble_cycles: u32,
 
-    rom_bitmask: u16
+    rom_mask: usize
 }
 
 impl MBC5 {
-    fn gen_bitmask(val: u8) -> u16 {
-        match rom_banks(val) {
-            2 =>   0b00000001,
-            4 =>   0b00000011,
-            8 =>   0b00000111,
-            16 =>  0b00001111,
-            32 =>  0b00011111,
-            64 =>  0b00111111,
-            128 => 0b01111111,
-            256 => 0b11111111,
-            512 => 0b111111111,
-            v => panic!("Unexpected MBC5 rom bank value {} from {}", v, val)
-        }
-    }
-
-    pub fn new(data: Vec<u8>) -> Result<Box<MBC5>, &'static str> {
+    const MAX_RAM_SIZE: usize = 128*1024;     // 128kB (in bytes)
+
+    pub fn new(data: Vec<u8>) -> Result<Box<MBC5>, EmulatorError> {
         let ram_s = ram_size(data[0x149])?;
         let rom_s = rom_size(data[0x148])?;
-        let rom_bitmask = MBC5::gen_bitmask(data[0x148]);
-        let bat = data[0x147] == 0x03;
+        let bat = data[0x147] == 0x1B || data[0x147] == 0x1E;
+        let rumble = data[0x147] >= 0x1C;
+        let ram_s = battery_ram_size(ram_s, bat);
 
-        if ram_s > MBC1::MAX_RAM_SIZE {
-            return Err(&"header ram size too big for MBC5")
+        if ram_s > MBC5::MAX_RAM_SIZE {
+            return Err(EmulatorError::RamTooBig("MBC5"))
         }
         if rom_s != data.len() {
-            return Err(&"header rom size != rom size")
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
         }
 
         Ok(Box::new(MBC5 {
@@ -475,10 +613,17 @@ impl MBC5 {
             bank: 1,
             ram_bank: 0,
             battery: bat,
+            rumble,
+            rumble_cycles: 0,
 
-            rom_bitmask: rom_bitmask
+            rom_mask: bank_mask(rom_s, 0x4000)
         }))
     }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = self.ram_bank as usize & if self.rumble { 0x7 } else { 0xF };
+        (addr as usize + bank*0x2000) % self.ram.len()
+    }
 }
 
 impl MemoryBankController for MBC5 {
@@ -489,9 +634,9 @@ impl MemoryBankController for MBC5 {
                 self.rom[addr as usize]
             },
             0x4000 ..= 0x7FFF => {
-                self.rom[(addr as usize&0x3FFF) + 0x4000*(self.bank&self.rom_bitmask) as usize]
+                self.rom[(addr as usize&0x3FFF) + 0x4000*(self.bank as usize & self.rom_mask)]
             },
-            _ => panic!()
+            _ => 0xFF
         }
     }
 
@@ -513,15 +658,952 @@ impl MemoryBankController for MBC5 {
         }
     }
 
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled && !self.ram.is_empty()
+    }
+
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        let addr = self.ram_offset(addr);
+        self.ram[addr] = val;
+    }
+
+    fn tick(&mut self) {
+        if self.rumble && self.ram_bank&0x8 != 0 {
+            self.rumble_cycles += 1;
+        }
+    }
+
+    fn take_rumble_cycles(&mut self) -> u32 {
+        core::mem::replace(&mut self.rumble_cycles, 0)
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble && self.ram_bank&0x8 != 0
+    }
+
+    fn battery(&self) -> bool { self.battery }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        load_battery_ram(&mut self.ram, data, MBC5::MAX_RAM_SIZE)
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, if self.ram_enabled { 0xA } else { 0 }), (0x2000, self.bank as u8), (0x3000, (self.bank >> 8) as u8), (0x4000, self.ram_bank)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.ram_enabled);
+        w.write_u16(self.bank);
+        w.write_u8(self.ram_bank);
+        w.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.ram_enabled = r.read_bool()?;
+        self.bank = r.read_u16()?&0x1FF;
+        self.ram_bank = r.read_u8()?;
+        load_state_ram(&mut self.ram, r.read_bytes()?, MBC5::MAX_RAM_SIZE)
+    }
+}
+
+
+pub struct MBC7 {
+    rom: Vec<u8>,
+    ram_enable1: bool,  // 0000-1FFF, 0x0A
+    ram_enable2: bool,  // 4000-5FFF, 0x40
+    bank: u8,
+    rom_mask: usize,
+    eeprom: Eeprom,
+    tilt: (u16, u16),  // what the accelerometer reads right now
+    latched: (u16, u16),
+    erased: bool  // 0x55 written to Ax0x, the next 0xAA to Ax1x latches
+}
+
+impl MBC7 {
+    const ACCEL_CENTER: f32 = 0x81D0 as f32;
+    const ACCEL_G: f32 = 0x70 as f32;
+
+    pub fn new(data: Vec<u8>) -> Result<Box<MBC7>, EmulatorError> {
+        let rom_s = rom_size(data[0x148])?;
+        if rom_s != data.len() {
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
+        }
+
+        let center = MBC7::ACCEL_CENTER as u16;
+        Ok(Box::new(MBC7 {
+            rom_mask: bank_mask(data.len(), 0x4000),
+            rom: data,
+            ram_enable1: false,
+            ram_enable2: false,
+            bank: 1,
+            eeprom: Eeprom::new(),
+            tilt: (center, center),
+            latched: (0x8000, 0x8000),
+            erased: false
+        }))
+    }
+}
+
+// A000-AFFF are the sensor and EEPROM registers picked by bits 4-7, B000-BFFF reads 0xFF
+impl MemoryBankController for MBC7 {
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => self.rom[addr as usize],
+            0x4000 ..= 0x7FFF => self.rom[(addr as usize&0x3FFF) + (self.bank as usize & self.rom_mask)*0x4000],
+            _ => 0xFF
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram_enable1 = val&0xF == 0xA,
+            0x2000 ..= 0x3FFF => self.bank = val,
+            0x4000 ..= 0x5FFF => self.ram_enable2 = val == 0x40,
+            _ => ()
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable1 && self.ram_enable2
+    }
+
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        if addr >= 0x1000 {
+            return 0xFF
+        }
+        match addr >> 4 & 0xF {
+            0x2 => self.latched.0 as u8,
+            0x3 => (self.latched.0 >> 8) as u8,
+            0x4 => self.latched.1 as u8,
+            0x5 => (self.latched.1 >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if addr >= 0x1000 {
+            return
+        }
+        match addr >> 4 & 0xF {
+            0x0 if val == 0x55 => {
+                self.erased = true;
+                self.latched = (0x8000, 0x8000);
+            },
+            0x1 if val == 0xAA && self.erased => {
+                self.erased = false;
+                self.latched = self.tilt;
+            },
+            0x8 => self.eeprom.write(val),
+            _ => ()
+        }
+    }
+
+    // -1.0 - 1.0 on each axis, 1.0 is a full g of tilt. Positive x is tilted right, positive y towards the player
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        let axis = |v: f32| (MBC7::ACCEL_CENTER - v.clamp(-1., 1.) * MBC7::ACCEL_G) as u16;
+        self.tilt = (axis(x), axis(y));
+    }
+
+    fn battery(&self) -> bool { true }
+    fn ram(&self) -> &[u8] { &self.eeprom.data }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.eeprom.data }
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(EEPROM_SIZE);
+        self.eeprom.data[.. len].copy_from_slice(&data[.. len]);
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, if self.ram_enable1 { 0xA } else { 0 }), (0x2000, self.bank), (0x4000, if self.ram_enable2 { 0x40 } else { 0 })]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.ram_enable1 as u8, self.ram_enable2 as u8, self.bank, self.erased as u8]);
+        w.write_u16(self.latched.0);
+        w.write_u16(self.latched.1);
+        self.eeprom.save_state(w);
+        w.write_bytes(&self.eeprom.data);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.ram_enable1 = r.read_bool()?;
+        self.ram_enable2 = r.read_bool()?;
+        self.bank = r.read_u8()?;
+        self.erased = r.read_bool()?;
+        self.latched = (r.read_u16()?, r.read_u16()?);
+        self.eeprom.load_state(r)?;
+        r.read_bytes_into(&mut self.eeprom.data)
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum FlashState {
+    Ready,
+    Unlock1,       // 0xAA written to 5555
+    Unlock2,       // then 0x55 to 2AAA, a command to 5555 comes next
+    EraseUnlock1,  // after 0x80, another unlock and an erase command
+    EraseUnlock2,
+    EraseUnlock3,
+    Program,       // the next write programs a byte
+    Id             // reads return the chip id until reset
+}
+
+// MBC6 of Net de Get. 4000-5FFF and 6000-7FFF each map their own 8KB bank of rom or of the 1MB
+// flash, A000-AFFF and B000-BFFF their own 4KB ram bank. The flash takes the usual AMD style
+// unlocked commands at flash offsets 5555/2AAA, erases clear 128KB sectors to 0xFF and
+// programming can only clear bits. Its contents persist next to the save, see flash()
+pub struct MBC6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    ram_enable: bool,
+    ram_bank: [u8; 2],
+    rom_bank: [u8; 2],
+    flash_select: [bool; 2],  // the bank reads flash instead of rom
+    flash_enable: bool,
+    flash_write: bool,
+    flash_state: FlashState,
+    rom_mask: usize
+}
+
+impl MBC6 {
+    const MAX_RAM_SIZE: usize = 0x8000;
+    const FLASH_SIZE: usize = 0x100000;
+    const FLASH_SECTOR: usize = 0x20000;
+
+    pub fn new(data: Vec<u8>) -> Result<Box<MBC6>, EmulatorError> {
+        let ram_s = battery_ram_size(ram_size(data[0x149])?, true);
+        let rom_s = rom_size(data[0x148])?;
+
+        if ram_s > MBC6::MAX_RAM_SIZE {
+            return Err(EmulatorError::RamTooBig("MBC6"))
+        }
+        if rom_s != data.len() {
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
+        }
+
+        Ok(Box::new(MBC6 {
+            rom_mask: bank_mask(rom_s, 0x2000),
+            rom: data,
+            ram: vec![0; ram_s],
+            flash: vec![0xFF; MBC6::FLASH_SIZE],
+            ram_enable: false,
+            ram_bank: [0, 0],
+            rom_bank: [0, 0],
+            flash_select: [false, false],
+            flash_enable: false,
+            flash_write: false,
+            flash_state: FlashState::Ready
+        }))
+    }
+
+    // 4000-7FFF address to offset in the rom or flash, whichever the half maps
+    fn bank_offset(&self, addr: u16) -> (bool, usize) {
+        let half = (addr as usize >> 13) & 1;
+        let bank = self.rom_bank[half] as usize;
+        if self.flash_select[half] {
+            (true, (addr as usize&0x1FFF) + (bank & 0x7F)*0x2000)
+        } else {
+            (false, (addr as usize&0x1FFF) + (bank & self.rom_mask)*0x2000)
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        let bank = self.ram_bank[(addr as usize >> 12) & 1] as usize;
+        ((addr as usize&0xFFF) + bank*0x1000) % self.ram.len()
+    }
+
+    fn write_flash(&mut self, offset: usize, val: u8) {
+        let cmd = offset & 0x7FFF;
+        self.flash_state = match (self.flash_state, cmd, val) {
+            (_, _, 0xF0) => FlashState::Ready,
+            (FlashState::Program, _, _) => {
+                self.flash[offset] &= val;
+                FlashState::Ready
+            },
+            (FlashState::Ready, 0x5555, 0xAA) | (FlashState::Id, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseUnlock1,
+            (FlashState::Unlock2, 0x5555, 0x90) => FlashState::Id,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::EraseUnlock1, 0x5555, 0xAA) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, 0x2AAA, 0x55) => FlashState::EraseUnlock3,
+            (FlashState::EraseUnlock3, 0x5555, 0x10) => {
+                for b in self.flash.iter_mut() {
+                    *b = 0xFF;
+                }
+                FlashState::Ready
+            },
+            (FlashState::EraseUnlock3, _, 0x30) => {
+                let start = offset / MBC6::FLASH_SECTOR * MBC6::FLASH_SECTOR;
+                for b in self.flash[start .. start + MBC6::FLASH_SECTOR].iter_mut() {
+                    *b = 0xFF;
+                }
+                FlashState::Ready
+            },
+            (FlashState::Id, _, _) => FlashState::Id,
+            _ => FlashState::Ready
+        };
+    }
+}
+
+impl MemoryBankController for MBC6 {
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => self.rom[addr as usize],
+            0x4000 ..= 0x7FFF => match self.bank_offset(addr) {
+                (false, offset) => self.rom[offset],
+                (true, _) if !self.flash_enable => 0xFF,
+                (true, offset) if self.flash_state == FlashState::Id => match offset & 0xFF {
+                    0x00 => 0xC2,  // Macronix
+                    0x01 => 0x81,  // MX29F008
+                    _ => 0xFF
+                },
+                (true, offset) => self.flash[offset]
+            },
+            _ => 0xFF
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x03FF => self.ram_enable = val&0xF == 0xA,
+            0x0400 ..= 0x07FF => self.ram_bank[0] = val&0x7,
+            0x0800 ..= 0x0BFF => self.ram_bank[1] = val&0x7,
+            0x0C00 ..= 0x0FFF => self.flash_enable = val&0x1 != 0,
+            0x1000 => self.flash_write = val&0x1 != 0,
+            0x2000 ..= 0x27FF => self.rom_bank[0] = val,
+            0x2800 ..= 0x2FFF => self.flash_select[0] = val == 0x08,
+            0x3000 ..= 0x37FF => self.rom_bank[1] = val,
+            0x3800 ..= 0x3FFF => self.flash_select[1] = val == 0x08,
+            0x4000 ..= 0x7FFF => match self.bank_offset(addr) {
+                (true, offset) if self.flash_enable && self.flash_write => self.write_flash(offset, val),
+                _ => ()
+            },
+            _ => ()
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable && !self.ram.is_empty()
+    }
+
     fn read_ram(&mut self, addr: u16) -> u8 {
-        if self.ram_enabled {
+        self.ram[self.ram_addr(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        let addr = self.ram_addr(addr);
+        self.ram[addr] = val;
+    }
+
+    fn battery(&self) -> bool { true }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        load_battery_ram(&mut self.ram, data, MBC6::MAX_RAM_SIZE)
+    }
+
+    fn flash(&self) -> &[u8] { &self.flash }
+    fn load_flash(&mut self, data: &[u8]) {
+        let len = data.len().min(MBC6::FLASH_SIZE);
+        self.flash[.. len].copy_from_slice(&data[.. len]);
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        let select = |flash: bool| if flash { 0x08 } else { 0 };
+        vec![
+            (0x0000, if self.ram_enable { 0xA } else { 0 }),
+            (0x0400, self.ram_bank[0]), (0x0800, self.ram_bank[1]),
+            (0x0C00, self.flash_enable as u8), (0x1000, self.flash_write as u8),
+            (0x2000, self.rom_bank[0]), (0x2800, select(self.flash_select[0])),
+            (0x3000, self.rom_bank[1]), (0x3800, select(self.flash_select[1]))
+        ]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        let state = match self.flash_state {
+            FlashState::Ready => 0,
+            FlashState::Unlock1 => 1,
+            FlashState::Unlock2 => 2,
+            FlashState::EraseUnlock1 => 3,
+            FlashState::EraseUnlock2 => 4,
+            FlashState::EraseUnlock3 => 5,
+            FlashState::Program => 6,
+            FlashState::Id => 7
+        };
+        w.write(&[self.ram_enable as u8, self.ram_bank[0], self.ram_bank[1], self.rom_bank[0], self.rom_bank[1],
+            self.flash_select[0] as u8, self.flash_select[1] as u8, self.flash_enable as u8, self.flash_write as u8, state]);
+        w.write_bytes(&self.ram);
+        w.write_bytes(&self.flash);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.ram_enable = r.read_bool()?;
+        self.ram_bank = [r.read_u8()?&0x7, r.read_u8()?&0x7];
+        self.rom_bank = [r.read_u8()?, r.read_u8()?];
+        self.flash_select = [r.read_bool()?, r.read_bool()?];
+        self.flash_enable = r.read_bool()?;
+        self.flash_write = r.read_bool()?;
+        self.flash_state = match r.read_u8()? {
+            0 => FlashState::Ready,
+            1 => FlashState::Unlock1,
+            2 => FlashState::Unlock2,
+            3 => FlashState::EraseUnlock1,
+            4 => FlashState::EraseUnlock2,
+            5 => FlashState::EraseUnlock3,
+            6 => FlashState::Program,
+            7 => FlashState::Id,
+            _ => return Err("save state has an invalid flash state")
+        };
+        load_state_ram(&mut self.ram, r.read_bytes()?, MBC6::MAX_RAM_SIZE)?;
+        r.read_bytes_into(&mut self.flash)
+    }
+}
+
+
+// Pocket Camera. 4000-5FFF picks one of 16 ram banks, or with bit 4 set the camera registers,
+// see Camera. Ram can't be read while a picture is being taken
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enable: bool,
+    bank: u8,
+    ram_bank: u8,
+    camera_select: bool,
+    rom_mask: usize,
+    camera: Camera
+}
+
+impl PocketCamera {
+    const RAM_SIZE: usize = 0x20000;
+
+    pub fn new(data: Vec<u8>) -> Result<Box<PocketCamera>, EmulatorError> {
+        let rom_s = rom_size(data[0x148])?;
+        if rom_s != data.len() {
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
+        }
+
+        Ok(Box::new(PocketCamera {
+            rom_mask: bank_mask(data.len(), 0x4000),
+            rom: data,
+            ram: vec![0; PocketCamera::RAM_SIZE],
+            ram_enable: false,
+            bank: 1,
+            ram_bank: 0,
+            camera_select: false,
+            camera: Camera::new()
+        }))
+    }
+}
+
+impl MemoryBankController for PocketCamera {
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => self.rom[addr as usize],
+            0x4000 ..= 0x7FFF => self.rom[(addr as usize&0x3FFF) + (self.bank as usize & self.rom_mask)*0x4000],
+            _ => 0xFF
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram_enable = val&0xF == 0xA,
+            0x2000 ..= 0x3FFF => self.bank = val&0x3F,
+            0x4000 ..= 0x5FFF => {
+                self.camera_select = val&0x10 != 0;
+                self.ram_bank = val&0xF;
+            },
+            _ => ()
+        }
+    }
+
+    // ram reads don't need the enable, only writes
+    fn ram_enabled(&self) -> bool {
+        true
+    }
+
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        if self.camera_select {
+            self.camera.read(addr)
+        } else if self.camera.busy() {
+            0x00
+        } else {
             self.ram[addr as usize + self.ram_bank as usize*0x2000]
-        } else { 0xFF }
+        }
     }
 
     fn write_ram(&mut self, addr: u16, val: u8) {
-        if self.ram_enabled && self.ram.len() > 0 {
+        if self.camera_select {
+            self.camera.write(addr, val);
+        } else if self.ram_enable && !self.camera.busy() {
             self.ram[addr as usize + self.ram_bank as usize*0x2000] = val;
         }
     }
-}
\ No newline at end of file
+
+    fn tick(&mut self) {
+        if self.camera.tick() {
+            self.camera.develop(&mut self.ram);
+        }
+    }
+
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.camera.set_source(source);
+    }
+
+    fn battery(&self) -> bool { true }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(PocketCamera::RAM_SIZE);
+        self.ram[.. len].copy_from_slice(&data[.. len]);
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        let ram_bank = self.ram_bank | if self.camera_select { 0x10 } else { 0 };
+        vec![(0x0000, if self.ram_enable { 0xA } else { 0 }), (0x2000, self.bank), (0x4000, ram_bank)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.ram_enable as u8, self.bank, self.ram_bank, self.camera_select as u8]);
+        self.camera.save_state(w);
+        w.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.ram_enable = r.read_bool()?;
+        self.bank = r.read_u8()?&0x3F;
+        self.ram_bank = r.read_u8()?&0xF;
+        self.camera_select = r.read_bool()?;
+        self.camera.load_state(r)?;
+        r.read_bytes_into(&mut self.ram)
+    }
+}
+
+
+// Hudson HuC1. 0000-1FFF picks between cart ram and the infrared port (0x0E),
+// there's no separate ram enable. Nothing ever shines at the IR sensor
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ir_mode: bool,
+    bank: u8,
+    ram_bank: u8,
+    rom_mask: usize
+}
+
+impl HuC1 {
+    pub fn new(data: Vec<u8>) -> Result<Box<HuC1>, EmulatorError> {
+        let ram_s = battery_ram_size(ram_size(data[0x149])?, true);
+        let rom_s = rom_size(data[0x148])?;
+
+        if ram_s > MBC1::MAX_RAM_SIZE {
+            return Err(EmulatorError::RamTooBig("HuC1"))
+        }
+        if rom_s != data.len() {
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
+        }
+
+        Ok(Box::new(HuC1 {
+            rom_mask: bank_mask(data.len(), 0x4000),
+            rom: data,
+            ram: vec![0; ram_s],
+            ir_mode: false,
+            bank: 1,
+            ram_bank: 0
+        }))
+    }
+}
+
+impl MemoryBankController for HuC1 {
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => self.rom[addr as usize],
+            0x4000 ..= 0x7FFF => self.rom[(addr as usize&0x3FFF) + (self.bank as usize & self.rom_mask)*0x4000],
+            _ => 0xFF
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ir_mode = val&0xF == 0xE,
+            0x2000 ..= 0x3FFF => self.bank = val&0x3F,
+            0x4000 ..= 0x5FFF => self.ram_bank = val&0x3,
+            _ => ()
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ir_mode || !self.ram.is_empty()
+    }
+
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        if self.ir_mode {
+            return 0xC0  // no light
+        }
+        self.ram[(addr as usize + self.ram_bank as usize*0x2000) % self.ram.len()]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ir_mode {  // IR writes only switch the LED
+            let addr = (addr as usize + self.ram_bank as usize*0x2000) % self.ram.len();
+            self.ram[addr] = val;
+        }
+    }
+
+    fn battery(&self) -> bool { true }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        load_battery_ram(&mut self.ram, data, MBC1::MAX_RAM_SIZE)
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, if self.ir_mode { 0xE } else { 0 }), (0x2000, self.bank), (0x4000, self.ram_bank)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.ir_mode as u8, self.bank, self.ram_bank]);
+        w.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.ir_mode = r.read_bool()?;
+        self.bank = r.read_u8()?&0x3F;
+        self.ram_bank = r.read_u8()?&0x3;
+        load_state_ram(&mut self.ram, r.read_bytes()?, MBC1::MAX_RAM_SIZE)
+    }
+}
+
+
+// Hudson HuC3. The low nibble written to 0000-1FFF picks what A000-BFFF is:
+// 0 ram read only, A ram, B clock command, C clock response, D clock semaphore, E infrared
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mode: u8,
+    bank: u8,
+    ram_bank: u8,
+    rom_mask: usize,
+    rtc: Huc3Rtc
+}
+
+impl HuC3 {
+    pub fn new(data: Vec<u8>) -> Result<Box<HuC3>, EmulatorError> {
+        let ram_s = battery_ram_size(ram_size(data[0x149])?, true);
+        let rom_s = rom_size(data[0x148])?;
+
+        if ram_s > MBC1::MAX_RAM_SIZE {
+            return Err(EmulatorError::RamTooBig("HuC3"))
+        }
+        if rom_s != data.len() {
+            return Err(EmulatorError::RomSizeMismatch { header: rom_s, actual: data.len() })
+        }
+
+        Ok(Box::new(HuC3 {
+            rom_mask: bank_mask(data.len(), 0x4000),
+            rom: data,
+            ram: vec![0; ram_s],
+            mode: 0,
+            bank: 1,
+            ram_bank: 0,
+            rtc: Huc3Rtc::new(RtcClock::new(RtcSource::Host, 0))
+        }))
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (addr as usize + self.ram_bank as usize*0x2000) % self.ram.len()
+    }
+}
+
+impl MemoryBankController for HuC3 {
+    fn read_rom(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => self.rom[addr as usize],
+            0x4000 ..= 0x7FFF => self.rom[(addr as usize&0x3FFF) + (self.bank as usize & self.rom_mask)*0x4000],
+            _ => 0xFF
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.mode = val&0xF,
+            0x2000 ..= 0x3FFF => self.bank = val&0x7F,
+            0x4000 ..= 0x5FFF => self.ram_bank = val&0x3,
+            _ => ()
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        true
+    }
+
+    fn read_ram(&mut self, addr: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA => self.ram[self.ram_addr(addr)],
+            0xC => self.rtc.read_response(),
+            0xD => 0xFF,  // commands finish at once, always ready
+            0xE => 0xC0,  // no light
+            _ => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        match self.mode {
+            0xA => {
+                let addr = self.ram_addr(addr);
+                self.ram[addr] = val;
+            },
+            0xB => self.rtc.write_command(val),
+            _ => ()
+        }
+    }
+
+    fn tick(&mut self) {
+        self.rtc.tick();
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc.set_clock(clock);
+    }
+
+    fn battery(&self) -> bool { true }
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+    fn load_ram(&mut self, data: &[u8]) {
+        load_battery_ram(&mut self.ram, data, MBC1::MAX_RAM_SIZE)
+    }
+
+    fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_SIZE]> {
+        Some(self.rtc.footer())
+    }
+
+    fn load_rtc_footer(&mut self, data: &[u8]) {
+        self.rtc.load_footer(data);
+    }
+
+    fn register_writes(&self) -> Vec<(u16, u8)> {
+        vec![(0x0000, self.mode), (0x2000, self.bank), (0x4000, self.ram_bank)]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&[self.mode, self.bank, self.ram_bank]);
+        w.write_bytes(&self.ram);
+        self.rtc.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.mode = r.read_u8()?&0xF;
+        self.bank = r.read_u8()?&0x7F;
+        self.ram_bank = r.read_u8()?&0x3;
+        load_state_ram(&mut self.ram, r.read_bytes()?, MBC1::MAX_RAM_SIZE)?;
+        self.rtc.load_state(r)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 32KB rom, the header is all the controllers look at
+    fn rom(cart_type: u8, ram: u8) -> Vec<u8> {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = cart_type;
+        data[0x149] = ram;
+        data
+    }
+
+    // what the memory bus sees at A000-BFFF, see Cartridge::read_ram
+    fn read(mbc: &mut dyn MemoryBankController, addr: u16) -> u8 {
+        if mbc.ram_enabled() { mbc.read_ram(addr) } else { 0xFF }
+    }
+
+    // rom_code from 0x148, every 16KB bank starts with its number
+    fn banked_rom(cart_type: u8, rom_code: u8, ram: u8) -> Vec<u8> {
+        let mut data = vec![0; 0x8000 << rom_code];
+        for (bank, b) in data.chunks_mut(0x4000).enumerate() {
+            b[0] = bank as u8;
+        }
+        data[0x147] = cart_type;
+        data[0x148] = rom_code;
+        data[0x149] = ram;
+        data
+    }
+
+    #[test]
+    fn mbc1_mode1_maps_upper_bits_to_bank0() {
+        let mut mbc = MBC1::new(banked_rom(0x01, 0x05, 0x00)).unwrap();  // 1MB, 64 banks
+        mbc.write_rom(0x2000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        // bank 0x20 can't be mapped at 4000, the zero check sees the low 5 bits only
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn mbc1_small_rom_ignores_upper_bits() {
+        let mut mbc = MBC1::new(banked_rom(0x01, 0x03, 0x00)).unwrap();  // 256KB, 16 banks
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x13);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x03);
+    }
+
+    #[test]
+    fn mbc1_ram_banks_in_mode1_only() {
+        let mut mbc = MBC1::new(rom(0x03, 0x03)).unwrap();  // 32KB ram
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0 .. 4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0x0000, 0x10 + bank);
+        }
+        // mode 0 only ever sees bank 0, which took every write
+        assert_eq!(read(mbc.as_mut(), 0x0000), 0x13);
+        assert_eq!(&mbc.ram()[.. 1], &[0x13]);
+
+        mbc.write_rom(0x6000, 0x01);
+        for bank in 0 .. 4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0x0000, 0x20 + bank);
+        }
+        for bank in 0 .. 4 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(read(mbc.as_mut(), 0x0000), 0x20 + bank);
+            assert_eq!(mbc.ram()[bank as usize * 0x2000], 0x20 + bank);
+        }
+    }
+
+    #[test]
+    fn mbc1_8kb_ram_ignores_bank_bits() {
+        let mut mbc = MBC1::new(rom(0x03, 0x02)).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_ram(0x1234, 0x77);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(read(mbc.as_mut(), 0x1234), 0x77);
+    }
+
+    #[test]
+    fn mbc5_banks_all_of_128kb_ram() {
+        let mut mbc = MBC5::new(rom(0x1B, 0x04)).unwrap();
+        assert_eq!(mbc.ram_banks(), 16);
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0 .. 16 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0x0000, bank);
+        }
+        for bank in 0 .. 16 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(read(mbc.as_mut(), 0x0000), bank);
+            assert_eq!(mbc.peek_ram(bank as usize, 0x0000), bank);
+        }
+
+        let save = vec![0x33; 0x20000];
+        mbc.load_ram(&save);
+        assert_eq!(mbc.ram(), &save[..]);
+        assert!(MBC5::new(rom(0x1A, 0x05)).is_ok());
+    }
+
+    #[test]
+    fn mbc2_reads_back_nibbles() {
+        let mut mbc = MBC2::new(rom(0x06, 0x00)).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0xAB);
+        assert_eq!(read(mbc.as_mut(), 0x0000), 0xFB);
+        assert_eq!(mbc.ram()[0], 0x0B);
+    }
+
+    #[test]
+    fn mbc2_ram_echoes() {
+        let mut mbc = MBC2::new(rom(0x06, 0x00)).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        for addr in 0 .. 0x200 {
+            mbc.write_ram(addr, addr as u8 ^ 0x5);
+        }
+        for addr in 0 .. 0x2000u16 {
+            assert_eq!(read(mbc.as_mut(), addr), (addr as u8 ^ 0x5) | 0xF0, "at {:04X}", 0xA000 + addr);
+        }
+    }
+
+    #[test]
+    fn battery_without_ram_size_persists() {
+        let save: Vec<u8> = (0 .. 0x2000).map(|i| i as u8).collect();
+        let carts: [Box<dyn MemoryBankController>; 3] = [
+            MBC1::new(rom(0x03, 0x00)).unwrap(),
+            MBC3::new(rom(0x10, 0x00)).unwrap(),
+            MBC5::new(rom(0x1B, 0x00)).unwrap()
+        ];
+        for mut mbc in carts {
+            assert!(mbc.battery());
+            assert_eq!(mbc.ram().len(), 0x2000);
+
+            mbc.write_rom(0x0000, 0x0A);
+            mbc.write_ram(0x0010, 0x42);
+            assert_eq!(mbc.ram()[0x10], 0x42);
+
+            mbc.load_ram(&save);
+            assert_eq!(mbc.ram(), &save[..]);
+            assert_eq!(read(mbc.as_mut(), 0x0010), 0x10);
+        }
+    }
+
+    // name, controller, the writes enabling ram, what A000-BFFF reads before and after them
+    type RamCase = (&'static str, Box<dyn MemoryBankController>, &'static [(u16, u8)], u8, Option<u8>);
+
+    // With the ram filled with 0x5A. None where the range isn't plain ram once enabled.
+    // HuC1 and HuC3 have no ram enable, it's always mapped
+    #[test]
+    fn disabled_or_absent_ram_reads_ff() {
+        let enable: &[(u16, u8)] = &[(0x0000, 0x0A)];
+        let carts: Vec<RamCase> = vec![
+            ("dummy", dummyMBC::new(rom(0x00, 0x00)), enable, 0xFF, Some(0xFF)),
+            ("no MBC", noMBC::new(rom(0x00, 0x00)), enable, 0xFF, Some(0xFF)),
+            ("MBC1", MBC1::new(rom(0x02, 0x02)).unwrap(), enable, 0xFF, Some(0x5A)),
+            ("MBC1 without ram", MBC1::new(rom(0x01, 0x00)).unwrap(), enable, 0xFF, Some(0xFF)),
+            ("MBC2", MBC2::new(rom(0x05, 0x00)).unwrap(), enable, 0xFF, Some(0xFA)),
+            ("MBC3", MBC3::new(rom(0x12, 0x03)).unwrap(), enable, 0xFF, Some(0x5A)),
+            ("MBC3 without ram", MBC3::new(rom(0x11, 0x00)).unwrap(), enable, 0xFF, Some(0xFF)),
+            ("MBC5", MBC5::new(rom(0x1A, 0x02)).unwrap(), enable, 0xFF, Some(0x5A)),
+            ("MBC5 without ram", MBC5::new(rom(0x19, 0x00)).unwrap(), enable, 0xFF, Some(0xFF)),
+            ("MBC7", MBC7::new(rom(0x22, 0x00)).unwrap(), &[(0x0000, 0x0A), (0x4000, 0x40)], 0xFF, None),
+            ("HuC1", HuC1::new(rom(0xFF, 0x02)).unwrap(), enable, 0x5A, Some(0x5A)),
+            ("HuC3", HuC3::new(rom(0xFE, 0x02)).unwrap(), enable, 0x5A, Some(0x5A))
+        ];
+
+        for (name, mut mbc, writes, before, after) in carts {
+            let fill = vec![0x5A; mbc.ram().len()];
+            mbc.load_ram(&fill);
+            for addr in 0 .. 0x2000 {
+                assert_eq!(read(mbc.as_mut(), addr), before, "{} before enabling at {:04X}", name, 0xA000 + addr);
+            }
+            for &(addr, val) in writes {
+                mbc.write_rom(addr, val);
+            }
+            if let Some(after) = after {
+                for addr in 0 .. 0x2000 {
+                    assert_eq!(read(mbc.as_mut(), addr), after, "{} enabled at {:04X}", name, 0xA000 + addr);
+                }
+            }
+        }
+    }
+}
@@ -1,19 +1,54 @@
 #![allow(non_snake_case)]
 
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::io::prelude::*;
-use std::fs::File;
-use std::path::Path;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
 use std::error::Error;
 
-use crate::emulator::{mbc, PPU, APU, MODE, PPU_MODE};
+use crate::emulator::{mbc, EmulatorError, CartridgeInfo, RomIntegrity, PPU, APU, MODE, PPU_MODE, BusQuirks, PowerLoss, RamInit, CameraSource, RtcClock, StateHasher, StateWriter, StateReader, SerialDevice, Event, EventBus, RTC_FOOTER_SIZE, io_register};
+#[cfg(feature = "std")]
+use crate::emulator::RTC_FOOTER_SIZE_SHORT;
+#[cfg(feature = "debugger")]
+use crate::emulator::{IoLog, IoWrite, IrqLatency};
 
 const TIMA_SPEED: [u16; 4] = [512, 8, 32, 128];
+const SPEED_SWITCH_CYCLES: u16 = 2050*4;  // the clock stays off this long after a speed switch
+const STOPPED_FRAME_CYCLES: u32 = 70224;  // how often a frame is handed out while stopped
+
+fn fnv1a(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C9DC5;
+
+    for b in data.iter() {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+
+    hash
+}
 
 pub struct Cartridge {
     rom: Box<dyn mbc::MemoryBankController>,
     pub bootrom: Vec<u8>,
     pub bootrom_enable: bool,
     pub title: String,
+    pub header_hash: u32,  // identifies the game for per game files
+    pub cgb_flag: u8,  // 0x143, 0x80 - runs on both models, 0xC0 - CGB only
+    pub header: [u8; 0x50],  // 0x100 - 0x14F as loaded
+    info: CartridgeInfo,
+    integrity: RomIntegrity,
+    pub lenient: bool,  // load roms with a bad header anyway, see interprete_header
+    pub verify_global_checksum: bool,  // a bad one fails the load like the header checksum does
+    pub warnings: Vec<&'static str>,  // header problems skipped over by a lenient load
+    pub power_loss: Option<PowerLoss>,  // keeps recent ram writes around to drop them, see pull_power
+    #[cfg(feature = "std")]
+    save_path: Option<PathBuf>,
     pub gb_cart_type: MODE
 }
 
@@ -24,6 +59,17 @@ impl Cartridge {
             bootrom: vec![],
             bootrom_enable: false,
             title: String::new(),
+            header_hash: 0,
+            cgb_flag: 0,
+            header: [0; 0x50],
+            info: CartridgeInfo::default(),
+            integrity: RomIntegrity::default(),
+            lenient: false,
+            verify_global_checksum: false,
+            warnings: vec![],
+            power_loss: None,
+            #[cfg(feature = "std")]
+            save_path: None,
             gb_cart_type: MODE::DMG
         }
     }
@@ -38,23 +84,204 @@ impl Cartridge {
         self.rom.write_rom(addr, val)
     }
 
+    // disabled or absent ram isn't driving the bus
     #[inline]
-    fn read_ram(&mut self, addr: u16) -> u8 {
-        self.rom.read_ram(addr)
+    fn read_ram(&mut self, addr: u16, open_bus: u8) -> u8 {
+        if self.rom.ram_enabled() {
+            self.rom.read_ram(addr)
+        } else { open_bus }
     }
 
     #[inline]
     fn write_ram(&mut self, addr: u16, val: u8) {
-        self.rom.write_ram(addr, val)
+        if self.rom.ram_enabled() {
+            self.rom.write_ram(addr, val)
+        }
+    }
+
+    #[inline]
+    fn tick(&mut self) {
+        self.rom.tick()
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        self.rom.ram()
+    }
+
+    // cart ram as the game would see it with the bank mapped, without touching the mapper
+    pub fn ram_banks(&self) -> usize {
+        self.rom.ram_banks()
+    }
+
+    pub fn peek_ram(&self, bank: usize, offset: u16) -> u8 {
+        self.rom.peek_ram(bank, offset)
+    }
+
+    // accelerometer of MBC7 carts, -1.0 - 1.0 per axis, ignored by everything else
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.rom.set_tilt(x, y)
+    }
+
+    // pictures for the Pocket Camera, a test image until this is called
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.rom.set_camera_source(source)
+    }
+
+    // M-cycles the rumble motor was on since the last call, games pulse it to set the strength
+    pub fn take_rumble_cycles(&mut self) -> u32 {
+        self.rom.take_rumble_cycles()
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        self.rom.ram_mut()
+    }
+
+    pub(crate) fn power_loss_frame(&mut self) {
+        if let Some(p) = &mut self.power_loss {
+            p.frame(self.rom.ram());
+        }
+    }
+
+    // the ram changed by something other than the game, not a write that can get lost
+    pub(crate) fn settle_ram(&mut self) {
+        if let Some(p) = &mut self.power_loss {
+            p.settle(self.rom.ram());
+        }
+    }
+
+    // Drops the ram writes power_loss still holds, like the cart was pulled before they reached it.
+    // The game keeps running, power it back on with this ram. Returns how many bytes went back
+    pub fn pull_power(&mut self) -> usize {
+        match &mut self.power_loss {
+            Some(p) => p.pull(self.rom.ram_mut()),
+            None => 0
+        }
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rom.set_rtc_clock(clock)
+    }
+
+    // missing save file is not an error, the game starts with cleared ram
+    #[cfg(feature = "std")]
+    pub fn load_save(&mut self, p: &Path) -> Result<(), Box<dyn Error>> {
+        self.save_path = Some(p.to_path_buf());
+        self.load_flash(p)?;
+        if !self.rom.battery() || !p.exists() {
+            return Ok(())
+        }
+
+        let data = fs::read(p)?;
+        let data = self.split_rtc_footer(&data);
+        if data.len() < self.rom.ram().len() {
+            eprintln!("{}: save is {} bytes, cartridge ram is {}, truncated? The rest is left blank",
+                p.display(), data.len(), self.rom.ram().len());
+        } else if data.len() != self.rom.ram().len() {
+            eprintln!("{}: save size {} doesn't match cartridge ram size {}", p.display(), data.len(), self.rom.ram().len());
+        }
+        self.rom.load_ram(data);
+        println!("{}: loaded {} bytes, checksum {:08x}", p.display(), self.rom.ram().len(), self.ram_checksum());
+        Ok(())
+    }
+
+    // MBC6 flash lives in its own file next to the save, name.flash
+    #[cfg(feature = "std")]
+    fn load_flash(&mut self, p: &Path) -> Result<(), Box<dyn Error>> {
+        let p = p.with_extension("flash");
+        if self.rom.flash().is_empty() || !p.exists() {
+            return Ok(())
+        }
+        let data = fs::read(&p)?;
+        if data.len() != self.rom.flash().len() {
+            eprintln!("{}: flash size {} doesn't match cartridge flash size {}", p.display(), data.len(), self.rom.flash().len());
+        }
+        self.rom.load_flash(&data);
+        println!("{}: loaded {} bytes of flash", p.display(), data.len());
+        Ok(())
     }
 
-    pub fn load_bootrom(&mut self, p: &Path) -> Result<MODE, Box<dyn Error>> {
+    // picks up a save edited on disk while the game runs
+    #[cfg(feature = "std")]
+    pub fn reload_save(&mut self, bypass_enable: bool) -> Result<(), Box<dyn Error>> {
+        let data = match &self.save_path {
+            Some(p) => fs::read(p)?,
+            None => return Err("no save file loaded".into())
+        };
+        let data = self.split_rtc_footer(&data);
+        self.apply_ram(data, bypass_enable)?;
+        Ok(())
+    }
+
+    // ram part of a save, a clock footer after it goes to the mbc
+    #[cfg(feature = "std")]
+    fn split_rtc_footer<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        if self.rom.rtc_footer().is_none() {
+            return data
+        }
+        match data.len().checked_sub(self.rom.ram().len()) {
+            Some(n) if n == RTC_FOOTER_SIZE || n == RTC_FOOTER_SIZE_SHORT => {
+                let (ram, footer) = data.split_at(self.rom.ram().len());
+                self.rom.load_rtc_footer(footer);
+                ram
+            },
+            _ => data
+        }
+    }
+
+    // replaces cart ram with an edited buffer, like a save editor would.
+    // Unless bypassed the game has to have ram enabled, same as for its own writes
+    pub fn apply_ram(&mut self, data: &[u8], bypass_enable: bool) -> Result<(), &'static str> {
+        if !bypass_enable && !self.rom.ram_enabled() {
+            return Err("cartridge ram is disabled")
+        }
+        if self.rom.ram().is_empty() {
+            return Err("cartridge has no ram")
+        }
+        self.rom.load_ram(data);
+        self.settle_ram();
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_save(&self, p: &Path) -> Result<(), Box<dyn Error>> {
+        if self.rom.battery() {
+            let mut data = self.rom.ram().to_vec();
+            if let Some(footer) = self.rom.rtc_footer() {
+                data.extend_from_slice(&footer);
+            }
+            fs::write(p, &data)?;
+
+            // read it back, a short or garbled write would otherwise show up on the next load
+            let written = fs::read(p)?;
+            if written != data {
+                return Err(format!("{} doesn't read back as written ({} of {} bytes)", p.display(), written.len(), data.len()).into())
+            }
+            println!("{}: wrote {} bytes, checksum {:08x}", p.display(), self.rom.ram().len(), self.ram_checksum());
+        }
+        if !self.rom.flash().is_empty() {
+            let p = p.with_extension("flash");
+            fs::write(&p, self.rom.flash())?;
+            println!("{}: wrote {} bytes of flash", p.display(), self.rom.flash().len());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_bootrom(&mut self, p: &Path) -> Result<MODE, EmulatorError> {
         let mut file = File::open(p)?;
         let mut data: Vec<u8> = vec![];
         file.read_to_end(&mut data)?;
-        
+
+        self.load_bootrom_from_bytes(data)
+    }
+
+    pub fn load_bootrom_from_bytes(&mut self, data: Vec<u8>) -> Result<MODE, EmulatorError> {
         if data.len() != 0x100 && data.len() != 0x900 {
-            panic!("Invalid bootrom");
+            return Err(EmulatorError::InvalidBootrom(data.len()))
+        }
+        #[cfg(not(feature = "cgb"))]
+        if data.len() == 0x900 {
+            return Err(EmulatorError::NoCgbSupport)
         }
         self.bootrom = data;
         self.bootrom_enable = true;
@@ -70,25 +297,52 @@ impl Cartridge {
         self.rom = mbc::dummyMBC::new(v)
     }
 
-    pub fn load_from_file(&mut self, p: &Path) -> Result<MODE, Box<dyn Error>> {
+    #[cfg(feature = "std")]
+    pub fn load_from_file(&mut self, p: &Path) -> Result<RomIntegrity, EmulatorError> {
         let mut file = File::open(p)?;
         let mut data: Vec<u8> = vec![];
         file.read_to_end(&mut data)?;
 
+        self.load_from_bytes(data)
+    }
+
+    // the model it runs on ends up in gb_cart_type
+    pub fn load_from_bytes(&mut self, data: Vec<u8>) -> Result<RomIntegrity, EmulatorError> {
         let mode = self.interprete_header(data)?;
         self.gb_cart_type = mode;
 
-        Ok(mode)
+        Ok(self.integrity)
     }
 
-    fn interprete_header(&mut self, data: Vec<u8>) -> Result<MODE, &str> {
+    // homebrew and hacked roms often have a wrong checksum or rom size, when lenient those are
+    // warnings: the rom gets padded to the size in the header byte, unknown mappers run as no MBC
+    fn interprete_header(&mut self, mut data: Vec<u8>) -> Result<MODE, EmulatorError> {
+        self.warnings.clear();
         if data.len() > 0x14F {
+            let integrity = RomIntegrity::new(&data);
             if data[0x014D] != Cartridge::calculate_header_checksum(&data) {
-                return Err(&"Invalid ROM header checksum")
+                if !self.lenient {
+                    return Err(EmulatorError::InvalidChecksum)
+                }
+                self.warnings.push("invalid header checksum");
+            }
+            if self.verify_global_checksum && !integrity.global_checksum {
+                if !self.lenient {
+                    return Err(EmulatorError::InvalidGlobalChecksum)
+                }
+                self.warnings.push("invalid global checksum");
             }
 
             let cgb_mode = data[0x143];
             self.title = Cartridge::get_title(&data);
+            self.header_hash = Cartridge::hash_header(&data);
+            self.cgb_flag = cgb_mode;
+            self.header.copy_from_slice(&data[0x100 .. 0x150]);
+            self.info = CartridgeInfo::new(&data);
+            self.integrity = integrity;
+            if self.lenient {
+                Cartridge::fix_rom_size(&mut data, &mut self.warnings);
+            }
             match data[0x147] {
                 0x00 => {
                     self.rom = mbc::noMBC::new(data);
@@ -104,20 +358,51 @@ impl Cartridge {
                 },
                 0x19 ..= 0x1E => {
                     self.rom = mbc::MBC5::new(data)?;
+                },
+                0x20 => {
+                    self.rom = mbc::MBC6::new(data)?;
+                },
+                0x22 => {
+                    self.rom = mbc::MBC7::new(data)?;
+                },
+                0xFC => {
+                    self.rom = mbc::PocketCamera::new(data)?;
+                },
+                0xFE => {
+                    self.rom = mbc::HuC3::new(data)?;
+                },
+                0xFF => {
+                    self.rom = mbc::HuC1::new(data)?;
                 }
-                _ => panic!("{:x} - unsupported cartridge type", data[0x147])
+                _ if self.lenient => {
+                    self.warnings.push("unsupported cartridge type, running without a mapper");
+                    self.rom = mbc::noMBC::new(data);
+                },
+                t => return Err(EmulatorError::UnsupportedMapper(t))
             };
 
-            if cgb_mode == 0x80 || cgb_mode == 0xC0 {
+            if cfg!(not(feature = "cgb")) && cgb_mode == 0xC0 {
+                Err(EmulatorError::NoCgbSupport)
+            } else if cfg!(feature = "cgb") && (cgb_mode == 0x80 || cgb_mode == 0xC0) {
                 Ok(MODE::CGB)
             } else {
                 Ok(MODE::DMG)
             }
         } else {
-            Err(&"ROM too small")
+            Err(EmulatorError::RomTooSmall)
         }
     }
 
+    // what the header says, for rom info screens and picking a model without reading the file again
+    pub fn info(&self) -> &CartridgeInfo {
+        &self.info
+    }
+
+    // checksums, logo and size of the rom as loaded, before a lenient load fixed anything
+    pub fn integrity(&self) -> RomIntegrity {
+        self.integrity
+    }
+
     fn get_title(data: &Vec<u8>) -> String {
         let mut t = String::new();
         for i in 0x134 ..= 0x13E {
@@ -127,6 +412,49 @@ impl Cartridge {
         t
     }
 
+    // FNV-1a over the whole header, titles alone collide between revisions
+    fn hash_header(data: &[u8]) -> u32 {
+        fnv1a(&data[0x100 ..= 0x14F])
+    }
+
+    pub fn register_writes(&self) -> Vec<(u16, u8)> {
+        self.rom.register_writes()
+    }
+
+    pub fn rtc_footer(&self) -> Option<[u8; RTC_FOOTER_SIZE]> {
+        self.rom.rtc_footer()
+    }
+
+    pub fn load_rtc_footer(&mut self, data: &[u8]) {
+        self.rom.load_rtc_footer(data)
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.bootrom_enable);
+        self.rom.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.bootrom_enable = r.read_bool()? && !self.bootrom.is_empty();
+        self.rom.load_state(r)
+    }
+
+    // of cart ram, printed when the save is loaded and written
+    pub fn ram_checksum(&self) -> u32 {
+        fnv1a(self.rom.ram())
+    }
+
+    // pads to a power of two with 0xFF and makes the header agree
+    fn fix_rom_size(data: &mut Vec<u8>, warnings: &mut Vec<&'static str>) {
+        let size = data.len().max(0x8000).next_power_of_two();
+        let code = (size / 0x8000).trailing_zeros() as u8;
+        if data[0x148] != code || data.len() != size {
+            warnings.push("rom size doesn't match the header");
+            data.resize(size, 0xFF);
+            data[0x148] = code;
+        }
+    }
+
     fn calculate_header_checksum(data: &Vec<u8>) -> u8 {
         let mut sum: u8 = 0;
 
@@ -138,26 +466,58 @@ impl Cartridge {
     }
 }
 
+#[cfg(feature = "cgb")]
+pub const VRAM_BANKS: usize = 2;
+#[cfg(not(feature = "cgb"))]
+pub const VRAM_BANKS: usize = 1;
+#[cfg(feature = "cgb")]
+pub const WRAM_BANKS: usize = 8;
+#[cfg(not(feature = "cgb"))]
+pub const WRAM_BANKS: usize = 2;
+
 pub struct Memory {
     pub cart: Cartridge,  // ROM -> 0x0000-0x7FFF 32kB, RAM -> 0xA000-0xBFFF 8kB
     pub ppu: PPU,
-    apu: APU,
+    pub apu: APU,
     pub mode: MODE,
 
-    vram: [u8; 16*1024],  // 0x8000 - 0x9FFF 16kB (2 banks in cgb)
-    ram: [u8; 32*1024], // 0xC000 - 0xDFFF 32kB (8 banks in cgb) + echo at 0xE000 - 0xFDFF
-    OAM: [u8; 160],  // 0xFE00 - 0xFE9F sprite attribute memory
+    pub vram: [u8; VRAM_BANKS*0x2000],  // 0x8000 - 0x9FFF 16kB (2 banks in cgb)
+    ram: [u8; WRAM_BANKS*0x1000], // 0xC000 - 0xDFFF 32kB (8 banks in cgb) + echo at 0xE000 - 0xFDFF
+    pub OAM: [u8; 160],  // 0xFE00 - 0xFE9F sprite attribute memory
     hram: [u8; 127],  // 0xFF80 - 0xFFFE high ram
+    unusable: [u8; 0x30],  // 0xFEA0 - 0xFEFF on a CGB, FEC0-FEFF is one 16 byte row mirrored 4 times
+    data_bus: u8,  // the last byte read or written on the cartridge bus, what open bus reads see
+    ram_init: RamInit,  // what vram, wram and hram were filled with at power on
     pub IF: u8,  // interrupt flag 0xFF0F
     pub IER: u8,  // interrupt enable register 0xFFFF
     vram_bank: u8,
     ram_bank: u8,
+    undocumented: [u8; 4],  // FF72-FF75 on a CGB, nothing uses them but hardware checks read them back
+
+    // KEY1, the cpu, timers, serial and OAM DMA run at twice the clock of the ppu and apu in double speed
+    pub double_speed: bool,
+    speed_switch: bool,  // armed with bit 0, STOP then switches
+    slow_cycle: bool,  // in double speed the ppu and apu only tick every other cycle
+    pub(crate) clock: u64,  // time run in 8MiHz cycles, a double speed tick is one and others two. Not saved, only compared between linked instances
+    stopped: bool,  // STOP mode, the clock is off and only the joypad (or stop_timeout) starts it again
+    stop_timeout: u16,  // cycles until a speed switch's stop ends by itself, 0 - waits for the joypad
+    stop_clock: u32,  // counts towards the next frame while stopped, so the frontend still gets input in
+    bootrom_palettes: bool,  // a dumped CGB bootrom picks the palettes of a dmg game, kept when it hands over
 
     vdma_src: u16,
     vdma_dst: u16,
     hdma5: u8,
     hdma_active: bool,
     hdma_length: u8,
+    hdma_copy: u16,  // 4MHz cycles left in the running block, 2 a byte whatever the speed, the cpu waits meanwhile
+    hdma_offset: u16,  // of the next byte from vdma_src and vdma_dst
+    hdma_general: bool,  // the running block is a GDMA, the whole length at once
+
+    oam_dma_src: u16,
+    oam_dma_cycles: u16,  // 0 when inactive, counts down from 640 (160 M-cycles, a byte per M-cycle)
+    oam_dma_start: u8,  // cycles until a written transfer starts, a running one carries on until then
+    oam_dma_next: u16,  // its source
+    pub bus: BusQuirks,
 
     // timer registers
     DIV: u16,  // FF04
@@ -169,15 +529,37 @@ pub struct Memory {
 
     serial_control: u8,
     serial_transfer: u8,
-    serial_count_interrupt: u8,
+    serial_count_interrupt: u8,  // bits left in the running transfer
+    serial_out: u8,  // byte being sent, handed to the serial device when the transfer finishes
+    serial_device: Option<Box<dyn SerialDevice>>,  // None - nothing plugged in, 0xFF is received
+
+    pub events: EventBus,
+
+    #[cfg(feature = "debugger")]
+    pub watchpoints: Vec<u16>,  // addresses that stop the debugger when written
+    #[cfg(feature = "debugger")]
+    pub watch_hit: Option<u16>,
+    #[cfg(feature = "debugger")]
+    pub io_log: IoLog,
+    #[cfg(feature = "debugger")]
+    pub pc: u16,  // address of the executing instruction, kept up to date only while io_log is enabled
+    #[cfg(feature = "debugger")]
+    pub irq_latency: IrqLatency,
+    #[cfg(feature = "debugger")]
+    pub vram_dirty: Vec<bool>,  // one flag per 16 bytes of vram written since the debugger last cleared it
+    #[cfg(feature = "debugger")]
+    pub io_touched: [bool; 0x100],  // FF00-FFFF accessed by the cpu, indexed by the low byte
 
     input_select: u8,
+    // input per frame - 0 is pressed
+    in_button: u8,     // p15 5th bit
+    in_direction: u8,  // p14 4th bit
 }
 
 impl Memory {
     pub fn new() -> Memory {
         let ppu = PPU::new();
-        let apu = APU::new(&ppu.d.thread);
+        let apu = APU::new();
 
         Memory {
             cart: Cartridge::new(),
@@ -185,20 +567,42 @@ impl Memory {
             apu: apu,
             mode: MODE::DMG,
 
-            vram: [0; 16*1024],
-            ram: [0; 32*1024],
+            vram: [0; VRAM_BANKS*0x2000],
+            ram: [0; WRAM_BANKS*0x1000],
             OAM: [0; 160],
             hram: [0; 127],
+            unusable: [0; 0x30],
+            data_bus: 0xFF,
+            undocumented: [0; 4],
+            ram_init: RamInit::Zero,
             IF: 0b11100000,
             IER: 0b11100000,
             ram_bank: 1,
             vram_bank: 0,
 
+            double_speed: false,
+            speed_switch: false,
+            stopped: false,
+            stop_timeout: 0,
+            stop_clock: 0,
+            slow_cycle: false,
+            clock: 0,
+            bootrom_palettes: false,
+
             vdma_src: 0,
             vdma_dst: 0,
             hdma5: 0,
             hdma_active: false,
             hdma_length: 0,
+            hdma_copy: 0,
+            hdma_offset: 0,
+            hdma_general: false,
+
+            oam_dma_src: 0,
+            oam_dma_cycles: 0,
+            oam_dma_start: 0,
+            oam_dma_next: 0,
+            bus: BusQuirks::new(),
 
             DIV: 0,
             TIMA: 0,
@@ -210,25 +614,441 @@ impl Memory {
             serial_control: 0b01111110,
             serial_transfer: 0xFF,
             serial_count_interrupt: 0,
+            serial_out: 0xFF,
+            serial_device: None,
+
+            events: EventBus::new(),
+
+            #[cfg(feature = "debugger")]
+            watchpoints: vec![],
+            #[cfg(feature = "debugger")]
+            watch_hit: None,
+            #[cfg(feature = "debugger")]
+            io_log: IoLog::new(4096),
+            #[cfg(feature = "debugger")]
+            pc: 0,
+            #[cfg(feature = "debugger")]
+            irq_latency: IrqLatency::new(),
+            #[cfg(feature = "debugger")]
+            vram_dirty: vec![true; VRAM_BANKS*0x200],
+            #[cfg(feature = "debugger")]
+            io_touched: [false; 0x100],
 
             input_select: 0,
+            in_button: 0xF,
+            in_direction: 0xF,
         }
     }
 
-    pub fn load_bootrom(&mut self, p: &Path) -> Result<(), Box<dyn Error>> {
-        self.mode = self.cart.load_bootrom(p)?;
-        self.ppu.gb_mode = self.mode;
+    #[cfg(feature = "std")]
+    pub fn load_bootrom(&mut self, p: &Path) -> Result<(), EmulatorError> {
+        self.load_bootrom_from_bytes(fs::read(p)?)
+    }
+
+    // call after loading the rom, a bootrom for the wrong model is refused and nothing changes
+    pub fn load_bootrom_from_bytes(&mut self, data: Vec<u8>) -> Result<(), EmulatorError> {
+        self.check_bootrom(data.len())?;
+        self.bootrom_palettes = data.len() == 0x900;
+        let mode = self.cart.load_bootrom_from_bytes(data)?;
+        self.set_bootrom_mode(mode);
+        Ok(())
+    }
+
+    // A DMG bootrom can't start a CGB only game, it'd sit on a blank screen. A CGB one is fine with
+    // dmg games, it drops them to compatibility mode, but not with a dual mode one set_model made a DMG
+    fn check_bootrom(&self, len: usize) -> Result<(), EmulatorError> {
+        if len == 0x100 && self.cart.cgb_flag == 0xC0 {
+            return Err(EmulatorError::BootromMismatch(MODE::DMG))
+        }
+        if len == 0x900 && self.mode != MODE::CGB && self.cart.gb_cart_type == MODE::CGB {
+            return Err(EmulatorError::BootromMismatch(MODE::CGB))
+        }
         Ok(())
     }
 
-    pub fn load_rom(&mut self, p: &Path) -> Result<(), Box<dyn Error>> {
-        self.mode = self.cart.load_from_file(p)?;
+    // a DMG sized bootrom keeps a DMG0 picked with set_model, the two have the same size
+    fn set_bootrom_mode(&mut self, mode: MODE) {
+        if !(mode == MODE::DMG && self.mode == MODE::DMG0) {
+            self.mode = mode;
+            self.ppu.gb_mode = mode;
+            self.apu.set_model(mode);
+        }
+    }
+
+    // the built-in replacement for the current model, see free_bootrom
+    #[cfg(feature = "free_bootrom")]
+    pub fn load_free_bootrom(&mut self) -> Result<(), EmulatorError> {
+        self.load_bootrom_from_bytes(crate::emulator::free_bootrom(self.mode))?;
+        self.bootrom_palettes = false;  // it only sets up grays for its logo
+        Ok(())
+    }
+
+    // plugs a device into the link port, replacing the previous one
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial_device = Some(device);
+    }
+
+    // time run in 8MiHz cycles, for keeping linked instances in step
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    // runs a dual mode cartridge as the other model, or a dmg one on a CGB in compatibility mode.
+    // Call before loading a bootrom and booting
+    pub fn set_model(&mut self, mode: MODE) -> Result<(), &'static str> {
+        if mode == MODE::CGB && cfg!(not(feature = "cgb")) {
+            return Err("built without cgb support")
+        }
+        if mode != MODE::CGB && self.cart.cgb_flag == 0xC0 {
+            return Err("CGB only cartridge")
+        }
+        self.mode = mode;
+        self.ppu.gb_mode = mode;
+        self.apu.set_model(mode);
+        Ok(())
+    }
+
+    // A CGB running a dmg cart drops to compatibility mode when the bootrom is done, keeping the
+    // palettes a dumped bootrom picked. Without one they stay as set_dmg_palette left them
+    pub(crate) fn enter_compat_mode(&mut self) {
+        if self.cart.gb_cart_type == MODE::CGB {
+            return
+        }
+        if self.bootrom_palettes {
+            let palette = self.ppu.compat_palette();
+            self.ppu.set_dmg_palette(palette);
+        }
+        self.mode = self.cart.gb_cart_type;
+        self.ppu.gb_mode = self.cart.gb_cart_type;
+    }
+
+    // dmg_boot.bin, dmg0_boot.bin or cgb_boot.bin from dir, matching the model. Ok(false) when there's none
+    #[cfg(feature = "std")]
+    pub fn load_bootrom_from_dir(&mut self, dir: &Path) -> Result<bool, EmulatorError> {
+        let name = match self.mode {
+            MODE::DMG => "dmg_boot.bin",
+            MODE::DMG0 => "dmg0_boot.bin",
+            MODE::CGB => "cgb_boot.bin"
+        };
+
+        let p = dir.join(name);
+        if !p.exists() {
+            return Ok(false)
+        }
+        self.load_bootrom(&p)?;
+        Ok(true)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_rom(&mut self, p: &Path) -> Result<RomIntegrity, EmulatorError> {
+        let integrity = self.cart.load_from_file(p)?;
+        self.mode = self.cart.gb_cart_type;
         self.ppu.gb_mode = self.mode;
+        self.apu.set_model(self.mode);
+        Ok(integrity)
+    }
+
+    // rom contents from the frontend, for targets without a filesystem
+    pub fn load_rom_from_bytes(&mut self, data: Vec<u8>) -> Result<RomIntegrity, EmulatorError> {
+        let integrity = self.cart.load_from_bytes(data)?;
+        self.mode = self.cart.gb_cart_type;
+        self.ppu.gb_mode = self.mode;
+        self.apu.set_model(self.mode);
+        Ok(integrity)
+    }
+
+    // io, wram, vram, oam, ppu, apu, cart
+    pub fn state_hashes(&mut self) -> [u64; 7] {
+        let mut io = StateHasher::new();
+        io.write(&[self.IF, self.IER, self.vram_bank, self.ram_bank, self.hdma5, self.hdma_active as u8, self.hdma_length]);
+        io.write_u16(self.vdma_src);
+        io.write_u16(self.vdma_dst);
+        io.write_u16(self.hdma_copy);
+        io.write_u16(self.hdma_offset);
+        io.write_u8(self.hdma_general as u8);
+        io.write_u16(self.oam_dma_src);
+        io.write_u16(self.oam_dma_cycles);
+        io.write_u8(self.oam_dma_start);
+        io.write_u16(self.oam_dma_next);
+        io.write_u16(self.DIV);
+        io.write(&[self.TIMA, self.TMA, self.TAC, self.tima_schedule as u8]);
+        io.write(&[self.serial_control, self.serial_transfer, self.serial_count_interrupt]);
+        io.write(&[self.input_select, self.in_button, self.in_direction]);
+        io.write(&[self.double_speed as u8, self.speed_switch as u8, self.slow_cycle as u8, self.stopped as u8]);
+        io.write_u16(self.stop_timeout);
+        io.write(&[self.data_bus]);
+        io.write(&self.undocumented);
+
+        let mut wram = StateHasher::new();
+        wram.write(&self.ram);
+        wram.write(&self.hram);
+        wram.write(&self.unusable);
+
+        let mut vram = StateHasher::new();
+        vram.write(&self.vram);
+
+        let mut oam = StateHasher::new();
+        oam.write(&self.OAM);
+
+        let mut ppu = StateHasher::new();
+        self.ppu.hash_state(&mut ppu);
+
+        let mut apu = StateHasher::new();
+        self.apu.hash_state(&mut apu);
+
+        let mut cart = StateHasher::new();
+        cart.write(self.cart.ram());
+
+        [io.finish(), wram.finish(), vram.finish(), oam.finish(), ppu.finish(), apu.finish(), cart.finish()]
+    }
+
+    // the link cable device, joypad state and debugger bookkeeping stay as they are
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.vram);
+        w.write_bytes(&self.ram);
+        w.write(&self.OAM);
+        w.write(&self.hram);
+        w.write(&self.unusable);
+        w.write_u8(self.data_bus);
+        w.write(&[self.IF, self.IER, self.vram_bank, self.ram_bank]);
+        w.write(&self.undocumented);
+        w.write_u16(self.vdma_src);
+        w.write_u16(self.vdma_dst);
+        w.write(&[self.hdma5, self.hdma_active as u8, self.hdma_length]);
+        w.write_u16(self.hdma_copy);
+        w.write_u16(self.hdma_offset);
+        w.write_bool(self.hdma_general);
+        w.write_u16(self.oam_dma_src);
+        w.write_u16(self.oam_dma_cycles);
+        w.write_u8(self.oam_dma_start);
+        w.write_u16(self.oam_dma_next);
+        w.write_u16(self.DIV);
+        w.write(&[self.TIMA, self.TMA, self.TAC, self.tima_schedule as u8]);
+        w.write_u16(self.last_div);
+        w.write(&[self.serial_control, self.serial_transfer, self.serial_count_interrupt, self.serial_out]);
+        w.write_u8(self.input_select);
+        w.write(&[self.double_speed as u8, self.speed_switch as u8, self.slow_cycle as u8, self.stopped as u8]);
+        w.write_u16(self.stop_timeout);
+        self.ram_init.save_state(w);
+
+        self.ppu.save_state(w);
+        // length prefixed, builds without the apu feature skip over it
+        let mut apu = StateWriter::new();
+        self.apu.save_state(&mut apu);
+        w.write_bytes(&apu.finish());
+        self.cart.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        r.read_bytes_into(&mut self.vram)?;
+        r.read_bytes_into(&mut self.ram)?;
+        r.read_into(&mut self.OAM)?;
+        r.read_into(&mut self.hram)?;
+        r.read_into(&mut self.unusable)?;
+        self.data_bus = r.read_u8()?;
+        self.IF = r.read_u8()?;
+        self.IER = r.read_u8()?;
+        self.vram_bank = r.read_u8()? & (VRAM_BANKS as u8 - 1);
+        self.ram_bank = (r.read_u8()? & (WRAM_BANKS as u8 - 1)).max(1);
+        r.read_into(&mut self.undocumented)?;
+        self.vdma_src = r.read_u16()?;
+        self.vdma_dst = r.read_u16()?;
+        self.hdma5 = r.read_u8()?;
+        self.hdma_active = r.read_bool()?;
+        self.hdma_length = r.read_u8()?;
+        self.hdma_copy = r.read_u16()?;
+        self.hdma_offset = r.read_u16()?;
+        self.hdma_general = r.read_bool()?;
+        self.oam_dma_src = r.read_u16()?;
+        self.oam_dma_cycles = r.read_u16()?;
+        self.oam_dma_start = r.read_u8()?;
+        self.oam_dma_next = r.read_u16()?;
+        self.DIV = r.read_u16()?;
+        self.TIMA = r.read_u8()?;
+        self.TMA = r.read_u8()?;
+        self.TAC = r.read_u8()?;
+        self.tima_schedule = r.read_u8()? as i8;
+        self.last_div = r.read_u16()?;
+        self.serial_control = r.read_u8()?;
+        self.serial_transfer = r.read_u8()?;
+        self.serial_count_interrupt = r.read_u8()?;
+        self.serial_out = r.read_u8()?;
+        self.input_select = r.read_u8()?;
+        self.double_speed = r.read_bool()?;
+        self.speed_switch = r.read_bool()?;
+        self.slow_cycle = r.read_bool()?;
+        self.stopped = r.read_bool()?;
+        self.stop_timeout = r.read_u16()?;
+        self.ram_init = RamInit::load_state(r)?;
+
+        self.ppu.load_state(r)?;
+        self.apu.load_state(&mut StateReader::new(r.read_bytes()?))?;
+        self.apu.samples.clear();
+        self.cart.load_state(r)?;
+
+        #[cfg(feature = "debugger")]
+        self.vram_dirty.iter_mut().for_each(|d| *d = true);
         Ok(())
     }
 
+    // before boot, the bootrom and games start from this instead of zeroed ram
+    pub fn init_ram(&mut self, init: RamInit) {
+        init.fill(&mut self.vram, 0);
+        init.fill(&mut self.ram, 1);
+        init.fill(&mut self.hram, 2);
+        self.ram_init = init;
+        #[cfg(feature = "debugger")]
+        self.vram_dirty.iter_mut().for_each(|d| *d = true);
+    }
+
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init
+    }
+
+    pub fn wram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn wram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    pub fn hram(&self) -> &[u8] {
+        &self.hram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.OAM
+    }
+
+    pub fn hram_mut(&mut self) -> &mut [u8] {
+        &mut self.hram
+    }
+
+    // FF00-FF7F from a state that only has the register values (BESS). Registers that start
+    // something (DMA, HDMA, channel triggers) are set without starting it, LY and the STAT mode
+    // bits go to the PPU as its position
+    pub fn restore_io(&mut self, io: &[u8]) {
+        for (i, val) in io.iter().enumerate().take(0x80) {
+            let addr = 0xFF00 + i as u16;
+            match addr {
+                _ if io_register(addr).is_none() => (),
+                0xFF04 => self.DIV = (*val as u16) << 8,
+                0xFF0F => self.IF = 0b11100000 | val,
+                0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => self.apu.write(addr, val&0x7F),
+                0xFF46 => self.ppu.write(addr, *val),
+                0xFF4D if self.mode == MODE::CGB => {
+                    self.double_speed = val&0x80 != 0;
+                    self.speed_switch = val&0x1 != 0;
+                },
+                0xFF44 | 0xFF69 | 0xFF6B => (),
+                0xFF50 => self.cart.bootrom_enable = *val == 0 && !self.cart.bootrom.is_empty(),
+                0xFF55 => {
+                    self.hdma5 = 0xFF;
+                    self.hdma_active = false;
+                    self.hdma_copy = 0;
+                },
+                _ => self.write(addr, *val)
+            }
+        }
+        self.last_div = self.DIV&TIMA_SPEED[self.TAC as usize&0x03];
+        self.tima_schedule = -1;
+        self.oam_dma_cycles = 0;
+        self.oam_dma_start = 0;
+        self.stopped = false;
+        self.stop_timeout = 0;
+        self.ppu.set_position(io[0x44], io[0x41]);
+    }
+
+    // reads from a given bank instead of the mapped one, for switchable vram, wram and cart ram
+    pub fn peek_bank(&mut self, addr: u16, bank: u8) -> u8 {
+        match addr {
+            0x8000 ..= 0x9FFF => self.vram[(addr as usize&0x1FFF) + (bank as usize&(VRAM_BANKS-1))*0x2000],
+            0xA000 ..= 0xBFFF => self.cart.peek_ram(bank as usize, addr - 0xA000),
+            0xD000 ..= 0xDFFF => self.ram[(addr as usize&0xFFF) + (bank as usize&(WRAM_BANKS-1))*0x1000],
+            _ => self.read(addr)
+        }
+    }
+
+    pub fn poke_bank(&mut self, addr: u16, bank: u8, val: u8) {
+        match addr {
+            0xA000 ..= 0xBFFF => {  // ignores ram enable
+                let ram = self.cart.ram_mut();
+                let pos = (addr as usize&0x1FFF) + bank as usize*0x2000;
+                if pos < ram.len() { ram[pos] = val }
+            },
+            0x8000 ..= 0x9FFF => {
+                let pos = (addr as usize&0x1FFF) + (bank as usize&(VRAM_BANKS-1))*0x2000;
+                self.vram[pos] = val;
+                #[cfg(feature = "debugger")]
+                { self.vram_dirty[pos/16] = true; }
+            },
+            0xD000 ..= 0xDFFF => self.ram[(addr as usize&0xFFF) + (bank as usize&(WRAM_BANKS-1))*0x1000] = val,
+            _ => self.write(addr, val)
+        }
+    }
+
+    // the cpu's view of the bus, see BusQuirks::dma_conflicts
     #[inline]
     pub fn read(&mut self, addr: u16) -> u8 {
+        if self.dma_conflict(addr) {
+            let i = ((640 - self.oam_dma_cycles) / 4).min(159);
+            return self.read_bus(self.oam_dma_src + i)
+        }
+        let val = self.read_bus(addr);
+        if self.on_cart_bus(addr) {
+            self.data_bus = val;
+        }
+        val
+    }
+
+    // what reads of nothing on the cartridge bus get, the last byte on it or a fixed value
+    fn open_bus(&self) -> u8 {
+        if self.bus.data_bus { self.data_bus } else { self.bus.open_bus }
+    }
+
+    fn on_cart_bus(&self, addr: u16) -> bool {
+        addr < 0xFE00 && self.dma_bus(addr) == 0
+    }
+
+    // FF51-FF55 as written, they read back FF
+    pub(crate) fn hdma_registers(&self) -> [u8; 4] {
+        [(self.vdma_src >> 8) as u8, self.vdma_src as u8, (self.vdma_dst >> 8) as u8, self.vdma_dst as u8]
+    }
+
+    // the running transfer holds the bus addr is on, only hram and io are left to the cpu
+    #[inline]
+    fn dma_conflict(&self, addr: u16) -> bool {
+        self.bus.dma_conflicts && self.oam_dma_cycles > 0 && addr < 0xFE00 && self.dma_bus(addr) == self.dma_bus(self.oam_dma_src)
+    }
+
+    // FEA0-FEFF: a CGB has ram behind it, a DMG reads 00 unless OAM is blocked, then FF
+    fn read_unusable(&self, addr: u16) -> u8 {
+        if self.mode == MODE::CGB {
+            return self.unusable[Memory::unusable_index(addr)]
+        }
+        let blocked = self.oam_dma_cycles > 0 || (self.ppu.lcd_enabled() && (self.ppu.mode == PPU_MODE::OAM || self.ppu.mode == PPU_MODE::DRAW));
+        if blocked { 0xFF } else { 0x00 }
+    }
+
+    fn unusable_index(addr: u16) -> usize {
+        match addr {
+            0xFEA0 ..= 0xFEBF => (addr - 0xFEA0) as usize,
+            _ => 0x20 + (addr as usize&0xF)
+        }
+    }
+
+    // vram has its own bus, so does wram on a CGB. Everything else shares the cartridge's
+    fn dma_bus(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000 ..= 0x9FFF => 1,
+            0xC000 ..= 0xFDFF if self.mode == MODE::CGB => 2,
+            _ => 0
+        }
+    }
+
+    #[inline]
+    fn read_bus(&mut self, addr: u16) -> u8 {
         if self.cart.bootrom_enable {
             match addr {
                 0x0000 ..= 0x00FF => {
@@ -244,21 +1064,27 @@ impl Memory {
         match addr {
             0x0000 ..= 0x7FFF => self.cart.read_rom(addr),
             0x8000 ..= 0x9FFF => self.vram[(addr as usize&0x1FFF) + self.vram_bank as usize*0x2000],
-            0xA000 ..= 0xBFFF => self.cart.read_ram(addr-0xa000),
+            0xA000 ..= 0xBFFF => self.cart.read_ram(addr-0xa000, self.open_bus()),
             0xC000 ..= 0xCFFF => self.ram[(addr&0xFFF) as usize],
             0xD000 ..= 0xDFFF => self.ram[(addr as usize&0xFFF) + self.ram_bank as usize*0x1000],
-            0xE000 ..= 0xFDFF => self.ram[(addr-0xe000) as usize],
+            0xE000 ..= 0xEFFF if self.bus.echo_ram => self.ram[(addr&0xFFF) as usize],
+            0xF000 ..= 0xFDFF if self.bus.echo_ram => self.ram[(addr as usize&0xFFF) + self.ram_bank as usize*0x1000],
+            0xE000 ..= 0xFDFF => self.open_bus(),
+            0xFE00 ..= 0xFE9F if self.oam_dma_cycles > 0 => 0xFF,
             0xFE00 ..= 0xFE9F => self.OAM[(addr-0xfe00) as usize],
+            0xFEA0 ..= 0xFEFF => self.read_unusable(addr),
 
             // Memory mapped io
             0xFF00 => {
-                match self.input_select&0x30 {
+                let val = match self.input_select&0x30 {
                     0x00 => 0xF,
-                    0x10 => self.ppu.in_button | self.input_select,
-                    0x20 => self.ppu.in_direction | self.input_select,
+                    0x10 => self.in_button | self.input_select,
+                    0x20 => self.in_direction | self.input_select,
                     0x30 => 0xFF,
                     _ => panic!()
-                }
+                };
+                self.events.push(Event::JoypadRead(val));
+                val
             },
             0xFF01 => self.serial_transfer,
             0xFF02 => self.serial_control,
@@ -269,14 +1095,16 @@ impl Memory {
             0xFF0F => self.IF,
             0xFF10 ..= 0xFF3F => self.apu.read(addr),
             0xFF40 ..= 0xFF4B => self.ppu.read(addr),
-            0xFF4F => self.vram_bank | 0xFE,
-            0xFF51 => (self.vdma_src >> 8) as u8,
-            0xFF52 => self.vdma_src as u8,
-            0xFF53 => (self.vdma_dst >> 8) as u8,
-            0xFF54 => self.vdma_dst as u8,
-            0xFF55 => self.hdma5,
+            0xFF4D if self.mode == MODE::CGB => (self.double_speed as u8) << 7 | 0x7E | self.speed_switch as u8,
+            // CGB registers are unmapped on a DMG and in compat mode, FF51-FF54 are write only
+            0xFF4F if self.mode == MODE::CGB => self.vram_bank | 0xFE,
+            0xFF55 if self.mode == MODE::CGB => self.hdma5,
             0xFF68 ..= 0xFF6C if self.mode == MODE::CGB => self.ppu.read(addr),
-            0xFF70 => self.ram_bank | 0xF8, // only 3 LSb used
+            0xFF70 if self.mode == MODE::CGB => self.ram_bank | 0xF8, // only 3 LSb used
+            0xFF72 ..= 0xFF74 if self.mode == MODE::CGB => self.undocumented[addr as usize - 0xFF72],
+            0xFF75 if self.mode == MODE::CGB => self.undocumented[3] | 0x8F,  // bits 4-6
+            0xFF76 if self.mode == MODE::CGB => self.apu.pcm12(),
+            0xFF77 if self.mode == MODE::CGB => self.apu.pcm34(),
             0xFF80 ..= 0xFFFE => self.hram[(addr-0xff80) as usize],
             0xFFFF => self.IER,
             _ => 0xFF
@@ -285,13 +1113,54 @@ impl Memory {
 
     #[inline]
     pub fn write(&mut self, addr: u16, mut val: u8) {
+        #[cfg(feature = "debugger")]
+        if !self.watchpoints.is_empty() && self.watchpoints.contains(&addr) {
+            self.watch_hit = Some(addr);
+        }
+        #[cfg(feature = "debugger")]
+        if self.io_log.logs(addr) {
+            self.io_log.push(IoWrite {
+                addr,
+                val,
+                pc: self.pc,
+                frame: self.ppu.frame_count,
+                ly: self.ppu.ly()
+            });
+        }
+
+        if self.dma_conflict(addr) {
+            return
+        }
+        if self.on_cart_bus(addr) {
+            self.data_bus = val;
+        }
+
         match addr {
-            0x0000 ..= 0x7FFF => self.cart.write_rom(addr, val),
-            0x8000 ..= 0x9FFF => self.vram[(addr as usize&0x1FFF) + self.vram_bank as usize * 0x2000] = val,
-            0xA000 ..= 0xBFFF => self.cart.write_ram(addr-0xA000, val),
+            0x0000 ..= 0x7FFF => {
+                let rumble = self.cart.rom.rumble();
+                self.cart.write_rom(addr, val);
+                if self.cart.rom.rumble() != rumble {
+                    self.events.push(Event::Rumble(!rumble));
+                }
+            },
+            0x8000 ..= 0x9FFF => {
+                let pos = (addr as usize&0x1FFF) + self.vram_bank as usize * 0x2000;
+                self.vram[pos] = val;
+                #[cfg(feature = "debugger")]
+                { self.vram_dirty[pos/16] = true; }
+            },
+            0xA000 ..= 0xBFFF => {
+                if self.cart.rom.ram_enabled() {
+                    self.events.push(Event::CartRamDirty);
+                }
+                self.cart.write_ram(addr-0xA000, val)
+            },
             0xC000 ..= 0xCFFF => self.ram[(addr&0xFFF) as usize] = val,
             0xD000 ..= 0xDFFF => self.ram[(addr as usize&0xFFF) + self.ram_bank as usize*0x1000] = val,
-            0xE000 ..= 0xFDFF => self.ram[(addr-0xe000) as usize] = val,
+            0xE000 ..= 0xEFFF if self.bus.echo_ram => self.ram[(addr&0xFFF) as usize] = val,
+            0xF000 ..= 0xFDFF if self.bus.echo_ram => self.ram[(addr as usize&0xFFF) + self.ram_bank as usize*0x1000] = val,
+            0xFEA0 ..= 0xFEFF if self.mode == MODE::CGB => self.unusable[Memory::unusable_index(addr)] = val,
+            0xFE00 ..= 0xFE9F if self.oam_dma_cycles > 0 => (),
             0xFE00 ..= 0xFE9F => self.OAM[(addr-0xfe00) as usize] = val,
 
             // Memory mapped io
@@ -301,14 +1170,13 @@ impl Memory {
             0xFF01 => {
                 self.serial_transfer = val;
             },
-            0xFF02 => { // intercept serial 
-                let v = self.read(0xFF01);
-                if v != 0 {
-                    print!("{}", v as char); 
-                }
-                self.serial_control = 0b01111110 | val;
-                if val&0x80 != 0 {
-                    self.serial_count_interrupt = 8;
+            0xFF02 => {
+                // bit 1, the fast clock, only exists on a CGB
+                self.serial_control = if self.mode == MODE::CGB { 0b01111100 } else { 0b01111110 } | val;
+                self.serial_count_interrupt = if val&0x80 != 0 { 8 } else { 0 };
+                self.serial_out = self.serial_transfer;
+                if let Some(device) = &mut self.serial_device {
+                    device.transfer_cancelled();
                 }
             },
             0xFF04 => {
@@ -328,25 +1196,39 @@ impl Memory {
             0xFF10 ..= 0xFF3F => {
                 self.apu.write(addr, val)
             }
-            0xFF46 => {  // TODO: real timings, not instant
-                let mut pos = (val as u16) << 8;
-                loop {
-                    self.OAM[pos as usize&0xFF] = self.read(pos);
-                    if pos&0xFF == 0x9F { break }
-                    pos += 1;
-                }
+            0xFF46 => {
+                self.ppu.write(addr, val);
+                // E0-FF read work ram the way echo ram does, whatever echo_ram says
+                let src = (val as u16) << 8;
+                self.oam_dma_next = if src >= 0xE000 { src - 0x2000 } else { src };
+                self.oam_dma_start = 4;
             }
+            0xFF41 => {
+                if self.ppu.stat_write_bug() {
+                    self.IF |= 0x2;
+                }
+                self.ppu.write(addr, val);
+                self.ppu.update_stat_line(&mut self.IF);
+            },
+            0xFF45 => {
+                self.ppu.write(addr, val);
+                self.ppu.update_stat_line(&mut self.IF);
+            },
             0xFF40 ..= 0xFF4B => {
                 self.ppu.write(addr, val)
             },
+            0xFF4D if self.mode == MODE::CGB => {
+                self.speed_switch = val&0x1 != 0;
+            },
             0xFF4F if self.mode == MODE::CGB => {
                 self.vram_bank = val&0x1;
             },
             0xFF50 => {
                 self.cart.bootrom = vec![];
                 self.cart.bootrom_enable = false;
-                self.mode = self.cart.gb_cart_type;
-                self.ppu.gb_mode = self.cart.gb_cart_type;
+                if self.mode == MODE::CGB {  // DMG models stay as they are
+                    self.enter_compat_mode();
+                }
             },
             0xFF51 => {
                 self.vdma_src = (self.vdma_src&0xFF) | ((val as u16) << 8);
@@ -360,24 +1242,18 @@ impl Memory {
             0xFF54 => {
                 self.vdma_dst = (self.vdma_dst&0x1F00) | (val as u16&0xF0);
             },
-            0xFF55 if self.mode == MODE::CGB => {  // TODO: "real" HDMA timings
-                let length = ((val as u16&0x7F)+1) * 0x10;
-
-                if val&0x80 != 0 { // hdma
+            0xFF55 if self.mode == MODE::CGB => {
+                if val&0x80 != 0 { // hdma, a block every hblank
                     self.hdma_active = true;
                     self.hdma_length = val&0x7F;
                     self.hdma5 = self.hdma_length;
-                } else { // gdma
-                    if self.hdma_active {
-                        self.hdma_active = false;
-                        self.hdma5 |= 0x80;
-                    } else {
-                        for i in 0 .. length {
-                            let v = self.read(self.vdma_src + i);
-                            self.write((0x8000 | self.vdma_dst) + i, v);
-                        }
-                        self.hdma5 = 0xFF;
-                    }
+                } else if self.hdma_active {  // stops the hdma after the block it's on
+                    self.hdma_active = false;
+                    self.hdma5 |= 0x80;
+                } else if self.hdma_copy == 0 {  // gdma
+                    self.hdma_general = true;
+                    self.hdma_offset = 0;
+                    self.hdma_copy = ((val as u16&0x7F)+1) * 0x20;
                 }
             },
             0xFF68 ..= 0xFF6C if self.mode == MODE::CGB => {
@@ -388,6 +1264,9 @@ impl Memory {
                 if val == 0 { val = 1; }
                 self.ram_bank = val;
             },
+            0xFF72 ..= 0xFF75 if self.mode == MODE::CGB => {
+                self.undocumented[addr as usize - 0xFF72] = val;
+            },
             0xFF80..=0xFFFE => {
                 self.hram[(addr-0xff80) as usize] = val
             },
@@ -396,42 +1275,205 @@ impl Memory {
         }
     }
 
-    pub fn tick(&mut self) {
+    // DIV, TIMA, TMA and TAC back to power on, for the debugger to tell the timers apart from the rest
+    pub fn reset_timers(&mut self) {
+        self.DIV = 0;
+        self.TIMA = 0;
+        self.TMA = 0;
+        self.TAC = 0b11111000;
+        self.tima_schedule = -1;
+        self.last_div = 0;
+    }
+
+    // STOP with KEY1 bit 0 set on a CGB, false for a plain STOP. DIV is reset either way on hardware
+    pub fn switch_speed(&mut self) -> bool {
+        self.DIV = 0;
+        if self.mode != MODE::CGB || !self.speed_switch {
+            return false
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch = false;
+        true
+    }
+
+    // turns the clock off, until a joypad line goes low or for the speed switch pause
+    pub(crate) fn stop(&mut self, speed_switch: bool) {
+        self.stopped = true;
+        self.stop_timeout = if speed_switch { SPEED_SWITCH_CYCLES } else { 0 };
+        self.stop_clock = 0;
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // a pressed button on a row P1 selects, what wakes STOP and turns it into a HALT
+    pub(crate) fn joypad_held(&self) -> bool {
+        (self.input_select&0x10 == 0 && self.in_direction != 0xF) || (self.input_select&0x20 == 0 && self.in_button != 0xF)
+    }
+
+    // Internal clock, a bit goes out on every falling edge of DIV bit 8 (8192Hz), bit 3 (262144Hz)
+    // with the CGB's fast clock, doubled in double speed. The other side's bits come in with them,
+    // they're only known once the device swapped the whole byte so 1s are shifted in meanwhile.
+    // External clock, nothing moves until the device says the other side clocked the byte through
+    fn serial_tick(&mut self) {
+        let received = if self.serial_control&0x01 != 0 {
+            let bit = if self.mode == MODE::CGB && self.serial_control&0x02 != 0 { 0x8 } else { 0x100 };
+            if self.DIV.wrapping_sub(1)&bit == 0 || self.DIV&bit != 0 {
+                return
+            }
+            self.serial_transfer = (self.serial_transfer << 1) | 1;
+            self.serial_count_interrupt -= 1;
+            if self.serial_count_interrupt > 0 {
+                return
+            }
+            match &mut self.serial_device {
+                Some(device) => device.exchange(self.serial_out),
+                None => 0xFF
+            }
+        } else {
+            let out = self.serial_transfer;
+            match self.serial_device.as_mut().and_then(|d| d.external_clock(out)) {
+                Some(b) => b,
+                None => return
+            }
+        };
+
+        if let Some(device) = &mut self.serial_device {
+            if device.take_print_job() {
+                self.events.push(Event::PrintJob);
+            }
+        }
+        self.serial_count_interrupt = 0;
+        self.serial_transfer = received;
+        self.events.push(Event::SerialByte { sent: self.serial_out, received });
+        self.serial_control &= 0x7F;
+        self.IF |= 0x8;
+    }
+
+    // nothing runs, DIV stays at 0 and the picture stays as it was
+    fn tick_stopped(&mut self) {
+        if self.stop_timeout > 0 {
+            self.stop_timeout -= 1;
+            self.stopped = self.stop_timeout > 0;
+        } else if self.joypad_held() {
+            self.stopped = false;
+        }
+
+        self.stop_clock += 1;
+        if self.stop_clock == STOPPED_FRAME_CYCLES {
+            self.stop_clock = 0;
+            self.ppu.frame_ready = true;
+            self.ppu.frame_count = self.ppu.frame_count.wrapping_add(1);
+        }
+    }
+
+    // direction and button nibbles in P1 order, bit cleared when pressed
+    pub fn set_joypad(&mut self, direction: u8, button: u8) {
+        let before_dir = self.in_direction;
+        let before_butt = self.in_button;
+        self.in_direction = direction&0xF;
+        self.in_button = button&0xF;
+
+        match self.input_select&0x30 {
+            0x10 if before_butt & (!self.in_button) != 0 => self.IF |= 0x10,
+            0x20 if before_dir & (!self.in_direction) != 0 => self.IF |= 0x10,
+            0x30 if before_dir & (!self.in_direction) != 0 || before_butt & (!self.in_button) != 0 => self.IF |= 0x10,
+            _ => ()
+        }
+    }
+
+    // starts an M-cycle after the FF46 write, then copies a byte every 4 cycles.
+    // OAM reads return 0xFF until the transfer ends, a restart keeps it blocked
+    fn oam_dma_tick(&mut self) {
+        if self.oam_dma_start > 0 {
+            self.oam_dma_start -= 1;
+            if self.oam_dma_start == 0 {
+                self.oam_dma_src = self.oam_dma_next;
+                self.oam_dma_cycles = 640;
+                return
+            }
+        }
+        if self.oam_dma_cycles == 0 {
+            return
+        }
+        self.oam_dma_cycles -= 1;
+        if self.oam_dma_cycles & 3 == 0 {
+            let i = 159 - self.oam_dma_cycles / 4;
+            self.OAM[i as usize] = self.read_bus(self.oam_dma_src + i);
+        }
+    }
+
+    // the ppu and apu side, on the 4MHz clock whatever speed the cpu runs at
+    fn tick_slow(&mut self) {
         let ppu_mode = self.ppu.mode;
-        self.ppu.tick(&mut self.vram, &mut self.OAM, &mut self.IF, &self.input_select);
+        self.ppu.tick(&mut self.vram, &mut self.OAM, self.oam_dma_cycles > 0, &mut self.IF);
+        if ppu_mode != self.ppu.mode && self.ppu.mode == PPU_MODE::VBLANK {
+            self.events.push(Event::VBlank);
+        }
         self.apu.tick();
+        self.cart.tick();
 
-        if self.hdma_active {
-            if ppu_mode != self.ppu.mode && self.ppu.mode == PPU_MODE::HBLANK {
-                let mut offset = self.hdma_length as u16 - (self.hdma5 as u16&0x7F);
-                offset = offset * 0x10;
+        if self.hdma_active && self.hdma_copy == 0 && ppu_mode != self.ppu.mode && self.ppu.mode == PPU_MODE::HBLANK {
+            self.hdma_general = false;
+            self.hdma_offset = (self.hdma_length as u16 - (self.hdma5 as u16&0x7F)) * 0x10;
+            self.hdma_copy = 0x20;
+        }
+        if self.hdma_copy > 0 {
+            self.hdma_tick();
+        }
+    }
 
-                for i in 0 .. 0x10 {
-                    let v = self.read(self.vdma_src + i + offset);
-                    self.write((0x8000 | self.vdma_dst) + i + offset, v);
-                    self.tick();
-                    self.tick();
-                }
+    // a byte every other cycle of the running block, then hdma5 counts down or the gdma is done
+    fn hdma_tick(&mut self) {
+        self.hdma_copy -= 1;
+        if self.hdma_copy&1 == 0 {
+            let v = self.read(self.vdma_src + self.hdma_offset);
+            self.write((0x8000 | self.vdma_dst) + self.hdma_offset, v);
+            self.hdma_offset += 1;
+        }
+        if self.hdma_copy > 0 {
+            return
+        }
 
-                if self.hdma5&0x7F == 0 {
-                    self.hdma_active = false;
-                    self.hdma5 = 0xFF;
-                } else {
-                    self.hdma5 -= 1;
-                }
+        if self.hdma_general {
+            self.hdma5 = 0xFF;
+        } else if self.hdma_active {
+            if self.hdma5&0x7F == 0 {
+                self.hdma_active = false;
+                self.hdma5 = 0xFF;
+            } else {
+                self.hdma5 -= 1;
             }
         }
+    }
+
+    // the cpu sits out whole machine cycles while a gdma or hdma block copies
+    pub fn dma_stall(&self) -> bool {
+        self.hdma_copy > 0
+    }
+
+    pub fn tick(&mut self) {
+        self.clock += if self.double_speed { 1 } else { 2 };
+        if self.stopped {
+            self.tick_stopped();
+            return
+        }
+        if self.oam_dma_cycles > 0 || self.oam_dma_start > 0 {
+            self.oam_dma_tick();
+        }
 
-        self.serial_transfer = (self.serial_transfer >> 1) | 0x80;
-        if self.serial_count_interrupt > 0 {
-            self.serial_count_interrupt -= 1;
-            if self.serial_count_interrupt == 0 {
-                self.IF |= 0x8;
-            }
+        self.slow_cycle = !self.slow_cycle;
+        if !self.double_speed || self.slow_cycle {
+            self.tick_slow();
         }
 
         self.DIV = self.DIV.wrapping_add(1);
 
+        if self.serial_count_interrupt > 0 {
+            self.serial_tick();
+        }
+
         if self.tima_schedule >= 0 {
             if self.tima_schedule <= 2 {
                 self.TIMA = self.TMA;
@@ -451,5 +1493,68 @@ impl Memory {
             }
         }
         self.last_div = self.DIV&TIMA_SPEED[self.TAC as usize&0x03];
+
+        #[cfg(feature = "debugger")]
+        if self.irq_latency.enabled {
+            self.irq_latency.tick(self.IF);
+        }
+    }
+
+    // Up to max cycles at once, the same as that many tick calls while nothing happens but counting:
+    // the ppu idle in hblank or vblank (see PPU::idle_dots), no dma or serial transfer, TIMA short
+    // of overflowing. Whole machine cycles, 0 when the next one needs tick
+    pub(crate) fn tick_idle(&mut self, max: u32) -> u32 {
+        if self.stopped || self.oam_dma_cycles > 0 || self.oam_dma_start > 0 || self.hdma_copy > 0
+            || self.serial_count_interrupt > 0 || self.tima_schedule >= 0 {
+            return 0
+        }
+        #[cfg(feature = "debugger")]
+        if self.irq_latency.enabled {
+            return 0
+        }
+
+        let idle = self.ppu.idle_dots();
+        let mut cycles = (if self.double_speed { idle*2 } else { idle }).min(max) & !3;
+        while cycles > 0 && self.tima_increments(cycles) > 0xFF - self.TIMA as u32 {
+            cycles = (cycles / 2) & !3;
+        }
+        if cycles == 0 {
+            return 0
+        }
+
+        // an even count in double speed, slow_cycle ends up where it was
+        let dots = if self.double_speed { cycles / 2 } else { cycles };
+        self.clock += cycles as u64 * if self.double_speed { 1 } else { 2 };
+        self.TIMA += self.tima_increments(cycles) as u8;
+        self.DIV = self.DIV.wrapping_add(cycles as u16);
+        self.last_div = self.DIV&TIMA_SPEED[self.TAC as usize&0x03];
+        self.ppu.skip_dots(dots, &mut self.IF);
+        for _ in 0 .. dots {
+            self.apu.tick();
+            self.cart.tick();
+        }
+        cycles
+    }
+
+    // How often the next cycles of tick would increment TIMA: on the falling edge of the DIV bit,
+    // or while it's set with the timer disabled. Counted over the values DIV steps through
+    fn tima_increments(&self, cycles: u32) -> u32 {
+        let bit = TIMA_SPEED[self.TAC as usize&0x03] as u32;
+        let enabled = self.TAC&0x4 != 0;
+        let div = self.DIV as u32;
+        // values below x ending a run of the bit, or having it set
+        let below = |x: u32| if enabled {
+            x / (bit*2)
+        } else {
+            x / (bit*2) * bit + (x % (bit*2)).saturating_sub(bit)
+        };
+        let first = self.last_div != 0 && (!enabled || (div + 1) & bit == 0);
+        first as u32 + below(div + cycles) - below(div + 1)
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
     }
 }
@@ -1,18 +1,85 @@
+mod error;
 mod memory;
 mod cpu;
 mod ppu;
 mod opcodes;
+mod accuracy;
+mod audioconfig;
+mod rtc;
+mod eeprom;
+mod camera;
+mod powerloss;
+mod raminit;
+mod events;
+#[cfg(feature = "debugger")]
+mod iolog;
+#[cfg(feature = "debugger")]
+mod irqlatency;
+#[cfg(feature = "debugger")]
+mod watch;
+mod triggers;
+mod testscript;
+mod statehash;
+mod statediff;
+mod savestate;
+mod bess;
+pub mod compress;
+#[cfg(feature = "std")]
+pub mod png;
+mod compat;
+mod cartinfo;
+mod serial;
+mod ioregs;
+#[cfg(feature = "std")]
+mod wav;
+#[cfg(feature = "free_bootrom")]
+mod bootrom;
+#[cfg(feature = "rcheevos")]
+pub mod rcheevos;
 pub mod mbc;
 pub mod apu;
 
-pub use cpu::{CPU, Flag};
-pub use memory::{Memory, Cartridge};
+pub use cpu::{CPU, Flag, RunState};
+pub use error::EmulatorError;
+pub use memory::{Memory, Cartridge, VRAM_BANKS, WRAM_BANKS};
 pub use opcodes::{execute, PUSH};
-pub use ppu::{PPU, PPU_MODE};
+pub use ppu::{PPU, PPU_MODE, Color, DmgPalette, DMG_PALETTES, dmg_palette};
+#[cfg(feature = "debugger")]
+pub use ppu::{Pixel_palette, compose_two_bytes};
 pub use apu::APU;
+pub use accuracy::{Accuracy, AccuracyPreset, BusQuirks};
+pub use audioconfig::{AudioConfig, Resampler, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE};
+pub use rtc::{RtcClock, RtcSource, Mbc3Rtc, Huc3Rtc, RTC_FOOTER_SIZE, RTC_FOOTER_SIZE_SHORT};
+pub use eeprom::{Eeprom, EEPROM_SIZE};
+pub use camera::{Camera, CameraSource, TestImage, StillImage, CAMERA_WIDTH, CAMERA_HEIGHT};
+pub use powerloss::PowerLoss;
+pub use raminit::RamInit;
+pub use events::{Event, EventKind, EventBus};
+#[cfg(feature = "debugger")]
+pub use iolog::{IoLog, IoWrite};
+#[cfg(feature = "debugger")]
+pub use irqlatency::{IrqLatency, IrqStats, IRQ_NAMES};
+#[cfg(feature = "debugger")]
+pub use watch::{Watch, WatchType, Symbols};
+pub use triggers::{Triggers, TriggerAction, Condition};
+pub use testscript::{TestScript, Failure};
+pub use statehash::StateHasher;
+pub use statediff::{StateDiff, RangeDiff};
+pub use savestate::{StateWriter, StateReader, STATE_MAGIC, STATE_VERSION, COMPRESSED_MAGIC, compress_state, decompress_state};
+pub use compat::CompatReport;
+pub use cartinfo::{CartridgeInfo, RomIntegrity, Region};
+pub use serial::{SerialDevice, Loopback, LinkCable, LinkPort};
+#[cfg(feature = "free_bootrom")]
+pub use bootrom::free_bootrom;
+pub use ioregs::{IoRegister, Support, IO_REGISTERS, IE_REGISTER, io_register};
+#[cfg(feature = "std")]
+pub use statehash::StateHashLog;
+#[cfg(feature = "std")]
+pub use wav::WavWriter;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum MODE {
     DMG,
     CGB,
+    DMG0,  // the first DMG revision, different boot registers and no STAT write bug. Runs as a DMG otherwise
 }
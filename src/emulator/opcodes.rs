@@ -395,14 +395,16 @@ fn RR(cpu: &mut CPU, mut val: u8) -> u8 {
 
 pub fn execute(cpu: &mut CPU, inst: u8) -> u8 {
     match inst {
-        // STOP
-        0x10 => {
-            1  // TODO
-        }
+        // STOP, switches speed when KEY1 is armed on a CGB
+        0x10 => cpu.stop(),
 
         // HALT
         0x76 => {
-            cpu.halt = true;
+            if !cpu.IME && cpu.memory.IF & cpu.memory.IER & 0x1F != 0 {
+                cpu.halt_bug = true;  // doesn't halt at all
+            } else {
+                cpu.halt = true;
+            }
             1
         },
 
@@ -0,0 +1,508 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_BLOCK: usize = 0xFFFF;  // stored deflate block limit
+const WINDOW: usize = 32768;  // deflate's furthest match
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN: u32 = 32;  // earlier positions with the same hash tried for a match
+const ACTL_OFFSET: u64 = 33;  // after the signature and IHDR
+
+// deflate's length and distance codes, from 257 and 0
+const LEN_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LEN_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn crc32(data: &[u8], mut crc: u32) -> u32 {
+    for b in data.iter() {
+        crc ^= *b as u32;
+        for _ in 0 .. 8 {
+            crc = if crc&1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for d in data.iter() {
+        a = (a + *d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = !crc32(data, crc32(kind, 0xFFFFFFFF));
+    w.write_all(&crc.to_be_bytes())
+}
+
+// zlib stream of uncompressed blocks, the images are small enough that it doesn't matter
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// deflate's bit order, least significant first
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    n: u8
+}
+
+impl BitWriter {
+    fn put(&mut self, v: u32, bits: u8) {
+        self.acc |= v << self.n;
+        self.n += bits;
+        while self.n >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    // huffman codes go most significant bit first
+    fn put_code(&mut self, code: u32, bits: u8) {
+        let reversed = (0 .. bits).fold(0, |r, i| r | ((code >> i) & 1) << (bits - 1 - i));
+        self.put(reversed, bits);
+    }
+
+    fn put_literal(&mut self, sym: u32) {
+        match sym {
+            0 ..= 143 => self.put_code(0x30 + sym, 8),
+            144 ..= 255 => self.put_code(0x190 + sym - 144, 9),
+            256 ..= 279 => self.put_code(sym - 256, 7),
+            _ => self.put_code(0xC0 + sym - 280, 8)
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+// zlib stream of one fixed huffman block. Matches are found through a hash of the next
+// 3 bytes, plenty for emulated frames, mostly flat color and rows repeating the one above
+fn zlib_compressed(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: vec![0x78, 0x01], acc: 0, n: 0 };
+    w.put(1, 1);  // last block
+    w.put(1, 2);  // fixed codes
+
+    let hash = |i: usize| ((data[i] as usize) << 10 ^ (data[i+1] as usize) << 5 ^ data[i+2] as usize) & ((1 << HASH_BITS) - 1);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut i = 0;
+    while i < data.len() {
+        let (mut len, mut dist) = (0, 0);
+        if i + 3 <= data.len() {
+            let max = (data.len() - i).min(MAX_MATCH);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let l = (0 .. max).find(|k| data[candidate + k] != data[i + k]).unwrap_or(max);
+                if l > len {
+                    len = l;
+                    dist = i - candidate;
+                    if l == max {
+                        break;
+                    }
+                }
+                candidate = prev[candidate % WINDOW];
+                chain += 1;
+            }
+        }
+
+        let step = if len >= 3 {
+            let l = LEN_BASE.iter().rposition(|b| *b as usize <= len).unwrap();
+            w.put_literal(257 + l as u32);
+            w.put((len - LEN_BASE[l] as usize) as u32, LEN_EXTRA[l]);
+            let d = DIST_BASE.iter().rposition(|b| *b as usize <= dist).unwrap();
+            w.put_code(d as u32, 5);
+            w.put((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d]);
+            len
+        } else {
+            w.put_literal(data[i] as u32);
+            1
+        };
+        for j in i .. (i + step).min(data.len().saturating_sub(2)) {
+            let h = hash(j);
+            prev[j % WINDOW] = head[h];
+            head[h] = j;
+        }
+        i += step;
+    }
+    w.put_literal(256);
+
+    let mut out = w.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_header(w: &mut impl Write, width: u32, height: u32) -> io::Result<()> {
+    w.write_all(&SIGNATURE)?;
+    let mut header = vec![];
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);  // depth, color type rgb, compression, filter, interlace
+    write_chunk(w, b"IHDR", &header)
+}
+
+// 8 bit rgb, rows top to bottom
+pub fn write_rgb(p: &Path, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(p)?);
+    write_header(&mut file, width, height)?;
+
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgb.chunks(stride) {
+        raw.push(0);  // no filter
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut file, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(&mut file, b"IEND", &[])?;
+    file.flush()
+}
+
+// Animated PNG of 8 bit rgb frames, written as they come. Each row is stored as the difference
+// to the one above, unchanged picture compresses to next to nothing. The frame count is
+// filled in by finish
+pub struct ApngWriter {
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    frame_time: f64,  // ms
+    frames: u32,
+    sequence: u32  // fcTL and fdAT share one numbering
+}
+
+impl ApngWriter {
+    pub fn create(p: &Path, width: u32, height: u32, fps: f64) -> io::Result<ApngWriter> {
+        let mut file = BufWriter::new(File::create(p)?);
+        write_header(&mut file, width, height)?;
+        write_chunk(&mut file, b"acTL", &[0; 8])?;  // frames, plays forever
+
+        Ok(ApngWriter {
+            file,
+            width,
+            height,
+            frame_time: 1000. / fps,
+            frames: 0,
+            sequence: 0
+        })
+    }
+
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        // whole ms the frame is shown, rounded so the total stays in step with the frame rate
+        let at = |n: u32| (n as f64 * self.frame_time).round() as u16;
+        let delay = at(self.frames + 1).wrapping_sub(at(self.frames));
+
+        let mut control = vec![];
+        control.extend_from_slice(&self.sequence.to_be_bytes());
+        control.extend_from_slice(&self.width.to_be_bytes());
+        control.extend_from_slice(&self.height.to_be_bytes());
+        control.extend_from_slice(&[0; 8]);  // x, y offset
+        control.extend_from_slice(&delay.to_be_bytes());
+        control.extend_from_slice(&1000u16.to_be_bytes());
+        control.extend_from_slice(&[0, 0]);  // dispose none, blend source
+        write_chunk(&mut self.file, b"fcTL", &control)?;
+        self.sequence += 1;
+
+        let stride = self.width as usize * 3;
+        let mut raw = Vec::with_capacity((stride + 1) * self.height as usize);
+        for (y, row) in rgb.chunks(stride).enumerate() {
+            raw.push(2);  // up
+            for (x, v) in row.iter().enumerate() {
+                raw.push(if y > 0 { v.wrapping_sub(rgb[(y-1)*stride + x]) } else { *v });
+            }
+        }
+        let data = zlib_compressed(&raw);
+        if self.frames == 0 {
+            write_chunk(&mut self.file, b"IDAT", &data)?;
+        } else {
+            let mut chunk = self.sequence.to_be_bytes().to_vec();
+            chunk.extend_from_slice(&data);
+            write_chunk(&mut self.file, b"fdAT", &chunk)?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // returns the frame count, a black frame stands in when there were none
+    pub fn finish(mut self) -> io::Result<u32> {
+        if self.frames == 0 {
+            self.write_frame(&vec![0; (self.width * self.height * 3) as usize])?;
+        }
+        write_chunk(&mut self.file, b"IEND", &[])?;
+
+        let mut control = self.frames.to_be_bytes().to_vec();
+        control.extend_from_slice(&[0; 4]);
+        self.file.seek(SeekFrom::Start(ACTL_OFFSET))?;
+        write_chunk(&mut self.file, b"acTL", &control)?;
+        self.file.flush()?;
+        Ok(self.frames)
+    }
+}
+
+// deflate's bit order, least significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8
+}
+
+impl<'a> Bits<'a> {
+    fn read(&mut self, n: u8) -> io::Result<u32> {
+        let mut v = 0;
+        for i in 0 .. n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("image data ends early"))?;
+            v |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// canonical huffman code from the code length of every symbol
+struct Huffman {
+    counts: [u16; 16],  // codes of each length
+    symbols: Vec<u16>  // ordered by code
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for l in lengths.iter() {
+            counts[*l as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = vec![];
+        for len in 1 .. 16 {
+            for (s, l) in lengths.iter().enumerate() {
+                if *l as usize == len {
+                    symbols.push(s as u16);
+                }
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1 .. 16 {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad huffman code"))
+    }
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+    loop {
+        let sym = lit.decode(bits)? as usize;
+        if sym < 256 {
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(())
+        }
+
+        let i = sym - 257;
+        if i >= LEN_BASE.len() {
+            return Err(invalid("bad length code"))
+        }
+        let len = LEN_BASE[i] as usize + bits.read(LEN_EXTRA[i])? as usize;
+        let d = dist.decode(bits)? as usize;
+        if d >= DIST_BASE.len() {
+            return Err(invalid("bad distance code"))
+        }
+        let back = DIST_BASE[d] as usize + bits.read(DIST_EXTRA[d])? as usize;
+        if back > out.len() {
+            return Err(invalid("distance past the start"))
+        }
+        for _ in 0 .. len {
+            out.push(out[out.len() - back]);
+        }
+    }
+}
+
+// the code lengths of a dynamic block, themselves huffman coded
+fn dynamic_codes(bits: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    let lit_count = bits.read(5)? as usize + 257;
+    let dist_count = bits.read(5)? as usize + 1;
+    let code_count = bits.read(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for i in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[*i] = bits.read(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths);
+
+    let mut lengths = vec![];
+    while lengths.len() < lit_count + dist_count {
+        let (value, repeat) = match codes.decode(bits)? {
+            l @ 0 ..= 15 => (l as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("repeat without a length"))?, 3 + bits.read(2)?),
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?)
+        };
+        for _ in 0 .. repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > lit_count + dist_count {
+        return Err(invalid("code lengths overrun"))
+    }
+    Ok((Huffman::new(&lengths[.. lit_count]), Huffman::new(&lengths[lit_count ..])))
+}
+
+// a zlib stream, the checksum isn't checked
+fn zlib_inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut bits = Bits { data, pos: 2, bit: 0 };
+    let mut out = vec![];
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.pos .. bits.pos + 4).ok_or_else(|| invalid("image data ends early"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let block = data.get(bits.pos + 4 .. bits.pos + 4 + len).ok_or_else(|| invalid("image data ends early"))?;
+                out.extend_from_slice(block);
+                bits.pos += 4 + len;
+            },
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (s, l) in lengths.iter_mut().enumerate() {
+                    *l = match s { 0 ..= 143 => 8, 144 ..= 255 => 9, 256 ..= 279 => 7, _ => 8 };
+                }
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &lit, &dist)?;
+            },
+            _ => return Err(invalid("bad deflate block type"))
+        }
+        if last {
+            return Ok(out)
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// 8 bit rgb or rgba without interlacing, what screenshot tools write. Width, height and rgb rows
+pub fn read_rgb(p: &Path) -> io::Result<(u32, u32, Vec<u8>)> {
+    let file = fs::read(p)?;
+    if !file.starts_with(&SIGNATURE) {
+        return Err(invalid("not a png"))
+    }
+
+    let (mut width, mut height, mut channels) = (0, 0, 0);
+    let mut idat = vec![];
+    let mut pos = SIGNATURE.len();
+    while pos + 8 <= file.len() {
+        let len = u32::from_be_bytes([file[pos], file[pos+1], file[pos+2], file[pos+3]]) as usize;
+        let kind = &file[pos+4 .. pos+8];
+        let data = file.get(pos + 8 .. pos + 8 + len).ok_or_else(|| invalid("chunk past the end"))?;
+        match kind {
+            b"IHDR" if len == 13 => {
+                width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                channels = match (data[8], data[9], data[12]) {
+                    (8, 2, 0) => 3,
+                    (8, 6, 0) => 4,
+                    _ => return Err(invalid("only 8 bit rgb or rgba without interlacing"))
+                };
+            },
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => break,
+            _ => ()
+        }
+        pos += 12 + len;
+    }
+    if channels == 0 {
+        return Err(invalid("no header"))
+    }
+
+    let raw = zlib_inflate(&idat)?;
+    let stride = width as usize * channels;
+    if raw.len() < (stride + 1) * height as usize {
+        return Err(invalid("image data ends early"))
+    }
+    let mut pixels = vec![0u8; stride * height as usize];
+    for y in 0 .. height as usize {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1 .. (y + 1) * (stride + 1)];
+        for x in 0 .. stride {
+            let a = if x >= channels { pixels[y*stride + x - channels] } else { 0 };
+            let b = if y > 0 { pixels[(y-1)*stride + x] } else { 0 };
+            let c = if x >= channels && y > 0 { pixels[(y-1)*stride + x - channels] } else { 0 };
+            pixels[y*stride + x] = line[x].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(invalid("bad filter type"))
+            });
+        }
+    }
+
+    let rgb = if channels == 3 { pixels } else { pixels.chunks(4).flat_map(|px| px[.. 3].to_vec()).collect() };
+    Ok((width, height, rgb))
+}
@@ -0,0 +1,75 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+// Cart ram writes of the last few frames, as if they were still on their way to the battery
+// backed chip. Pulling the power drops them and leaves the ram the way it was that many frames
+// ago, so a save routine cut off part way can be tried out. Writes are picked up by comparing
+// the ram after every frame, what changed goes in the journal with the value it replaced
+pub struct PowerLoss {
+    pub frames: u32,  // how old a write has to be to survive
+    frame: u32,
+    settled: Vec<u8>,  // the ram as of the last frame
+    journal: VecDeque<(u32, usize, u8)>  // frame, ram offset, value before
+}
+
+impl PowerLoss {
+    pub fn new(frames: u32) -> PowerLoss {
+        PowerLoss {
+            frames,
+            frame: 0,
+            settled: vec![],
+            journal: VecDeque::new()
+        }
+    }
+
+    // after every emulated frame
+    pub fn frame(&mut self, ram: &[u8]) {
+        self.frame = self.frame.wrapping_add(1);
+        self.record(ram);
+        while let Some(&(f, _, _)) = self.journal.front() {
+            if self.frame.wrapping_sub(f) < self.frames {
+                break;
+            }
+            self.journal.pop_front();
+        }
+    }
+
+    fn record(&mut self, ram: &[u8]) {
+        if self.settled.len() != ram.len() {
+            self.settle(ram);
+            return
+        }
+        for (i, (old, new)) in self.settled.iter_mut().zip(ram.iter()).enumerate() {
+            if old != new {
+                self.journal.push_back((self.frame, i, *old));
+                *old = *new;
+            }
+        }
+    }
+
+    // takes the ram as it is now as written for good, after a state load or a ram edit
+    pub fn settle(&mut self, ram: &[u8]) {
+        self.settled.clear();
+        self.settled.extend_from_slice(ram);
+        self.journal.clear();
+    }
+
+    // undoes the writes still in the journal, newest first. Returns how many bytes went back
+    pub fn pull(&mut self, ram: &mut [u8]) -> usize {
+        self.record(ram);  // and the ones of the frame that got cut off
+        let mut reverted = 0;
+        while let Some((_, i, old)) = self.journal.pop_back() {
+            if i < ram.len() {
+                ram[i] = old;
+                reverted += 1;
+            }
+        }
+        self.settle(ram);
+        reverted
+    }
+
+    // bytes that would be lost right now
+    pub fn pending(&self) -> usize {
+        self.journal.len()
+    }
+}
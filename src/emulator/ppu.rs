@@ -1,11 +1,81 @@
 #![allow(non_snake_case, non_camel_case_types)]
 
-use raylib::prelude::*;
+use alloc::vec::Vec;
 
-use crate::emulator::MODE;
+use crate::emulator::{MODE, StateHasher, StateWriter, StateReader};
+
+// 8 bit channels on dmg, 5 bit on cgb (see draw_pixel_rgb_correct)
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
+}
+
+impl Color {
+    pub const WHITE: Color = Color::new(255, 255, 255, 255);
+    pub const LIGHTGRAY: Color = Color::new(200, 200, 200, 255);
+    pub const GRAY: Color = Color::new(130, 130, 130, 255);
+    pub const BLACK: Color = Color::new(0, 0, 0, 255);
+    pub const MAGENTA: Color = Color::new(255, 0, 255, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+}
 
 const GRAYSCALE_COLOR: [Color; 4] = [Color::WHITE, Color::LIGHTGRAY, Color::GRAY, Color::BLACK];
-const WH_RATIO: f32 = 160./144.;
+
+// colors for BGP, OBP0 and OBP1 on dmg
+pub type DmgPalette = [[Color; 4]; 3];
+
+const fn rgb(c: u32) -> Color {
+    Color::new((c >> 16) as u8, (c >> 8) as u8, c as u8, 255)
+}
+
+const fn same(c: [u32; 4]) -> DmgPalette {
+    let p = [rgb(c[0]), rgb(c[1]), rgb(c[2]), rgb(c[3])];
+    [p, p, p]
+}
+
+// the ones the CGB bootrom lets you pick for dmg games by holding a direction and A/B
+pub const DMG_PALETTES: [(&str, DmgPalette); 13] = [
+    ("grayscale", [GRAYSCALE_COLOR, GRAYSCALE_COLOR, GRAYSCALE_COLOR]),
+    ("up", same([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000])),
+    ("up+a", same([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000])),
+    ("up+b", same([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108])),
+    ("left", [
+        [rgb(0xFFFFFF), rgb(0x63A5FF), rgb(0x0000FF), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0xFF8484), rgb(0x943A3A), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0xFF8484), rgb(0x943A3A), rgb(0x000000)]
+    ]),
+    ("left+a", [
+        [rgb(0xFFFFFF), rgb(0x8C8CDE), rgb(0x52528C), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0xFF8484), rgb(0x943A3A), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0xFFAD63), rgb(0x843100), rgb(0x000000)]
+    ]),
+    ("left+b", same([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000])),
+    ("down", same([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000])),
+    ("down+a", same([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000])),
+    ("down+b", [
+        [rgb(0xFFFFFF), rgb(0xFFFF00), rgb(0x7B4A00), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0x63A5FF), rgb(0x0000FF), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0x7BFF31), rgb(0x008400), rgb(0x000000)]
+    ]),
+    ("right", same([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000])),
+    ("right+a", same([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF])),
+    ("right+b", [
+        [rgb(0xFFFFFF), rgb(0x7BFF31), rgb(0x0063C5), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0xFF8484), rgb(0x943A3A), rgb(0x000000)],
+        [rgb(0xFFFFFF), rgb(0xFF8484), rgb(0x943A3A), rgb(0x000000)]
+    ])
+];
+
+pub fn dmg_palette(name: &str) -> Option<DmgPalette> {
+    DMG_PALETTES.iter().find(|(n, _)| *n == name).map(|(_, p)| *p)
+}
+const SCANLINE_DRAW_CYCLES: u16 = 172;  // mode 3 length with the scanline renderer, the shortest the FIFO takes
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum PPU_MODE {
@@ -36,6 +106,7 @@ impl From<Pixel_palette> for usize {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Pixel_FIFO {
     palette: Pixel_palette,
     color: u8,
@@ -99,10 +170,8 @@ impl Sprite {
         }
     }
 
-    pub fn is_in_scanline(x: u8, y: u8, ly: u8, size: bool) -> bool {
-        if x == 0 {
-            return false;
-        }
+    // x doesn't matter, sprites off the left or right edge still count towards the 10
+    pub fn is_in_scanline(y: u8, ly: u8, size: bool) -> bool {
         if size {
             ly+16 >= y && ly+16 < y+16
         } else {
@@ -112,7 +181,7 @@ impl Sprite {
 }
 
 
-fn compose_two_bytes(low: u8, high: u8) -> [u8; 8] {
+pub fn compose_two_bytes(low: u8, high: u8) -> [u8; 8] {
     let mut pixels: [u8; 8] = [0; 8];
 
     for (i, val) in [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01].iter().enumerate() {
@@ -122,7 +191,7 @@ fn compose_two_bytes(low: u8, high: u8) -> [u8; 8] {
     pixels
 }
 
-fn map_to_palette(pixel: u8, palette: u8) -> usize {
+pub fn map_to_palette(pixel: u8, palette: u8) -> usize {
     ((palette >> (pixel << 1)) & 0x3) as usize
 }
 
@@ -148,117 +217,6 @@ fn cgb_set_color_byte_by_index(index: u8, palette: &mut [[Color; 4]; 8], val: u8
     }
 }
 
-pub struct Draw {
-    pub handle: RaylibHandle,
-    pub thread: RaylibThread,
-    txt: Texture2D,
-    frame: [u8; 144*160*3],
-
-    // tiles: Texture2D,
-    // tile_arr: Box<[u8; 192*128*3]>,
-
-    // tiles_dest_rect: Rectangle,
-    frame_dest_rect: Rectangle,
-    // tiles_src_rect: Rectangle,
-    frame_src_rect: Rectangle,
-    position: Vector2
-}
-
-impl Draw {
-    pub fn new() -> Draw {
-        set_trace_log(raylib::consts::TraceLogType::LOG_NONE);
-        let (mut handle, thread) = raylib::init()
-            .size(160*2, 144*2)
-            .title("Gameboy emulator")
-            .resizable()
-            .build();
-        handle.set_target_fps(60);
-
-        let mut img = Image::gen_image_color(160, 144, Color::BLACK);
-        img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
-        let txt = handle.load_texture_from_image(&thread, &img).expect("Couldnt load texture from image");
-
-        
-        // let mut img = Image::gen_image_color(128, 192, Color::BLACK);
-        // img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
-        // let tiles = handle.load_texture_from_image(&thread, &img).expect("Couldnt load texture from image");
-        
-        Draw {
-            handle: handle,
-            thread: thread,
-            txt: txt,
-            frame: [0; 144*160*3],
-            // tiles: tiles,
-            // tile_arr: Box::new([0; 192*128*3]),
-
-            // tiles_dest_rect: Rectangle::new(0., 144.*2.+1., 256., 192.*2.),
-            frame_dest_rect: Rectangle::new(0., 0., 160.*2., 144.*2.),
-            // tiles_src_rect: Rectangle::new(0., 0., 128., 192.),
-            frame_src_rect: Rectangle::new(0., 0., 160., 144.),
-            position: Vector2::new(0., 0.)
-        }
-    }
-
-    #[inline]
-    pub fn new_frame(&mut self, vram: &[u8]) {
-        // self.draw_vram_tiles(vram);
-        // self.tiles.update_texture(self.tile_arr.as_ref());
-        if self.handle.is_window_resized() {
-            let h = self.handle.get_screen_height() as f32;
-            let w = WH_RATIO * h;
-            let x = (w - self.handle.get_screen_width() as f32)/2.;
-
-            self.frame_dest_rect = Rectangle::new(0., 0., w, h);
-            self.position = Vector2::new(x, 0.);
-        }
-
-        self.txt.update_texture(&self.frame);
-        let mut d = self.handle.begin_drawing(&self.thread);
-        d.clear_background(Color::BLACK);
-        d.draw_texture_pro(&self.txt, self.frame_src_rect, self.frame_dest_rect, self.position, 0., Color::WHITE);
-        // d.draw_texture_pro(&self.tiles, self.tiles_src_rect, self.tiles_dest_rect, Vector2::new(0., 0.), 0., Color::WHITE);
-        d.draw_fps(0, 0);
-    }
-
-    // #[inline]
-    // fn draw_vram_tiles(&mut self, vram: &[u8]) {
-    //     let mut vram_pos: usize;
-    //     let mut pixel_pos: usize;
-    //     'a: for i in 0 ..= 192 {
-    //         for j in 0 ..= 15 {
-    //             vram_pos = (i / 8) * 256 + j*16 + (i%8)*2;
-    //             if vram_pos > 0x17FF { break 'a; }
-    //             pixel_pos = (j*8 + i*128)*3;
-    //             let p = compose_two_bytes(vram[vram_pos], vram[vram_pos+1]);
-    //             for pix in p.iter() {
-    //                 let a = GRAYSCALE_COLOR[*pix as usize];
-    //                 self.tile_arr[pixel_pos] = a.r;
-    //                 self.tile_arr[pixel_pos+1] = a.g;
-    //                 self.tile_arr[pixel_pos+2] = a.b;
-    //                 pixel_pos += 3;
-    //             }
-    //         }
-    //     }
-    // }
-
-    #[inline]
-    pub fn draw_pixel_rgb_correct(&mut self, x: u8, y: u8, color: Color) {
-        let pos = (y as usize * 160 + x as usize)*3;
-        self.frame[pos] = color.r << 3;
-        self.frame[pos+1] = color.g << 3;
-        self.frame[pos+2] = color.b << 3;
-    }
-
-    #[inline]
-    pub fn draw_pixel(&mut self, x: u8, y: u8, color: Color) {
-        let pos = (y as usize * 160 + x as usize)*3;
-        self.frame[pos] = color.r;
-        self.frame[pos+1] = color.g;
-        self.frame[pos+2] = color.b;
-    }
-}
-
-
 pub enum FetcherMode {
     TILE_DATA,
     TILE_LOW,
@@ -274,14 +232,16 @@ pub enum FetcherTileMode {
 
 
 pub struct Fetcher {
-    lx: u8,
-    cycles: u8,
+    lx: u8,  // of the next tile, from the start of the background or window
+    cycles: u8,  // dots into the current step
     mode: FetcherMode,
     tile_mode: FetcherTileMode,
-    current_pixel_push: u8,
+    first_fetch: bool,
+    current_pixel_push: u8,  // screen x of the next pixel out
     discard_pixels: u8,
     current_sprite: Option<Sprite>,
-    sprite_cycles: u8,
+    sprite_stall: u8,  // dots left until current_sprite is in the sprite FIFO
+    sprite_column: Option<u8>,  // background tile that already made a sprite wait
     data: [u8; 3],
     tile_attrib: TileAttributes
 }
@@ -294,11 +254,13 @@ impl Fetcher {
             cycles: 0,
             mode: FetcherMode::TILE_DATA,
             tile_mode: FetcherTileMode::BG,
+            first_fetch: true,
             current_pixel_push: 0,
             discard_pixels: 0,
             data: [0; 3],
             current_sprite: None,
-            sprite_cycles: 0,
+            sprite_stall: 0,
+            sprite_column: None,
             tile_attrib: TileAttributes::new(0)
         }
     }
@@ -307,9 +269,12 @@ impl Fetcher {
 pub struct PPU {
     pub mode: PPU_MODE,
     cycles: u16,
-    pub d: Draw,
+    pub frame: [u8; 144*160*3],
+    pub frame_ready: bool,  // set at the end of every frame, cleared by whoever presents it
+    pub frame_count: u32,
     pub gb_mode: MODE,
-    color_map: [Color; 4],
+    pub scanline_renderer: bool,  // draw whole lines at the start of mode 3, see render_scanline
+    color_map: DmgPalette,
 
     // lcdc bools
     lcd_enabled: bool,
@@ -354,21 +319,24 @@ pub struct PPU {
     window_line: u8,
     window_y_trigger: bool,
 
-    // input per frame - 0 is pressed
-    pub in_button: u8,     // p15 5th bit
-    pub in_direction: u8,  // p14 4th bit
+    // STAT interrupt sources OR'd together, only a rising edge requests the interrupt
+    stat_line: bool,
+    // LY as the LYC comparator sees it, None for the dots at a line's start where it matches nothing
+    ly_compare: Option<u8>,
 }
 
 impl PPU {
     pub fn new() -> PPU {
-        //let cm = [Color::from((0xf0,0xff,0xf0,0xff)), Color::from((0x70,0x80,0x70,0xff)), Color::from((0x20, 0x30, 0x20, 0xff)), Color::from((0,0,0,255))];
-        let cm = GRAYSCALE_COLOR;
+        let cm = DMG_PALETTES[0].1;
         
         PPU {
             mode: PPU_MODE::OAM,
             cycles: 0,
-            d: Draw::new(),
+            frame: [0; 144*160*3],
+            frame_ready: false,
+            frame_count: 0,
             gb_mode: MODE::DMG,
+            scanline_renderer: false,
             color_map: cm,
 
             // lcdc bools
@@ -411,40 +379,8 @@ impl PPU {
             window_line: 0,
             window_y_trigger: false,
 
-            in_button: 0xF,
-            in_direction: 0xF
-        }
-    }
-
-    #[inline]
-    fn update_input(&mut self, IF: &mut u8, input_select: &u8) {
-        use raylib::consts::KeyboardKey::{KEY_W, KEY_S, KEY_A, KEY_D, KEY_J, KEY_K, KEY_N, KEY_M};
-
-        let hl = &self.d.handle;
-        let before_dir = self.in_direction;
-        let before_butt = self.in_button;
-
-        self.in_direction = hl.is_key_up(KEY_D) as u8 | ((hl.is_key_up(KEY_A) as u8) << 1) | ((hl.is_key_up(KEY_W) as u8) << 2) | ((hl.is_key_up(KEY_S) as u8) << 3);
-        self.in_button = hl.is_key_up(KEY_J) as u8 | ((hl.is_key_up(KEY_K) as u8) << 1) | ((hl.is_key_up(KEY_N) as u8) << 2) | ((hl.is_key_up(KEY_M) as u8) << 3);
-    
-        
-        match input_select&0x30 {
-            0x10 => {
-                if before_butt & (!self.in_button) != 0 {
-                    *IF |= 0x10;
-                }
-            },
-            0x20 => {
-                if before_dir & (!self.in_direction) != 0 {
-                    *IF |= 0x10;
-                }
-            },
-            0x30 => {
-                if before_dir & (!self.in_direction) != 0 || before_butt & (!self.in_button) != 0 {
-                    *IF |= 0x10;
-                }
-            },
-            _ => ()
+            stat_line: false,
+            ly_compare: Some(0),
         }
     }
 
@@ -462,12 +398,14 @@ impl PPU {
                     self.mode = PPU_MODE::OAM;
                     self.FIFO = vec![];
                     self.FIFO_sprite = vec![];
+                    self.sprites.clear();
                     self.ly = 0;
+                    self.ly_compare = Some(0);
                     self.window_enabled = false;
                     self.window_line = 0;
                     self.set_stat(PPU_MODE::OAM);
                 } else if old_en && !self.lcd_enabled {
-                    self.d.frame = [0; 144*160*3];
+                    self.frame = [0; 144*160*3];
                 }
                 
                 self.window_tilemap = val&0x40 != 0;
@@ -485,7 +423,7 @@ impl PPU {
             0xFF43 => self.scx = val,
             0xFF44 => (),  // ly is read only or reset the counter on write?
             0xFF45 => self.lyc = val,
-            0xFF46 => self.dma = val,  // init dma here in future
+            0xFF46 => self.dma = val,  // transfer is done by memory
             0xFF47 => self.palette[usize::from(Pixel_palette::BG)] = val,
             0xFF48 => self.palette[usize::from(Pixel_palette::OBP0)] = val,
             0xFF49 => self.palette[usize::from(Pixel_palette::OBP1)] = val,
@@ -555,27 +493,330 @@ impl PPU {
         self.stat = (self.stat&0xFC) | mode as u8;
     }
 
+    // whether any enabled STAT source is active, also refreshes the LY=LYC flag.
+    // Entering vblank counts as mode 2 for a dot, so OAM interrupts fire on line 144 too
     #[inline]
-    pub fn tick(&mut self, vram: &mut [u8], oam: &mut [u8], IF: &mut u8, input_select: &u8) {
-        use PPU_MODE::*;
+    fn stat_sources(&mut self) -> bool {
+        if self.ly_compare == Some(self.lyc) {
+            self.stat |= 0b100;
+        } else {
+            self.stat &= !0b100;
+        }
 
-        if self.d.handle.window_should_close() { panic!("Window closed"); }
+        let oam = self.mode == PPU_MODE::OAM ||
+            (self.mode == PPU_MODE::VBLANK && self.ly == 144 && self.cycles == 0);
+        (self.stat&0x40 != 0 && self.stat&0x04 != 0)
+            || (self.stat&0x20 != 0 && oam)
+            || (self.stat&0x10 != 0 && self.mode == PPU_MODE::VBLANK)
+            || (self.stat&0x08 != 0 && self.mode == PPU_MODE::HBLANK)
+    }
+
+    // A new source only requests the interrupt when none was active before, one that is already up
+    // blocks the rest (the STAT IRQ glitch). Called every dot and after STAT and LYC writes
+    #[inline]
+    pub fn update_stat_line(&mut self, IF: &mut u8) {
+        let line = self.lcd_enabled && self.stat_sources();
+        if line && !self.stat_line {
+            *IF |= 0b10;
+        }
+        self.stat_line = line;
+    }
+
+    #[inline]
+    fn draw_pixel_rgb_correct(&mut self, x: u8, y: u8, color: Color) {
+        let pos = (y as usize * 160 + x as usize)*3;
+        self.frame[pos] = color.r << 3;
+        self.frame[pos+1] = color.g << 3;
+        self.frame[pos+2] = color.b << 3;
+    }
+
+    #[inline]
+    fn draw_pixel(&mut self, x: u8, y: u8, color: Color) {
+        let pos = (y as usize * 160 + x as usize)*3;
+        self.frame[pos] = color.r;
+        self.frame[pos+1] = color.g;
+        self.frame[pos+2] = color.b;
+    }
+
+    pub fn hash_state(&self, h: &mut StateHasher) {
+        h.write_u8(self.mode as u8);
+        h.write_u16(self.cycles);
+        h.write(&[self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.dma, self.wy, self.wx]);
+        h.write(&self.palette);
+        h.write(&[self.bg_index, self.obj_index, self.window_line, self.window_y_trigger as u8]);
+        for c in self.bg_palette.iter().chain(self.obj_palette.iter()).flatten() {
+            h.write(&[c.r, c.g, c.b]);
+        }
+        h.write(&self.frame);
+    }
+
+    // The pixel pipeline (fetcher, FIFOs and the line's sprites) isn't saved and restarts empty,
+    // a state taken in the middle of mode 3 comes back at its start and draws that line again
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.mode as u8);
+        w.write_u16(self.cycles);
+        w.write_u16(self.draw_timing);
+        w.write_u32(self.frame_count);
+        w.write(&[self.lcd_enabled as u8, self.window_tilemap as u8, self.window_enabled as u8, self.bg_window_tiledata as u8,
+            self.bg_tilemap as u8, self.sprite_size as u8, self.sprite_enabled as u8, self.bg_enabled as u8]);
+        w.write(&[self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.dma, self.wy, self.wx]);
+        w.write(&self.palette);
+        w.write(&[self.bg_index, self.bg_ai, self.obj_index, self.obj_ai, self.obj_priority_mode as u8]);
+        for c in self.bg_palette.iter().chain(self.obj_palette.iter()).flatten() {
+            w.write(&[c.r, c.g, c.b]);
+        }
+        w.write(&[self.window_line, self.window_y_trigger as u8]);
+        w.write(&self.frame);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.mode = match r.read_u8()? {
+            0 => PPU_MODE::HBLANK,
+            1 => PPU_MODE::VBLANK,
+            2 => PPU_MODE::OAM,
+            3 => PPU_MODE::DRAW,
+            _ => return Err("save state has an invalid PPU mode")
+        };
+        self.cycles = r.read_u16()?;
+        self.draw_timing = r.read_u16()?;
+        self.frame_count = r.read_u32()?;
+
+        let mut lcdc = [0; 8];
+        r.read_into(&mut lcdc)?;
+        self.lcd_enabled = lcdc[0] != 0;
+        self.window_tilemap = lcdc[1] != 0;
+        self.window_enabled = lcdc[2] != 0;
+        self.bg_window_tiledata = lcdc[3] != 0;
+        self.bg_tilemap = lcdc[4] != 0;
+        self.sprite_size = lcdc[5] != 0;
+        self.sprite_enabled = lcdc[6] != 0;
+        self.bg_enabled = lcdc[7] != 0;
+
+        let mut regs = [0; 9];
+        r.read_into(&mut regs)?;
+        let [lcdc, stat, scy, scx, ly, lyc, dma, wy, wx] = regs;
+        self.lcdc = lcdc;
+        self.stat = stat;
+        self.scy = scy;
+        self.scx = scx;
+        self.ly = ly.min(153);
+        self.lyc = lyc;
+        self.dma = dma;
+        self.wy = wy;
+        self.wx = wx;
+        r.read_into(&mut self.palette)?;
+
+        self.bg_index = r.read_u8()?&0x3F;
+        self.bg_ai = r.read_u8()?;
+        self.obj_index = r.read_u8()?&0x3F;
+        self.obj_ai = r.read_u8()?;
+        self.obj_priority_mode = r.read_bool()?;
+        for c in self.bg_palette.iter_mut().chain(self.obj_palette.iter_mut()).flatten() {
+            let mut rgb = [0; 3];
+            r.read_into(&mut rgb)?;
+            *c = Color::new(rgb[0], rgb[1], rgb[2], 255);
+        }
+        self.window_line = r.read_u8()?;
+        self.window_y_trigger = r.read_bool()?;
+        r.read_into(&mut self.frame)?;
+
+        // the line is never past its end, and only HBLANK and VBLANK are past the visible lines
+        let last = match self.mode {
+            PPU_MODE::OAM => 79,
+            PPU_MODE::DRAW => 455,
+            _ => 456
+        };
+        if self.lcd_enabled && (self.cycles > last || (self.cycles < 80 && self.mode == PPU_MODE::DRAW)) {
+            return Err("save state has the PPU past the end of its mode")
+        }
+        if self.ly >= 144 && (self.mode == PPU_MODE::OAM || self.mode == PPU_MODE::DRAW) {
+            return Err("save state has the PPU drawing below the screen")
+        }
+        // a whole mode 3 from where it was could run past the end of the line
+        if self.mode == PPU_MODE::DRAW {
+            self.cycles = 80;
+            self.draw_timing = 0;
+        }
+
+        self.ly_compare = Some(self.ly);
+        self.stat_line = self.stat_sources();
+        self.sprites.clear();
+        self.FIFO.clear();
+        self.FIFO_sprite.clear();
+        self.fetcher = Fetcher::new();
+        Ok(())
+    }
+
+    // Line and mode for a state that only has the registers (BESS), the line restarts at the mode's
+    // first cycle. Lines below the screen are always VBLANK, whatever STAT says
+    pub fn set_position(&mut self, ly: u8, stat: u8) {
+        self.ly = ly.min(153);
+        let stat = if self.ly >= 144 { 1 } else { stat&0x3 };
+        self.stat = (self.stat&0x78) | stat | 0x80;
+        self.ly_compare = Some(self.ly);
+        if self.ly == self.lyc {
+            self.stat |= 0b100;
+        }
+        let (mode, cycles) = match stat {
+            0 => (PPU_MODE::HBLANK, 80 + SCANLINE_DRAW_CYCLES),
+            1 => (PPU_MODE::VBLANK, 0),
+            2 => (PPU_MODE::OAM, 0),
+            _ => (PPU_MODE::DRAW, 80)
+        };
+        self.mode = mode;
+        self.cycles = cycles;
+        self.draw_timing = 0;
+        self.stat_line = self.stat_sources();
+        self.sprites.clear();
+        self.FIFO.clear();
+        self.FIFO_sprite.clear();
+        self.fetcher = Fetcher::new();
+    }
+
+    // CGB palette ram as BCPD/OCPD read it, 8 palettes of 4 little endian RGB555 colors
+    pub fn palette_bytes(&self, obj: bool) -> [u8; 64] {
+        let palette = if obj { &self.obj_palette } else { &self.bg_palette };
+        let mut out = [0; 64];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = cgb_get_color_byte_by_index(i as u8, palette);
+        }
+        out
+    }
+
+    pub fn set_palette_bytes(&mut self, obj: bool, data: &[u8]) {
+        let palette = if obj { &mut self.obj_palette } else { &mut self.bg_palette };
+        for (i, b) in data.iter().take(64).enumerate() {
+            cgb_set_color_byte_by_index(i as u8, palette, *b);
+        }
+    }
+
+    // for frontends that keep their own frame buffer, rgb 160x144
+    pub fn copy_frame(&self, out: &mut [u8]) {
+        out[.. self.frame.len()].copy_from_slice(&self.frame);
+    }
+
+    // debugger access
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn dmg_color(&self, pixel: u8, palette: Pixel_palette) -> Color {
+        self.color_map[usize::from(palette)][map_to_palette(pixel, self.palette[usize::from(palette)])]
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcd_enabled
+    }
+
+    // overlapping sprites go by x position instead of OAM index. Always on a DMG and in compat mode,
+    // a CGB game picks with OPRI (FF6C) bit 0 and can switch it while running
+    pub fn x_priority(&self) -> bool {
+        self.gb_mode != MODE::CGB || self.obj_priority_mode
+    }
+
+    // Writing STAT on a DMG sets every source for a cycle, so an interrupt fires when the line matches
+    // LYC or the ppu is in hblank or vblank. Road Rash and Zerd no Densetsu need it, DMG0 and CGB don't have it
+    pub fn stat_write_bug(&self) -> bool {
+        self.gb_mode == MODE::DMG && self.lcd_enabled && !self.stat_line &&
+            (self.stat&0x4 != 0 || self.mode == PPU_MODE::HBLANK || self.mode == PPU_MODE::VBLANK)
+    }
+
+    // back to power on, keeping the model, renderer, dmg colors and the last picture
+    pub fn reset(&mut self) {
+        *self = PPU {
+            frame: self.frame,
+            frame_count: self.frame_count,
+            gb_mode: self.gb_mode,
+            scanline_renderer: self.scanline_renderer,
+            color_map: self.color_map,
+            ..PPU::new()
+        };
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.color_map = palette;
+    }
+
+    // A CGB runs a dmg game through BG palette 0 and OBJ palettes 0 and 1, the CGB bootrom fills them
+    // from its title checksum table or the button combo held during the logo
+    pub fn compat_palette(&self) -> DmgPalette {
+        let rgb = |p: &[Color; 4]| {
+            let mut row = *p;
+            for c in row.iter_mut() {
+                *c = Color::new(c.r << 3, c.g << 3, c.b << 3, 255);
+            }
+            row
+        };
+        [rgb(&self.bg_palette[0]), rgb(&self.obj_palette[0]), rgb(&self.obj_palette[1])]
+    }
+
+    pub fn cgb_bg_palette(&self) -> &[[Color; 4]; 8] {
+        &self.bg_palette
+    }
+
+    pub fn cgb_obj_palette(&self) -> &[[Color; 4]; 8] {
+        &self.obj_palette
+    }
+
+    // palettes in use as 8 bit rgb, 4 colors per row.
+    // cgb - BG 0-7 then OBJ 0-7, dmg - BGP, OBP0, OBP1 through the color map
+    pub fn palettes(&self) -> Vec<[Color; 4]> {
+        if self.gb_mode == MODE::CGB {
+            self.bg_palette.iter().chain(self.obj_palette.iter()).map(|p| {
+                let mut row = *p;
+                for c in row.iter_mut() {
+                    *c = Color::new(c.r << 3, c.g << 3, c.b << 3, 255);
+                }
+                row
+            }).collect()
+        } else {
+            [Pixel_palette::BG, Pixel_palette::OBP0, Pixel_palette::OBP1].iter().map(|p| {
+                let mut row = [Color::BLACK; 4];
+                for (i, c) in row.iter_mut().enumerate() {
+                    *c = self.dmg_color(i as u8, *p);
+                }
+                row
+            }).collect()
+        }
+    }
+
+    #[inline]
+    // oam_dma - OAM is on the DMA bus, the scan reads 0xFF and finds no sprites
+    pub fn tick(&mut self, vram: &mut [u8], oam: &mut [u8], oam_dma: bool, IF: &mut u8) {
+        use PPU_MODE::*;
 
         if !self.lcd_enabled {
             if self.cycles % 65535 == 0 { // that doesnt need to be accurate
-                self.d.new_frame(vram);
-                self.update_input(IF, input_select);
+                self.frame_ready = true;
+                self.frame_count = self.frame_count.wrapping_add(1);
                 self.cycles = 0;
             }
             self.cycles += 1;
+            self.stat_line = false;
             return;
         }
 
+        // the comparator sees the new LY 4 dots into a line. Line 153 reads LY 0 from dot 4 on
+        // after comparing 153, blanks again and compares 0 from dot 12
+        match self.cycles {
+            4 => {
+                self.ly_compare = Some(self.ly);
+                if self.ly == 153 {
+                    self.ly = 0;
+                }
+            },
+            8 if self.mode == VBLANK && self.ly == 0 => self.ly_compare = None,
+            12 if self.mode == VBLANK && self.ly == 0 => self.ly_compare = Some(0),
+            _ => ()
+        }
+
         match self.mode {
             OAM => {
                 if self.cycles == 79 {
                     self.mode = DRAW;
                     self.set_stat(DRAW);
+                    self.fetcher.discard_pixels = self.scx % 8;
                     
                     if self.wy == self.ly {
                         self.window_y_trigger = true;
@@ -584,12 +825,11 @@ impl PPU {
 
                 if self.cycles == 0 {
                     self.set_stat(OAM);
-                    if self.stat&0x20 != 0 { *IF |= 0b10; }
                 }
                 
-                if self.cycles % 2 == 0 && self.sprites.len() < 10 {
+                if self.cycles & 1 == 0 && self.sprites.len() < 10 && !oam_dma {
                     let oam_pos = self.cycles as usize * 2;
-                    if Sprite::is_in_scanline(oam[oam_pos+1], oam[oam_pos], self.ly, self.sprite_size) {
+                    if Sprite::is_in_scanline(oam[oam_pos], self.ly, self.sprite_size) {
                         self.sprites.push(Sprite::new(&oam[oam_pos .. oam_pos+4], oam_pos as u8));
                     }
                 }
@@ -597,14 +837,20 @@ impl PPU {
                 self.cycles += 1;
             },
             DRAW => {
-                let a = self.fetcher_tick(vram);
+                let a = if self.scanline_renderer {
+                    if self.draw_timing == 0 {
+                        self.render_scanline(vram);
+                    }
+                    self.draw_timing + 1 < SCANLINE_DRAW_CYCLES
+                } else {
+                    self.fetcher_tick(vram)
+                };
                 self.draw_timing += 1;
                 if !a {
                     self.mode = HBLANK;
                     self.set_stat(HBLANK);
                     // println!("{}", self.draw_timing);
                     self.draw_timing = 0;
-                    if self.stat&0x08 != 0 { *IF |= 0b10; }
                 }
                 self.cycles += 1;
             },
@@ -612,97 +858,71 @@ impl PPU {
                 if self.cycles == 456 {
                     self.cycles = 0;
                     self.ly += 1;
-                    if self.stat&0x40 != 0 {
-                        if self.ly == self.lyc {
-                            *IF |= 0b10;
-                        }
-                    }
-                    if self.ly == self.lyc {
-                        self.stat |= 0b100;
-                    } else {
-                        self.stat &= !0b100;
-                    }
+                    self.ly_compare = None;
                     self.fetcher = Fetcher::new();
+                    self.sprites.clear();  // the OAM buffer, line 0 starts from an empty one too
 
                     if self.ly == 144 {
                         self.mode = VBLANK;
                         self.set_stat(VBLANK);
                         *IF |= 0b1;
-                        if self.stat&0x10 != 0 { *IF |= 0b10; }
                     } else {
                         self.mode = OAM;
-                        self.sprites = vec![];  // clear oam sprite buffer
                     }
                 } else { self.cycles += 1; }
             },
             VBLANK => {
                 if self.cycles == 456 {
                     self.cycles = 0;
-                    self.ly += 1;
-                    if self.stat&0x40 != 0 {
-                        if self.ly == self.lyc {
-                            *IF |= 0b10;
-                        }
-                    }
-                    if self.ly == self.lyc {
-                        self.stat |= 0b100;
-                    } else {
-                        self.stat &= !0b100;
-                    }
-                    if self.ly == 154 {
-                        if self.stat&0x40 != 0 {
-                            if 0 == self.lyc {
-                                *IF |= 0b10;
-                            }
-                        }
+                    if self.ly == 0 {  // end of line 153, LY is already 0 and stays matched
                         self.window_y_trigger = false;
                         self.mode = OAM;
-                        self.ly = 0;
                         self.window_line = 0;
-                        self.d.new_frame(vram);
-                        self.update_input(IF, input_select);
+                        self.frame_ready = true;
+                        self.frame_count = self.frame_count.wrapping_add(1);
+                    } else {
+                        self.ly += 1;
+                        self.ly_compare = None;
                     }
                 } else {
                     self.cycles += 1;
                 }
             }
         }
+
+        self.update_stat_line(IF);
+    }
+
+    // Dots from here on that tick only counts, up to the end of the line: hblank, or vblank past
+    // the comparator's changes at the line start. Mode, LY and the stat line stay the same
+    pub fn idle_dots(&self) -> u32 {
+        match self.mode {
+            PPU_MODE::HBLANK if self.lcd_enabled => 456 - self.cycles as u32,
+            PPU_MODE::VBLANK if self.lcd_enabled && self.cycles > 12 => 456 - self.cycles as u32,
+            _ => 0
+        }
+    }
+
+    // that many tick calls at once, at most idle_dots
+    pub fn skip_dots(&mut self, dots: u32, IF: &mut u8) {
+        self.cycles += dots as u16;
+        self.update_stat_line(IF);
     }
 
+    // One dot of mode 3. The fetcher takes 2 dots for each of tile number, low and high byte and
+    // pushes a row once the background FIFO is empty, the shifter moves a pixel out every dot it
+    // has one. Mode 3 comes out as 172 dots plus SCX % 8 discarded pixels, 6 for the window
+    // restart and 6 - 11 per sprite. Returns false after the line's last pixel
     #[inline]
     pub fn fetcher_tick(&mut self, vram: &[u8]) -> bool {
-        use FetcherMode::*;
         use FetcherTileMode::*;
 
+        // the shifter waits for a sprite fetch, the background fetcher carries on to its push
         if let Some(mut sprite) = self.fetcher.current_sprite {
-            self.fetcher.sprite_cycles += 1;
-            if self.fetcher.sprite_cycles >= 5 {
-                self.fetcher.current_sprite = None;
-                self.fetcher.sprite_cycles = 0;
-            } else if self.fetcher.sprite_cycles == 4 {
-                let (low, high) = {
-                    let mut data_pos = if self.fetcher.current_sprite.unwrap().y_flip {
-                        if self.sprite_size {
-                            (sprite.tile_location & 0xFE) as u16 * 16 + 30 - (((self.ly as u16 + 16) - sprite.y as u16)%16) * 2
-                        } else {
-                            sprite.tile_location as u16 * 16 + 14 - (((self.ly as u16 + 16) - sprite.y as u16)%8) * 2
-                        }
-                    } else {
-                        if self.sprite_size {
-                            (sprite.tile_location & 0xFE) as u16 * 16 + (((self.ly as u16 + 16) - sprite.y as u16)%16) * 2
-                        } else {
-                            sprite.tile_location as u16 * 16 + (((self.ly as u16 + 16) - sprite.y as u16)%8) * 2
-                        }
-                    };
-
-                    data_pos += 0x2000*sprite.vram_bank as u16;
-
-                    (vram[data_pos as usize], vram[data_pos as usize + 1])
-                };
-
-                let mut _px = compose_two_bytes(low, high);
-                if self.fetcher.current_sprite.unwrap().x_flip { _px.reverse(); }
-
+            self.bg_fetcher_tick(vram);
+            self.fetcher.sprite_stall -= 1;
+            if self.fetcher.sprite_stall == 0 {
+                let _px = self.sprite_row(&sprite, vram);
                 let px = if sprite.x < 8 {
                     &_px[8-sprite.x as usize..8]
                 } else { &_px[..] };
@@ -712,215 +932,420 @@ impl PPU {
                 }
 
                 for (i, val) in px.iter().enumerate() {
+                    let new_px = Pixel_FIFO {
+                        palette: sprite.palette,
+                        color: *val,
+                        priority: sprite.priority,
+                        bg_attrib: None,
+                        oam_pos: sprite.oam_addr
+                    };
                     if i + 1 > self.FIFO_sprite.len() { // push to vec
-                        self.FIFO_sprite.push({
-                            Pixel_FIFO {
-                                palette: sprite.palette,
-                                color: *val,
-                                priority: sprite.priority,
-                                bg_attrib: None,
-                                oam_pos: sprite.oam_addr
-                            }
-                        })
-                    } else { // compose
-                        let other_px = &self.FIFO_sprite[i];
-                        if !self.obj_priority_mode {
-                            if other_px.oam_pos > sprite.oam_addr {
-                                self.FIFO_sprite[i] = Pixel_FIFO {
-                                    palette: sprite.palette,
-                                    color: *val,
-                                    priority: sprite.priority,
-                                    bg_attrib: None,
-                                    oam_pos: sprite.oam_addr
-                                };
-                                continue;
-                            }
-                        }
-                        if other_px.color == 0 {
-                            self.FIFO_sprite[i] = Pixel_FIFO {
-                                palette: sprite.palette,
-                                color: *val,
-                                priority: sprite.priority,
-                                bg_attrib: None,
-                                oam_pos: sprite.oam_addr
-                            }
-                        }
+                        self.FIFO_sprite.push(new_px)
+                    } else if self.sprite_replaces(&self.FIFO_sprite[i], &new_px) { // compose
+                        self.FIFO_sprite[i] = new_px;
                     }
                 }
+                self.fetcher.current_sprite = None;
             }
-
             return true;
         }
 
-        if self.fetcher.tile_mode == BG && self.window_enabled && self.fetcher.current_pixel_push+7 == self.wx && self.window_y_trigger {
-            self.FIFO = vec![];
+        if self.fetcher.tile_mode == BG && self.window_enabled && self.fetcher.discard_pixels == 0
+            && self.fetcher.current_pixel_push+7 == self.wx && self.window_y_trigger {
+            self.FIFO.clear();
             self.fetcher.tile_mode = WIN;
             self.fetcher.cycles = 0;
             self.fetcher.lx = 0;
-            self.fetcher.mode = TILE_DATA;
+            self.fetcher.mode = FetcherMode::TILE_DATA;
+            self.fetcher.sprite_column = None;
         }
 
-        if self.fetcher.discard_pixels > 1 {
-            if self.FIFO.len() > 0 {
-                self.FIFO.remove(0);
+        self.bg_fetcher_tick(vram);
+        if self.FIFO.is_empty() {
+            return true;
+        }
+
+        if self.sprite_enabled && self.fetcher.discard_pixels == 0 {
+            let x = self.fetcher.current_pixel_push;
+            if let Some(i) = self.sprites.iter().position(|s| x + 8 >= s.x) {
+                let sprite = self.sprites.remove(i);
+                self.fetcher.sprite_stall = 5 + self.sprite_wait(&sprite);  // this dot is the first of 6
+                self.fetcher.current_sprite = Some(sprite);
+                return true;
             }
+        }
+
+        let pixel = self.FIFO.remove(0);
+        if self.fetcher.discard_pixels > 0 {  // scx % 8 at the start of the line
             self.fetcher.discard_pixels -= 1;
+            return true;
+        }
+        let sprite_pixel = if !self.FIFO_sprite.is_empty() {
+            Some(self.FIFO_sprite.remove(0))
+        } else { None };
+
+        self.mix_pixel(self.fetcher.current_pixel_push, pixel, sprite_pixel);
+        self.fetcher.current_pixel_push += 1;
+
+        if self.fetcher.current_pixel_push == 160 {
+            self.FIFO.clear();
+            self.FIFO_sprite.clear();
+            if self.fetcher.tile_mode == WIN {
+                self.window_line += 1;
+            }
+            return false;
+        }
+        true
+    }
+
+    // dots the shifter waits for the background fetcher before a sprite's own 6: the pixels of
+    // the sprite's first background tile to its right less 2, only for the first sprite on that
+    // tile. One at OAM x 0 always waits the longest
+    fn sprite_wait(&mut self, sprite: &Sprite) -> u8 {
+        let tile_x = if self.fetcher.tile_mode == FetcherTileMode::WIN {
+            sprite.x.wrapping_sub(self.wx).wrapping_sub(1)
         } else {
-            match self.fetcher.mode {
-                TILE_DATA => {
-                    if self.fetcher.cycles == 1 {
-                        let pos = if self.fetcher.tile_mode == BG {
-                            let pos = ((self.ly.wrapping_add(self.scy) as u16)/8) * 32 + (self.fetcher.lx.wrapping_add(self.scx)/8) as u16;
-                            match self.bg_tilemap {
-                                false => 0x1800 + pos,
-                                true => 0x1C00 + pos,
-                            }
-                        } else {
-                            let pos = (self.window_line as u16 / 8) * 32 + (self.fetcher.lx/8) as u16;
-                            match self.window_tilemap {
-                                false => 0x1800 + pos,
-                                true => 0x1C00 + pos,
-                            }
-                        };
-                        self.fetcher.tile_attrib = TileAttributes::new(vram[pos as usize + 0x2000]);
-                        self.fetcher.data[0] = vram[pos as usize];
-                    
-                        self.fetcher.mode = TILE_LOW;
+            sprite.x.wrapping_add(self.scx)
+        };
+        if sprite.x == 0 {
+            return 5
+        }
+        if self.fetcher.sprite_column == Some(tile_x / 8) {
+            return 0
+        }
+        self.fetcher.sprite_column = Some(tile_x / 8);
+        5 - (tile_x % 8).min(5)
+    }
+
+    // one dot of the background/window fetcher
+    #[inline]
+    fn bg_fetcher_tick(&mut self, vram: &[u8]) {
+        use FetcherMode::*;
+        use FetcherTileMode::*;
+
+        match self.fetcher.mode {
+            TILE_DATA => {
+                self.fetcher.cycles += 1;
+                if self.fetcher.cycles == 2 {
+                    let pos = if self.fetcher.tile_mode == BG {
+                        let x = (self.scx/8) as u16 + (self.fetcher.lx/8) as u16;
+                        let pos = ((self.ly.wrapping_add(self.scy) as u16)/8) * 32 + (x & 31);
+                        match self.bg_tilemap {
+                            false => 0x1800 + pos,
+                            true => 0x1C00 + pos,
+                        }
+                    } else {
+                        let pos = (self.window_line as u16 / 8) * 32 + (self.fetcher.lx/8) as u16;
+                        match self.window_tilemap {
+                            false => 0x1800 + pos,
+                            true => 0x1C00 + pos,
+                        }
+                    };
+                    self.fetcher.tile_attrib = if self.gb_mode == MODE::CGB {
+                        TileAttributes::new(vram[pos as usize + 0x2000])
+                    } else {
+                        TileAttributes::new(0)
+                    };
+                    self.fetcher.data[0] = vram[pos as usize];
+
+                    self.fetcher.mode = TILE_LOW;
+                    self.fetcher.cycles = 0;
+                }
+            },
+            TILE_LOW => {  // tile attrib is always 0 in dmg mode
+                self.fetcher.cycles += 1;
+                if self.fetcher.cycles == 2 {
+                    let pos = self.bg_tile_row();
+                    self.fetcher.data[1] = vram[pos];
+
+                    self.fetcher.mode = TILE_HIGH;
+                    self.fetcher.cycles = 0;
+                }
+            },
+            TILE_HIGH => {
+                self.fetcher.cycles += 1;
+                if self.fetcher.cycles == 2 {
+                    let pos = self.bg_tile_row();
+                    self.fetcher.data[2] = vram[pos + 1];
+
+                    // the first tile of the line is fetched twice
+                    self.fetcher.mode = if self.fetcher.first_fetch { TILE_DATA } else { TILE_PUSH };
+                    self.fetcher.first_fetch = false;
+                    self.fetcher.cycles = 0;
+                }
+            },
+            TILE_PUSH => {
+                if self.FIFO.is_empty() {
+                    let mut pixels = compose_two_bytes(self.fetcher.data[1], self.fetcher.data[2]);
+                    if self.fetcher.tile_attrib.x_flip {
+                        pixels.reverse();
                     }
-                    self.fetcher.cycles += 1;
-                },
-                TILE_LOW => {  // don't need to check if it's cgb because tile attrib data in dmg mode is always 0
-                    if self.fetcher.cycles == 3 {
-                        let pos = if self.fetcher.tile_attrib.y_flip {
-                            if self.fetcher.tile_mode == BG {
-                                match self.bg_window_tiledata {
-                                    true => self.fetcher.data[0] as u16 * 16 + 14 - (self.ly.wrapping_add(self.scy) as u16 % 8) * 2,
-                                    false => ((0x1000 as i16) + (self.fetcher.data[0] as i8 as i16 * 16)) as u16 + 14 - (self.ly.wrapping_add(self.scy) as u16 % 8) * 2,
-                                }
-                            } else {
-                                match self.bg_window_tiledata {
-                                    true => self.fetcher.data[0] as u16 * 16 + 14 - (self.window_line as u16 % 8) * 2,
-                                    false => ((0x1000 as i16) + (self.fetcher.data[0] as i8 as i16 * 16)) as u16 + 14 - (self.window_line as u16 % 8) * 2,
-                                }
-                            }
-                        } else {
-                            if self.fetcher.tile_mode == BG {
-                                match self.bg_window_tiledata {
-                                    true => self.fetcher.data[0] as u16 * 16 + (self.ly.wrapping_add(self.scy) as u16 % 8) * 2,
-                                    false => ((0x1000 as i16) + (self.fetcher.data[0] as i8 as i16 * 16)) as u16 + (self.ly.wrapping_add(self.scy) as u16 % 8) * 2,
-                                }
-                            } else {
-                                match self.bg_window_tiledata {
-                                    true => self.fetcher.data[0] as u16 * 16 + (self.window_line as u16 % 8) * 2,
-                                    false => ((0x1000 as i16) + (self.fetcher.data[0] as i8 as i16 * 16)) as u16 + (self.window_line as u16 % 8) * 2,
-                                }
+
+                    for pixel in pixels.iter() {
+                        self.FIFO.push(
+                            Pixel_FIFO {
+                                palette: Pixel_palette::BG,
+                                color: *pixel,
+                                priority: false,
+                                bg_attrib: Some(self.fetcher.tile_attrib),
+                                oam_pos: 0
                             }
-                        };
-                        
-                        let bank: usize = self.fetcher.tile_attrib.vram_bank as usize*0x2000;
-                        self.fetcher.data[1] = vram[pos as usize + bank];
-                        self.fetcher.data[2] = vram[(pos+1) as usize + bank];
-                    
-                        self.fetcher.mode = TILE_HIGH;
+                        );
                     }
-                    self.fetcher.cycles += 1;
-                },
-                TILE_HIGH => {
-                    if self.fetcher.cycles == 5 {
-                        self.fetcher.mode = TILE_PUSH;
-                        if self.cycles == 85 && self.fetcher.tile_mode != WIN {  // discard first background tile
-                            self.fetcher.mode = TILE_DATA;
-                            self.fetcher.cycles = 0;
-                            return true;
-                        }
-                    }
-                    self.fetcher.cycles += 1;
+                    self.fetcher.mode = TILE_DATA;
+                    self.fetcher.lx = self.fetcher.lx.wrapping_add(8);
                 }
-                TILE_PUSH => {
-                    if self.fetcher.cycles == 6 {
-                        let mut pixels = compose_two_bytes(self.fetcher.data[1], self.fetcher.data[2]);
-                        if self.fetcher.tile_attrib.x_flip {
-                            pixels.reverse();
-                        }
+            }
+        }
+    }
 
-                        for pixel in pixels.iter() {
-                            self.FIFO.push(
-                                Pixel_FIFO {
-                                    palette: Pixel_palette::BG,
-                                    color: *pixel,
-                                    priority: false,
-                                    bg_attrib: Some(self.fetcher.tile_attrib),
-                                    oam_pos: 0
-                                }
-                            );
-                        }
+    // address of the fetched tile's row, scy is read again for each byte
+    fn bg_tile_row(&self) -> usize {
+        let row = if self.fetcher.tile_mode == FetcherTileMode::BG {
+            self.ly.wrapping_add(self.scy) % 8
+        } else {
+            self.window_line % 8
+        };
+        self.bg_tile_addr(self.fetcher.data[0], self.fetcher.tile_attrib, row)
+    }
 
-                        self.fetcher.cycles += 1;
-                    } else { self.fetcher.mode = TILE_DATA; self.fetcher.cycles = 0; self.fetcher.lx += 8; }
-                }
+    // tile row of a background or window tile, row 0 - 7 before the flip
+    #[inline]
+    fn bg_tile_addr(&self, tile: u8, attrib: TileAttributes, row: u8) -> usize {
+        let base = match self.bg_window_tiledata {
+            true => tile as u16 * 16,
+            false => (0x1000_i16 + (tile as i8 as i16 * 16)) as u16,
+        };
+        let row = if attrib.y_flip { 7 - row } else { row } as u16;  // tile attrib is always 0 in dmg mode
+
+        (base + row * 2) as usize + attrib.vram_bank as usize*0x2000
+    }
+
+    // pixels of the sprite on the current line, flipped
+    fn sprite_row(&self, sprite: &Sprite, vram: &[u8]) -> [u8; 8] {
+        let mut data_pos = if sprite.y_flip {
+            if self.sprite_size {
+                (sprite.tile_location & 0xFE) as u16 * 16 + 30 - (((self.ly as u16 + 16) - sprite.y as u16)%16) * 2
+            } else {
+                sprite.tile_location as u16 * 16 + 14 - (((self.ly as u16 + 16) - sprite.y as u16)%8) * 2
+            }
+        } else {
+            if self.sprite_size {
+                (sprite.tile_location & 0xFE) as u16 * 16 + (((self.ly as u16 + 16) - sprite.y as u16)%16) * 2
+            } else {
+                sprite.tile_location as u16 * 16 + (((self.ly as u16 + 16) - sprite.y as u16)%8) * 2
             }
+        };
+
+        if self.gb_mode == MODE::CGB {
+            data_pos += 0x2000*sprite.vram_bank as u16;
+        }
+
+        let mut px = compose_two_bytes(vram[data_pos as usize], vram[data_pos as usize + 1]);
+        if sprite.x_flip { px.reverse(); }
+        px
+    }
+
+    // whether a pixel of a sprite fetched later covers the one already there. Sprites are fetched
+    // left to right, by x priority the first one keeps its pixels, by OAM priority the lower OAM index wins
+    #[inline]
+    fn sprite_replaces(&self, old: &Pixel_FIFO, new: &Pixel_FIFO) -> bool {
+        (!self.x_priority() && old.oam_pos > new.oam_pos) || old.color == 0
+    }
 
-            if self.FIFO.len() > 0 {
-                if self.fetcher.discard_pixels == 0 && self.scx%8 != 0 {
-                    self.fetcher.discard_pixels = self.scx%8 + 1;
-                    return true;
+    // bg/window and sprite priority, draws the resulting color
+    #[inline]
+    fn mix_pixel(&mut self, x: u8, pixel: Pixel_FIFO, sprite_pixel: Option<Pixel_FIFO>) {
+        if self.gb_mode != MODE::CGB {
+            let bg_color = if self.bg_enabled { pixel.color } else { 0 };
+            let mut color = if self.bg_enabled {
+                self.color_map[0][map_to_palette(bg_color, self.palette[usize::from(Pixel_palette::BG)])]
+            } else {
+                self.color_map[0][0]  // blank
+            };
+
+            if let Some(sprite_pixel) = sprite_pixel {
+                if sprite_pixel.color != 0 && (!sprite_pixel.priority || bg_color == 0) {
+                    let p = usize::from(sprite_pixel.palette);
+                    color = self.color_map[p][map_to_palette(sprite_pixel.color, self.palette[p])];
                 }
+            }
+            self.draw_pixel(x, self.ly, color);
+        } else {
+            let mut color = self.bg_palette[pixel.bg_attrib.unwrap().palette as usize][pixel.color as usize];
 
-                if self.sprites.len() > 0 {
-                    for (i, sprite) in self.sprites.iter().enumerate() {
-                        if self.fetcher.current_pixel_push + 8 >= sprite.x {
-                            if self.sprite_enabled {
-                                self.fetcher.current_sprite = Some(*sprite);
-                                self.sprites.remove(i);
-                                return true;
-                            }
-                        }
-                    }
+            if let Some(sprite_pixel) = sprite_pixel {
+                if sprite_pixel.color != 0
+                    && (((!sprite_pixel.priority || pixel.color == 0) && !pixel.bg_attrib.unwrap().priority) || (pixel.bg_attrib.unwrap().priority && pixel.color == 0) || !self.bg_enabled) {
+                    color = self.obj_palette[usize::from(sprite_pixel.palette)][sprite_pixel.color as usize];
                 }
+            }
 
-                let pixel = self.FIFO.remove(0);
+            self.draw_pixel_rgb_correct(x, self.ly, color);
+        }
+    }
 
-                if self.gb_mode == MODE::DMG {
-                    let mut color = self.color_map[map_to_palette(pixel.color, self.palette[usize::from(Pixel_palette::BG)])];
-                    if !self.bg_enabled {
-                        color = Color::WHITE;
+    // Draws the whole line from the registers at the start of mode 3, for AccuracyPreset::Fast.
+    // Same layers and priorities as the FIFO, but mid-line register writes aren't seen and mode 3
+    // always takes SCANLINE_DRAW_CYCLES
+    fn render_scanline(&mut self, vram: &[u8]) {
+        // sprites in the order the fetcher reaches them, by x then oam order
+        let mut sprite_line: [Option<Pixel_FIFO>; 160] = [None; 160];
+        if self.sprite_enabled {
+            let mut sprites = self.sprites.clone();
+            sprites.sort_by_key(|s| s.x);
+            for sprite in sprites.iter() {
+                let palette = if self.gb_mode == MODE::CGB { sprite.cgb_palette } else { sprite.palette };
+                for (i, val) in self.sprite_row(sprite, vram).iter().enumerate() {
+                    let x = sprite.x as usize + i;
+                    if !(8 .. 168).contains(&x) {
+                        continue;
                     }
 
-                    if self.FIFO_sprite.len() > 0 {
-                        let sprite_pixel = self.FIFO_sprite.remove(0);
-                        if sprite_pixel.color != 0 && (!sprite_pixel.priority || color == Color::WHITE) {
-                            color = self.color_map[map_to_palette(sprite_pixel.color, self.palette[usize::from(sprite_pixel.palette)])];
-                        }
+                    let new_px = Pixel_FIFO {
+                        palette,
+                        color: *val,
+                        priority: sprite.priority,
+                        bg_attrib: None,
+                        oam_pos: sprite.oam_addr
+                    };
+                    match &sprite_line[x - 8] {
+                        Some(old) if !self.sprite_replaces(old, &new_px) => (),
+                        _ => sprite_line[x - 8] = Some(new_px)
                     }
-                    self.d.draw_pixel(self.fetcher.current_pixel_push, self.ly, color);
-                    self.fetcher.current_pixel_push += 1;
-                } else {
-                    let mut color = self.bg_palette[pixel.bg_attrib.unwrap().palette as usize][pixel.color as usize];
+                }
+            }
+        }
 
-                    if self.FIFO_sprite.len() > 0 {
-                        let sprite_pixel = self.FIFO_sprite.remove(0);
-                        if sprite_pixel.color != 0 {
-                            if ((!sprite_pixel.priority || pixel.color == 0) && !pixel.bg_attrib.unwrap().priority) || (pixel.bg_attrib.unwrap().priority && pixel.color == 0) || !self.bg_enabled {
-                                color = self.obj_palette[usize::from(sprite_pixel.palette)][sprite_pixel.color as usize];
-                            }
-                        }
-                    }
+        // the FIFO switches to the window when wx - 7 is reached, never for wx below 7
+        let window_x = if self.window_enabled && self.window_y_trigger && self.wx >= 7 && self.wx < 167 {
+            Some(self.wx - 7)
+        } else { None };
+        let mut tile = (0xFFFF, [0; 8], TileAttributes::new(0));  // map position, pixels, attributes
+        for x in 0 .. 160u8 {
+            let (map_pos, tile_x, row) = match window_x {
+                Some(wx) if x >= wx => {
+                    let lx = x - wx;
+                    let base = if self.window_tilemap { 0x1C00 } else { 0x1800 };
+                    (base + (self.window_line as usize / 8) * 32 + lx as usize / 8, lx % 8, self.window_line % 8)
+                },
+                _ => {
+                    let lx = x.wrapping_add(self.scx);
+                    let ly = self.ly.wrapping_add(self.scy);
+                    let base = if self.bg_tilemap { 0x1C00 } else { 0x1800 };
+                    (base + (ly as usize / 8) * 32 + lx as usize / 8, lx % 8, ly % 8)
+                }
+            };
 
-                    self.d.draw_pixel_rgb_correct(self.fetcher.current_pixel_push, self.ly, color);
-                    self.fetcher.current_pixel_push += 1;
+            if tile.0 != map_pos {
+                let attrib = if self.gb_mode == MODE::CGB {
+                    TileAttributes::new(vram[map_pos + 0x2000])
+                } else {
+                    TileAttributes::new(0)
                 };
+                let pos = self.bg_tile_addr(vram[map_pos], attrib, row);
+                let mut pixels = compose_two_bytes(vram[pos], vram[pos + 1]);
+                if attrib.x_flip {
+                    pixels.reverse();
+                }
+                tile = (map_pos, pixels, attrib);
+            }
 
+            let pixel = Pixel_FIFO {
+                palette: Pixel_palette::BG,
+                color: tile.1[tile_x as usize],
+                priority: false,
+                bg_attrib: Some(tile.2),
+                oam_pos: 0
             };
+            self.mix_pixel(x, pixel, sprite_line[x as usize]);
+        }
 
-            if self.fetcher.current_pixel_push == 160 {
-                self.FIFO = vec![];
-                self.FIFO_sprite = vec![];
-                if self.fetcher.tile_mode == WIN {
-                    self.window_line += 1;
-                }
-                return false;
-            }
+        if window_x.is_some() {
+            self.window_line += 1;
         }
-        true
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // line 0 with ten sprites on it, in mode 3 at dot 300
+    fn drawing() -> (PPU, Vec<u8>, Vec<u8>) {
+        let vram = vec![0; 0x4000];
+        let mut oam = vec![0; 160];
+        for i in 0 .. 10 {
+            oam[i*4] = 16;
+            oam[i*4 + 1] = 8 + i as u8 * 9;
+        }
+        let mut ppu = PPU::new();
+        ppu.scanline_renderer = false;
+        ppu.write(0xFF40, 0x93);
+        (ppu, vram, oam)
+    }
+
+    fn reload(ppu: &PPU) -> Result<PPU, &'static str> {
+        let mut w = StateWriter::new();
+        ppu.save_state(&mut w);
+        let data = w.finish();
+        let mut loaded = PPU::new();
+        loaded.scanline_renderer = false;
+        loaded.load_state(&mut StateReader::new(&data))?;
+        Ok(loaded)
+    }
+
+    #[test]
+    fn state_in_mode3_finishes_the_line() {
+        let (mut ppu, mut vram, mut oam) = drawing();
+        let mut IF = 0;
+        while !(ppu.mode == PPU_MODE::DRAW && ppu.cycles == 300) {
+            ppu.tick(&mut vram, &mut oam, false, &mut IF);
+        }
+
+        let mut loaded = reload(&ppu).unwrap();
+        for _ in 0 .. 456 {
+            loaded.tick(&mut vram, &mut oam, false, &mut IF);
+        }
+        assert_eq!(loaded.ly, 1);
+        assert!(loaded.cycles <= 456);
+    }
+
+    #[test]
+    fn rejects_impossible_positions() {
+        let (mut ppu, _, _) = drawing();
+        assert!(reload(&ppu).is_ok());
+
+        ppu.mode = PPU_MODE::OAM;
+        ppu.cycles = 80;
+        assert!(reload(&ppu).is_err());
+
+        ppu.mode = PPU_MODE::DRAW;
+        ppu.cycles = 79;
+        assert!(reload(&ppu).is_err());
+        ppu.cycles = 456;
+        assert!(reload(&ppu).is_err());
+
+        ppu.mode = PPU_MODE::HBLANK;
+        ppu.cycles = 457;
+        assert!(reload(&ppu).is_err());
+        ppu.cycles = 200;
+        assert!(reload(&ppu).is_ok());
+
+        ppu.ly = 150;
+        assert!(reload(&ppu).is_ok());
+        for mode in [PPU_MODE::OAM, PPU_MODE::DRAW] {
+            ppu.mode = mode;
+            ppu.cycles = 80;
+            assert!(reload(&ppu).is_err());
+        }
+        ppu.mode = PPU_MODE::VBLANK;
+        assert!(reload(&ppu).is_ok());
+
+        // nothing runs with the lcd off
+        ppu.write(0xFF40, 0x13);
+        ppu.mode = PPU_MODE::HBLANK;
+        ppu.cycles = 30000;
+        assert!(reload(&ppu).is_ok());
+    }
+}
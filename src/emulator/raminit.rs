@@ -0,0 +1,59 @@
+use crate::emulator::{StateWriter, StateReader};
+
+// What WRAM, VRAM and HRAM hold at power on. The chips come up with whatever their cells settle
+// to and a few games read ram before writing it, so they behave differently between patterns.
+// Only the power on fill, the contents themselves go into save states like any other ram
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RamInit {
+    Zero,
+    Ones,  // all FF
+    Stripes,  // runs of 8 00s and 8 FFs, roughly what DMG work ram shows
+    Random(u32)  // xorshift from the seed, the same seed fills the same way every time
+}
+
+impl RamInit {
+    // salt keeps the regions filled from one seed from repeating each other
+    pub fn fill(&self, ram: &mut [u8], salt: u32) {
+        match *self {
+            RamInit::Zero => ram.iter_mut().for_each(|b| *b = 0),
+            RamInit::Ones => ram.iter_mut().for_each(|b| *b = 0xFF),
+            RamInit::Stripes => {
+                for (i, b) in ram.iter_mut().enumerate() {
+                    *b = if i&8 == 0 { 0x00 } else { 0xFF };
+                }
+            },
+            RamInit::Random(seed) => {
+                let mut x = (seed ^ salt.wrapping_mul(0x9E3779B9)).max(1);  // xorshift is stuck at 0
+                for b in ram.iter_mut() {
+                    x ^= x << 13;
+                    x ^= x >> 17;
+                    x ^= x << 5;
+                    *b = x as u8;
+                }
+            }
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let (kind, seed) = match *self {
+            RamInit::Zero => (0, 0),
+            RamInit::Ones => (1, 0),
+            RamInit::Stripes => (2, 0),
+            RamInit::Random(seed) => (3, seed)
+        };
+        w.write_u8(kind);
+        w.write_u32(seed);
+    }
+
+    pub fn load_state(r: &mut StateReader) -> Result<RamInit, &'static str> {
+        let kind = r.read_u8()?;
+        let seed = r.read_u32()?;
+        Ok(match kind {
+            0 => RamInit::Zero,
+            1 => RamInit::Ones,
+            2 => RamInit::Stripes,
+            3 => RamInit::Random(seed),
+            _ => return Err("invalid ram init pattern")
+        })
+    }
+}
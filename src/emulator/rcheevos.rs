@@ -0,0 +1,33 @@
+#![allow(dead_code)]  // entry points for frontends built on the core
+
+use core::ffi::c_void;
+
+use crate::emulator::{Memory, MODE};
+
+// RetroAchievements Game Boy memory map: the 16 bit address space as the cpu sees it,
+// followed by CGB wram banks 2-7 at 0x10000 - 0x15FFF
+pub const MEMORY_SIZE: u32 = 0x16000;
+
+impl Memory {
+    fn ra_peek_byte(&mut self, address: u32) -> u8 {
+        match address {
+            0x0000 ..= 0xFFFF => self.read(address as u16),
+            0x10000 ..= 0x15FFF if self.mode == MODE::CGB => {
+                let bank = (2 + (address - 0x10000) / 0x1000) as u8;
+                self.peek_bank(0xD000 | (address as u16&0xFFF), bank)
+            },
+            _ => 0
+        }
+    }
+
+    // little endian read of 1, 2 or 4 bytes
+    pub fn ra_peek(&mut self, address: u32, num_bytes: u32) -> u32 {
+        (0 .. num_bytes.min(4)).fold(0, |v, i| v | (self.ra_peek_byte(address + i) as u32) << (i*8))
+    }
+}
+
+// rc_runtime_do_frame peek callback, userdata has to point at the emulator's Memory
+pub extern "C" fn peek(address: u32, num_bytes: u32, userdata: *mut c_void) -> u32 {
+    let memory = unsafe { &mut *(userdata as *mut Memory) };
+    memory.ra_peek(address, num_bytes)
+}
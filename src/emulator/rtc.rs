@@ -0,0 +1,469 @@
+#[cfg(feature = "std")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::emulator::{StateWriter, StateReader};
+
+const CLOCK_SPEED: u64 = 4194304;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RtcSource {
+    Host,      // advances with wall-clock time, same as Emulated without std
+    Emulated,  // advances with emulated cycles, deterministic
+    Frozen     // never advances
+}
+
+// Time base for cartridge real-time clocks, counted in seconds since the cartridge was powered on
+pub struct RtcClock {
+    pub source: RtcSource,
+    pub offset: u64,  // seconds added to the elapsed time, lets the clock start at a chosen day/time
+    #[cfg(feature = "std")]
+    start: Instant,
+    cycles: u64
+}
+
+impl RtcClock {
+    pub fn new(source: RtcSource, offset: u64) -> RtcClock {
+        RtcClock {
+            source,
+            offset,
+            #[cfg(feature = "std")]
+            start: Instant::now(),
+            cycles: 0
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    pub fn seconds(&self) -> u64 {
+        let elapsed = match self.source {
+            #[cfg(feature = "std")]
+            RtcSource::Host => self.start.elapsed().as_secs(),
+            #[cfg(not(feature = "std"))]
+            RtcSource::Host => self.cycles / CLOCK_SPEED,
+            RtcSource::Emulated => self.cycles / CLOCK_SPEED,
+            RtcSource::Frozen => 0
+        };
+
+        self.offset + elapsed
+    }
+}
+
+// seconds since 1970 for the save footer, None without a host clock
+pub fn unix_time() -> Option<u64> {
+    #[cfg(feature = "std")]
+    {
+        SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        None
+    }
+}
+
+// bytes after cart ram in a .sav, current and latched registers as u32 then a unix timestamp,
+// the layout VBA and BGB use. Older saves have a 32 bit timestamp
+pub const RTC_FOOTER_SIZE: usize = 48;
+pub const RTC_FOOTER_SIZE_SHORT: usize = 44;
+
+#[derive(Clone, Copy, Default)]
+struct RtcTime {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,  // 9 bits
+    halt: bool,
+    carry: bool  // days overflowed, stays set until the game clears it
+}
+
+impl RtcTime {
+    fn add(&mut self, n: u64) {
+        if self.halt {
+            return;
+        }
+        let total = self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600 + self.days as u64 * 86400 + n;
+        let days = total / 86400;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.days = (days & 0x1FF) as u16;
+        if days > 0x1FF {
+            self.carry = true;
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        let dh = (self.days >> 8) as u8 | (self.halt as u8) << 6 | (self.carry as u8) << 7;
+        [self.seconds, self.minutes, self.hours, self.days as u8, dh]
+    }
+}
+
+// MBC3 clock registers. They only move when looked at, brought up to date from the RtcClock
+pub struct Mbc3Rtc {
+    clock: RtcClock,
+    time: RtcTime,
+    latched: [u8; 5],  // 08-0C as the game reads them
+    latch_armed: bool,  // 0 written to 6000-7FFF, a 1 next latches
+    synced_at: u64,  // clock seconds the registers are up to date with
+    saved_at: Option<u64>,  // unix time from a loaded footer, caught up with once the clock is set
+    loaded: bool
+}
+
+impl Mbc3Rtc {
+    pub fn new(clock: RtcClock) -> Mbc3Rtc {
+        let mut rtc = Mbc3Rtc {
+            clock,
+            time: RtcTime::default(),
+            latched: [0; 5],
+            latch_armed: false,
+            synced_at: 0,
+            saved_at: None,
+            loaded: false
+        };
+        rtc.start();
+        rtc
+    }
+
+    #[inline]
+    pub fn tick(&mut self) {
+        self.clock.tick();
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.clock = clock;
+        self.start();
+    }
+
+    // a fresh cartridge starts at the clock's offset, a saved one catches up with the time it was off
+    fn start(&mut self) {
+        let now = self.clock.seconds();
+        if !self.loaded {
+            self.time = RtcTime::default();
+            self.time.add(now);
+        } else if let (Some(saved), Some(unix), RtcSource::Host) = (self.saved_at.take(), unix_time(), self.clock.source) {
+            self.time.add(unix.saturating_sub(saved));
+        }
+        self.synced_at = now;
+    }
+
+    fn update(&mut self) {
+        let now = self.clock.seconds();
+        self.time.add(now.saturating_sub(self.synced_at));
+        self.synced_at = now;
+    }
+
+    // writes to 6000-7FFF
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 1 {
+            self.update();
+            self.latched = self.time.registers();
+        }
+        self.latch_armed = val == 0;
+    }
+
+    // 08 seconds, 09 minutes, 0A hours, 0B low 8 bits of the day counter,
+    // 0C bit 0 - day counter bit 8, bit 6 - halt, bit 7 - day counter carry
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched[(reg - 0x8) as usize]
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8) {
+        self.update();
+        let t = &mut self.time;
+        match reg {
+            0x8 => t.seconds = val&0x3F,
+            0x9 => t.minutes = val&0x3F,
+            0xA => t.hours = val&0x1F,
+            0xB => t.days = (t.days&0x100) | val as u16,
+            0xC => {
+                t.days = (t.days&0xFF) | ((val&0x1) as u16) << 8;
+                t.halt = val&0x40 != 0;
+                t.carry = val&0x80 != 0;
+            },
+            _ => ()
+        }
+    }
+
+    pub fn footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut time = self.time;
+        time.add(self.clock.seconds().saturating_sub(self.synced_at));
+
+        let mut out = [0; RTC_FOOTER_SIZE];
+        for (i, v) in time.registers().iter().chain(self.latched.iter()).enumerate() {
+            out[i*4] = *v;
+        }
+        out[40 ..].copy_from_slice(&unix_time().unwrap_or(0).to_le_bytes());
+        out
+    }
+
+    // registers as of the save, they pick up from there whatever the clock says now
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let mut time = self.time;
+        time.add(self.clock.seconds().saturating_sub(self.synced_at));
+        w.write(&time.registers());
+        w.write(&self.latched);
+        w.write_bool(self.latch_armed);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        let mut regs = [0; 5];
+        r.read_into(&mut regs)?;
+        self.synced_at = self.clock.seconds();
+        self.saved_at = None;
+        for (reg, val) in regs.iter().enumerate() {
+            self.write(0x8 + reg as u8, *val);
+        }
+        r.read_into(&mut self.latched)?;
+        self.latch_armed = r.read_bool()?;
+        Ok(())
+    }
+
+    // the time off is made up for by the next set_clock, when it's known whether the clock follows the host
+    pub fn load_footer(&mut self, data: &[u8]) {
+        if data.len() < RTC_FOOTER_SIZE_SHORT {
+            return;
+        }
+        let reg = |i: usize| data[i*4];
+        self.time = RtcTime {
+            seconds: reg(0),
+            minutes: reg(1),
+            hours: reg(2),
+            days: reg(3) as u16 | ((reg(4)&0x1) as u16) << 8,
+            halt: reg(4)&0x40 != 0,
+            carry: reg(4)&0x80 != 0
+        };
+        for i in 0 .. 5 {
+            self.latched[i] = reg(5 + i);
+        }
+
+        let mut ts = [0; 8];
+        let len = (data.len() - 40).min(8);
+        ts[.. len].copy_from_slice(&data[40 .. 40 + len]);
+        self.saved_at = Some(u64::from_le_bytes(ts)).filter(|t| *t != 0);
+        self.synced_at = self.clock.seconds();
+        self.loaded = true;
+    }
+}
+
+// HuC3 clock, a minute and day counter behind a nibble wide command port with 256 nibbles of
+// its own memory. Commands are written as cmd << 4 | argument:
+//     1 - read the nibble at the index and advance, 3 - write the argument there and advance,
+//     4/5 - set the low/high nibble of the index, 6 - 0 copies the time to memory 00-05
+//     (minutes then days, 12 bits each, low nibble first), 1 copies it back, 2 reports ready.
+// Saved after the ram in a footer the size of the MBC3 one so save files work the same:
+// seconds as u64 at 0, unix time at 40
+pub struct Huc3Rtc {
+    clock: RtcClock,
+    memory: [u8; 256],
+    index: u8,
+    command: u8,
+    response: u8,
+    time: u64,  // seconds, only minutes and days are visible
+    synced_at: u64,
+    saved_at: Option<u64>,
+    loaded: bool
+}
+
+impl Huc3Rtc {
+    pub fn new(clock: RtcClock) -> Huc3Rtc {
+        let mut rtc = Huc3Rtc {
+            clock,
+            memory: [0; 256],
+            index: 0,
+            command: 0,
+            response: 0,
+            time: 0,
+            synced_at: 0,
+            saved_at: None,
+            loaded: false
+        };
+        rtc.start();
+        rtc
+    }
+
+    #[inline]
+    pub fn tick(&mut self) {
+        self.clock.tick();
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.clock = clock;
+        self.start();
+    }
+
+    fn start(&mut self) {
+        let now = self.clock.seconds();
+        if !self.loaded {
+            self.time = now;
+        } else if let (Some(saved), Some(unix), RtcSource::Host) = (self.saved_at.take(), unix_time(), self.clock.source) {
+            self.time += unix.saturating_sub(saved);
+        }
+        self.synced_at = now;
+    }
+
+    fn now(&self) -> u64 {
+        self.time + self.clock.seconds().saturating_sub(self.synced_at)
+    }
+
+    // writes in mode 0x0B
+    pub fn write_command(&mut self, val: u8) {
+        let arg = val&0xF;
+        self.command = val >> 4 & 0x7;
+        match self.command {
+            0x1 => {
+                self.response = self.memory[self.index as usize];
+                self.index = self.index.wrapping_add(1);
+            },
+            0x3 => {
+                self.memory[self.index as usize] = arg;
+                self.index = self.index.wrapping_add(1);
+            },
+            0x4 => self.index = (self.index&0xF0) | arg,
+            0x5 => self.index = (self.index&0x0F) | arg << 4,
+            0x6 => match arg {
+                0x0 => {
+                    let now = self.now();
+                    let minutes = now / 60 % 1440;
+                    let days = (now / 86400) & 0xFFF;
+                    for i in 0 .. 3 {
+                        self.memory[i] = (minutes >> (i*4)) as u8 & 0xF;
+                        self.memory[3 + i] = (days >> (i*4)) as u8 & 0xF;
+                    }
+                },
+                0x1 => {
+                    let nibbles = |from: usize| (0 .. 3).fold(0, |v, i| v | (self.memory[from + i] as u64) << (i*4));
+                    self.time = nibbles(3) * 86400 + nibbles(0).min(1439) * 60;
+                    self.synced_at = self.clock.seconds();
+                },
+                0x2 => self.response = 1,
+                _ => ()
+            },
+            _ => ()
+        }
+    }
+
+    // reads in mode 0x0C
+    pub fn read_response(&self) -> u8 {
+        self.command << 4 | self.response
+    }
+
+    pub fn footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut out = [0; RTC_FOOTER_SIZE];
+        out[.. 8].copy_from_slice(&self.now().to_le_bytes());
+        out[40 ..].copy_from_slice(&unix_time().unwrap_or(0).to_le_bytes());
+        out
+    }
+
+    pub fn load_footer(&mut self, data: &[u8]) {
+        if data.len() < RTC_FOOTER_SIZE {
+            return;
+        }
+        let u64_at = |i: usize| {
+            let mut b = [0; 8];
+            b.copy_from_slice(&data[i .. i + 8]);
+            u64::from_le_bytes(b)
+        };
+        self.time = u64_at(0);
+        self.saved_at = Some(u64_at(40)).filter(|t| *t != 0);
+        self.synced_at = self.clock.seconds();
+        self.loaded = true;
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.now());
+        w.write(&self.memory);
+        w.write(&[self.index, self.command, self.response]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), &'static str> {
+        self.time = r.read_u64()?;
+        self.synced_at = self.clock.seconds();
+        self.saved_at = None;
+        r.read_into(&mut self.memory)?;
+        self.index = r.read_u8()?;
+        self.command = r.read_u8()? & 0x7;
+        self.response = r.read_u8()? & 0xF;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(rtc: &mut Mbc3Rtc, seconds: u64) {
+        for _ in 0 .. seconds * CLOCK_SPEED {
+            rtc.tick();
+        }
+    }
+
+    fn latch(rtc: &mut Mbc3Rtc) {
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+    }
+
+    fn registers(rtc: &Mbc3Rtc) -> [u8; 5] {
+        [rtc.read(0x8), rtc.read(0x9), rtc.read(0xA), rtc.read(0xB), rtc.read(0xC)]
+    }
+
+    #[test]
+    fn latched_registers_hold_until_the_next_latch() {
+        let mut rtc = Mbc3Rtc::new(RtcClock::new(RtcSource::Emulated, 59));
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [59, 0, 0, 0, 0]);
+
+        run(&mut rtc, 2);
+        assert_eq!(registers(&rtc), [59, 0, 0, 0, 0]);
+
+        // a 1 without a 0 before it doesn't latch
+        rtc.write_latch(1);
+        assert_eq!(registers(&rtc), [59, 0, 0, 0, 0]);
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn halt_stops_the_clock_and_days_carry() {
+        let mut rtc = Mbc3Rtc::new(RtcClock::new(RtcSource::Emulated, 511*86400 + 86399));
+        run(&mut rtc, 1);
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [0, 0, 0, 0, 0x80]);
+
+        rtc.write(0xC, 0x40);
+        run(&mut rtc, 1);
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [0, 0, 0, 0, 0x40]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = Mbc3Rtc::new(RtcClock::new(RtcSource::Emulated, 3*86400 + 2*3600 + 60 + 7));
+        latch(&mut rtc);
+        rtc.write(0xB, 0x34);
+        rtc.write(0xC, 0x01);
+        let footer = rtc.footer();
+        assert_eq!(footer[.. 20], [7, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0x34, 0, 0, 0, 0x01, 0, 0, 0]);
+        assert_eq!(footer[20 .. 40], [7, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut loaded = Mbc3Rtc::new(RtcClock::new(RtcSource::Frozen, 0));
+        loaded.load_footer(&footer);
+        loaded.set_clock(RtcClock::new(RtcSource::Frozen, 0));
+        assert_eq!(registers(&loaded), [7, 1, 2, 3, 0]);
+        latch(&mut loaded);
+        assert_eq!(registers(&loaded), [7, 1, 2, 0x34, 0x01]);
+
+        // old saves with a 32 bit timestamp load the same, shorter ones are ignored
+        let mut short = Mbc3Rtc::new(RtcClock::new(RtcSource::Frozen, 0));
+        short.load_footer(&footer[.. RTC_FOOTER_SIZE_SHORT]);
+        latch(&mut short);
+        assert_eq!(registers(&short), [7, 1, 2, 0x34, 0x01]);
+
+        let mut none = Mbc3Rtc::new(RtcClock::new(RtcSource::Frozen, 0));
+        none.load_footer(&footer[.. 40]);
+        latch(&mut none);
+        assert_eq!(registers(&none), [0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,238 @@
+use alloc::vec::Vec;
+
+use crate::emulator::compress;
+
+// Save state layout: "SGBS", u16 version, u32 cartridge header hash, u8 model, then every
+// module's fields in the order its save_state writes them. Numbers are little endian,
+// buffers whose size depends on the build or cartridge are prefixed with a u32 length
+pub const STATE_MAGIC: [u8; 4] = *b"SGBS";
+pub const STATE_VERSION: u16 = 12;
+
+// "SGBZ", u32 size of the state, then the state as an LZ4 block
+pub const COMPRESSED_MAGIC: [u8; 4] = *b"SGBZ";
+
+pub fn compress_state(state: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(state.len() / 4);
+    out.extend_from_slice(&COMPRESSED_MAGIC);
+    out.extend_from_slice(&(state.len() as u32).to_le_bytes());
+    out.extend_from_slice(&compress::compress(state));
+    out
+}
+
+pub fn decompress_state(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if data.len() < 8 || !data.starts_with(&COMPRESSED_MAGIC) {
+        return Err("not a compressed save state")
+    }
+    let len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let state = compress::decompress(&data[8 ..], len)?;
+    if !state.starts_with(&STATE_MAGIC) {
+        return Err("not a save state")
+    }
+    Ok(state)
+}
+
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            data: Vec::with_capacity(0x20000)
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.data.push(v as u8);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.write(&v.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.write(&v.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+
+    // length prefixed, read back with read_bytes
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.write_u32(data.len() as u32);
+        self.write(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            pos: 0
+        }
+    }
+
+    pub fn read(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        if self.data.len() - self.pos < n {
+            return Err("save state is truncated")
+        }
+        let out = &self.data[self.pos .. self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    // fills a fixed size buffer
+    pub fn read_into(&mut self, out: &mut [u8]) -> Result<(), &'static str> {
+        out.copy_from_slice(self.read(out.len())?);
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.read(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, &'static str> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, &'static str> {
+        let mut b = [0; 2];
+        self.read_into(&mut b)?;
+        Ok(u16::from_le_bytes(b))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, &'static str> {
+        let mut b = [0; 4];
+        self.read_into(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, &'static str> {
+        let mut b = [0; 8];
+        self.read_into(&mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], &'static str> {
+        let len = self.read_u32()? as usize;
+        self.read(len)
+    }
+
+    // a length prefixed buffer that has to be exactly out's size
+    pub fn read_bytes_into(&mut self, out: &mut [u8]) -> Result<(), &'static str> {
+        let data = self.read_bytes()?;
+        if data.len() != out.len() {
+            return Err("save state memory sizes don't match this build")
+        }
+        out.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), &'static str> {
+        if self.pos != self.data.len() {
+            return Err("save state has trailing data")
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::emulator::CPU;
+
+    // Counts up through C000-C0FF forever with the timer running, checksums filled in
+    pub(crate) fn cpu() -> CPU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100 .. 0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x134 .. 0x13C].copy_from_slice(b"SAVETEST");
+        rom[0x150 .. 0x15D].copy_from_slice(&[
+            0x3E, 0x05, 0xE0, 0x07,  // ld a, 5; ldh (TAC), a
+            0x21, 0x00, 0xC0,        // ld hl, C000
+            0x34, 0x2C, 0x7D, 0xEA, 0x00, 0x98  // inc (hl); inc l; ld a, l; ld (9800), a
+        ]);
+        rom[0x15D .. 0x15F].copy_from_slice(&[0x18, 0xF8]);  // jr -8
+        rom[0x14D] = rom[0x134 ..= 0x14C].iter().fold(0u8, |s, b| s.wrapping_sub(*b).wrapping_sub(1));
+        let sum = rom.iter().fold(0u16, |s, b| s.wrapping_add(*b as u16));
+        rom[0x14E .. 0x150].copy_from_slice(&sum.to_be_bytes());
+
+        let mut cpu = CPU::new();
+        cpu.memory.load_rom_from_bytes(rom).unwrap();
+        cpu.boot();
+        cpu
+    }
+
+    pub(crate) fn run(cpu: &mut CPU, frames: usize) {
+        for _ in 0 .. frames {
+            cpu.run_frame();
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut a = cpu();
+        run(&mut a, 3);
+        let state = a.save_state();
+        run(&mut a, 2);
+        let later = a.save_state();
+        assert_ne!(state, later);
+
+        let mut b = cpu();
+        b.load_state(&state).unwrap();
+        assert_eq!(b.save_state(), state);
+        run(&mut b, 2);
+        assert_eq!(b.save_state(), later);
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let mut a = cpu();
+        run(&mut a, 3);
+        let packed = a.save_state_compressed();
+        assert!(packed.len() < a.save_state().len() / 4);
+
+        let mut b = cpu();
+        b.load_state(&packed).unwrap();
+        assert_eq!(b.save_state(), a.save_state());
+    }
+
+    #[test]
+    fn failed_load_keeps_the_machine() {
+        let mut a = cpu();
+        run(&mut a, 3);
+        let state = a.save_state();
+        run(&mut a, 1);
+        let before = a.save_state();
+
+        assert!(a.load_state(&state[.. state.len() - 1]).is_err());
+        assert!(a.load_state(&[state.clone(), vec![0]].concat()).is_err());
+        assert!(a.load_state(b"SGBS").is_err());
+        let mut other_game = state.clone();
+        other_game[6] ^= 1;  // header hash
+        assert!(a.load_state(&other_game).is_err());
+        assert_eq!(a.save_state(), before);
+    }
+}
@@ -0,0 +1,127 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+// Whatever is plugged into the other end of the link cable
+pub trait SerialDevice {
+    // called when a transfer on the gameboy's own clock finishes with the byte it sent, returns the byte it receives
+    fn exchange(&mut self, out: u8) -> u8;
+
+    // External clock, called every cycle while the gameboy waits for the device to clock a transfer,
+    // with the byte it would send. Returns the received byte once all 8 bits came in.
+    // Devices that can't drive the clock leave the transfer waiting forever, like the hardware does
+    fn external_clock(&mut self, _out: u8) -> Option<u8> { None }
+
+    // the game stopped or restarted a transfer by writing SC
+    fn transfer_cancelled(&mut self) {}
+
+    // true once after a printer finished printing, for Event::PrintJob
+    fn take_print_job(&mut self) -> bool { false }
+}
+
+// Built-in peer that answers every byte by itself, echoing it back unless the table says otherwise
+#[derive(Clone, Debug)]
+pub struct Loopback {
+    table: Vec<(u8, u8)>,  // sent byte, reply
+    fallback: Option<u8>,  // reply to bytes missing from the table, None - echo them
+}
+
+impl Loopback {
+    pub fn echo() -> Loopback {
+        Loopback {
+            table: vec![],
+            fallback: None
+        }
+    }
+
+    // `echo` or whitespace separated `<sent>:<reply>` hex pairs, `*:<reply>` answers everything else
+    pub fn parse(spec: &str) -> Result<Loopback, &'static str> {
+        let mut l = Loopback::echo();
+        if spec == "echo" {
+            return Ok(l)
+        }
+
+        for pair in spec.split_whitespace() {
+            let mut it = pair.splitn(2, ':');
+            let sent = it.next().unwrap();
+            let reply = it.next()
+                .and_then(|r| u8::from_str_radix(r, 16).ok())
+                .ok_or("expected <sent>:<reply> hex pairs")?;
+
+            if sent == "*" {
+                l.fallback = Some(reply);
+            } else {
+                let sent = u8::from_str_radix(sent, 16).map_err(|_| "expected <sent>:<reply> hex pairs")?;
+                l.table.push((sent, reply));
+            }
+        }
+        Ok(l)
+    }
+}
+
+impl SerialDevice for Loopback {
+    fn exchange(&mut self, out: u8) -> u8 {
+        match self.table.iter().find(|(sent, _)| *sent == out) {
+            Some((_, reply)) => *reply,
+            None => self.fallback.unwrap_or(out)
+        }
+    }
+}
+
+// Both ends' state, shared by the two LinkPorts
+struct Wire {
+    waiting: [Option<u8>; 2],  // a side waiting for the other to clock, with the byte it sends
+    delivered: [Option<u8>; 2]  // clocked in by the other side, not yet picked up
+}
+
+// One end of a LinkCable
+pub struct LinkPort {
+    wire: Rc<RefCell<Wire>>,
+    side: usize
+}
+
+// Two emulated gameboys wired together in one process. Whichever side starts a transfer on its own
+// clock is the master, it swaps bytes with the other side if that one is waiting on the external
+// clock, or reads 0xFF when it isn't. The instances have to run in step for transfers to arrive
+// in time, see CPU::run_frame_linked
+pub struct LinkCable;
+
+impl LinkCable {
+    // the two ends, one to plug into each instance
+    pub fn ends() -> (LinkPort, LinkPort) {
+        let wire = Rc::new(RefCell::new(Wire {
+            waiting: [None; 2],
+            delivered: [None; 2]
+        }));
+        (LinkPort { wire: wire.clone(), side: 0 }, LinkPort { wire, side: 1 })
+    }
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let other = 1 - self.side;
+        match wire.waiting[other].take() {
+            Some(reply) => {
+                wire.delivered[other] = Some(out);
+                reply
+            },
+            None => 0xFF
+        }
+    }
+
+    fn external_clock(&mut self, out: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let received = wire.delivered[self.side].take();
+        if received.is_none() {
+            wire.waiting[self.side] = Some(out);
+        }
+        received
+    }
+
+    fn transfer_cancelled(&mut self) {
+        let mut wire = self.wire.borrow_mut();
+        wire.waiting[self.side] = None;
+        wire.delivered[self.side] = None;
+    }
+}
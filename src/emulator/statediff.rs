@@ -0,0 +1,128 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::emulator::{CPU, IO_REGISTERS, IE_REGISTER, MODE};
+
+// equal bytes shorter than this between two differences don't split a range
+const MERGE_GAP: usize = 4;
+
+// a memory region as it sits on the bus, banked regions repeat their window per bank
+struct Region {
+    name: &'static str,
+    base: u16,
+    bank_size: usize  // 0 when not banked
+}
+
+const VRAM: Region = Region { name: "vram", base: 0x8000, bank_size: 0x2000 };
+const CART_RAM: Region = Region { name: "cart ram", base: 0xA000, bank_size: 0x2000 };
+const WRAM: Region = Region { name: "wram", base: 0xC000, bank_size: 0x1000 };
+const OAM: Region = Region { name: "oam", base: 0xFE00, bank_size: 0 };
+const WAVE: Region = Region { name: "wave ram", base: 0xFF30, bank_size: 0 };
+const HRAM: Region = Region { name: "hram", base: 0xFF80, bank_size: 0 };
+const BG_PALETTES: Region = Region { name: "bg palettes", base: 0, bank_size: 0 };
+const OBJ_PALETTES: Region = Region { name: "obj palettes", base: 0, bank_size: 0 };
+
+pub struct RangeDiff {
+    region: &'static Region,
+    pub offset: usize,  // into the region's backing memory, all banks in order
+    pub old: Vec<u8>,
+    pub new: Vec<u8>
+}
+
+impl RangeDiff {
+    pub fn region(&self) -> &'static str {
+        self.region.name
+    }
+
+    // bank:address for banked regions, wram bank 0 is C000 and the others D000
+    pub fn location(&self) -> String {
+        let r = self.region;
+        if r.bank_size == 0 {
+            return format!("{:04X}", r.base as usize + self.offset)
+        }
+        let (bank, addr) = (self.offset / r.bank_size, self.offset % r.bank_size);
+        let base = if r.base == WRAM.base && bank > 0 { 0xD000 } else { r.base as usize };
+        format!("{}:{:04X}", bank, base + addr)
+    }
+}
+
+// What a cheat, a glitch or an emulator change touched: every cpu field, io register and
+// memory range that isn't the same in both machines. Both run the same game on the same model
+pub struct StateDiff {
+    pub cpu: Vec<(&'static str, u16, u16)>,
+    pub io: Vec<(u16, &'static str, u8, u8)>,
+    pub ranges: Vec<RangeDiff>
+}
+
+impl StateDiff {
+    pub fn new(a: &mut CPU, b: &mut CPU) -> StateDiff {
+        let mut diff = StateDiff { cpu: vec![], io: vec![], ranges: vec![] };
+
+        let fields = |c: &mut CPU| [
+            ("AF", *c.AF()), ("BC", *c.BC()), ("DE", *c.DE()), ("HL", *c.HL()), ("SP", c.SP), ("PC", c.PC),
+            ("IME", c.IME as u16), ("EI", c.EI as u16), ("halt", c.halt as u16)
+        ];
+        for ((name, old), (_, new)) in fields(a).iter().zip(fields(b).iter()) {
+            if old != new {
+                diff.cpu.push((*name, *old, *new));
+            }
+        }
+
+        let cgb = a.memory.mode == MODE::CGB;
+        for reg in IO_REGISTERS.iter().chain(core::iter::once(&IE_REGISTER)) {
+            if reg.len > 1 || (reg.cgb && !cgb) {
+                continue;
+            }
+            let (old, new) = (a.memory.read(reg.addr), b.memory.read(reg.addr));
+            if old != new {
+                diff.io.push((reg.addr, reg.name, old, new));
+            }
+        }
+
+        let wave = |c: &mut CPU| (0 .. 16).map(|i| c.memory.read(WAVE.base + i)).collect::<Vec<u8>>();
+        diff.compare(&VRAM, &a.memory.vram, &b.memory.vram);
+        diff.compare(&CART_RAM, a.memory.cart.ram(), b.memory.cart.ram());
+        diff.compare(&WRAM, a.memory.wram(), b.memory.wram());
+        diff.compare(&OAM, a.memory.oam(), b.memory.oam());
+        diff.compare(&WAVE, &wave(a), &wave(b));
+        diff.compare(&HRAM, a.memory.hram(), b.memory.hram());
+        if cgb {
+            diff.compare(&BG_PALETTES, &a.memory.ppu.palette_bytes(false), &b.memory.ppu.palette_bytes(false));
+            diff.compare(&OBJ_PALETTES, &a.memory.ppu.palette_bytes(true), &b.memory.ppu.palette_bytes(true));
+        }
+        diff
+    }
+
+    fn compare(&mut self, region: &'static Region, old: &[u8], new: &[u8]) {
+        let mut current: Option<(usize, usize)> = None;  // start and end of the open range
+        for i in 0 .. old.len().min(new.len()) {
+            if old[i] == new[i] {
+                continue;
+            }
+            current = match current {
+                Some((start, end)) if i - end <= MERGE_GAP => Some((start, i + 1)),
+                Some(range) => {
+                    self.push_range(region, old, new, range);
+                    Some((i, i + 1))
+                },
+                None => Some((i, i + 1))
+            };
+        }
+        if let Some(range) = current {
+            self.push_range(region, old, new, range);
+        }
+    }
+
+    fn push_range(&mut self, region: &'static Region, old: &[u8], new: &[u8], (start, end): (usize, usize)) {
+        self.ranges.push(RangeDiff {
+            region,
+            offset: start,
+            old: old[start .. end].to_vec(),
+            new: new[start .. end].to_vec()
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpu.is_empty() && self.io.is_empty() && self.ranges.is_empty()
+    }
+}
@@ -0,0 +1,79 @@
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[cfg(feature = "std")]
+use crate::emulator::CPU;
+
+// FNV-1a, only has to be stable between builds
+pub struct StateHasher(u64);
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        StateHasher(0xCBF29CE484222325)
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for b in data.iter() {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001B3);
+        }
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.write(&[v]);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.write(&v.to_le_bytes());
+    }
+
+    #[cfg_attr(not(feature = "apu"), allow(dead_code))]
+    pub fn write_u32(&mut self, v: u32) {
+        self.write(&v.to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for StateHasher {
+    fn default() -> StateHasher {
+        StateHasher::new()
+    }
+}
+
+// one hash per subsystem, in the order written to the log
+#[cfg(feature = "std")]
+pub const SUBSYSTEMS: [&str; 8] = ["cpu", "io", "wram", "vram", "oam", "ppu", "apu", "cart"];
+
+// A line per frame with every subsystem's hash. Diffing logs of two builds
+// gives the first frame and the subsystem where they went apart.
+#[cfg(feature = "std")]
+pub struct StateHashLog {
+    file: BufWriter<File>
+}
+
+#[cfg(feature = "std")]
+impl StateHashLog {
+    pub fn create(p: &Path) -> io::Result<StateHashLog> {
+        let mut file = BufWriter::new(File::create(p)?);
+        writeln!(file, "frame {}", SUBSYSTEMS.join(" "))?;
+
+        Ok(StateHashLog {
+            file
+        })
+    }
+
+    pub fn record(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        write!(self.file, "{}", cpu.memory.ppu.frame_count)?;
+        for hash in cpu.state_hashes().iter() {
+            write!(self.file, " {:016x}", hash)?;
+        }
+        writeln!(self.file)
+    }
+}
@@ -0,0 +1,389 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::emulator::{CPU, MODE, Condition, PowerLoss};
+
+// A regression test for one game, a small subset of TOML: `key = value` lines with strings
+// in double quotes and decimal or 0x hex integers, `[[input]]` and `[[expect]]` start a new
+// entry, `#` starts a comment. Frame N means after N frames have run since boot.
+//
+//     rom = "tetris.gb"         # relative to the test file
+//     model = "dmg"             # optional, dmg or cgb
+//     frames = 600              # optional, runs at least up to the last input or check
+//     unflushed = 30            # optional, frames of cart ram writes a power loss drops, 0 when left out
+//
+//     [[input]]
+//     frame = 120
+//     buttons = "start"         # right left up down a b select start, space separated
+//     hold = 4                  # frames, 1 when left out
+//
+//     [[expect]]
+//     frame = 300
+//     memory = "C0A0 == 01"     # a trigger condition, see triggers.rs
+//
+//     [[expect]]
+//     frame = 300
+//     register = "hl"           # a f b c d e h l af bc de hl sp pc
+//     value = 0xC0A0
+//
+//     [[power_loss]]            # the cart is pulled and the game powered on again with what
+//     frame = 400               # its ram kept, frames keep counting from the first boot
+pub struct TestScript {
+    pub rom: String,
+    pub model: Option<MODE>,
+    pub frames: u32,
+    unflushed: Option<u32>,
+    inputs: Vec<Input>,
+    expects: Vec<Expect>,
+    power_losses: Vec<(u32, usize)>  // frame, line
+}
+
+struct Input {
+    frame: u32,
+    hold: u32,
+    joypad: u8  // directions in the high nibble, buttons in the low, active high
+}
+
+#[derive(Clone, Copy)]
+enum Register {
+    A, F, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP, PC
+}
+
+enum Check {
+    Memory(Condition),
+    Register(Register, u16)
+}
+
+struct Expect {
+    frame: u32,
+    line: usize,
+    text: String,  // as written, for the failure message
+    check: Check
+}
+
+// one failed expectation
+pub struct Failure {
+    pub frame: u32,
+    pub line: usize,
+    pub message: String
+}
+
+#[derive(PartialEq)]
+enum Section {
+    Input,
+    Expect,
+    PowerLoss
+}
+
+// key/value pairs of one [[input]] or [[expect]], with the line it started on
+struct Entry {
+    line: usize,
+    values: Vec<(String, Value)>
+}
+
+enum Value {
+    Str(String),
+    Int(u32)
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        let inner = &s[1 .. s.len() - 1];
+        if inner.contains('"') || inner.contains('\\') {
+            return Err(format!("escapes aren't supported in `{}`", s))
+        }
+        return Ok(Value::Str(inner.to_string()))
+    }
+
+    let digits = s.replace('_', "");
+    let n = match digits.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => digits.parse::<u32>()
+    };
+    n.map(Value::Int).map_err(|_| format!("expected a string or an integer, got `{}`", s))
+}
+
+// a `#` outside of a string starts a comment
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[.. i],
+            _ => ()
+        }
+    }
+    line
+}
+
+impl Entry {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.values.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn int(&self, key: &str) -> Result<Option<u32>, String> {
+        match self.get(key) {
+            Some(Value::Int(n)) => Ok(Some(*n)),
+            Some(Value::Str(_)) => Err(format!("{} should be an integer", key)),
+            None => Ok(None)
+        }
+    }
+
+    fn str(&self, key: &str) -> Result<Option<&str>, String> {
+        match self.get(key) {
+            Some(Value::Str(s)) => Ok(Some(s)),
+            Some(Value::Int(_)) => Err(format!("{} should be a string", key)),
+            None => Ok(None)
+        }
+    }
+
+    fn frame(&self) -> Result<u32, String> {
+        self.int("frame")?.ok_or_else(|| "missing frame".to_string())
+    }
+
+    fn only(&self, keys: &[&str]) -> Result<(), String> {
+        match self.values.iter().find(|(k, _)| !keys.contains(&k.as_str())) {
+            Some((k, _)) => Err(format!("unknown key `{}`", k)),
+            None => Ok(())
+        }
+    }
+}
+
+fn parse_buttons(s: &str) -> Result<u8, String> {
+    let mut joypad = 0;
+    for b in s.split_whitespace() {
+        joypad |= match b {
+            "right" => 0x10,
+            "left" => 0x20,
+            "up" => 0x40,
+            "down" => 0x80,
+            "a" => 0x01,
+            "b" => 0x02,
+            "select" => 0x04,
+            "start" => 0x08,
+            _ => return Err(format!("unknown button `{}`", b))
+        };
+    }
+    Ok(joypad)
+}
+
+fn parse_register(s: &str) -> Result<Register, String> {
+    Ok(match s.to_ascii_lowercase().as_str() {
+        "a" => Register::A,
+        "f" => Register::F,
+        "b" => Register::B,
+        "c" => Register::C,
+        "d" => Register::D,
+        "e" => Register::E,
+        "h" => Register::H,
+        "l" => Register::L,
+        "af" => Register::AF,
+        "bc" => Register::BC,
+        "de" => Register::DE,
+        "hl" => Register::HL,
+        "sp" => Register::SP,
+        "pc" => Register::PC,
+        _ => return Err(format!("unknown register `{}`", s))
+    })
+}
+
+fn read_register(cpu: &mut CPU, r: Register) -> u16 {
+    match r {
+        Register::A => *cpu.AF() >> 8,
+        Register::F => *cpu.AF() & 0xFF,
+        Register::B => *cpu.BC() >> 8,
+        Register::C => *cpu.BC() & 0xFF,
+        Register::D => *cpu.DE() >> 8,
+        Register::E => *cpu.DE() & 0xFF,
+        Register::H => *cpu.HL() >> 8,
+        Register::L => *cpu.HL() & 0xFF,
+        Register::AF => *cpu.AF(),
+        Register::BC => *cpu.BC(),
+        Register::DE => *cpu.DE(),
+        Register::HL => *cpu.HL(),
+        Register::SP => cpu.SP,
+        Register::PC => cpu.PC
+    }
+}
+
+impl TestScript {
+    pub fn parse(data: &str) -> Result<TestScript, String> {
+        TestScript::parse_with(data, false)
+    }
+
+    // An assertion file for a scripted run: `model`, `frames` and [[expect]] entries,
+    // the rom and the input come from the command line
+    pub fn parse_assertions(data: &str) -> Result<TestScript, String> {
+        TestScript::parse_with(data, true)
+    }
+
+    fn parse_with(data: &str, assertions: bool) -> Result<TestScript, String> {
+        let mut top = Entry { line: 1, values: vec![] };
+        let mut entries: Vec<(Section, Entry)> = vec![];
+
+        for (n, line) in data.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let section = match line {
+                "[[input]]" if !assertions => Some(Section::Input),
+                "[[expect]]" => Some(Section::Expect),
+                "[[power_loss]]" if !assertions => Some(Section::PowerLoss),
+                l if l.starts_with('[') => return Err(format!("{}: unknown section `{}`", n + 1, l)),
+                _ => None
+            };
+            if let Some(s) = section {
+                entries.push((s, Entry { line: n + 1, values: vec![] }));
+                continue;
+            }
+
+            let i = line.find('=').ok_or_else(|| format!("{}: expected `key = value`", n + 1))?;
+            let value = parse_value(line[i+1 ..].trim()).map_err(|e| format!("{}: {}", n + 1, e))?;
+            let entry = match entries.last_mut() {
+                Some((_, e)) => e,
+                None => &mut top
+            };
+            entry.values.push((line[.. i].trim().to_string(), value));
+        }
+
+        let mut script = TestScript::from_top(&top, assertions)?;
+        for (section, e) in entries.iter() {
+            let result = match section {
+                Section::Input => script.add_input(e),
+                Section::Expect => script.add_expect(e),
+                Section::PowerLoss => script.add_power_loss(e)
+            };
+            result.map_err(|err| format!("{}: {}", e.line, err))?;
+        }
+
+        let last = script.inputs.iter().map(|i| i.frame + i.hold)
+            .chain(script.expects.iter().map(|e| e.frame))
+            .chain(script.power_losses.iter().map(|p| p.0))
+            .max().unwrap_or(0);
+        script.frames = script.frames.max(last);
+        Ok(script)
+    }
+
+    fn from_top(top: &Entry, assertions: bool) -> Result<TestScript, String> {
+        if assertions {
+            top.only(&["model", "frames"])?;
+        } else {
+            top.only(&["rom", "model", "frames", "unflushed"])?;
+        }
+        let model = match top.str("model")? {
+            Some("dmg") => Some(MODE::DMG),
+            Some("dmg0") => Some(MODE::DMG0),
+            Some("cgb") => Some(MODE::CGB),
+            Some(m) => return Err(format!("unknown model `{}`", m)),
+            None => None
+        };
+
+        Ok(TestScript {
+            rom: match top.str("rom")? {
+                Some(rom) => rom.to_string(),
+                None if assertions => String::new(),
+                None => return Err("missing rom".to_string())
+            },
+            model,
+            frames: top.int("frames")?.unwrap_or(0),
+            unflushed: top.int("unflushed")?,
+            inputs: vec![],
+            expects: vec![],
+            power_losses: vec![]
+        })
+    }
+
+    fn add_input(&mut self, e: &Entry) -> Result<(), String> {
+        e.only(&["frame", "buttons", "hold"])?;
+        self.inputs.push(Input {
+            frame: e.frame()?,
+            hold: e.int("hold")?.unwrap_or(1),
+            joypad: parse_buttons(e.str("buttons")?.ok_or("missing buttons")?)?
+        });
+        Ok(())
+    }
+
+    fn add_expect(&mut self, e: &Entry) -> Result<(), String> {
+        e.only(&["frame", "memory", "register", "value"])?;
+        let (text, check) = match (e.str("memory")?, e.str("register")?) {
+            (Some(cond), None) => (cond.to_string(), Check::Memory(Condition::parse(cond)?)),
+            (None, Some(reg)) => {
+                let value = e.int("value")?.ok_or("missing value")?;
+                if value > 0xFFFF {
+                    return Err(format!("value {:#X} doesn't fit a register", value))
+                }
+                (format!("{} == {:X}", reg, value), Check::Register(parse_register(reg)?, value as u16))
+            },
+            _ => return Err("expected either memory or register".to_string())
+        };
+
+        self.expects.push(Expect {
+            frame: e.frame()?,
+            line: e.line,
+            text,
+            check
+        });
+        Ok(())
+    }
+
+    fn add_power_loss(&mut self, e: &Entry) -> Result<(), String> {
+        e.only(&["frame"])?;
+        self.power_losses.push((e.frame()?, e.line));
+        Ok(())
+    }
+
+    // Runs a freshly booted cpu through the script, every expectation is checked even after one fails.
+    // power_on boots the same game again for [[power_loss]], without a save
+    pub fn run(&self, cpu: &mut CPU, power_on: &mut dyn FnMut() -> Result<CPU, String>) -> Vec<Failure> {
+        let mut failures = vec![];
+        if !self.power_losses.is_empty() {
+            cpu.memory.cart.power_loss = Some(PowerLoss::new(self.unflushed.unwrap_or(0)));
+        }
+
+        for frame in 0 ..= self.frames {
+            if let Some(&(_, line)) = self.power_losses.iter().find(|p| p.0 == frame) {
+                if let Err(e) = self.power_cycle(cpu, power_on) {
+                    failures.push(Failure { frame, line, message: format!("couldn't power on again: {}", e) });
+                    break;
+                }
+            }
+            self.check(cpu, frame, &mut failures);
+            if frame == self.frames {
+                break;
+            }
+
+            let joypad = self.inputs.iter()
+                .filter(|i| frame >= i.frame && frame < i.frame + i.hold)
+                .fold(0, |j, i| j | i.joypad);
+            cpu.memory.set_joypad(!joypad >> 4, !joypad & 0xF);
+            cpu.run_frame();
+        }
+        failures
+    }
+
+    // the expectations for after frame frames
+    pub fn check(&self, cpu: &mut CPU, frame: u32, failures: &mut Vec<Failure>) {
+        for e in self.expects.iter().filter(|e| e.frame == frame) {
+            let message = match e.check {
+                Check::Memory(c) if !c.test(&mut cpu.memory) => format!("{} failed, read {:02X}", e.text, c.value(&mut cpu.memory)),
+                Check::Register(r, v) if read_register(cpu, r) != v => format!("{} failed, read {:X}", e.text, read_register(cpu, r)),
+                _ => continue
+            };
+            failures.push(Failure { frame, line: e.line, message });
+        }
+    }
+
+    fn power_cycle(&self, cpu: &mut CPU, power_on: &mut dyn FnMut() -> Result<CPU, String>) -> Result<(), String> {
+        cpu.memory.cart.pull_power();
+        let mut new = power_on()?;
+        if !cpu.memory.cart.ram().is_empty() {
+            new.memory.cart.apply_ram(cpu.memory.cart.ram(), true)?;
+        }
+        new.memory.cart.power_loss = cpu.memory.cart.power_loss.take();
+        *cpu = new;
+        Ok(())
+    }
+}
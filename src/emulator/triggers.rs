@@ -0,0 +1,159 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::emulator::Memory;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And  // any of the value bits set
+}
+
+// `address[:bank] op value`, shared with test scripts
+#[derive(Clone, Copy, Debug)]
+pub struct Condition {
+    addr: u16,
+    bank: Option<u8>,  // wram/vram/cart ram bank, None - whatever is mapped in
+    cmp: Compare,
+    val: u8
+}
+
+impl Condition {
+    pub fn parse(s: &str) -> Result<Condition, String> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(format!("expected `address[:bank] op value`, got `{}`", s))
+        }
+
+        let (addr, bank) = match parts[0].find(':') {
+            Some(i) => (&parts[0][.. i], Some(&parts[0][i+1 ..])),
+            None => (parts[0], None)
+        };
+        let addr = u16::from_str_radix(addr, 16).map_err(|_| format!("invalid address `{}`", addr))?;
+        let bank = match bank {
+            Some(b) => Some(u8::from_str_radix(b, 16).map_err(|_| format!("invalid bank `{}`", b))?),
+            None => None
+        };
+
+        let cmp = match parts[1] {
+            "==" => Compare::Eq,
+            "!=" => Compare::Ne,
+            "<" => Compare::Lt,
+            "<=" => Compare::Le,
+            ">" => Compare::Gt,
+            ">=" => Compare::Ge,
+            "&" => Compare::And,
+            c => return Err(format!("unknown comparison `{}`", c))
+        };
+        let val = u8::from_str_radix(parts[2], 16).map_err(|_| format!("invalid value `{}`", parts[2]))?;
+
+        Ok(Condition {
+            addr,
+            bank,
+            cmp,
+            val
+        })
+    }
+
+    pub fn value(&self, memory: &mut Memory) -> u8 {
+        match self.bank {
+            Some(bank) => memory.peek_bank(self.addr, bank),
+            None => memory.read(self.addr)
+        }
+    }
+
+    pub fn test(&self, memory: &mut Memory) -> bool {
+        let v = self.value(memory);
+
+        match self.cmp {
+            Compare::Eq => v == self.val,
+            Compare::Ne => v != self.val,
+            Compare::Lt => v < self.val,
+            Compare::Le => v <= self.val,
+            Compare::Gt => v > self.val,
+            Compare::Ge => v >= self.val,
+            Compare::And => v & self.val != 0
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum TriggerAction {
+    Osd(String),      // osd <message>
+    Print(String),    // print <message>, to stdout
+    Webhook(String)   // webhook <http url>
+}
+
+struct Rule {
+    conditions: Vec<Condition>,
+    action: TriggerAction,
+    active: bool  // conditions held on the last check, rules fire only when they become true
+}
+
+// One rule per line, `#` starts a comment, addresses and values are hex:
+//     D35C == 05 && C000:2 & 80 => osd Got the badge
+pub struct Triggers {
+    rules: Vec<Rule>
+}
+
+impl Triggers {
+    pub fn parse(data: &str) -> Result<Triggers, String> {
+        let mut rules = vec![];
+
+        for (n, line) in data.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let rule = Triggers::parse_rule(line).map_err(|e| format!("{}: {}", n + 1, e))?;
+            rules.push(rule);
+        }
+
+        Ok(Triggers {
+            rules
+        })
+    }
+
+    fn parse_rule(line: &str) -> Result<Rule, String> {
+        let i = line.find("=>").ok_or("expected `conditions => action`")?;
+        let conditions = line[.. i].split("&&").map(|c| Condition::parse(c.trim())).collect::<Result<Vec<_>, _>>()?;
+
+        let action = line[i+2 ..].trim();
+        let (kind, arg) = match action.find(' ') {
+            Some(i) => (&action[.. i], action[i+1 ..].trim().to_string()),
+            None => (action, String::new())
+        };
+        let action = match kind {
+            "osd" => TriggerAction::Osd(arg),
+            "print" => TriggerAction::Print(arg),
+            "webhook" if arg.starts_with("http://") => TriggerAction::Webhook(arg),
+            "webhook" => return Err("webhook needs an http:// url".to_string()),
+            k => return Err(format!("unknown action `{}`", k))
+        };
+
+        Ok(Rule {
+            conditions,
+            action,
+            active: false
+        })
+    }
+
+    // actions of the rules that became true since the last call, meant to be called once per frame
+    pub fn check(&mut self, memory: &mut Memory) -> Vec<TriggerAction> {
+        let mut fired = vec![];
+
+        for rule in self.rules.iter_mut() {
+            let active = rule.conditions.iter().all(|c| c.test(memory));
+            if active && !rule.active {
+                fired.push(rule.action.clone());
+            }
+            rule.active = active;
+        }
+        fired
+    }
+}
@@ -0,0 +1,151 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+// How the bytes at a watched address are read
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WatchType {
+    U8,
+    S8,
+    U16,  // little endian, the way the cpu stores them
+    Bcd,  // 2 bytes, 4 digits, the first byte holds the high ones
+    Fixed  // 8.8, fraction byte first
+}
+
+impl WatchType {
+    pub const ALL: [WatchType; 5] = [WatchType::U8, WatchType::S8, WatchType::U16, WatchType::Bcd, WatchType::Fixed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatchType::U8 => "u8",
+            WatchType::S8 => "s8",
+            WatchType::U16 => "u16",
+            WatchType::Bcd => "bcd",
+            WatchType::Fixed => "8.8"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WatchType> {
+        WatchType::ALL.iter().find(|t| t.name() == name).copied()
+    }
+
+    pub fn next(&self) -> WatchType {
+        let i = WatchType::ALL.iter().position(|t| t == self).unwrap();
+        WatchType::ALL[(i + 1) % WatchType::ALL.len()]
+    }
+
+    pub fn size(&self) -> u16 {
+        match self {
+            WatchType::U8 | WatchType::S8 => 1,
+            _ => 2
+        }
+    }
+}
+
+// An address shown in the debugger's watch panel, with a label from the .sym file or the session
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub addr: u16,
+    pub kind: WatchType,
+    pub name: Option<String>
+}
+
+impl Watch {
+    // ADDR:type[:name] as kept in the session, ADDR can also be a label from the .sym file
+    pub fn parse(s: &str, symbols: &Symbols) -> Option<Watch> {
+        let mut parts = s.splitn(3, ':');
+        let target = parts.next()?;
+        let kind = WatchType::from_name(parts.next()?)?;
+        let name = parts.next().filter(|n| !n.is_empty()).map(|n| n.to_string());
+        match u16::from_str_radix(target, 16) {
+            Ok(addr) if target.len() == 4 => Some(Watch { addr, kind, name }),
+            _ => symbols.addr(target).map(|addr| Watch { addr, kind, name: name.or_else(|| Some(target.to_string())) })
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(n) => n.clone(),
+            None => format!("{:04X}", self.addr)
+        }
+    }
+
+    pub fn bytes(&self, read: &mut dyn FnMut(u16) -> u8) -> [u8; 2] {
+        let lo = read(self.addr);
+        let hi = if self.kind.size() > 1 { read(self.addr.wrapping_add(1)) } else { 0 };
+        [lo, hi]
+    }
+
+    // plain number, what the per frame log gets too
+    pub fn value(&self, read: &mut dyn FnMut(u16) -> u8) -> String {
+        let [lo, hi] = self.bytes(read);
+        match self.kind {
+            WatchType::U8 => format!("{}", lo),
+            WatchType::S8 => format!("{}", lo as i8),
+            WatchType::U16 => format!("{}", (hi as u16) << 8 | lo as u16),
+            WatchType::Bcd => format!("{:02X}{:02X}", lo, hi),  // nibbles past 9 show up as letters
+            WatchType::Fixed => format!("{:.3}", ((hi as u16) << 8 | lo as u16) as f32 / 256.)
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}:{}", self.addr, self.kind.name())?;
+        if let Some(n) = &self.name {
+            write!(f, ":{}", n)?;
+        }
+        Ok(())
+    }
+}
+
+// Labels from an rgblink style .sym file, "BB:AAAA name" a line, ; starts a comment
+#[derive(Default)]
+pub struct Symbols {
+    entries: Vec<(u8, u16, String)>
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols { entries: vec![] }
+    }
+
+    // lines that aren't labels are skipped, other linkers add their own sections
+    pub fn parse(data: &str) -> Symbols {
+        let mut entries = vec![];
+        for line in data.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let mut fields = line.split_whitespace();
+            let (location, name) = match (fields.next(), fields.next()) {
+                (Some(l), Some(n)) => (l, n),
+                _ => continue
+            };
+            let mut location = location.splitn(2, ':');
+            let bank = location.next().and_then(|b| u8::from_str_radix(b, 16).ok());
+            let addr = location.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+            if let (Some(bank), Some(addr)) = (bank, addr) {
+                entries.push((bank, addr, name.to_string()));
+            }
+        }
+        Symbols { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // the first label at addr in any bank, local labels (.loop, Func.loop) only when there's nothing else
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        let mut at = self.entries.iter().filter(|e| e.1 == addr);
+        let first = at.clone().next()?;
+        Some(at.find(|e| !e.2.contains('.')).unwrap_or(first).2.as_str())
+    }
+
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.entries.iter().find(|e| e.2 == name).map(|e| e.1)
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 44;
+
+// 16 bit stereo PCM. The sizes in the header are filled in by finish, or when dropped
+// without it, like with the CPU it records
+pub struct WavWriter {
+    file: BufWriter<File>,
+    frames: u32,
+    error: Option<io::Error>,  // the first failed write, finish returns it
+    finished: bool
+}
+
+impl WavWriter {
+    pub fn create(p: &Path, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(p)?);
+        file.write_all(b"RIFF")?;
+        file.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;  // PCM
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * 4).to_le_bytes())?;  // bytes per second
+        file.write_all(&4u16.to_le_bytes())?;  // bytes per frame
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file,
+            frames: 0,
+            error: None,
+            finished: false
+        })
+    }
+
+    // interleaved stereo, as the APU makes it
+    pub fn write(&mut self, samples: &[i16]) {
+        if self.error.is_some() {
+            return
+        }
+        for s in samples {
+            if let Err(e) = self.file.write_all(&s.to_le_bytes()) {
+                self.error = Some(e);
+                return
+            }
+        }
+        self.frames += samples.len() as u32 / 2;
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn finish(mut self) -> io::Result<u32> {
+        if let Some(e) = self.error.take() {
+            return Err(e)
+        }
+        self.write_sizes()?;
+        Ok(self.frames)
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        self.finished = true;
+        let data = self.frames * 4;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(HEADER_SIZE - 8 + data).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.write_sizes();
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use raylib::prelude::*;
+
+use crate::emulator::AudioConfig;
+use super::ring::{self, Writer, Reader, Overflow};
+
+const SAMPLE_SIZE: u32 = 16;
+const MAX_RATE_DELTA: f32 = 0.005;  // rate control stretches or shrinks by at most this much, too little to hear
+const FILL_SMOOTHING: f32 = 0.02;  // the ring's fill jumps a whole buffer at a time, rate control follows its average
+const TARGET_FILL: f32 = 0.5;
+
+// What paces the emulation
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SyncMode {
+    Audio,  // pushing samples waits for room in the stream, the display rate is only an upper bound
+    Video,  // frames are paced by the display, audio is stretched or shrunk to keep the stream half full
+    Uncapped  // as fast as the host goes, audio plays what fits
+}
+
+// raylib's stream calls take miniaudio's lock, the feeder thread is the only one making them while it runs
+struct Stream(raylib::ffi::AudioStream);
+unsafe impl Send for Stream {}
+
+// the emulator pushes into a ring of one stream buffer and a thread moves it to the stream, so a
+// full stream only holds up the emulation under Overflow::Block and never grows anything
+pub struct Audio {
+    config: AudioConfig,
+    ring: Writer,
+    feeder: Option<JoinHandle<Stream>>,  // hands the stream back when the ring closes
+    device: RaylibAudio,  // after feeder, the device outlives the thread
+    pending: Vec<i16>,  // resampled, before the ring
+    speed: f32,  // emulated samples per played one, see set_speed
+    rate_control: bool,  // see set_rate_control
+    fill_average: f32,  // of fill(), smoothed
+    pos: f32,  // into the samples of the next push, from the last frame of the previous one
+    last: [i16; 2]
+}
+
+impl Audio {
+    pub fn new(rl_thread: &RaylibThread, config: AudioConfig) -> Audio {
+        let mut device = RaylibAudio::init_audio_device();
+        let (ring, feeder) = Audio::open(rl_thread, &mut device, config);
+
+        Audio {
+            config: config,
+            ring: ring,
+            feeder: Some(feeder),
+            device: device,
+            pending: Vec::with_capacity(config.buffer*4),
+            speed: 1.,
+            rate_control: false,
+            fill_average: TARGET_FILL,
+            pos: 0.,
+            last: [0, 0]
+        }
+    }
+
+    // a playing stream in the config's format and the thread feeding it from the ring
+    fn open(rl_thread: &RaylibThread, device: &mut RaylibAudio, config: AudioConfig) -> (Writer, JoinHandle<Stream>) {
+        // in frames, raylib keeps two of these and each update refills one
+        unsafe { raylib::ffi::SetAudioStreamBufferSizeDefault(config.buffer as i32) };
+        let mut stream = AudioStream::init_audio_stream(rl_thread, config.sample_rate, SAMPLE_SIZE, 2);
+        device.play_audio_stream(&mut stream);
+        let (writer, reader) = ring::ring(config.buffer*2);
+        let stream = Stream(stream.to_raw());
+        (writer, thread::spawn(move || Audio::feed(stream, reader, config.buffer*2)))
+    }
+
+    fn close(&mut self) {
+        self.ring.close();
+        if let Some(feeder) = self.feeder.take() {
+            if let Ok(stream) = feeder.join() {
+                unsafe { raylib::ffi::UnloadAudioStream(stream.0) };
+            }
+        }
+    }
+
+    // a new stream for a changed sample rate or buffer length, what was queued for the old one is dropped
+    pub fn set_config(&mut self, rl_thread: &RaylibThread, config: AudioConfig) {
+        self.close();
+        let (ring, feeder) = Audio::open(rl_thread, &mut self.device, config);
+        self.config = config;
+        self.ring = ring;
+        self.feeder = Some(feeder);
+        self.pending.clear();
+        self.fill_average = TARGET_FILL;
+        self.pos = 0.;
+        self.last = [0, 0];
+    }
+
+    // one stream buffer of len samples at a time
+    fn feed(stream: Stream, mut reader: Reader, len: usize) -> Stream {
+        let mut buffer = vec![0i16; len];
+        while !reader.is_closed() {
+            let ready = unsafe { raylib::ffi::IsAudioStreamProcessed(stream.0) };
+            if ready && reader.pop_exact(&mut buffer) {
+                unsafe {
+                    raylib::ffi::UpdateAudioStream(
+                        stream.0,
+                        buffer.as_ptr() as *const std::os::raw::c_void,
+                        buffer.len() as i32
+                    );
+                }
+            } else {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        stream
+    }
+
+    // the game runs this much faster than real time, its audio is stretched back to the stream's rate
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        self.pos = 0.;
+    }
+
+    // Under SyncMode::Video nothing waits for the stream, its clock and the display's drift apart.
+    // Rate control keeps the stream from running dry or piling up latency over a long session
+    pub fn set_rate_control(&mut self, on: bool) {
+        self.rate_control = on;
+        self.fill_average = TARGET_FILL;
+    }
+
+    // linear, the ratio is never more than a fraction of a percent off
+    fn resample(&mut self, samples: &[i16], ratio: f32) {
+        let frames = samples.len() / 2;
+        let last = self.last;
+        // frame 0 is the last one of the previous push, frame n is samples' n-1
+        let at = |i: usize, c: usize| if i == 0 { last[c] } else { samples[(i-1)*2 + c] } as f32;
+
+        while self.pos < frames as f32 {
+            let i = self.pos as usize;
+            let t = self.pos - i as f32;
+            for c in 0 .. 2 {
+                self.pending.push((at(i, c) + (at(i+1, c) - at(i, c)) * t) as i16);
+            }
+            self.pos += ratio;
+        }
+        self.pos -= frames as f32;
+        if frames > 0 {
+            self.last = [samples[frames*2 - 2], samples[frames*2 - 1]];
+        }
+    }
+
+    // queues emulated samples, what the ring has no room for waits or goes by overflow
+    pub fn push(&mut self, samples: &mut Vec<i16>, overflow: Overflow) {
+        let ratio = if self.rate_control {
+            // fuller than the target and more emulated samples go into each played one
+            self.fill_average += (self.fill() - self.fill_average) * FILL_SMOOTHING;
+            self.speed * (1. + (self.fill_average - TARGET_FILL) * 2. * MAX_RATE_DELTA)
+        } else {
+            self.speed
+        };
+
+        if ratio == 1. {
+            self.pending.append(samples);
+        } else {
+            self.resample(samples, ratio);
+            samples.clear();
+        }
+
+        self.ring.push(&self.pending, overflow);
+        self.pending.clear();
+    }
+
+    // how much of the next stream buffer is already filled, 0.0 - 1.0
+    pub fn fill(&self) -> f32 {
+        self.ring.queued() as f32 / (self.config.buffer*2) as f32
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
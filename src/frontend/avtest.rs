@@ -0,0 +1,53 @@
+use super::display::GB_REFRESH;
+
+const PERIOD: u32 = 60;  // presented frames between marks, about a second
+const CLICK_MS: u32 = 2;  // burst length
+const CLICK_FREQUENCY: u32 = 3000;  // Hz, easy to pick out on a recording
+const CLICK_LEVEL: i16 = 20000;
+
+// F10 test pattern for measuring audio/video offset: every second the game's picture is replaced
+// with white and a click goes into its audio at the start of the same emulated frame. Film the
+// screen with a microphone on, the distance between flash and click is what the backend adds
+pub struct AvTest {
+    pub enabled: bool,
+    count: u32
+}
+
+impl AvTest {
+    pub fn new() -> AvTest {
+        AvTest {
+            enabled: false,
+            count: 0
+        }
+    }
+
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        self.count = 0;
+        self.enabled
+    }
+
+    // frame is the picture about to be presented, samples the stereo audio up to its end
+    pub fn mark(&mut self, frame: &mut [u8], samples: &mut Vec<i16>, sample_rate: u32) {
+        if !self.enabled {
+            return
+        }
+        self.count += 1;
+        if self.count < PERIOD {
+            return
+        }
+        self.count = 0;
+
+        for p in frame.iter_mut() {
+            *p = 0xFF;
+        }
+        // the last emulated frame's audio starts this far from the end
+        let frame_samples = (sample_rate as f32 / GB_REFRESH) as usize;
+        let half_wave = (sample_rate / CLICK_FREQUENCY / 2).max(1) as usize;
+        let start = samples.len().saturating_sub(frame_samples*2);
+        let end = (start + (sample_rate * CLICK_MS / 1000) as usize * 2).min(samples.len());
+        for (i, s) in samples[start .. end].iter_mut().enumerate() {
+            *s = if (i/2 / half_wave) % 2 == 0 { CLICK_LEVEL } else { -CLICK_LEVEL };
+        }
+    }
+}
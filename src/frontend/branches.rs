@@ -0,0 +1,219 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::frontend::captures::SLOTS;
+
+const BRANCH_FILE: &str = "branches.txt";
+
+#[derive(Clone, Copy)]
+struct Node {
+    parent: Option<usize>,
+    frame: u64  // the game's position when it was saved
+}
+
+// Where every save state slot came from, kept next to them in branches.txt. The game's position
+// is counted in emulated frames, rewinding takes it back and loading a slot moves it to the slot's.
+// A save hangs off the newest slot on the way back to the root that's still behind the position,
+// so saving after rewinding past the current slot starts a branch next to it
+pub struct Branches {
+    nodes: [Option<Node>; SLOTS],
+    current: Option<usize>,  // saved to or loaded last
+    position: u64
+}
+
+impl Branches {
+    pub fn new() -> Branches {
+        Branches {
+            nodes: [None; SLOTS],
+            current: None,
+            position: 0
+        }
+    }
+
+    // lines of `slot parent frame`, slots counted from 1 like the file names and - for no parent
+    pub fn load(dir: &Path) -> Branches {
+        let mut branches = Branches::new();
+        let text = fs::read_to_string(dir.join(BRANCH_FILE)).unwrap_or_default();
+        for line in text.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let slot = |s: &str| s.parse::<usize>().ok().filter(|n| (1 ..= SLOTS).contains(n)).map(|n| n - 1);
+            if let [s, parent, frame] = parts[..] {
+                if let (Some(s), Ok(frame)) = (slot(s), frame.parse()) {
+                    branches.nodes[s] = Some(Node { parent: slot(parent).filter(|p| *p != s), frame });
+                }
+            }
+        }
+        branches
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let mut text = String::new();
+        for (s, node) in self.nodes.iter().enumerate() {
+            if let Some(n) = node {
+                let parent = n.parent.map_or("-".to_string(), |p| (p + 1).to_string());
+                text += &format!("{} {} {}\n", s + 1, parent, n.frame);
+            }
+        }
+        fs::write(dir.join(BRANCH_FILE), text)
+    }
+
+    // after every emulated frame
+    pub fn frame(&mut self) {
+        self.position += 1;
+    }
+
+    pub fn rewound(&mut self, frames: usize) {
+        self.position = self.position.saturating_sub(frames as u64);
+    }
+
+    // true when that started a new branch instead of carrying on from the current slot
+    pub fn saved(&mut self, slot: usize) -> bool {
+        let mut parent = self.current;
+        let mut steps = 0;
+        while let Some(p) = parent {
+            steps += 1;
+            match self.nodes[p] {
+                _ if steps > SLOTS => parent = None,  // branches.txt was edited into a loop
+                Some(n) if p != slot && n.frame <= self.position => break,
+                Some(n) => parent = n.parent,
+                None => parent = None
+            }
+        }
+
+        // whatever hung off the state being overwritten moves up to its parent
+        let replaced = self.nodes[slot].and_then(|n| n.parent);
+        for n in self.nodes.iter_mut().flatten() {
+            if n.parent == Some(slot) {
+                n.parent = replaced;
+            }
+        }
+
+        let branched = self.current.is_some() && parent != self.current;
+        self.nodes[slot] = Some(Node { parent, frame: self.position });
+        self.current = Some(slot);
+        branched
+    }
+
+    // a state from before the tree was kept starts one of its own
+    pub fn loaded(&mut self, slot: usize) {
+        let node = *self.nodes[slot].get_or_insert(Node { parent: None, frame: 0 });
+        self.position = node.frame;
+        self.current = Some(slot);
+    }
+
+    // The slots that pass exists depth first from the roots, with their depth. One whose
+    // parent is gone is a root itself
+    pub fn tree(&self, exists: &dyn Fn(usize) -> bool) -> Vec<(usize, usize)> {
+        let parent = |s: usize| self.nodes[s].and_then(|n| n.parent).filter(|p| exists(*p));
+        let mut stack: Vec<(usize, usize)> = (0 .. SLOTS).rev()
+            .filter(|s| exists(*s) && parent(*s).is_none())
+            .map(|s| (s, 0))
+            .collect();
+
+        let mut tree = vec![];
+        while let Some((s, depth)) = stack.pop() {
+            tree.push((s, depth));
+            stack.extend((0 .. SLOTS).rev().filter(|c| exists(*c) && parent(*c) == Some(s)).map(|c| (c, depth + 1)));
+        }
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent(branches: &Branches, slot: usize) -> Option<usize> {
+        branches.nodes[slot].and_then(|n| n.parent)
+    }
+
+    fn run(branches: &mut Branches, frames: usize) {
+        for _ in 0 .. frames {
+            branches.frame();
+        }
+    }
+
+    #[test]
+    fn saves_carry_on_from_the_current_slot() {
+        let mut branches = Branches::new();
+        assert!(!branches.saved(0));
+        run(&mut branches, 10);
+        assert!(!branches.saved(1));
+        assert_eq!(parent(&branches, 1), Some(0));
+        assert_eq!(branches.nodes[1].map(|n| n.frame), Some(10));
+    }
+
+    #[test]
+    fn saving_after_rewinding_branches() {
+        let mut branches = Branches::new();
+        branches.saved(0);
+        run(&mut branches, 10);
+        branches.saved(1);
+        branches.rewound(5);
+        assert!(branches.saved(2));
+        assert_eq!(parent(&branches, 2), Some(0));
+        assert_eq!(branches.nodes[2].map(|n| n.frame), Some(5));
+
+        // not past the start of the game
+        branches.rewound(100);
+        assert!(branches.saved(3));
+        assert_eq!(parent(&branches, 3), Some(0));
+    }
+
+    #[test]
+    fn loading_moves_to_the_slot() {
+        let mut branches = Branches::new();
+        branches.saved(0);
+        run(&mut branches, 10);
+        branches.saved(1);
+        branches.loaded(0);
+        run(&mut branches, 3);
+        assert!(!branches.saved(2));
+        assert_eq!(parent(&branches, 2), Some(0));
+
+        // a slot saved before the tree was kept
+        branches.loaded(3);
+        assert_eq!(branches.position, 0);
+        assert_eq!(parent(&branches, 3), None);
+    }
+
+    #[test]
+    fn overwriting_reparents_the_children() {
+        let mut branches = Branches::new();
+        branches.saved(0);
+        run(&mut branches, 10);
+        branches.saved(1);
+        run(&mut branches, 10);
+        branches.saved(2);
+        branches.loaded(0);
+        run(&mut branches, 30);
+        assert!(!branches.saved(1));
+        assert_eq!(parent(&branches, 2), Some(0));
+        assert_eq!(parent(&branches, 1), Some(0));
+    }
+
+    #[test]
+    fn saving_survives_a_loop() {
+        let mut branches = Branches::new();
+        branches.nodes[0] = Some(Node { parent: Some(1), frame: 50 });
+        branches.nodes[1] = Some(Node { parent: Some(0), frame: 50 });
+        branches.loaded(0);
+        branches.rewound(10);
+        assert!(branches.saved(2));
+        assert_eq!(parent(&branches, 2), None);
+    }
+
+    #[test]
+    fn tree_is_depth_first() {
+        let mut branches = Branches::new();
+        branches.saved(0);
+        run(&mut branches, 10);
+        branches.saved(1);
+        branches.rewound(5);
+        branches.saved(2);
+        branches.nodes[3] = Some(Node { parent: Some(1), frame: 20 });
+        assert_eq!(branches.tree(&|_| true), vec![(0, 0), (1, 1), (3, 2), (2, 1)]);
+        assert_eq!(branches.tree(&|s| s != 0), vec![(1, 0), (3, 1), (2, 0)]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use raylib::prelude::*;
+
+use crate::emulator::png;
+use crate::frontend::Lang;
+use crate::frontend::branches::Branches;
+
+const CAPTURE_DIR: &str = "captures";
+pub const SLOTS: usize = 4;
+const GALLERY_ROWS: usize = 16;
+
+// captures/<game title>/, anything that can't go in a file name is dropped.
+// Untitled roms fall back to the header hash
+pub fn game_dir(title: &str, header_hash: u32) -> PathBuf {
+    let name: String = title.trim().chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
+        .collect();
+    if name.is_empty() {
+        PathBuf::from(CAPTURE_DIR).join(format!("{:08x}", header_hash))
+    } else {
+        PathBuf::from(CAPTURE_DIR).join(name)
+    }
+}
+
+pub fn state_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("slot{}.state", slot + 1))
+}
+
+// a picture of the moment each state was saved, shown next to it in the gallery listing
+pub fn state_shot_path(dir: &Path, slot: usize) -> PathBuf {
+    dir.join(format!("slot{}.png", slot + 1))
+}
+
+// named by the time it was taken, a number added for several in the same second
+pub fn screenshot_path(dir: &Path) -> PathBuf {
+    capture_path(dir, "png")
+}
+
+pub fn capture_path(dir: &Path, ext: &str) -> PathBuf {
+    let stamp = format_time(SystemTime::now());
+    let mut p = dir.join(format!("{}.{}", stamp, ext));
+    let mut n = 2;
+    while p.exists() {
+        p = dir.join(format!("{}-{}.{}", stamp, n, ext));
+        n += 1;
+    }
+    p
+}
+
+// the 160x144 rgb frame
+pub fn write_png(p: &Path, frame: &[u8]) -> io::Result<()> {
+    if let Some(dir) = p.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    png::write_rgb(p, 160, 144, frame)
+}
+
+// a 160x144 rgb frame, someone else's screenshot as long as it's unscaled
+pub fn read_png(p: &Path) -> io::Result<Vec<u8>> {
+    let (width, height, rgb) = png::read_rgb(p)?;
+    if (width, height) != (160, 144) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}x{}, expected 160x144", width, height)))
+    }
+    Ok(rgb)
+}
+
+// UTC, 2024-01-31_13-45-00, sorts the same as the times do
+fn format_time(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // days since 1970 to a civil date, Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}", year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+struct Entry {
+    name: String,
+    modified: String,
+    slot: Option<usize>,  // a save state, Enter loads it
+    depth: usize  // in the branch tree
+}
+
+// F12 lists the running game's save states as their branch tree (see Branches), then the other
+// captures newest first. Up/down pick one, Enter loads a state
+pub struct Gallery {
+    pub visible: bool,
+    entries: Vec<Entry>,
+    selected: usize
+}
+
+impl Gallery {
+    pub fn new() -> Gallery {
+        Gallery {
+            visible: false,
+            entries: vec![],
+            selected: 0
+        }
+    }
+
+    pub fn toggle(&mut self, dir: &Path) {
+        self.visible = !self.visible;
+        if self.visible {
+            self.refresh(dir);
+        }
+    }
+
+    pub fn refresh(&mut self, dir: &Path) {
+        let mut files: Vec<(SystemTime, PathBuf)> = match fs::read_dir(dir) {
+            Ok(d) => d.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("png") | Some("state") | Some("wav") | Some("apng") | Some("mp4")))
+                .map(|p| (fs::metadata(&p).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH), p))
+                .collect(),
+            Err(_) => vec![]
+        };
+        files.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let slots: Vec<Option<usize>> = files.iter().map(|(_, p)| (0 .. SLOTS).find(|s| *p == state_path(dir, *s))).collect();
+        let mut tree = Branches::load(dir).tree(&|s| slots.contains(&Some(s)));
+        // the tree leaves out slots on a broken parent line, they go at the top level
+        for s in slots.iter().flatten() {
+            if !tree.iter().any(|(t, _)| t == s) {
+                tree.push((*s, 0));
+            }
+        }
+
+        let entry = |i: usize, depth: usize| {
+            let (t, p) = &files[i];
+            Entry {
+                name: p.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned()),
+                modified: format_time(*t),
+                slot: slots[i],
+                depth
+            }
+        };
+        self.entries = tree.iter()
+            .filter_map(|&(s, depth)| slots.iter().position(|x| *x == Some(s)).map(|i| entry(i, depth)))
+            .chain((0 .. files.len()).filter(|i| slots[*i].is_none()).map(|i| entry(i, 0)))
+            .collect();
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+    }
+
+    // returns the slot to load when Enter is pressed on a state
+    pub fn handle_keys(&mut self, hl: &RaylibHandle) -> Option<usize> {
+        use raylib::consts::KeyboardKey::{KEY_UP, KEY_DOWN, KEY_ENTER};
+
+        if hl.is_key_pressed(KEY_UP) {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if hl.is_key_pressed(KEY_DOWN) && self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+        if hl.is_key_pressed(KEY_ENTER) {
+            return self.entries.get(self.selected).and_then(|e| e.slot)
+        }
+        None
+    }
+
+    pub fn draw(&self, d: &mut RaylibDrawHandle, x: i32, y: i32, title: &str, lang: &Lang) {
+        let first = self.selected.saturating_sub(GALLERY_ROWS - 1);
+        let rows = self.entries.len().min(GALLERY_ROWS) as i32;
+        d.draw_rectangle(x, y, 300, 16 + 12 * rows.max(1), Color::new(0, 0, 0, 200));
+        d.draw_text(&lang.text("gallery_title", &[&title, &self.entries.len()]), x + 4, y + 3, 10, Color::WHITE);
+
+        if self.entries.is_empty() {
+            d.draw_text(&lang.text("gallery_empty", &[]), x + 4, y + 15, 10, Color::GRAY);
+        }
+        for (i, e) in self.entries.iter().enumerate().skip(first).take(GALLERY_ROWS) {
+            let row = y + 15 + 12 * (i - first) as i32;
+            let color = if i == self.selected { Color::YELLOW } else { Color::LIGHTGRAY };
+            d.draw_text(&e.name, x + 4 + 8 * e.depth as i32, row, 10, color);
+            d.draw_text(&e.modified, x + 180, row, 10, color);
+        }
+    }
+}
@@ -0,0 +1,1005 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use raylib::prelude::*;
+
+use crate::frontend::palette;
+use crate::emulator::{self, CPU, MODE, VRAM_BANKS, WRAM_BANKS, IRQ_NAMES, IO_REGISTERS, IE_REGISTER, IoRegister, Support, Watch, WatchType, Symbols, Flag, Pixel_palette, RunState, compose_two_bytes};
+
+pub const WINDOW_WIDTH: i32 = 850;
+pub const WINDOW_HEIGHT: i32 = 580;
+
+const PANEL_X: i32 = 330;
+const PANEL_Y: i32 = 20;
+const TEXT_SIZE: i32 = 10;
+const LINE: i32 = 12;
+const IO_LOG_LINES: usize = 40;
+const IO_LOG_DUMP: &str = "iolog.txt";
+const PALETTE_EXPORTS: [&str; 3] = ["palette.png", "palette.pal", "palette.txt"];
+const WAVE_STEP: i32 = 16;  // pixels per sample and per level in the wave editor
+const IRQ_GRAPH_HEIGHT: i32 = 72;
+const IRQ_BAR_WIDTH: i32 = 2;  // pixels per serviced interrupt in the latency graphs
+const IO_REG_ROWS: usize = 32;  // registers per column in the io register table
+const WATCH_LOG: &str = "watch.csv";
+const WATCH_ROWS: usize = 40;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Panel {
+    Tiles,
+    Tilemap,
+    OAM,
+    Palettes,
+    Memory,
+    IoLog,
+    Wave,
+    Interrupts,
+    IoRegs,
+    Watch
+}
+
+impl Panel {
+    const ALL: [Panel; 10] = [Panel::Tiles, Panel::Tilemap, Panel::OAM, Panel::Palettes, Panel::Memory, Panel::IoLog, Panel::Wave, Panel::Interrupts, Panel::IoRegs, Panel::Watch];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Panel::Tiles => "Tiles",
+            Panel::Tilemap => "Tilemap",
+            Panel::OAM => "OAM",
+            Panel::Palettes => "Palettes",
+            Panel::Memory => "Memory",
+            Panel::IoLog => "IO log",
+            Panel::Wave => "Wave",
+            Panel::Interrupts => "IRQ",
+            Panel::IoRegs => "IO regs",
+            Panel::Watch => "Watch"
+        }
+    }
+
+    fn index(&self) -> usize {
+        Panel::ALL.iter().position(|p| p == self).unwrap()
+    }
+
+    fn from_name(name: &str) -> Option<Panel> {
+        Panel::ALL.iter().find(|p| p.name() == name).copied()
+    }
+}
+
+// None -> 0 -> 1 ... -> max -> None
+fn next_bank(bank: Option<u8>, max: u8) -> Option<u8> {
+    match bank {
+        None => Some(0),
+        Some(b) if b < max => Some(b + 1),
+        Some(_) => None
+    }
+}
+
+fn parse_addresses(val: &str) -> Option<Vec<u16>> {
+    val.split_whitespace().map(|a| u16::from_str_radix(a, 16).ok()).collect()
+}
+
+fn format_addresses(addresses: &[u16]) -> String {
+    addresses.iter().map(|a| format!("{:04X}", a)).collect::<Vec<_>>().join(" ")
+}
+
+fn cgb_color(c: emulator::Color) -> Color {
+    Color::new(c.r << 3, c.g << 3, c.b << 3, 255)
+}
+
+fn dmg_color(c: emulator::Color) -> Color {
+    Color::new(c.r, c.g, c.b, c.a)
+}
+
+fn hex_key(hl: &RaylibHandle) -> Option<u8> {
+    use raylib::consts::KeyboardKey::*;
+    const KEYS: [KeyboardKey; 16] = [
+        KEY_ZERO, KEY_ONE, KEY_TWO, KEY_THREE, KEY_FOUR, KEY_FIVE, KEY_SIX, KEY_SEVEN,
+        KEY_EIGHT, KEY_NINE, KEY_A, KEY_B, KEY_C, KEY_D, KEY_E, KEY_F
+    ];
+
+    KEYS.iter().position(|k| hl.is_key_pressed(*k)).map(|v| v as u8)
+}
+
+// F1 shows the panels, F3/F4 switch between them, F6 pauses/continues, F7 steps one instruction.
+// While paused the memory view takes hex input at the cursor, Shift+B toggles a breakpoint
+// and Shift+W a write watchpoint there, Shift+A adds it to the watch panel. Layout, breakpoints,
+// watchpoints and watches are kept per game.
+// Drawn with raylib into the game's window. egui has no raylib backend, its integrations want
+// to own a winit window and a GL or wgpu context, raylib already owns both
+pub struct DebugUI {
+    pub visible: bool,
+    pub paused: bool,
+    stop: Option<RunState>,  // why the emulation stopped by itself
+    panel: Panel,
+
+    tiles: Option<Texture2D>,  // None while the display is being reopened
+    tile_arr: Vec<u8>,  // 2 banks of 384 tiles side by side, 256x192 RGB
+    tilemap: Option<Texture2D>,
+    tilemap_arr: Vec<u8>,  // 256x256 RGB
+    tilemap_high: bool,  // false - 9800-9BFF, true - 9C00-9FFF
+    // vram chunks changed since each view was last decoded, taken from Memory::vram_dirty
+    tiles_dirty: Vec<bool>,
+    tilemap_dirty: Vec<bool>,
+    // what the views were last decoded with, any change redraws them whole
+    tiles_key: Option<Vec<[emulator::Color; 4]>>,
+    tilemap_key: Option<(Vec<[emulator::Color; 4]>, bool, bool)>,
+
+    mem_addr: u16,  // first address shown in the memory view
+    cursor: u16,
+    nibble: Option<u8>,  // high nibble typed in the memory view
+    wram_bank: Option<u8>,  // bank shown at D000-DFFF, None - the mapped one
+    vram_bank: Option<u8>,  // bank shown at 8000-9FFF
+    sram_bank: Option<u8>,  // cart ram bank shown at A000-BFFF, picked banks bypass ram enable
+
+    wave_cursor: usize,  // selected channel 3 sample
+    io_cursor: usize,  // selected row of the io register table, IE is the last one
+
+    watches: Vec<Watch>,
+    watch_cursor: usize,
+    symbols: Symbols,  // from the .sym file next to the rom, names new watches
+    watch_log: Option<BufWriter<File>>,  // a line of values every frame
+    watch_log_header: bool  // the watch list changed, the next line names the columns again
+}
+
+impl DebugUI {
+    pub fn new(handle: &mut RaylibHandle, thread: &RaylibThread) -> DebugUI {
+        let mut debug = DebugUI {
+            visible: false,
+            paused: false,
+            stop: None,
+            panel: Panel::Tiles,
+
+            tiles: None,
+            tile_arr: vec![0; 256*192*3],
+            tilemap: None,
+            tilemap_arr: vec![0; 256*256*3],
+            tilemap_high: false,
+            tiles_dirty: vec![true; VRAM_BANKS*0x200],
+            tilemap_dirty: vec![true; VRAM_BANKS*0x200],
+            tiles_key: None,
+            tilemap_key: None,
+
+            mem_addr: 0xC000,
+            cursor: 0xC000,
+            nibble: None,
+            wram_bank: None,
+            vram_bank: None,
+            sram_bank: None,
+
+            wave_cursor: 0,
+            io_cursor: 0,
+
+            watches: vec![],
+            watch_cursor: 0,
+            symbols: Symbols::new(),
+            watch_log: None,
+            watch_log_header: true
+        };
+        debug.load_textures(handle, thread);
+        debug
+    }
+
+    // returns true when the window needs to be resized
+    pub fn handle_keys(&mut self, hl: &mut RaylibHandle, cpu: &mut CPU) -> bool {
+        use raylib::consts::KeyboardKey::*;
+
+        if hl.is_key_pressed(KEY_F1) {
+            self.visible = !self.visible;
+            return true;
+        }
+        if !self.visible {
+            return false;
+        }
+
+        if hl.is_key_pressed(KEY_F3) {
+            self.panel = Panel::ALL[(self.panel.index() + Panel::ALL.len() - 1) % Panel::ALL.len()];
+        }
+        if hl.is_key_pressed(KEY_F4) {
+            self.panel = Panel::ALL[(self.panel.index() + 1) % Panel::ALL.len()];
+        }
+        if hl.is_key_pressed(KEY_F6) {
+            self.paused = !self.paused;
+            self.stop = None;
+            cpu.resuming = !self.paused;
+        }
+        if hl.is_key_pressed(KEY_F7) && self.paused {
+            self.stop = None;
+            cpu.resuming = true;
+            cpu.step();
+            cpu.memory.ppu.frame_ready = false;
+        }
+
+        match self.panel {
+            Panel::Tilemap => {
+                if hl.is_key_pressed(KEY_T) {
+                    self.tilemap_high = !self.tilemap_high;
+                }
+            },
+            Panel::Palettes => {
+                if hl.is_key_pressed(KEY_O) {
+                    let palettes = cpu.memory.ppu.palettes();
+                    for name in PALETTE_EXPORTS.iter() {
+                        match palette::export(&palettes, Path::new(name)) {
+                            Ok(()) => println!("Palettes written to {}", name),
+                            Err(e) => eprintln!("Couldn't write palettes to {}: {}", name, e)
+                        }
+                    }
+                }
+            },
+            Panel::Memory => self.memory_keys(hl, cpu),
+            Panel::Watch => self.watch_keys(hl),
+            Panel::Wave => self.wave_keys(hl, cpu),
+            Panel::Interrupts => {
+                let latency = &mut cpu.memory.irq_latency;
+                if hl.is_key_pressed(KEY_P) {
+                    latency.toggle();
+                }
+                if hl.is_key_pressed(KEY_X) {
+                    latency.clear();
+                }
+            },
+            Panel::IoRegs => {
+                let rows = IO_REGISTERS.len() + 1;
+                if hl.is_key_pressed(KEY_UP) {
+                    self.io_cursor = (self.io_cursor + rows - 1) % rows;
+                } else if hl.is_key_pressed(KEY_DOWN) {
+                    self.io_cursor = (self.io_cursor + 1) % rows;
+                } else if hl.is_key_pressed(KEY_LEFT) || hl.is_key_pressed(KEY_RIGHT) {
+                    self.io_cursor = (self.io_cursor + IO_REG_ROWS) % (2*IO_REG_ROWS);
+                    self.io_cursor = self.io_cursor.min(rows - 1);
+                }
+                if hl.is_key_pressed(KEY_X) {
+                    cpu.memory.io_touched = [false; 0x100];
+                }
+                // one component back to power on with the rest left running, to find which one a bug is in
+                if self.paused && hl.is_key_down(KEY_LEFT_SHIFT) {
+                    if hl.is_key_pressed(KEY_P) {
+                        cpu.memory.ppu.reset();
+                        println!("PPU reset");
+                    } else if hl.is_key_pressed(KEY_S) {
+                        cpu.memory.apu.reset();
+                        println!("APU reset");
+                    } else if hl.is_key_pressed(KEY_T) {
+                        cpu.memory.reset_timers();
+                        println!("Timers reset");
+                    }
+                }
+            },
+            Panel::IoLog => {
+                let log = &mut cpu.memory.io_log;
+                if hl.is_key_pressed(KEY_P) {
+                    log.enabled = !log.enabled;
+                }
+                if hl.is_key_pressed(KEY_X) {
+                    log.clear();
+                }
+                if hl.is_key_pressed(KEY_O) {
+                    match log.dump(Path::new(IO_LOG_DUMP)) {
+                        Ok(()) => println!("IO log written to {}", IO_LOG_DUMP),
+                        Err(e) => eprintln!("Couldn't write IO log to {}: {}", IO_LOG_DUMP, e)
+                    }
+                }
+            },
+            _ => ()
+        }
+
+        false
+    }
+
+    fn memory_keys(&mut self, hl: &RaylibHandle, cpu: &mut CPU) {
+        use raylib::consts::KeyboardKey::*;
+
+        let mut moved = true;
+        if hl.is_key_pressed(KEY_LEFT) {
+            self.cursor = self.cursor.wrapping_sub(1);
+        } else if hl.is_key_pressed(KEY_RIGHT) {
+            self.cursor = self.cursor.wrapping_add(1);
+        } else if hl.is_key_pressed(KEY_UP) {
+            self.cursor = self.cursor.wrapping_sub(0x10);
+        } else if hl.is_key_pressed(KEY_DOWN) {
+            self.cursor = self.cursor.wrapping_add(0x10);
+        } else if hl.is_key_pressed(KEY_PAGE_UP) {
+            self.cursor = self.cursor.wrapping_sub(0x100);
+        } else if hl.is_key_pressed(KEY_PAGE_DOWN) {
+            self.cursor = self.cursor.wrapping_add(0x100);
+        } else {
+            moved = false;
+        }
+
+        if moved {
+            self.nibble = None;
+            if self.cursor < self.mem_addr || self.cursor >= self.mem_addr.wrapping_add(0x100) {
+                self.mem_addr = self.cursor & 0xFFF0;
+            }
+        }
+
+        if hl.is_key_pressed(KEY_R) {
+            self.wram_bank = next_bank(self.wram_bank, WRAM_BANKS as u8 - 1);
+        }
+        if hl.is_key_pressed(KEY_V) {
+            self.vram_bank = next_bank(self.vram_bank, VRAM_BANKS as u8 - 1);
+        }
+        if hl.is_key_pressed(KEY_Y) {
+            let banks = cpu.memory.cart.ram_banks();
+            self.sram_bank = if banks == 0 { None } else { next_bank(self.sram_bank, banks as u8 - 1) };
+        }
+
+        if !self.paused {  // letter keys belong to the game while it runs
+            return;
+        }
+
+        if hl.is_key_pressed(KEY_G) {
+            self.cursor = cpu.PC;
+            self.mem_addr = cpu.PC & 0xFFF0;
+        } else if hl.is_key_pressed(KEY_B) && hl.is_key_down(KEY_LEFT_SHIFT) {
+            match cpu.breakpoints.iter().position(|&b| b == self.cursor) {
+                Some(i) => { cpu.breakpoints.remove(i); },
+                None => cpu.breakpoints.push(self.cursor)
+            }
+        } else if hl.is_key_pressed(KEY_L) && hl.is_key_down(KEY_LEFT_SHIFT) {
+            match cpu.memory.cart.reload_save(true) {
+                Ok(()) => println!("Save reloaded"),
+                Err(e) => eprintln!("Couldn't reload save: {}", e)
+            }
+        } else if hl.is_key_pressed(KEY_A) && hl.is_key_down(KEY_LEFT_SHIFT) {
+            let name = self.symbols.name(self.cursor).map(|n| n.to_string());
+            self.watches.push(Watch { addr: self.cursor, kind: WatchType::U8, name: name });
+            self.watch_log_header = true;
+        } else if hl.is_key_pressed(KEY_W) && hl.is_key_down(KEY_LEFT_SHIFT) {
+            let watchpoints = &mut cpu.memory.watchpoints;
+            match watchpoints.iter().position(|&w| w == self.cursor) {
+                Some(i) => { watchpoints.remove(i); },
+                None => watchpoints.push(self.cursor)
+            }
+        } else if let Some(v) = hex_key(hl) {
+            match self.nibble.take() {
+                None => self.nibble = Some(v),
+                Some(high) => {
+                    self.poke(cpu, self.cursor, (high << 4) | v);
+                    self.cursor = self.cursor.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    // up/down pick a watch, T changes its type, X removes it, P starts and stops the log
+    fn watch_keys(&mut self, hl: &RaylibHandle) {
+        use raylib::consts::KeyboardKey::*;
+
+        if hl.is_key_pressed(KEY_UP) {
+            self.watch_cursor = self.watch_cursor.saturating_sub(1);
+        } else if hl.is_key_pressed(KEY_DOWN) && self.watch_cursor + 1 < self.watches.len() {
+            self.watch_cursor += 1;
+        }
+
+        if let Some(w) = self.watches.get_mut(self.watch_cursor) {
+            if hl.is_key_pressed(KEY_T) {
+                w.kind = w.kind.next();
+                self.watch_log_header = true;
+            }
+            if hl.is_key_pressed(KEY_X) {
+                self.watches.remove(self.watch_cursor);
+                self.watch_cursor = self.watch_cursor.min(self.watches.len().saturating_sub(1));
+                self.watch_log_header = true;
+            }
+        }
+
+        if hl.is_key_pressed(KEY_P) {
+            if self.watch_log.take().is_some() {
+                println!("Watch log written to {}", WATCH_LOG);
+            } else {
+                match File::create(WATCH_LOG) {
+                    Ok(f) => {
+                        self.watch_log = Some(BufWriter::new(f));
+                        self.watch_log_header = true;
+                    },
+                    Err(e) => eprintln!("Couldn't create {}: {}", WATCH_LOG, e)
+                }
+            }
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    // after every emulated frame, logs the watches when the log is on
+    pub fn frame_done(&mut self, cpu: &mut CPU) {
+        let mut log = match self.watch_log.take() {
+            Some(log) => log,
+            None => return
+        };
+
+        let mut line = String::new();
+        if self.watch_log_header {
+            let names: Vec<String> = self.watches.iter().map(|w| w.label()).collect();
+            line = format!("frame,{}\n", names.join(","));
+            self.watch_log_header = false;
+        }
+        let values: Vec<String> = self.watches.iter().map(|w| w.value(&mut |a| self.peek(cpu, a))).collect();
+        line.push_str(&format!("{},{}\n", cpu.memory.ppu.frame_count, values.join(",")));
+
+        match log.write_all(line.as_bytes()) {
+            Ok(()) => self.watch_log = Some(log),
+            Err(e) => eprintln!("Couldn't write {}: {}", WATCH_LOG, e)
+        }
+    }
+
+    // mouse draws the waveform, arrows pick a sample and change its level, hex digits set it while paused
+    fn wave_keys(&mut self, hl: &RaylibHandle, cpu: &mut CPU) {
+        use raylib::consts::KeyboardKey::*;
+
+        if hl.is_mouse_button_down(MouseButton::MOUSE_LEFT_BUTTON) {
+            let pos = hl.get_mouse_position();
+            let x = pos.x as i32 - PANEL_X;
+            let y = pos.y as i32 - PANEL_Y;
+            if x >= 0 && x < 32*WAVE_STEP && y >= 0 && y < 16*WAVE_STEP {
+                self.wave_cursor = (x / WAVE_STEP) as usize;
+                cpu.memory.apu.set_wave_sample(self.wave_cursor, 15 - (y / WAVE_STEP) as u8);
+            }
+        }
+
+        if hl.is_key_pressed(KEY_LEFT) {
+            self.wave_cursor = (self.wave_cursor + 31) % 32;
+        } else if hl.is_key_pressed(KEY_RIGHT) {
+            self.wave_cursor = (self.wave_cursor + 1) % 32;
+        }
+
+        let val = Self::wave_sample(cpu, self.wave_cursor);
+        let new = if hl.is_key_pressed(KEY_UP) {
+            Some((val + 1).min(15))
+        } else if hl.is_key_pressed(KEY_DOWN) {
+            Some(val.saturating_sub(1))
+        } else if self.paused {
+            hex_key(hl)
+        } else { None };
+
+        if let Some(v) = new {
+            cpu.memory.apu.set_wave_sample(self.wave_cursor, v);
+        }
+    }
+
+    fn wave_sample(cpu: &CPU, i: usize) -> u8 {
+        let byte = cpu.memory.apu.wave_ram()[i/2];
+        if i % 2 == 0 { byte >> 4 } else { byte&0xF }
+    }
+
+    pub fn stopped(&mut self, state: RunState) {
+        self.paused = true;
+        self.stop = Some(state);
+    }
+
+    pub fn load_session(&mut self, cpu: &mut CPU, p: &Path) {
+        let data = match fs::read_to_string(p) {
+            Ok(data) => data,
+            Err(_) => return
+        };
+
+        for (n, line) in data.lines().enumerate() {
+            let (key, val) = match line.find('=') {
+                Some(i) => (line[.. i].trim(), line[i+1 ..].trim()),
+                None => continue
+            };
+
+            let ok = match key {
+                "visible" => { self.visible = val == "true"; true },
+                "tilemap" => { self.tilemap_high = val == "9C00"; true },
+                "panel" => Panel::from_name(val).map(|p| self.panel = p).is_some(),
+                "memory" => parse_addresses(val).filter(|a| a.len() == 2).map(|a| {
+                    self.mem_addr = a[0];
+                    self.cursor = a[1];
+                }).is_some(),
+                "breakpoints" => parse_addresses(val).map(|a| cpu.breakpoints = a).is_some(),
+                "watchpoints" => parse_addresses(val).map(|a| cpu.memory.watchpoints = a).is_some(),
+                "watches" => {
+                    let symbols = &self.symbols;
+                    val.split_whitespace().map(|w| Watch::parse(w, symbols)).collect::<Option<Vec<_>>>()
+                        .map(|w| self.watches = w).is_some()
+                },
+                _ => false
+            };
+            if !ok {
+                eprintln!("{}:{}: invalid session entry", p.display(), n + 1);
+            }
+        }
+    }
+
+    pub fn save_session(&self, cpu: &CPU, p: &Path) -> io::Result<()> {
+        let data = format!(
+            "visible = {}\npanel = {}\ntilemap = {}\nmemory = {:04X} {:04X}\nbreakpoints = {}\nwatchpoints = {}\nwatches = {}\n",
+            self.visible,
+            self.panel.name(),
+            if self.tilemap_high { "9C00" } else { "9800" },
+            self.mem_addr, self.cursor,
+            format_addresses(&cpu.breakpoints),
+            format_addresses(&cpu.memory.watchpoints),
+            self.watches.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(" ")
+        );
+
+        if let Some(dir) = p.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(p, data)
+    }
+
+    pub fn load_textures(&mut self, handle: &mut RaylibHandle, thread: &RaylibThread) {
+        let mut img = Image::gen_image_color(256, 192, Color::BLACK);
+        img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
+        self.tiles = Some(handle.load_texture_from_image(thread, &img).expect("Couldnt load texture from image"));
+
+        let mut img = Image::gen_image_color(256, 256, Color::BLACK);
+        img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
+        self.tilemap = Some(handle.load_texture_from_image(thread, &img).expect("Couldnt load texture from image"));
+    }
+
+    // must be called before the window they were loaded in is closed
+    pub fn unload_textures(&mut self) {
+        self.tiles = None;
+        self.tilemap = None;
+    }
+
+    pub fn update_textures(&mut self, cpu: &mut CPU) {
+        // both views consume the same flags, so collect them before either clears its own
+        for (i, dirty) in cpu.memory.vram_dirty.iter_mut().enumerate() {
+            if *dirty {
+                self.tiles_dirty[i] = true;
+                self.tilemap_dirty[i] = true;
+                *dirty = false;
+            }
+        }
+
+        match self.panel {
+            Panel::Tiles => {
+                self.decode_tiles(cpu);
+                if let Some(t) = &mut self.tiles {
+                    t.update_texture(&self.tile_arr);
+                }
+            },
+            Panel::Tilemap => {
+                self.decode_tilemap(cpu);
+                if let Some(t) = &mut self.tilemap {
+                    t.update_texture(&self.tilemap_arr);
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn tile_color(cpu: &CPU, pixel: u8, palette: u8) -> Color {
+        if cpu.memory.mode == MODE::CGB {
+            cgb_color(cpu.memory.ppu.cgb_bg_palette()[palette as usize][pixel as usize])
+        } else {
+            dmg_color(cpu.memory.ppu.dmg_color(pixel, Pixel_palette::BG))
+        }
+    }
+
+    fn decode_tiles(&mut self, cpu: &CPU) {
+        let key = cpu.memory.ppu.palettes();
+        let full = self.tiles_key.as_ref() != Some(&key);
+        self.tiles_key = Some(key);
+        let vram = &cpu.memory.vram;
+
+        for bank in 0 .. VRAM_BANKS {
+            for tile in 0 .. 384 {
+                if !full && !self.tiles_dirty[bank*0x200 + tile] { continue }
+                let x = bank*128 + (tile%16)*8;
+                let y = (tile/16)*8;
+
+                for row in 0 .. 8 {
+                    let pos = bank*0x2000 + tile*16 + row*2;
+                    let pixels = compose_two_bytes(vram[pos], vram[pos+1]);
+
+                    let mut pixel_pos = ((y + row)*256 + x)*3;
+                    for pix in pixels.iter() {
+                        let c = DebugUI::tile_color(cpu, *pix, 0);
+                        self.tile_arr[pixel_pos] = c.r;
+                        self.tile_arr[pixel_pos+1] = c.g;
+                        self.tile_arr[pixel_pos+2] = c.b;
+                        pixel_pos += 3;
+                    }
+                }
+            }
+        }
+        self.tiles_dirty.iter_mut().for_each(|d| *d = false);
+    }
+
+    fn decode_tilemap(&mut self, cpu: &mut CPU) {
+        let unsigned_tiledata = cpu.memory.ppu.read(0xFF40)&0x10 != 0;
+        let base = if self.tilemap_high { 0x1C00 } else { 0x1800 };
+        let key = (cpu.memory.ppu.palettes(), unsigned_tiledata, self.tilemap_high);
+        let full = self.tilemap_key.as_ref() != Some(&key);
+        self.tilemap_key = Some(key);
+        let vram = &cpu.memory.vram;
+
+        for ty in 0 .. 32 {
+            for tx in 0 .. 32 {
+                let map_pos = base + ty*32 + tx;
+                let tile = vram[map_pos];
+                let attrib = vram.get(map_pos + 0x2000).copied().unwrap_or(0);  // always 0 on DMG
+                let bank = ((attrib&0x8) as usize >> 3) * 0x2000;
+
+                let tile_pos = if unsigned_tiledata {
+                    tile as usize * 16
+                } else {
+                    (0x1000 + tile as i8 as i32 * 16) as usize
+                };
+
+                let dirty = &self.tilemap_dirty;
+                let attrib_dirty = dirty.get((map_pos + 0x2000)/16).copied().unwrap_or(false);
+                if !full && !dirty[map_pos/16] && !attrib_dirty && !dirty[(bank + tile_pos)/16] { continue }
+
+                for row in 0 .. 8 {
+                    let r = if attrib&0x40 != 0 { 7 - row } else { row };
+                    let pos = bank + tile_pos + r*2;
+                    let mut pixels = compose_two_bytes(vram[pos], vram[pos+1]);
+                    if attrib&0x20 != 0 { pixels.reverse(); }
+
+                    let mut pixel_pos = ((ty*8 + row)*256 + tx*8)*3;
+                    for pix in pixels.iter() {
+                        let c = DebugUI::tile_color(cpu, *pix, attrib&0x7);
+                        self.tilemap_arr[pixel_pos] = c.r;
+                        self.tilemap_arr[pixel_pos+1] = c.g;
+                        self.tilemap_arr[pixel_pos+2] = c.b;
+                        pixel_pos += 3;
+                    }
+                }
+            }
+        }
+        self.tilemap_dirty.iter_mut().for_each(|d| *d = false);
+    }
+
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, cpu: &mut CPU) {
+        let mut x = PANEL_X;
+        for panel in Panel::ALL.iter() {
+            let color = if *panel == self.panel { Color::YELLOW } else { Color::GRAY };
+            d.draw_text(panel.name(), x, 4, TEXT_SIZE, color);
+            x += 52;
+        }
+
+        match self.panel {
+            Panel::Tiles => self.draw_tiles(d),
+            Panel::Tilemap => self.draw_tilemap(d, cpu),
+            Panel::OAM => self.draw_oam(d, cpu),
+            Panel::Palettes => self.draw_palettes(d, cpu),
+            Panel::Memory => self.draw_memory(d, cpu),
+            Panel::IoLog => self.draw_io_log(d, cpu),
+            Panel::Wave => self.draw_wave(d, cpu),
+            Panel::Interrupts => self.draw_interrupts(d, cpu),
+            Panel::IoRegs => self.draw_io_regs(d, cpu),
+            Panel::Watch => self.draw_watches(d, cpu)
+        }
+
+        self.draw_cpu(d, cpu);
+    }
+
+    fn draw_tiles(&self, d: &mut RaylibDrawHandle) {
+        let tiles = match &self.tiles {
+            Some(t) => t,
+            None => return
+        };
+        d.draw_texture_pro(
+            tiles,
+            Rectangle::new(0., 0., 256., 192.),
+            Rectangle::new(PANEL_X as f32, PANEL_Y as f32, 512., 384.),
+            Vector2::new(0., 0.), 0., Color::WHITE
+        );
+        d.draw_line(PANEL_X + 256, PANEL_Y, PANEL_X + 256, PANEL_Y + 384, Color::RED);
+        d.draw_text("VRAM bank 0", PANEL_X, PANEL_Y + 390, TEXT_SIZE, Color::WHITE);
+        d.draw_text("VRAM bank 1", PANEL_X + 260, PANEL_Y + 390, TEXT_SIZE, Color::WHITE);
+    }
+
+    fn draw_tilemap(&self, d: &mut RaylibDrawHandle, cpu: &mut CPU) {
+        let tilemap = match &self.tilemap {
+            Some(t) => t,
+            None => return
+        };
+        d.draw_texture_pro(
+            tilemap,
+            Rectangle::new(0., 0., 256., 256.),
+            Rectangle::new(PANEL_X as f32, PANEL_Y as f32, 512., 512.),
+            Vector2::new(0., 0.), 0., Color::WHITE
+        );
+
+        let scy = cpu.memory.ppu.read(0xFF42) as i32;
+        let scx = cpu.memory.ppu.read(0xFF43) as i32;
+        d.draw_rectangle_lines(PANEL_X + scx*2, PANEL_Y + scy*2, 320, 288, Color::RED);
+
+        let name = if self.tilemap_high { "9C00 (T to switch)" } else { "9800 (T to switch)" };
+        d.draw_text(name, PANEL_X, PANEL_Y + 518, TEXT_SIZE, Color::WHITE);
+    }
+
+    fn draw_oam(&self, d: &mut RaylibDrawHandle, cpu: &CPU) {
+        let oam = &cpu.memory.OAM;
+
+        for i in 0 .. 40 {
+            let s = &oam[i*4 .. i*4+4];
+            let text = format!("{:02} Y:{:02X} X:{:02X} T:{:02X} A:{:02X}", i, s[0], s[1], s[2], s[3]);
+            let x = PANEL_X + (i as i32 / 20) * 200;
+            let y = PANEL_Y + (i as i32 % 20) * LINE;
+            let color = if s[1] == 0 || s[1] >= 168 || s[0] == 0 || s[0] >= 160 { Color::GRAY } else { Color::WHITE };
+            d.draw_text(&text, x, y, TEXT_SIZE, color);
+        }
+    }
+
+    fn draw_palettes(&self, d: &mut RaylibDrawHandle, cpu: &CPU) {
+        let ppu = &cpu.memory.ppu;
+
+        let dmg = [("BGP", Pixel_palette::BG), ("OBP0", Pixel_palette::OBP0), ("OBP1", Pixel_palette::OBP1)];
+        for (i, (name, palette)) in dmg.iter().enumerate() {
+            let y = PANEL_Y + i as i32 * 20;
+            d.draw_text(name, PANEL_X, y + 4, TEXT_SIZE, Color::WHITE);
+            for c in 0 .. 4 {
+                d.draw_rectangle(PANEL_X + 40 + c*20, y, 18, 18, dmg_color(ppu.dmg_color(c as u8, *palette)));
+            }
+        }
+
+        let cgb = [("BG", ppu.cgb_bg_palette()), ("OBJ", ppu.cgb_obj_palette())];
+        for (col, (name, palettes)) in cgb.iter().enumerate() {
+            let x = PANEL_X + col as i32 * 150;
+            d.draw_text(name, x, PANEL_Y + 70, TEXT_SIZE, Color::WHITE);
+            for (i, palette) in palettes.iter().enumerate() {
+                let y = PANEL_Y + 84 + i as i32 * 20;
+                d.draw_text(&format!("{}", i), x, y + 4, TEXT_SIZE, Color::WHITE);
+                for (c, color) in palette.iter().enumerate() {
+                    d.draw_rectangle(x + 20 + c as i32 * 20, y, 18, 18, cgb_color(*color));
+                }
+            }
+        }
+
+        d.draw_text(&format!("O export to {}", PALETTE_EXPORTS.join(", ")), PANEL_X, PANEL_Y + 250, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn bank_for(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000 ..= 0x9FFF => self.vram_bank,
+            0xA000 ..= 0xBFFF => self.sram_bank,
+            0xD000 ..= 0xDFFF => self.wram_bank,
+            _ => None
+        }
+    }
+
+    // banked areas go straight to the arrays when a bank is picked, so mapping registers stay as they are
+    fn peek(&self, cpu: &mut CPU, addr: u16) -> u8 {
+        match self.bank_for(addr) {
+            Some(bank) => cpu.memory.peek_bank(addr, bank),
+            None => cpu.memory.read(addr)
+        }
+    }
+
+    fn poke(&self, cpu: &mut CPU, addr: u16, val: u8) {
+        match self.bank_for(addr) {
+            Some(bank) => cpu.memory.poke_bank(addr, bank, val),
+            None => cpu.memory.write(addr, val)
+        }
+    }
+
+    fn draw_memory(&self, d: &mut RaylibDrawHandle, cpu: &mut CPU) {
+        for row in 0 .. 16u16 {
+            let addr = self.mem_addr.wrapping_add(row*16);
+            let y = PANEL_Y + row as i32 * LINE;
+            d.draw_text(&format!("{:04X}", addr), PANEL_X, y, TEXT_SIZE, Color::GRAY);
+
+            for col in 0 .. 16u16 {
+                let a = addr.wrapping_add(col);
+                let x = PANEL_X + 40 + col as i32 * 22;
+                let color = if a == self.cursor {
+                    Color::YELLOW
+                } else if cpu.breakpoints.contains(&a) {
+                    Color::RED
+                } else if cpu.memory.watchpoints.contains(&a) {
+                    Color::ORANGE
+                } else { Color::WHITE };
+                d.draw_text(&format!("{:02X}", self.peek(cpu, a)), x, y, TEXT_SIZE, color);
+            }
+        }
+
+        let help = if self.paused {
+            "arrows/PgUp/PgDn move, hex digits edit, Shift+B breakpoint, Shift+W watchpoint, Shift+A watch, G go to PC, Shift+L reload .sav"
+        } else {
+            "arrows/PgUp/PgDn move, pause (F6) to edit"
+        };
+        d.draw_text(help, PANEL_X, PANEL_Y + 16*LINE + 6, TEXT_SIZE, Color::GRAY);
+
+        let bank_name = |b: Option<u8>| b.map_or("mapped".to_string(), |b| format!("bank {}", b));
+        let banks = format!("D000: WRAM {} (R)   8000: VRAM {} (V)   A000: cart RAM {} (Y)",
+            bank_name(self.wram_bank), bank_name(self.vram_bank), bank_name(self.sram_bank));
+        d.draw_text(&banks, PANEL_X, PANEL_Y + 17*LINE + 6, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn draw_watches(&self, d: &mut RaylibDrawHandle, cpu: &mut CPU) {
+        d.draw_text("name                      addr  type  value        bytes", PANEL_X, PANEL_Y, TEXT_SIZE, Color::GRAY);
+        let first = self.watch_cursor.saturating_sub(WATCH_ROWS - 1);
+        for (i, w) in self.watches.iter().enumerate().skip(first).take(WATCH_ROWS) {
+            let y = PANEL_Y + (i - first + 1) as i32 * LINE;
+            let [lo, hi] = w.bytes(&mut |a| self.peek(cpu, a));
+            let bytes = if w.kind.size() > 1 { format!("{:02X} {:02X}", lo, hi) } else { format!("{:02X}", lo) };
+            let value = w.value(&mut |a| self.peek(cpu, a));
+            let text = format!("{:<25} {:04X}  {:<5} {:<12} {}", w.label(), w.addr, w.kind.name(), value, bytes);
+            d.draw_text(&text, PANEL_X, y, TEXT_SIZE, if i == self.watch_cursor { Color::YELLOW } else { Color::WHITE });
+        }
+        if self.watches.is_empty() {
+            d.draw_text("no watches, Shift+A in the memory view adds the cursor's address", PANEL_X, PANEL_Y + LINE, TEXT_SIZE, Color::GRAY);
+        }
+
+        let y = PANEL_Y + (WATCH_ROWS as i32 + 1) * LINE + 6;
+        let state = if self.watch_log.is_some() { "logging" } else { "not logging" };
+        let help = format!("{}, {} symbols - up/down select, T type, X remove, P log every frame to {}", state, self.symbols.len(), WATCH_LOG);
+        d.draw_text(&help, PANEL_X, y, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn draw_wave(&self, d: &mut RaylibDrawHandle, cpu: &CPU) {
+        let (position, volume) = cpu.memory.apu.wave_status();
+        let height = 16*WAVE_STEP;
+
+        d.draw_rectangle(PANEL_X, PANEL_Y, 32*WAVE_STEP, height, Color::new(20, 20, 20, 255));
+        for i in 0 .. 32 {
+            let v = Self::wave_sample(cpu, i) as i32;
+            let x = PANEL_X + i as i32 * WAVE_STEP;
+            let color = if i == self.wave_cursor {
+                Color::YELLOW
+            } else if i == position as usize {
+                Color::GREEN
+            } else { Color::SKYBLUE };
+            d.draw_rectangle(x + 1, PANEL_Y + (15 - v)*WAVE_STEP, WAVE_STEP - 2, (v + 1)*WAVE_STEP, color);
+        }
+
+        let mut y = PANEL_Y + height + 6;
+        let ram = cpu.memory.apu.wave_ram();
+        let hex = ram.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+        d.draw_text(&format!("FF30: {}", hex), PANEL_X, y, TEXT_SIZE, Color::WHITE);
+        y += LINE;
+
+        let level = ["mute", "100%", "50%", "25%"][volume as usize&0x3];
+        let status = format!("sample {:02} = {:X}   playing {:02} (green)   output {}",
+            self.wave_cursor, Self::wave_sample(cpu, self.wave_cursor), position, level);
+        d.draw_text(&status, PANEL_X, y, TEXT_SIZE, Color::WHITE);
+        y += LINE;
+
+        let help = if self.paused {
+            "mouse draws, left/right pick a sample, up/down or a hex digit set it"
+        } else {
+            "mouse draws, left/right pick a sample, up/down change it (hex digits when paused)"
+        };
+        d.draw_text(help, PANEL_X, y + 6, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn draw_io_log(&self, d: &mut RaylibDrawHandle, cpu: &CPU) {
+        let log = &cpu.memory.io_log;
+
+        d.draw_text("frame    LY   PC     addr  val", PANEL_X, PANEL_Y, TEXT_SIZE, Color::GRAY);
+        let skip = log.len().saturating_sub(IO_LOG_LINES);
+        for (i, e) in log.iter().skip(skip).enumerate() {
+            let text = format!("{:<8} {:<4} {:04X}   {:04X}  {:02X}", e.frame, e.ly, e.pc, e.addr, e.val);
+            d.draw_text(&text, PANEL_X, PANEL_Y + (i as i32 + 1) * LINE, TEXT_SIZE, Color::WHITE);
+        }
+
+        let state = if log.enabled { "logging" } else { "stopped" };
+        let help = format!("{}, {} entries - P start/stop, X clear, O dump to {}", state, log.len(), IO_LOG_DUMP);
+        d.draw_text(&help, PANEL_X, PANEL_Y + (IO_LOG_LINES as i32 + 1) * LINE + 6, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn draw_interrupts(&self, d: &mut RaylibDrawHandle, cpu: &CPU) {
+        let latency = &cpu.memory.irq_latency;
+        let mut y = PANEL_Y;
+
+        for (i, name) in IRQ_NAMES.iter().enumerate() {
+            let s = latency.stats(i);
+            let text = if s.count == 0 {
+                format!("{:<7} none serviced", name)
+            } else {
+                format!("{:<7} {:<7} last {:<5} min {:<5} avg {:<7.1} max {}", name, s.count, s.last, s.min, s.average(), s.max)
+            };
+            d.draw_text(&text, PANEL_X, y, TEXT_SIZE, Color::WHITE);
+            y += LINE;
+
+            // bars scaled to the worst latency on screen, newest on the right
+            let history = latency.history(i);
+            let top = history.iter().copied().max().unwrap_or(1).max(1);
+            d.draw_rectangle(PANEL_X, y, 512, IRQ_GRAPH_HEIGHT, Color::new(30, 30, 30, 255));
+            let start = 512 - history.len() as i32 * IRQ_BAR_WIDTH;
+            for (n, l) in history.iter().enumerate() {
+                let h = ((*l as u64 * IRQ_GRAPH_HEIGHT as u64) / top as u64).max(1) as i32;
+                let x = PANEL_X + start + n as i32 * IRQ_BAR_WIDTH;
+                d.draw_rectangle(x, y + IRQ_GRAPH_HEIGHT - h, IRQ_BAR_WIDTH, h, Color::SKYBLUE);
+            }
+            d.draw_text(&format!("{:>6}", top), PANEL_X + 470, y - LINE, TEXT_SIZE, Color::GRAY);
+            y += IRQ_GRAPH_HEIGHT + 8;
+        }
+
+        let state = if latency.enabled { "measuring" } else { "stopped" };
+        let help = format!("{}, cycles from IF set to the handler - P start/stop, X clear", state);
+        d.draw_text(&help, PANEL_X, y, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn io_register_at(row: usize) -> &'static IoRegister {
+        IO_REGISTERS.get(row).unwrap_or(&IE_REGISTER)
+    }
+
+    fn draw_io_regs(&self, d: &mut RaylibDrawHandle, cpu: &mut CPU) {
+        for row in 0 .. IO_REGISTERS.len() + 1 {
+            let reg = Self::io_register_at(row);
+            let x = PANEL_X + (row / IO_REG_ROWS) as i32 * 260;
+            let y = PANEL_Y + (row % IO_REG_ROWS) as i32 * LINE;
+
+            let mapped = !reg.cgb || cpu.memory.mode == MODE::CGB;
+            let touched = (0 .. reg.len).any(|i| cpu.memory.io_touched[(reg.addr + i) as usize&0xFF]);
+            let value = if reg.len > 1 {
+                "--".to_string()
+            } else if mapped {
+                format!("{:02X}", cpu.memory.read(reg.addr))
+            } else { "FF".to_string() };
+            let text = format!("{}{:04X} {:<6} {}  {:02X} {}",
+                if row == self.io_cursor { '>' } else { ' ' },
+                reg.addr, reg.name, value, reg.read_mask, if touched { '*' } else { ' ' });
+            let color = if !mapped {
+                Color::DARKGRAY
+            } else {
+                match reg.support {
+                    Support::Full => Color::WHITE,
+                    Support::Partial => Color::ORANGE,
+                    Support::Missing => Color::RED
+                }
+            };
+            d.draw_text(&text, x, y, TEXT_SIZE, color);
+        }
+
+        let mut y = PANEL_Y + IO_REG_ROWS as i32 * LINE + 6;
+        let reg = Self::io_register_at(self.io_cursor);
+        let selected = format!("{} ({}{}): {}", reg.name, reg.support.name(), if reg.cgb { ", CGB only" } else { "" }, reg.write);
+        d.draw_text(&selected, PANEL_X, y, TEXT_SIZE, Color::WHITE);
+        y += LINE;
+        d.draw_text("addr name value, bits reading 1, * accessed by the game", PANEL_X, y, TEXT_SIZE, Color::GRAY);
+        y += LINE;
+        d.draw_text("white full, orange partial, red missing - arrows select, X clear accessed", PANEL_X, y, TEXT_SIZE, Color::GRAY);
+        y += LINE;
+        d.draw_text("paused: Shift+P reset PPU, Shift+S reset APU, Shift+T reset timers", PANEL_X, y, TEXT_SIZE, Color::GRAY);
+    }
+
+    fn draw_cpu(&self, d: &mut RaylibDrawHandle, cpu: &mut CPU) {
+        let x = 4;
+        let mut y = 296;
+
+        let state = if self.paused { "PAUSED (F6 continue, F7 step)" } else { "RUNNING (F6 pause)" };
+        d.draw_text(state, x, y, TEXT_SIZE, if self.paused { Color::RED } else { Color::GREEN });
+        match self.stop {
+            Some(RunState::Breakpoint) => d.draw_text("breakpoint", x + 180, y, TEXT_SIZE, Color::RED),
+            Some(RunState::Watchpoint(addr)) => d.draw_text(&format!("{:04X} written", addr), x + 180, y, TEXT_SIZE, Color::ORANGE),
+            _ => ()
+        }
+        y += LINE + 4;
+
+        let flags = format!("{}{}{}{}",
+            if cpu.get_flag(Flag::Z) { 'Z' } else { '-' },
+            if cpu.get_flag(Flag::N) { 'N' } else { '-' },
+            if cpu.get_flag(Flag::H) { 'H' } else { '-' },
+            if cpu.get_flag(Flag::C) { 'C' } else { '-' }
+        );
+        let (af, bc, de, hl) = (*cpu.AF(), *cpu.BC(), *cpu.DE(), *cpu.HL());
+        let lines = [
+            format!("AF {:04X}   BC {:04X}   DE {:04X}   HL {:04X}", af, bc, de, hl),
+            format!("SP {:04X}   PC {:04X}   flags {}", cpu.SP, cpu.PC, flags),
+            format!("IME {}   halt {}   IE {:02X}   IF {:02X}", cpu.IME as u8, cpu.halt as u8, cpu.memory.IER, cpu.memory.IF),
+            format!("LCDC {:02X}   STAT {:02X}   LY {:02X}   OBJ by {}", cpu.memory.ppu.read(0xFF40), cpu.memory.ppu.read(0xFF41),
+                cpu.memory.ppu.read(0xFF44), if cpu.memory.ppu.x_priority() { "x" } else { "OAM" }),
+        ];
+        for line in lines.iter() {
+            d.draw_text(line, x, y, TEXT_SIZE, Color::WHITE);
+            y += LINE;
+        }
+
+        let mut bytes = String::from("PC ->");
+        for i in 0 .. 8 {
+            bytes.push_str(&format!(" {:02X}", cpu.memory.read(cpu.PC.wrapping_add(i))));
+        }
+        d.draw_text(&bytes, x, y, TEXT_SIZE, Color::WHITE);
+        y += LINE + 4;
+
+        d.draw_text("Breakpoints:", x, y, TEXT_SIZE, Color::GRAY);
+        y += LINE;
+        for chunk in cpu.breakpoints.chunks(6) {
+            let line: Vec<String> = chunk.iter().map(|b| format!("{:04X}", b)).collect();
+            d.draw_text(&line.join("  "), x, y, TEXT_SIZE, Color::WHITE);
+            y += LINE;
+        }
+    }
+}
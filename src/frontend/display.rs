@@ -0,0 +1,167 @@
+use raylib::prelude::*;
+
+const WH_RATIO: f32 = 160./144.;
+pub const GB_REFRESH: f32 = 59.7275;
+const MAX_SPEED_CHANGE: f32 = 0.005;  // further off than this and it's heard in the pitch and play time
+
+// Runs the game at the display's refresh rate instead of the Game Boy's, so every frame is shown for
+// exactly one refresh. Only for displays within half a percent, the audio is resampled to match
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VideoSync {
+    Off,
+    Auto,  // whatever the monitor reports, raylib only knows whole Hz
+    Rate(f32)
+}
+
+// What a new window is opened with, taken from the old one when the display is reopened
+pub struct WindowSettings {
+    pub width: i32,
+    pub height: i32,
+    pub position: Option<Vector2>,  // None - wherever the system puts it
+    pub vsync: bool
+}
+
+impl WindowSettings {
+    pub fn new(vsync: bool) -> WindowSettings {
+        WindowSettings {
+            width: 160*2,
+            height: 144*2,
+            position: None,
+            vsync: vsync
+        }
+    }
+}
+
+// The window, its gpu context and everything living in it. Holds no emulation state, so it can
+// be dropped and opened again (see Frontend::reopen_display) while the game keeps running
+pub struct Display {
+    txt: Texture2D,  // before the handle, textures have to go before the context they live in
+    peer_txt: Texture2D,  // the linked instance's frame, right of ours while split
+    pub handle: RaylibHandle,
+    pub thread: RaylibThread,
+    vsync: bool,
+    split: bool,
+
+    frame_dest_rect: Rectangle,
+    frame_src_rect: Rectangle,
+    position: Vector2,
+}
+
+impl Display {
+    pub fn open(settings: &WindowSettings) -> Display {
+        set_trace_log(raylib::consts::TraceLogType::LOG_NONE);
+        let mut builder = raylib::init();
+        builder.size(settings.width, settings.height)
+            .title("Gameboy emulator")
+            .resizable();
+        if settings.vsync {
+            builder.vsync();
+        }
+        let (mut handle, thread) = builder.build();
+        handle.set_target_fps(60);
+        if let Some(p) = settings.position {
+            handle.set_window_position(p.x as i32, p.y as i32);
+        }
+
+        let mut img = Image::gen_image_color(160, 144, Color::BLACK);
+        img.set_format(raylib::ffi::PixelFormat::UNCOMPRESSED_R8G8B8);
+        let txt = handle.load_texture_from_image(&thread, &img).expect("Couldnt load texture from image");
+        let peer_txt = handle.load_texture_from_image(&thread, &img).expect("Couldnt load texture from image");
+
+        let mut display = Display {
+            txt: txt,
+            peer_txt: peer_txt,
+            handle: handle,
+            thread: thread,
+            vsync: settings.vsync,
+            split: false,
+
+            frame_dest_rect: Rectangle::new(0., 0., 160.*2., 144.*2.),
+            frame_src_rect: Rectangle::new(0., 0., 160., 144.),
+            position: Vector2::new(0., 0.),
+        };
+        display.fit_frame();
+        display
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.vsync
+    }
+
+    // emulation speed for the sync mode, 1.0 - as is. Err with the rate when the display is too far off
+    pub fn sync_speed(&self, sync: VideoSync) -> Result<f32, f32> {
+        let hz = match sync {
+            VideoSync::Off => return Ok(1.),
+            VideoSync::Auto => unsafe { raylib::ffi::GetMonitorRefreshRate(raylib::ffi::GetCurrentMonitor()) as f32 },
+            VideoSync::Rate(hz) => hz
+        };
+        let speed = hz / GB_REFRESH;
+        if (speed - 1.).abs() > MAX_SPEED_CHANGE {
+            return Err(hz)
+        }
+        Ok(speed)
+    }
+
+    pub fn settings(&self) -> WindowSettings {
+        WindowSettings {
+            width: self.handle.get_screen_width(),
+            height: self.handle.get_screen_height(),
+            position: Some(self.handle.get_window_position()),
+            vsync: self.vsync
+        }
+    }
+
+    // room for a second frame next to ours, the window is left as it is
+    pub fn set_split(&mut self, split: bool) {
+        self.split = split;
+        self.fit_frame();
+    }
+
+    // how big the frame is drawn, 2x while the debugger is open
+    pub fn frame_size(&self, debug_visible: bool) -> (u32, u32) {
+        if debug_visible {
+            (160*2, 144*2)
+        } else {
+            (self.frame_dest_rect.width as u32, self.frame_dest_rect.height as u32)
+        }
+    }
+
+    // scales the frame to the window height, centered. Both frames together while split
+    fn fit_frame(&mut self) {
+        let h = self.handle.get_screen_height() as f32;
+        let w = WH_RATIO * h;
+        let frames = if self.split { 2. } else { 1. };
+        let x = (w * frames - self.handle.get_screen_width() as f32)/2.;
+
+        self.frame_dest_rect = Rectangle::new(0., 0., w, h);
+        self.position = Vector2::new(x, 0.);
+    }
+
+    // uploads the emulated frame and starts drawing with it, at 2x in the corner while the debugger is open.
+    // The linked instance's frame goes to the right of it while split, not under the debugger.
+    // shake moves it by that many pixels. Returns where the frame went for anything drawn over it
+    pub fn draw_frame(&mut self, frame: &[u8], peer: Option<&[u8]>, debug_visible: bool, shake: Vector2) -> (RaylibDrawHandle<'_>, Rectangle) {
+        if self.handle.is_window_resized() && !debug_visible {
+            self.fit_frame();
+        }
+
+        let (dest, position) = if debug_visible {
+            (Rectangle::new(0., 0., 160.*2., 144.*2.), Vector2::new(0., 0.))
+        } else {
+            (self.frame_dest_rect, self.position - shake)
+        };
+
+        self.txt.update_texture(frame);
+        let peer = peer.filter(|_| self.split && !debug_visible);
+        if let Some(p) = peer {
+            self.peer_txt.update_texture(p);
+        }
+        let mut d = self.handle.begin_drawing(&self.thread);
+        d.clear_background(Color::BLACK);
+        d.draw_texture_pro(&self.txt, self.frame_src_rect, dest, position, 0., Color::WHITE);
+        if peer.is_some() {
+            d.draw_texture_pro(&self.peer_txt, self.frame_src_rect, dest, position - Vector2::new(dest.width, 0.), 0., Color::WHITE);
+        }
+        (d, dest)
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Instant;
+
+const FRAME_MS: f32 = 16.74;
+pub const MAX_SKIP: usize = 4;
+const BEHIND: f32 = 1.15;  // presents this much later than planned mean the host isn't keeping up
+const SMOOTHING: f32 = 0.05;
+const SETTLE_PRESENTS: u32 = 60;  // ignored after a change, pacing takes a moment to follow
+const PROBE_PRESENTS: u32 = 600;  // before trying one step lower, doubled every time that fails
+const MAX_PROBE_PRESENTS: u32 = 9600;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameSkipMode {
+    Off,
+    Auto,
+    Fixed(usize)  // always present 1 of n frames
+}
+
+// Emulated frames per presented one. Every frame is still emulated and its audio played,
+// only drawing is skipped. Presents are paced to the real frame rate divided by the skip.
+// Time between presents includes the pacing wait, so spare time can't be measured directly:
+// auto steps up when presents fall behind and probes one step down every so often
+pub struct FrameSkip {
+    mode: FrameSkipMode,
+    frames: usize,
+    last: Option<Instant>,
+    average: f32,  // ms between presents
+    settle: u32,
+    probe: u32,
+    probe_period: u32,
+    probing: bool  // the last change was a step down that hasn't held for a whole probe period yet
+}
+
+impl FrameSkip {
+    pub fn new(mode: FrameSkipMode) -> FrameSkip {
+        FrameSkip {
+            mode: mode,
+            frames: match mode {
+                FrameSkipMode::Fixed(n) => n,
+                _ => 1
+            },
+            last: None,
+            average: 0.,
+            settle: SETTLE_PRESENTS,
+            probe: 0,
+            probe_period: PROBE_PRESENTS,
+            probing: false
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    // call after every present, returns the new skip when auto changes it
+    pub fn presented(&mut self) -> Option<usize> {
+        let now = Instant::now();
+        let elapsed = (now - self.last.replace(now)?).as_secs_f32() * 1000.;
+        if self.mode != FrameSkipMode::Auto {
+            return None
+        }
+        if self.settle > 0 {
+            self.settle -= 1;
+            self.average = elapsed;
+            return None
+        }
+
+        self.average += (elapsed - self.average) * SMOOTHING;
+        if self.average > self.frames as f32 * FRAME_MS * BEHIND && self.frames < MAX_SKIP {
+            if self.probing {
+                self.probe_period = (self.probe_period * 2).min(MAX_PROBE_PRESENTS);
+            }
+            self.probing = false;
+            return self.set(self.frames + 1)
+        }
+
+        self.probe += 1;
+        if self.probe >= self.probe_period {
+            if self.probing {  // the last step down held
+                self.probing = false;
+                self.probe_period = PROBE_PRESENTS;
+            }
+            self.probe = 0;
+            if self.frames > 1 {
+                self.probing = true;
+                return self.set(self.frames - 1)
+            }
+        }
+        None
+    }
+
+    fn set(&mut self, frames: usize) -> Option<usize> {
+        self.frames = frames;
+        self.settle = SETTLE_PRESENTS;
+        self.probe = 0;
+        Some(frames)
+    }
+
+    // after pauses, fast-forward or anything else that stretches a frame on purpose
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.settle = SETTLE_PRESENTS;
+    }
+}
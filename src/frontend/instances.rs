@@ -0,0 +1,114 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const PREFIX: &str = "@link ";
+const MESSAGE_BACKLOG: usize = 64;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SyncMessage {
+    Pause(bool),
+    FastForward(bool),
+    Quit
+}
+
+impl SyncMessage {
+    fn encode(&self) -> String {
+        match self {
+            SyncMessage::Pause(v) => format!("{}pause {}", PREFIX, *v as u8),
+            SyncMessage::FastForward(v) => format!("{}ff {}", PREFIX, *v as u8),
+            SyncMessage::Quit => format!("{}quit", PREFIX)
+        }
+    }
+
+    fn decode(line: &str) -> Option<SyncMessage> {
+        let mut parts = line.strip_prefix(PREFIX)?.split_whitespace();
+        match (parts.next()?, parts.next()) {
+            ("pause", Some(v)) => Some(SyncMessage::Pause(v == "1")),
+            ("ff", Some(v)) => Some(SyncMessage::FastForward(v == "1")),
+            ("quit", None) => Some(SyncMessage::Quit),
+            _ => None
+        }
+    }
+}
+
+// --link-local: the first instance spawns a second one and both keep pause/fast-forward in sync.
+// Messages go over the child's stdin/stdout, anything else the child prints is passed through.
+pub struct LinkedInstance {
+    child: Option<Child>,  // only set in the instance that did the spawning
+    output: Box<dyn Write>,
+    messages: Receiver<SyncMessage>
+}
+
+impl LinkedInstance {
+    pub fn spawn(rom: &Path) -> io::Result<LinkedInstance> {
+        let mut child = Command::new(env::current_exe()?)
+            .arg(rom)
+            .arg("--link-child")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let output = Box::new(child.stdin.take().unwrap());
+        let messages = LinkedInstance::read_messages(child.stdout.take().unwrap());
+
+        Ok(LinkedInstance {
+            child: Some(child),
+            output: output,
+            messages: messages
+        })
+    }
+
+    pub fn child() -> LinkedInstance {
+        LinkedInstance {
+            child: None,
+            output: Box::new(io::stdout()),
+            messages: LinkedInstance::read_messages(io::stdin())
+        }
+    }
+
+    pub fn is_child(&self) -> bool {
+        self.child.is_none()
+    }
+
+    fn read_messages<R: Read + Send + 'static>(input: R) -> Receiver<SyncMessage> {
+        // the reader blocks when it's this far ahead, the pipe backs up behind it
+        let (tx, rx) = mpsc::sync_channel(MESSAGE_BACKLOG);
+
+        thread::spawn(move || {
+            for line in BufReader::new(input).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break
+                };
+                match SyncMessage::decode(&line) {
+                    Some(msg) => if tx.send(msg).is_err() { return },
+                    None => println!("{}", line)
+                }
+            }
+            let _ = tx.send(SyncMessage::Quit);  // other side is gone
+        });
+        rx
+    }
+
+    pub fn send(&mut self, msg: SyncMessage) {
+        // a closed pipe means the other instance already quit, which poll reports
+        let _ = writeln!(self.output, "{}", msg.encode()).and_then(|_| self.output.flush());
+    }
+
+    pub fn poll(&self) -> Option<SyncMessage> {
+        self.messages.try_recv().ok()
+    }
+}
+
+impl Drop for LinkedInstance {
+    fn drop(&mut self) {
+        self.send(SyncMessage::Quit);
+        if let Some(child) = &mut self.child {
+            let _ = child.wait();
+        }
+    }
+}
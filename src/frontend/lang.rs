@@ -0,0 +1,124 @@
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// OSD, gallery and stats text by key. A language file replaces any of these with `key = text`
+// lines, `{}` stands for the numbers and names in the order the English has them, `#` starts a
+// comment. raylib's default font only has Latin-1, other scripts need a spelling that fits in it
+const ENGLISH: &[(&str, &str)] = &[
+    ("video_sync_off", "{} Hz display is too far from {} Hz, video sync is off"),
+    ("printer_done", "Printer finished a page"),
+    ("av_test_on", "A/V test on, flash and click every second"),
+    ("av_test_off", "A/V test off"),
+    ("latency_on", "Latency test on, press a button"),
+    ("latency_off", "Latency test off"),
+    ("latency_timeout", "Latency: no change in {} frames"),
+    ("latency_read", "read in {}"),
+    ("latency_not_read", "not read"),
+    ("latency_result", "Latency: {}, shown in {} frames, {} ms (avg {} frames, {} ms of {})"),
+    ("rewind_empty", "Nothing to rewind"),
+    ("frame_skip_on", "Host too slow, drawing 1 of {} frames"),
+    ("frame_skip_off", "Drawing every frame again"),
+    ("state_slot", "State slot {}"),
+    ("state_saved", "State {} saved"),
+    ("state_branched", "State {} saved on a new branch"),
+    ("state_save_failed", "Couldn't save state"),
+    ("state_empty", "No save state in slot {}"),
+    ("state_loaded", "State {} loaded"),
+    ("screenshot_saved", "Screenshot saved"),
+    ("screenshot_failed", "Couldn't save screenshot"),
+    ("audio_recording", "Recording audio, Shift+F11 stops"),
+    ("audio_recording_saved", "Audio recording saved, {} seconds"),
+    ("audio_recording_failed", "Couldn't record audio"),
+    ("video_recording", "Recording video, Ctrl+F11 stops"),
+    ("video_saved", "Video saved, {} seconds"),
+    ("video_failed", "Couldn't record video"),
+    ("palette", "Palette {}"),
+    ("macro_recording", "Recording macro {}"),
+    ("macro_recorded", "Macro {} recorded, {} frames"),
+    ("macro_playing", "Playing macro {}"),
+    ("channel_muted", "Sound channel {} muted"),
+    ("channel_unmuted", "Sound channel {} on"),
+    ("channel_solo", "Sound channel {} alone"),
+    ("channels_all", "All sound channels on"),
+    ("net_link_lost", "The other player disconnected"),
+    ("state_net_link", "Save states can't be loaded while linked over the network"),
+    ("net_resynced", "Link out of sync, went back {} seconds"),
+    ("net_desynced", "Link out of sync"),
+    ("config_reloaded", "Config reloaded"),
+    ("vsync_on", "Display reopened, vsync on"),
+    ("vsync_off", "Display reopened, vsync off"),
+    ("power_pulled", "Power pulled, {} bytes of cart ram lost"),
+    ("restarted", "Restarted as {}"),
+    ("restart_failed", "Can't restart as {}: {}"),
+    ("gallery_title", "{} - {} captures"),
+    ("gallery_empty", "nothing yet, F5 saves a state, F11 a screenshot, Shift+F11 records audio, Ctrl+F11 video"),
+    ("stats_emulation", "emulation {}ms avg {}ms max"),
+    ("stats_present", "present {}ms avg {}ms max"),
+    ("stats_audio", "audio buffer {}% avg"),
+    ("stats_rewind", "rewind {}s in {}kB")
+];
+
+pub struct Lang {
+    texts: Vec<String>  // in the order of ENGLISH
+}
+
+fn placeholders(s: &str) -> usize {
+    s.matches("{}").count()
+}
+
+impl Lang {
+    pub fn english() -> Lang {
+        Lang {
+            texts: ENGLISH.iter().map(|(_, t)| t.to_string()).collect()
+        }
+    }
+
+    // English for whatever the file leaves out or gets wrong
+    pub fn load(p: &Path) -> io::Result<Lang> {
+        let data = fs::read_to_string(p)?;
+        let mut lang = Lang::english();
+
+        for (n, line) in data.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, text) = match line.find('=') {
+                Some(i) => (line[.. i].trim(), line[i+1 ..].trim()),
+                None => {
+                    eprintln!("{}:{}: expected `key = text`", p.display(), n + 1);
+                    continue;
+                }
+            };
+            match ENGLISH.iter().position(|(k, _)| *k == key) {
+                Some(i) if placeholders(text) == placeholders(ENGLISH[i].1) => lang.texts[i] = text.to_string(),
+                Some(i) => eprintln!("{}:{}: `{}` needs {} {{}}", p.display(), n + 1, key, placeholders(ENGLISH[i].1)),
+                None => eprintln!("{}:{}: unknown text `{}`", p.display(), n + 1, key)
+            }
+        }
+        Ok(lang)
+    }
+
+    // the text for key with each {} filled from args in turn
+    pub fn text(&self, key: &str, args: &[&dyn Display]) -> String {
+        let template = match ENGLISH.iter().position(|(k, _)| *k == key) {
+            Some(i) => &self.texts[i],
+            None => return key.to_string()
+        };
+
+        let mut out = String::new();
+        let mut args = args.iter();
+        for (i, part) in template.split("{}").enumerate() {
+            if i > 0 {
+                if let Some(a) = args.next() {
+                    out += &a.to_string();
+                }
+            }
+            out += part;
+        }
+        out
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::Instant;
+
+use crate::emulator::StateHasher;
+use crate::frontend::Lang;
+
+const TIMEOUT_FRAMES: u32 = 120;  // emulated frames without a change before a press counts as not shown
+
+struct Measurement {
+    start: Instant,
+    pressed: u8,  // the new buttons, directions in the high nibble, active high
+    frames: u32,  // emulated since the press
+    read: Option<u32>,  // frame the game first saw the press in FF00
+    shown: Option<u32>,  // frame the picture first changed
+    picture: u64
+}
+
+// Shift+F10 input latency test: a newly pressed button starts a measurement of the emulated frames
+// until the game reads it and until the picture changes, and the host time until that picture is
+// presented. Meant for test roms that hold still until a button is pressed, anything that animates
+// on its own changes the picture right away. The presented time is what vsync, video_sync and
+// frame_skip change, the averages make configurations comparable
+pub struct LatencyTest {
+    pub enabled: bool,
+    measurement: Option<Measurement>,
+    last_input: u8,
+    samples: u32,
+    total_ms: f32,
+    total_frames: u32
+}
+
+fn hash_picture(frame: &[u8]) -> u64 {
+    let mut h = StateHasher::new();
+    h.write(frame);
+    h.finish()
+}
+
+impl LatencyTest {
+    pub fn new() -> LatencyTest {
+        LatencyTest {
+            enabled: false,
+            measurement: None,
+            last_input: 0xFF,
+            samples: 0,
+            total_ms: 0.,
+            total_frames: 0
+        }
+    }
+
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        self.measurement = None;
+        self.samples = 0;
+        self.total_ms = 0.;
+        self.total_frames = 0;
+        self.enabled
+    }
+
+    // before the frames run, with the joypad state from read_input and the picture on screen
+    pub fn input(&mut self, input: u8, frame: &[u8]) {
+        let pressed = self.last_input & !input;
+        self.last_input = input;
+        if !self.enabled || self.measurement.is_some() || pressed == 0 {
+            return
+        }
+        self.measurement = Some(Measurement {
+            start: Instant::now(),
+            pressed: pressed,
+            frames: 0,
+            read: None,
+            shown: None,
+            picture: hash_picture(frame)
+        });
+    }
+
+    // a JoypadRead event, FF00 bit 4 low selects the directions and bit 5 the buttons
+    pub fn joypad_read(&mut self, val: u8) {
+        let m = match &mut self.measurement {
+            Some(m) if m.read.is_none() => m,
+            _ => return
+        };
+        let low = !val & 0xF;
+        let directions = val&0x10 == 0 && low & (m.pressed >> 4) != 0;
+        let buttons = val&0x20 == 0 && low & (m.pressed & 0xF) != 0;
+        if directions || buttons {
+            m.read = Some(m.frames);
+        }
+    }
+
+    // after every emulated frame, before its events. A message when the press never showed up
+    pub fn frame(&mut self, frame: &[u8], lang: &Lang) -> Option<String> {
+        let m = self.measurement.as_mut()?;
+        m.frames += 1;
+        if m.shown.is_none() && hash_picture(frame) != m.picture {
+            m.shown = Some(m.frames);
+        }
+        if m.shown.is_none() && m.frames >= TIMEOUT_FRAMES {
+            self.measurement = None;
+            return Some(lang.text("latency_timeout", &[&TIMEOUT_FRAMES]))
+        }
+        None
+    }
+
+    // after the present, the result once the changed picture is on screen
+    pub fn presented(&mut self, lang: &Lang) -> Option<String> {
+        let shown = self.measurement.as_ref()?.shown?;
+        let m = self.measurement.take()?;
+        let ms = m.start.elapsed().as_secs_f32() * 1000.;
+        self.samples += 1;
+        self.total_ms += ms;
+        self.total_frames += shown;
+
+        let read = m.read.map_or(lang.text("latency_not_read", &[]), |f| lang.text("latency_read", &[&f]));
+        let avg_frames = format!("{:.1}", self.total_frames as f32 / self.samples as f32);
+        let avg_ms = format!("{:.1}", self.total_ms / self.samples as f32);
+        Some(lang.text("latency_result", &[&read, &shown, &format!("{:.1}", ms), &avg_frames, &avg_ms, &self.samples]))
+    }
+}
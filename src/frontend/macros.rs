@@ -0,0 +1,102 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SLOTS: usize = 4;
+const MAX_FRAMES: usize = 60*60;  // a minute of input per macro
+
+// Recorded joypad input, one byte per emulated frame: directions in the high nibble,
+// buttons in the low, active low like P1. Played back frame by frame in place of the keyboard.
+pub struct Macros {
+    slots: [Vec<u8>; SLOTS],
+    recording: Option<usize>,
+    playing: Option<(usize, usize)>  // slot, next frame
+}
+
+impl Macros {
+    pub fn new() -> Macros {
+        Macros {
+            slots: Default::default(),
+            recording: None,
+            playing: None
+        }
+    }
+
+    // starts recording into slot, or stops if it's the one being recorded. Returns whether it's recording now
+    pub fn toggle_record(&mut self, slot: usize) -> bool {
+        if self.recording == Some(slot) {
+            self.recording = None;
+            return false
+        }
+        self.playing = None;
+        self.recording = Some(slot);
+        self.slots[slot].clear();
+        true
+    }
+
+    // false when there's nothing recorded in slot
+    pub fn play(&mut self, slot: usize) -> bool {
+        if self.slots[slot].is_empty() || self.recording.is_some() {
+            return false
+        }
+        self.playing = Some((slot, 0));
+        true
+    }
+
+    pub fn len(&self, slot: usize) -> usize {
+        self.slots[slot].len()
+    }
+
+    // takes the keyboard state for the next frame, gives the one to feed the joypad
+    pub fn next(&mut self, input: u8) -> u8 {
+        if let Some(slot) = self.recording {
+            if self.slots[slot].len() < MAX_FRAMES {
+                self.slots[slot].push(input);
+            } else {
+                self.recording = None;
+            }
+        }
+
+        if let Some((slot, frame)) = self.playing {
+            if frame < self.slots[slot].len() {
+                self.playing = Some((slot, frame + 1));
+                return self.slots[slot][frame]
+            }
+            self.playing = None;
+        }
+        input
+    }
+
+    // a line per slot, frames as hex bytes
+    pub fn load(&mut self, p: &Path) {
+        let data = match fs::read_to_string(p) {
+            Ok(d) => d,
+            Err(_) => return
+        };
+
+        for (line, slot) in data.lines().zip(self.slots.iter_mut()) {
+            match line.split_whitespace().map(|b| u8::from_str_radix(b, 16).ok()).collect() {
+                Some(frames) => *slot = frames,
+                None => eprintln!("{}: invalid macro `{}`", p.display(), line)
+            }
+        }
+    }
+
+    pub fn save(&self, p: &Path) -> io::Result<()> {
+        if self.slots.iter().all(|s| s.is_empty()) {
+            return Ok(())
+        }
+
+        let mut data = String::new();
+        for slot in self.slots.iter() {
+            let frames: Vec<String> = slot.iter().map(|b| format!("{:02X}", b)).collect();
+            data += &frames.join(" ");
+            data.push('\n');
+        }
+
+        if let Some(dir) = p.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(p, data)
+    }
+}
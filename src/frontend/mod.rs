@@ -0,0 +1,990 @@
+use std::fs;
+use std::fmt;
+use std::time::Instant;
+use std::path::{Path, PathBuf};
+
+use raylib::prelude::*;
+
+use crate::emulator::{self, CPU, MODE, AudioConfig, LinkCable, RunState, Event, EventKind, Triggers, TriggerAction, StateHashLog};
+use crate::config;
+
+#[cfg_attr(not(feature = "debugger"), path = "nodebugger.rs")]
+mod debugger;
+mod audio;
+mod stats;
+mod instances;
+mod netlink;
+mod osd;
+mod webhook;
+mod macros;
+mod branches;
+mod display;
+mod frameskip;
+mod rewind;
+mod ring;
+mod avtest;
+mod latency;
+mod captures;
+mod video;
+mod lang;
+#[cfg(feature = "debugger")]
+mod palette;
+
+use debugger::DebugUI;
+use audio::Audio;
+use ring::Overflow;
+use osd::Osd;
+use macros::Macros;
+use display::{Display, WindowSettings, GB_REFRESH};
+use stats::{FrameStats, FrameTiming};
+use frameskip::FrameSkip;
+use rewind::Rewind;
+use branches::Branches;
+use avtest::AvTest;
+use latency::LatencyTest;
+use captures::Gallery;
+use video::VideoCapture;
+pub use lang::Lang;
+pub use frameskip::{FrameSkipMode, MAX_SKIP};
+pub use display::VideoSync;
+pub use audio::SyncMode;
+pub use video::VideoFormat;
+pub use captures::{write_png, read_png};
+pub use rewind::DEFAULT_SECONDS as DEFAULT_REWIND_SECONDS;
+pub use instances::{LinkedInstance, SyncMessage};
+pub use netlink::NetLink;
+
+const SESSION_DIR: &str = "sessions";
+const FAST_FORWARD_FRAMES: usize = 4;
+const FRAME_RATE: u64 = 60;  // play time is counted in emulated frames, rounded from 59.73
+const FRAME_M_CYCLES: f32 = 17556.;
+const AUDIO_SYNC_HEADROOM: u64 = 20;  // SyncMode::Audio caps presents 1/20 above the real rate
+const RUMBLE_SHAKE: f32 = 3.;  // pixels at full strength
+const TILT_STEP: f32 = 0.08;  // per frame while a tilt key is held, a full tilt takes about 12 frames
+const STICK_DEADZONE: f32 = 0.15;
+
+pub struct Frontend {
+    display: Display,
+    audio: Audio,
+    debug: DebugUI,
+    stats: FrameStats,
+    osd: Osd,
+    lang: Lang,  // everything the osd, gallery and stats say
+    triggers: Option<Triggers>,
+    hash_log: Option<StateHashLog>,
+    macros: Macros,  // Shift+1-4 records, 1-4 plays back
+    rewind: Rewind,  // Backspace held steps back through it
+    av_test: AvTest,  // F10
+    latency: LatencyTest,  // Shift+F10
+    gallery: Gallery,  // F12
+    captures: PathBuf,  // the running game's save states and screenshots
+    video: Option<VideoCapture>,  // Ctrl+F11
+    video_format: VideoFormat,
+    ffmpeg: PathBuf,  // the program VideoFormat::Ffmpeg runs
+    state_slot: usize,  // Ctrl+F5 cycles
+    branches: Branches,  // where each slot was saved from, the gallery shows the tree
+
+    fast_forward: bool,  // Tab held, runs several frames per presented one, audio only as it fits
+    frame_skip: FrameSkip,
+    video_sync: VideoSync,
+    sync: SyncMode,
+    speed: f32,  // emulated frames run this much faster than the Game Boy's to match the display
+    link: Option<LinkedInstance>,
+    peer: Option<CPU>,  // --link, the second player's game on the other end of the link cable
+    net: Option<NetLink>,  // --host or --join, the link cable goes to another machine
+    synced: (bool, bool),  // pause and fast-forward state last sent to or received from the link
+    quit: bool,
+    restart: Option<bool>,  // F8 reboots as the other model, Shift+F8 pulls the power (true) and boots the same one
+    reopen: Option<bool>,  // F9, close the window and open a new one with this vsync, Shift+F9 toggles it
+    reload: bool,  // Shift+F2 reads the config files again, the same as saving them does
+    bess_states: bool,  // F5 writes states other emulators can load instead of compressed ones
+    screenshot_window: bool,  // F11 saves the frame at the window's size instead of 160x144
+    rumble: f32,  // 0.0 - 1.0, how much of the last frames the cartridge's motor was on
+    rumble_shake: bool,  // raylib can't drive gamepad motors, the screen shakes instead
+    tilt: (f32, f32),  // arrow keys or the left stick, for accelerometer carts
+
+    play_frames: u64,  // emulated frames run in this game, over all sessions
+    palette_combo_frames: u64,  // left to hold a direction and A/B to pick a dmg palette, like on a CGB
+    title: String,
+    title_play_time: bool
+}
+
+impl Frontend {
+    pub fn new(vsync: bool, audio: AudioConfig) -> Frontend {
+        let mut display = Display::open(&WindowSettings::new(vsync));
+        let debug = DebugUI::new(&mut display.handle, &display.thread);
+        let audio = Audio::new(&display.thread, audio);
+
+        Frontend {
+            display: display,
+            audio: audio,
+            debug: debug,
+            stats: FrameStats::new(),
+            osd: Osd::new(),
+            lang: Lang::english(),
+            triggers: None,
+            hash_log: None,
+            macros: Macros::new(),
+            rewind: Rewind::new(DEFAULT_REWIND_SECONDS),
+            av_test: AvTest::new(),
+            latency: LatencyTest::new(),
+            gallery: Gallery::new(),
+            captures: PathBuf::new(),
+            video: None,
+            video_format: VideoFormat::Apng,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            state_slot: 0,
+            branches: Branches::new(),
+
+            fast_forward: false,
+            frame_skip: FrameSkip::new(FrameSkipMode::Auto),
+            video_sync: VideoSync::Off,
+            sync: SyncMode::Audio,
+            speed: 1.,
+            link: None,
+            peer: None,
+            net: None,
+            synced: (false, false),
+            quit: false,
+            restart: None,
+            reopen: None,
+            reload: false,
+            bess_states: false,
+            screenshot_window: false,
+            rumble: 0.,
+            rumble_shake: true,
+            tilt: (0., 0.),
+
+            play_frames: 0,
+            palette_combo_frames: FRAME_RATE,
+            title: String::new(),
+            title_play_time: false
+        }
+    }
+
+    pub fn should_close(&self) -> bool {
+        self.quit || self.display.handle.window_should_close()
+    }
+
+    pub fn take_restart(&mut self) -> Option<bool> {
+        self.restart.take()
+    }
+
+    pub fn take_reopen(&mut self) -> Option<bool> {
+        self.reopen.take()
+    }
+
+    pub fn take_reload(&mut self) -> bool {
+        std::mem::replace(&mut self.reload, false)
+    }
+
+    // Tears the window down and opens it again with the same size, place and title, for a changed
+    // monitor or vsync setting or a broken gpu context. The audio device and everything else
+    // outside the display carry on, the old window has to be gone before the new one is opened
+    pub fn reopen_display(mut self, vsync: bool) -> Frontend {
+        let mut settings = self.display.settings();
+        settings.vsync = vsync;
+
+        self.debug.unload_textures();
+        self.frame_skip.reset();
+        drop(self.display);
+        let mut display = Display::open(&settings);
+        self.debug.load_textures(&mut display.handle, &display.thread);
+
+        let mut f = Frontend { display: display, ..self };
+        f.display.set_split(f.peer.is_some());
+        f.update_title();
+        f.set_video_sync(f.video_sync);  // the window may be on another display now
+        f
+    }
+
+    // as is, for text that doesn't come from the language file
+    pub fn notify(&mut self, msg: &str) {
+        self.osd.push(msg);
+    }
+
+    // a text from the language file, see lang.rs for the keys
+    pub fn say(&mut self, key: &str, args: &[&dyn fmt::Display]) {
+        let msg = self.lang.text(key, args);
+        self.osd.push(&msg);
+    }
+
+    pub fn set_lang(&mut self, lang: Lang) {
+        self.lang = lang;
+    }
+
+    pub fn set_title(&mut self, title: &str, play_time: bool) {
+        self.title = title.to_string();
+        self.title_play_time = play_time;
+        self.update_title();
+    }
+
+    fn update_title(&mut self) {
+        if self.title_play_time {
+            let t = format!("{} - {}", self.title, config::format_time(self.play_time()));
+            self.display.handle.set_window_title(&self.display.thread, &t);
+        } else {
+            self.display.handle.set_window_title(&self.display.thread, &self.title);
+        }
+    }
+
+    pub fn set_play_time(&mut self, seconds: u64) {
+        self.play_frames = seconds * FRAME_RATE;
+    }
+
+    // seconds of emulated frames, fast-forward counts at emulated speed
+    pub fn play_time(&self) -> u64 {
+        self.play_frames / FRAME_RATE
+    }
+
+    pub fn set_hash_log(&mut self, log: StateHashLog) {
+        self.hash_log = Some(log);
+    }
+
+    // labels for the debugger's watches, from the rom's .sym file
+    #[cfg(feature = "debugger")]
+    pub fn set_symbols(&mut self, symbols: emulator::Symbols) {
+        self.debug.set_symbols(symbols);
+    }
+
+    pub fn set_frame_skip(&mut self, mode: FrameSkipMode) {
+        self.frame_skip = FrameSkip::new(mode);
+    }
+
+    pub fn set_video_sync(&mut self, sync: VideoSync) {
+        self.video_sync = sync;
+        self.speed = match self.display.sync_speed(sync) {
+            Ok(speed) => speed,
+            Err(hz) => {
+                self.say("video_sync_off", &[&hz, &GB_REFRESH]);
+                1.
+            }
+        };
+        self.audio.set_speed(self.speed);
+    }
+
+    pub fn set_sync(&mut self, sync: SyncMode) {
+        self.sync = sync;
+        self.audio.set_rate_control(sync == SyncMode::Video);
+    }
+
+    // the APU makes samples in the new format from here on, the stream is reopened to play them
+    pub fn set_audio_config(&mut self, cpu: &mut CPU, config: AudioConfig) {
+        cpu.memory.apu.set_audio_config(config);
+        cpu.memory.apu.samples.clear();
+        self.audio.set_config(&self.display.thread, config);
+        self.audio.set_speed(self.speed);
+    }
+
+    pub fn set_bess_states(&mut self, bess: bool) {
+        self.bess_states = bess;
+    }
+
+    pub fn set_screenshot_window(&mut self, window: bool) {
+        self.screenshot_window = window;
+    }
+
+    // for the next Ctrl+F11, a running recording carries on as it started
+    pub fn set_video_capture(&mut self, format: VideoFormat, ffmpeg: &Path) {
+        self.video_format = format;
+        self.ffmpeg = ffmpeg.to_path_buf();
+    }
+
+    pub fn set_rumble_shake(&mut self, shake: bool) {
+        self.rumble_shake = shake;
+    }
+
+    // seconds of gameplay kept to rewind through, 0 turns it off
+    pub fn set_rewind(&mut self, seconds: usize) {
+        self.rewind = Rewind::new(seconds);
+    }
+
+    pub fn set_triggers(&mut self, triggers: Triggers) {
+        self.triggers = Some(triggers);
+    }
+
+    fn check_triggers(&mut self, cpu: &mut CPU) {
+        let triggers = match &mut self.triggers {
+            Some(t) => t,
+            None => return
+        };
+
+        for action in triggers.check(&mut cpu.memory) {
+            match action {
+                TriggerAction::Osd(msg) => self.osd.push(&msg),
+                TriggerAction::Print(msg) => println!("{}", msg),
+                TriggerAction::Webhook(url) => webhook::post(&url, &cpu.memory.cart.title)
+            }
+        }
+    }
+
+    // what the core queued while running a frame, see load_session for the subscriptions
+    fn handle_events(&mut self, cpu: &mut CPU) {
+        for event in cpu.memory.events.drain() {
+            match event {
+                Event::PrintJob => self.say("printer_done", &[]),
+                Event::JoypadRead(val) => self.latency.joypad_read(val),
+                _ => ()
+            }
+        }
+    }
+
+    pub fn set_link(&mut self, link: LinkedInstance) {
+        if link.is_child() {  // don't cover the first window
+            self.display.handle.set_window_position(160*2 + 60, 40);
+        }
+        self.link = Some(link);
+    }
+
+    // --link, another game next to this one in the window with a link cable between them. It runs in
+    // step with this one (see CPU::run_frame_linked), played with the gamepad and without sound
+    pub fn set_peer(&mut self, cpu: &mut CPU, peer: CPU) {
+        self.peer = Some(peer);
+        self.relink(cpu);
+        self.display.set_split(true);
+        self.resize_for_debug();
+    }
+
+    // --host or --join, the game keeps in step with the one on the other end
+    pub fn set_net_link(&mut self, cpu: &mut CPU, net: NetLink) {
+        self.net = Some(net);
+        self.relink(cpu);
+    }
+
+    // a new cable to the peer or the network, for after this one restarted with a new cpu
+    pub fn relink(&mut self, cpu: &mut CPU) {
+        if let Some(peer) = &mut self.peer {
+            let (a, b) = LinkCable::ends();
+            cpu.memory.set_serial_device(Box::new(a));
+            peer.memory.set_serial_device(Box::new(b));
+        }
+        if let Some(net) = &self.net {
+            cpu.memory.set_serial_device(Box::new(net.port()));
+        }
+    }
+
+    pub fn peer(&self) -> Option<&CPU> {
+        self.peer.as_ref()
+    }
+
+    #[cfg_attr(not(feature = "debugger"), allow(unused_variables))]
+    fn sync_link(&mut self, cpu: &mut CPU) {
+        let link = match &mut self.link {
+            Some(l) => l,
+            None => return
+        };
+
+        while let Some(msg) = link.poll() {
+            match msg {
+                SyncMessage::Pause(p) => {
+                    self.debug.paused = p;
+                    #[cfg(feature = "debugger")]
+                    {
+                        cpu.resuming = !p;
+                    }
+                    self.synced.0 = p;
+                },
+                SyncMessage::FastForward(f) => {
+                    self.fast_forward = f;
+                    self.synced.1 = f;
+                },
+                SyncMessage::Quit => self.quit = true
+            }
+        }
+
+        if self.debug.paused != self.synced.0 {
+            self.synced.0 = self.debug.paused;
+            link.send(SyncMessage::Pause(self.synced.0));
+        }
+        if self.fast_forward != self.synced.1 {
+            self.synced.1 = self.fast_forward;
+            link.send(SyncMessage::FastForward(self.synced.1));
+        }
+    }
+
+    // runs a single emulated frame (a few while fast-forwarding, none when stopped in the debugger) and presents it
+    pub fn frame(&mut self, cpu: &mut CPU) {
+        if self.debug.handle_keys(&mut self.display.handle, cpu) {
+            self.resize_for_debug();
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F2) {
+            let shift = self.display.handle.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+            if shift {
+                self.reload = true;
+            } else {
+                self.stats.visible = !self.stats.visible;
+            }
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F5) {
+            self.state_key(cpu);
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F8) {
+            let shift = self.display.handle.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+            self.restart = Some(shift);
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F9) {
+            let shift = self.display.handle.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+            self.reopen = Some(self.display.vsync() != shift);
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F10) {
+            let shift = self.display.handle.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+            if shift {
+                self.latency_key(cpu);
+            } else if self.av_test.toggle() {
+                self.say("av_test_on", &[]);
+            } else {
+                self.say("av_test_off", &[]);
+            }
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F11) {
+            let shift = self.display.handle.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+            let ctrl = self.display.handle.is_key_down(KeyboardKey::KEY_LEFT_CONTROL) || self.display.handle.is_key_down(KeyboardKey::KEY_RIGHT_CONTROL);
+            if ctrl {
+                self.video_key(cpu);
+            } else if shift {
+                self.audio_recording_key(cpu);
+            } else {
+                self.screenshot(cpu);
+            }
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_F12) {
+            self.gallery.toggle(&self.captures);
+        }
+        if self.gallery.visible {
+            if let Some(slot) = self.gallery.handle_keys(&self.display.handle) {
+                self.state_slot = slot;
+                self.load_state(cpu);
+            }
+        }
+        if self.display.handle.is_key_pressed(KeyboardKey::KEY_TAB) {
+            self.fast_forward = true;
+        } else if self.display.handle.is_key_released(KeyboardKey::KEY_TAB) {
+            self.fast_forward = false;
+        }
+        if !self.debug.visible {
+            self.macro_keys();
+            self.channel_keys(cpu);
+        }
+        self.sync_link(cpu);
+
+        let start = Instant::now();
+        let input = self.read_input();
+        let peer_input = self.read_peer_input();
+        self.latency.input(input, &cpu.memory.ppu.frame);
+        if self.palette_combo_frames > 0 && !self.debug.paused {
+            self.palette_combo_frames -= 1;
+            self.palette_combo(cpu, input);
+        }
+        // no rewinding while linked, the other instance would carry on without us
+        let rewinding = self.rewind.enabled() && self.link.is_none() && self.peer.is_none() && self.net.is_none() && !self.debug.paused
+            && self.display.handle.is_key_down(KeyboardKey::KEY_BACKSPACE);
+        if rewinding {
+            match self.rewind.step_back(cpu) {
+                Some(frames) => self.branches.rewound(frames),
+                None => if self.display.handle.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+                    self.say("rewind_empty", &[]);
+                }
+            }
+        }
+
+        if !self.debug.visible && !self.gallery.visible {
+            self.read_tilt(cpu);
+        }
+
+        // too far ahead of the other machine, wait for it
+        let net_stalled = match &mut self.net {
+            Some(net) => !net.may_run(cpu),
+            None => false
+        };
+        if let Some(frames) = self.net.as_mut().and_then(|n| n.take_resync()) {
+            self.branches.rewound(frames as usize);
+            self.say("net_resynced", &[&format!("{:.1}", frames as f32 / FRAME_RATE as f32)]);
+        }
+        if self.net.as_mut().map_or(false, |n| n.take_lost()) {
+            self.say("net_desynced", &[]);
+        }
+
+        let frames = if rewinding || net_stalled {
+            0
+        } else if self.fast_forward {
+            FAST_FORWARD_FRAMES
+        } else {
+            self.frame_skip.frames()
+        };
+        let mut ran = 0;
+        for _ in 0 .. frames {
+            if self.debug.paused {
+                break;
+            }
+            ran += 1;
+            let joypad = self.macros.next(input);
+            cpu.memory.set_joypad(joypad >> 4, joypad&0xF);
+
+            let state = match &mut self.peer {
+                Some(peer) => {
+                    peer.memory.set_joypad(peer_input >> 4, peer_input&0xF);
+                    cpu.run_frame_linked(peer).0
+                },
+                None => cpu.run_frame()
+            };
+            if state != RunState::FrameDone {
+                self.debug.stopped(state);
+            } else {
+                self.play_frames += 1;
+                if self.title_play_time && self.play_frames % FRAME_RATE == 0 {
+                    self.update_title();
+                }
+                if let Some(log) = &mut self.hash_log {
+                    if let Err(e) = log.record(cpu) {
+                        eprintln!("Couldn't write state hash log: {}", e);
+                        self.hash_log = None;
+                    }
+                }
+                self.debug.frame_done(cpu);
+                self.record_frame(cpu);
+                if let Some(msg) = self.latency.frame(&cpu.memory.ppu.frame, &self.lang) {
+                    self.osd.push(&msg);
+                }
+            }
+            self.handle_events(cpu);
+            self.check_triggers(cpu);
+            self.rewind.frame(cpu);
+            self.branches.frame();
+        }
+        if let Some(peer) = &mut self.peer {
+            peer.memory.apu.samples.clear();
+        }
+        if let Some(net) = &mut self.net {
+            net.ran(cpu);
+            if !net.connected() {
+                self.net = None;
+                self.say("net_link_lost", &[]);
+            }
+        }
+        self.stats.rewind = (self.rewind.seconds(), self.rewind.memory());
+        let rumble_cycles = cpu.memory.cart.take_rumble_cycles();
+        self.rumble = if ran > 0 { (rumble_cycles as f32 / (ran as f32 * FRAME_M_CYCLES)).min(1.) } else { 0. };
+        let emulated = Instant::now();
+
+        if ran > 0 && !self.fast_forward {
+            let rate = cpu.memory.apu.audio_config().sample_rate;
+            self.av_test.mark(&mut cpu.memory.ppu.frame, &mut cpu.memory.apu.samples, rate);
+        }
+        if rewinding {
+            cpu.memory.apu.samples.clear();
+        } else {
+            if let Some(video) = &mut self.video {
+                video.audio(&cpu.memory.apu.samples);
+            }
+            let overflow = if self.fast_forward || self.sync != SyncMode::Audio { Overflow::Drop } else { Overflow::Block };
+            self.audio.push(&mut cpu.memory.apu.samples, overflow);
+        }
+        // pace presents so skipped frames still take their real time, fast-forward and rewind run at the full rate
+        let rate = if self.speed == 1. { FRAME_RATE } else { (GB_REFRESH * self.speed).round() as u64 };
+        let fps = if self.fast_forward || rewinding || net_stalled { rate } else { rate / frames as u64 };
+        let fps = match self.sync {
+            // a little ahead so the stream sets the pace, the cap holds while nothing is played
+            SyncMode::Audio if ran > 0 => fps + fps / AUDIO_SYNC_HEADROOM,
+            SyncMode::Uncapped => 0,
+            _ => fps
+        };
+        self.display.handle.set_target_fps(fps as u32);
+        self.present(cpu);
+        if let Some(msg) = self.latency.presented(&self.lang) {
+            println!("{}", msg);
+            self.osd.push(&msg);
+        }
+
+        if ran == frames && !self.fast_forward && !rewinding && !net_stalled {
+            if let Some(n) = self.frame_skip.presented() {
+                if n > 1 {
+                    self.say("frame_skip_on", &[&n]);
+                } else {
+                    self.say("frame_skip_off", &[]);
+                }
+            }
+        } else {
+            self.frame_skip.reset();
+        }
+
+        self.stats.push(FrameTiming {
+            emulation: (emulated - start).as_secs_f32() * 1000.,
+            present: emulated.elapsed().as_secs_f32() * 1000.,
+            audio_fill: self.audio.fill()
+        });
+    }
+
+    fn session_path(cpu: &CPU, ext: &str) -> PathBuf {
+        PathBuf::from(SESSION_DIR).join(format!("{:08x}.{}", cpu.memory.cart.header_hash, ext))
+    }
+
+    // debugger state and input macros are kept per game, keyed by the cartridge header hash.
+    // Also where a new cpu gets the frontend's event subscriptions
+    pub fn load_session(&mut self, cpu: &mut CPU) {
+        cpu.memory.events.subscribe(EventKind::PrintJob);
+        if self.latency.enabled {
+            cpu.memory.events.subscribe(EventKind::JoypadRead);
+        }
+        self.captures = captures::game_dir(&cpu.memory.cart.title, cpu.memory.cart.header_hash);
+        self.branches = Branches::load(&self.captures);
+        self.gallery.refresh(&self.captures);
+        self.debug.load_session(cpu, &Frontend::session_path(cpu, "session"));
+        self.macros.load(&Frontend::session_path(cpu, "macros"));
+        self.rewind.clear();
+        self.resize_for_debug();
+    }
+
+    pub fn save_session(&self, cpu: &CPU) {
+        let p = Frontend::session_path(cpu, "session");
+        if let Err(e) = self.debug.save_session(cpu, &p) {
+            eprintln!("Couldn't save debugger session to {}: {}", p.display(), e);
+        }
+        let p = Frontend::session_path(cpu, "macros");
+        if let Err(e) = self.macros.save(&p) {
+            eprintln!("Couldn't save macros to {}: {}", p.display(), e);
+        }
+    }
+
+    // the game has to read FF00 for the test, the reads are only queued while it's on
+    fn latency_key(&mut self, cpu: &mut CPU) {
+        if self.latency.toggle() {
+            cpu.memory.events.subscribe(EventKind::JoypadRead);
+            self.say("latency_on", &[]);
+        } else {
+            cpu.memory.events.unsubscribe(EventKind::JoypadRead);
+            self.say("latency_off", &[]);
+        }
+    }
+
+    // F5 saves the whole machine to the current slot under captures/<game>/ with a screenshot
+    // next to it, Shift+F5 loads it back and Ctrl+F5 picks the next slot.
+    // Compressed unless bess_states is on, then the file can be swapped with other emulators
+    fn state_key(&mut self, cpu: &mut CPU) {
+        let hl = &self.display.handle;
+        let shift = hl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || hl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+        let ctrl = hl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL) || hl.is_key_down(KeyboardKey::KEY_RIGHT_CONTROL);
+
+        if ctrl {
+            self.state_slot = (self.state_slot + 1) % captures::SLOTS;
+            self.say("state_slot", &[&(self.state_slot + 1)]);
+        } else if shift {
+            self.load_state(cpu);
+        } else {
+            self.save_state(cpu);
+        }
+    }
+
+    fn save_state(&mut self, cpu: &mut CPU) {
+        let p = captures::state_path(&self.captures, self.state_slot);
+        let data = if self.bess_states { cpu.save_state_bess() } else { cpu.save_state_compressed() };
+        match fs::create_dir_all(&self.captures).and_then(|_| fs::write(&p, &data)) {
+            Ok(()) => if self.branches.saved(self.state_slot) {
+                self.say("state_branched", &[&(self.state_slot + 1)]);
+            } else {
+                self.say("state_saved", &[&(self.state_slot + 1)]);
+            },
+            Err(e) => {
+                eprintln!("Couldn't write save state {}: {}", p.display(), e);
+                self.say("state_save_failed", &[]);
+                return;
+            }
+        }
+        if let Err(e) = self.branches.save(&self.captures) {
+            eprintln!("Couldn't write the save state tree to {}: {}", self.captures.display(), e);
+        }
+
+        let shot = captures::state_shot_path(&self.captures, self.state_slot);
+        if let Err(e) = captures::write_png(&shot, &cpu.memory.ppu.frame) {
+            eprintln!("Couldn't write {}: {}", shot.display(), e);
+        }
+        self.gallery.refresh(&self.captures);
+    }
+
+    // not while linked over the network, the other side would carry on from where it was
+    fn load_state(&mut self, cpu: &mut CPU) {
+        if self.net.is_some() {
+            self.say("state_net_link", &[]);
+            return;
+        }
+        let p = captures::state_path(&self.captures, self.state_slot);
+        let data = match fs::read(&p) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("Couldn't read save state {}: {}", p.display(), e);
+                self.say("state_empty", &[&(self.state_slot + 1)]);
+                return;
+            }
+        };
+        match cpu.load_state(&data) {
+            Ok(()) => {
+                self.rewind.clear();
+                self.branches.loaded(self.state_slot);
+                self.say("state_loaded", &[&(self.state_slot + 1)]);
+            },
+            Err(e) => {
+                eprintln!("{}: {}", p.display(), e);
+                self.osd.push(e);
+            }
+        }
+    }
+
+    // F11, the game screen as it is under captures/<game>/
+    // at the game's 160x144, or as big as the window draws it with screenshot_size = window
+    fn screenshot(&mut self, cpu: &CPU) {
+        let p = captures::screenshot_path(&self.captures);
+        let (width, height) = if self.screenshot_window { self.display.frame_size(self.debug.visible) } else { (160, 144) };
+        match fs::create_dir_all(&self.captures).and_then(|_| cpu.screenshot(&p, width, height)) {
+            Ok(()) => {
+                println!("Screenshot saved to {}", p.display());
+                self.say("screenshot_saved", &[]);
+                self.gallery.refresh(&self.captures);
+            },
+            Err(e) => {
+                eprintln!("Couldn't write screenshot {}: {}", p.display(), e);
+                self.say("screenshot_failed", &[]);
+            }
+        }
+    }
+
+    // Shift+F11 starts a WAV recording of the game's audio next to the screenshots, again stops it
+    fn audio_recording_key(&mut self, cpu: &mut CPU) {
+        if cpu.recording_audio() {
+            let rate = cpu.memory.apu.audio_config().sample_rate;
+            match cpu.stop_audio_recording() {
+                Ok(frames) => {
+                    let seconds = frames.unwrap_or(0) / rate;
+                    self.say("audio_recording_saved", &[&seconds]);
+                    self.gallery.refresh(&self.captures);
+                },
+                Err(e) => {
+                    eprintln!("Couldn't write audio recording: {}", e);
+                    self.say("audio_recording_failed", &[]);
+                }
+            }
+            return
+        }
+
+        let p = captures::capture_path(&self.captures, "wav");
+        match fs::create_dir_all(&self.captures).and_then(|_| cpu.start_audio_recording(&p)) {
+            Ok(()) => {
+                println!("Recording audio to {}", p.display());
+                self.say("audio_recording", &[]);
+            },
+            Err(e) => {
+                eprintln!("Couldn't record audio to {}: {}", p.display(), e);
+                self.say("audio_recording_failed", &[]);
+            }
+        }
+    }
+
+    // Ctrl+F11 starts recording video next to the screenshots, as an apng or through ffmpeg
+    // with video_format = ffmpeg. Again stops it
+    fn video_key(&mut self, cpu: &CPU) {
+        if let Some(video) = self.video.take() {
+            let seconds = video.seconds();
+            match video.finish() {
+                Ok(p) => {
+                    println!("Video saved to {}", p.display());
+                    self.say("video_saved", &[&seconds]);
+                    self.gallery.refresh(&self.captures);
+                },
+                Err(e) => {
+                    eprintln!("Couldn't finish video recording: {}", e);
+                    self.say("video_failed", &[]);
+                }
+            }
+            return
+        }
+
+        let ext = match self.video_format {
+            VideoFormat::Apng => "apng",
+            VideoFormat::Ffmpeg => "mp4"
+        };
+        let p = captures::capture_path(&self.captures, ext);
+        let rate = cpu.memory.apu.audio_config().sample_rate;
+        match fs::create_dir_all(&self.captures).and_then(|_| VideoCapture::start(self.video_format, &p, &self.ffmpeg, rate)) {
+            Ok(video) => {
+                println!("Recording video to {}", p.display());
+                self.video = Some(video);
+                self.say("video_recording", &[]);
+            },
+            Err(e) => {
+                eprintln!("Couldn't record video to {}: {}", p.display(), e);
+                self.say("video_failed", &[]);
+            }
+        }
+    }
+
+    fn record_frame(&mut self, cpu: &CPU) {
+        let video = match &mut self.video {
+            Some(v) => v,
+            None => return
+        };
+        if let Err(e) = video.frame(&cpu.memory.ppu.frame) {
+            eprintln!("Video recording stopped: {}", e);
+            self.video = None;
+            self.say("video_failed", &[]);
+        }
+    }
+
+    // joypad state, directions in the high nibble, active low
+    #[inline]
+    fn read_input(&self) -> u8 {
+        use raylib::consts::KeyboardKey::{KEY_W, KEY_S, KEY_A, KEY_D, KEY_J, KEY_K, KEY_N, KEY_M};
+
+        let hl = &self.display.handle;
+        let direction = hl.is_key_up(KEY_D) as u8 | ((hl.is_key_up(KEY_A) as u8) << 1) | ((hl.is_key_up(KEY_W) as u8) << 2) | ((hl.is_key_up(KEY_S) as u8) << 3);
+        let button = hl.is_key_up(KEY_J) as u8 | ((hl.is_key_up(KEY_K) as u8) << 1) | ((hl.is_key_up(KEY_N) as u8) << 2) | ((hl.is_key_up(KEY_M) as u8) << 3);
+
+        (direction << 4) | button
+    }
+
+    // the --link peer's joypad, the first gamepad's d-pad and face buttons laid out as on a Game Boy
+    fn read_peer_input(&self) -> u8 {
+        use raylib::consts::GamepadButton::*;
+
+        let hl = &self.display.handle;
+        if self.peer.is_none() || !hl.is_gamepad_available(0) {
+            return 0xFF
+        }
+        let up = |b| !hl.is_gamepad_button_down(0, b) as u8;
+        let direction = up(GAMEPAD_BUTTON_LEFT_FACE_RIGHT) | (up(GAMEPAD_BUTTON_LEFT_FACE_LEFT) << 1) | (up(GAMEPAD_BUTTON_LEFT_FACE_UP) << 2) | (up(GAMEPAD_BUTTON_LEFT_FACE_DOWN) << 3);
+        let button = up(GAMEPAD_BUTTON_RIGHT_FACE_RIGHT) | (up(GAMEPAD_BUTTON_RIGHT_FACE_DOWN) << 1) | (up(GAMEPAD_BUTTON_MIDDLE_LEFT) << 2) | (up(GAMEPAD_BUTTON_MIDDLE_RIGHT) << 3);
+
+        (direction << 4) | button
+    }
+
+    // the left stick when it's pushed, otherwise the arrow keys ease the cart over and back level
+    fn read_tilt(&mut self, cpu: &mut CPU) {
+        use raylib::consts::GamepadAxis::{GAMEPAD_AXIS_LEFT_X, GAMEPAD_AXIS_LEFT_Y};
+        use raylib::consts::KeyboardKey::{KEY_LEFT, KEY_RIGHT, KEY_UP, KEY_DOWN};
+
+        let hl = &self.display.handle;
+        let stick = if hl.is_gamepad_available(0) {
+            (hl.get_gamepad_axis_movement(0, GAMEPAD_AXIS_LEFT_X), hl.get_gamepad_axis_movement(0, GAMEPAD_AXIS_LEFT_Y))
+        } else {
+            (0., 0.)
+        };
+
+        if stick.0.abs() > STICK_DEADZONE || stick.1.abs() > STICK_DEADZONE {
+            self.tilt = stick;
+        } else {
+            let target = |neg, pos| hl.is_key_down(pos) as i32 as f32 - hl.is_key_down(neg) as i32 as f32;
+            let ease = |v: f32, t: f32| if v < t { (v + TILT_STEP).min(t) } else { (v - TILT_STEP).max(t) };
+            self.tilt = (ease(self.tilt.0, target(KEY_LEFT, KEY_RIGHT)), ease(self.tilt.1, target(KEY_UP, KEY_DOWN)));
+        }
+        cpu.memory.cart.set_tilt(self.tilt.0, self.tilt.1);
+    }
+
+    // direction, then A or B, same combos as the CGB bootrom. Only for dmg games
+    fn palette_combo(&mut self, cpu: &mut CPU, input: u8) {
+        let direction = match !input >> 4 & 0xF {
+            d if d&0x4 != 0 => "up",
+            d if d&0x2 != 0 => "left",
+            d if d&0x8 != 0 => "down",
+            d if d&0x1 != 0 => "right",
+            _ => return
+        };
+        if cpu.memory.mode == MODE::CGB {
+            return;
+        }
+        let name = match !input & 0x3 {
+            b if b&0x1 != 0 => format!("{}+a", direction),
+            b if b&0x2 != 0 => format!("{}+b", direction),
+            _ => direction.to_string()
+        };
+
+        if let Some(p) = emulator::dmg_palette(&name) {
+            cpu.memory.ppu.set_dmg_palette(p);
+            self.say("palette", &[&name]);
+            self.palette_combo_frames = 0;
+        }
+    }
+
+    fn macro_keys(&mut self) {
+        use raylib::consts::KeyboardKey::*;
+        const KEYS: [KeyboardKey; macros::SLOTS] = [KEY_ONE, KEY_TWO, KEY_THREE, KEY_FOUR];
+
+        let shift = self.display.handle.is_key_down(KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KEY_RIGHT_SHIFT);
+        let ctrl = self.display.handle.is_key_down(KEY_LEFT_CONTROL) || self.display.handle.is_key_down(KEY_RIGHT_CONTROL);
+        for (slot, key) in KEYS.iter().enumerate() {
+            if ctrl || !self.display.handle.is_key_pressed(*key) {
+                continue;
+            }
+
+            if shift {
+                if self.macros.toggle_record(slot) {
+                    self.say("macro_recording", &[&(slot + 1)]);
+                } else {
+                    let frames = self.macros.len(slot);
+                    self.say("macro_recorded", &[&(slot + 1), &frames]);
+                }
+            } else if self.macros.play(slot) {
+                self.say("macro_playing", &[&(slot + 1)]);
+            }
+        }
+    }
+
+    // Ctrl+1-4 mutes or unmutes a sound channel, Ctrl+Shift+1-4 plays it alone
+    fn channel_keys(&mut self, cpu: &mut CPU) {
+        use raylib::consts::KeyboardKey::*;
+        const KEYS: [KeyboardKey; 4] = [KEY_ONE, KEY_TWO, KEY_THREE, KEY_FOUR];
+
+        let shift = self.display.handle.is_key_down(KEY_LEFT_SHIFT) || self.display.handle.is_key_down(KEY_RIGHT_SHIFT);
+        let ctrl = self.display.handle.is_key_down(KEY_LEFT_CONTROL) || self.display.handle.is_key_down(KEY_RIGHT_CONTROL);
+        for (ch, key) in KEYS.iter().enumerate() {
+            if !ctrl || !self.display.handle.is_key_pressed(*key) {
+                continue;
+            }
+
+            let apu = &mut cpu.memory.apu;
+            if shift {
+                apu.solo_channel(ch);
+                if apu.channel_muted((ch + 1) % 4) {
+                    self.say("channel_solo", &[&(ch + 1)]);
+                } else {
+                    self.say("channels_all", &[]);
+                }
+            } else {
+                let muted = !apu.channel_muted(ch);
+                apu.set_channel_muted(ch, muted);
+                self.say(if muted { "channel_muted" } else { "channel_unmuted" }, &[&(ch + 1)]);
+            }
+        }
+    }
+
+    fn resize_for_debug(&mut self) {
+        if self.debug.visible {
+            self.display.handle.set_window_size(debugger::WINDOW_WIDTH, debugger::WINDOW_HEIGHT);
+        } else {
+            let frames = if self.peer.is_some() { 2 } else { 1 };
+            self.display.handle.set_window_size(160*2 * frames, 144*2);
+        }
+    }
+
+    fn present(&mut self, cpu: &mut CPU) {
+        if self.debug.visible {
+            self.debug.update_textures(cpu);
+        }
+
+        let shake = if self.rumble_shake && self.rumble > 0. {
+            let side = if self.play_frames % 2 == 0 { 1. } else { -1. };
+            Vector2::new(side * (self.rumble * RUMBLE_SHAKE).max(1.), 0.)
+        } else {
+            Vector2::new(0., 0.)
+        };
+        let peer = self.peer.as_ref().map(|p| &p.memory.ppu.frame[..]);
+        let (mut d, dest) = self.display.draw_frame(&cpu.memory.ppu.frame, peer, self.debug.visible, shake);
+        if self.debug.visible {
+            self.debug.draw(&mut d, cpu);
+        }
+        if self.stats.visible {
+            self.stats.draw(&mut d, 10, 20, &self.lang);
+        }
+        if self.gallery.visible {
+            self.gallery.draw(&mut d, 10, 20, &cpu.memory.cart.title, &self.lang);
+        }
+        self.osd.draw(&mut d, 4, dest.height as i32 - 4);
+        d.draw_fps(0, 0);
+    }
+}
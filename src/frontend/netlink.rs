@@ -0,0 +1,681 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::emulator::{CPU, SerialDevice};
+
+const VERSION: u32 = 2;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PINGS: usize = 4;
+const FRAME_CLOCK: u64 = 70224 * 2;  // a frame in Memory::clock's 8MiHz cycles
+const FRAME_TIME: f32 = 70224. / 4194304.;  // seconds
+const MIN_LEAD_FRAMES: u64 = 2;
+const MAX_LEAD_FRAMES: u64 = 30;  // half a second, further apart and it's unplayable anyway
+const CHECK_FRAMES: u64 = 30;  // between the host looking for transfers done on one side only
+const STUCK_CHECKS: usize = 6;  // times they may stay that way, past the lead and the network
+const MAX_CHECKPOINTS: usize = 1200;  // the joiner's, 20 seconds without the host agreeing on one
+const MATCH_LEADS: u64 = 3;  // checkpoints further apart than this many leads don't make a pair
+const KEEP_LEADS: u64 = 4;  // the host's are kept this many leads
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Message {
+    Hello(u32),  // protocol version
+    Ping,
+    Pong,
+    Start(u64),  // the host's lead, in 8MiHz cycles
+    Time(u64, u64, i64),  // emulated time run since the start, transfers done and our skew
+    Wait(u32, u8),  // waiting on the external clock with this byte, the id answers come back with
+    Cancel(u32),
+    Data(u32, u8, u64),  // the byte clocked into the other side's wait with this id, at the start of our frame at this time
+    Check(u32, u64, u64, u64),  // the joiner's checkpoint with its time, transfers and transcript
+    Agreed(u32),  // the host found a match for the joiner's checkpoint, older ones can go
+    Missed(u64),  // a byte clocked out at this time found nobody waiting
+    Diverged,  // the joiner saw a Missed it was waiting for
+    Resync(u32, u64),  // go back to your checkpoint, ours was taken at this time
+    Resynced,
+    Bye
+}
+
+impl Message {
+    fn encode(&self) -> String {
+        match self {
+            Message::Hello(v) => format!("hello {}", v),
+            Message::Ping => "ping".to_string(),
+            Message::Pong => "pong".to_string(),
+            Message::Start(lead) => format!("start {}", lead),
+            Message::Time(t, transfers, skew) => format!("time {} {} {}", t, transfers, skew),
+            Message::Wait(id, b) => format!("wait {} {:02x}", id, b),
+            Message::Cancel(id) => format!("cancel {}", id),
+            Message::Data(id, b, t) => format!("data {} {:02x} {}", id, b, t),
+            Message::Check(index, t, transfers, transcript) => format!("check {} {} {} {:x}", index, t, transfers, transcript),
+            Message::Agreed(index) => format!("agreed {}", index),
+            Message::Missed(t) => format!("missed {}", t),
+            Message::Diverged => "diverged".to_string(),
+            Message::Resync(index, t) => format!("resync {} {}", index, t),
+            Message::Resynced => "resynced".to_string(),
+            Message::Bye => "bye".to_string()
+        }
+    }
+
+    fn decode(line: &str) -> Option<Message> {
+        let mut parts = line.split_whitespace();
+        let kind = parts.next()?;
+        let args: Vec<&str> = parts.collect();
+        let num = |i: usize| args.get(i)?.parse::<u64>().ok();
+        let byte = |i: usize| u8::from_str_radix(args.get(i)?, 16).ok();
+        let hex = |i: usize| u64::from_str_radix(args.get(i)?, 16).ok();
+
+        let msg = match kind {
+            "hello" => Message::Hello(num(0)? as u32),
+            "ping" => Message::Ping,
+            "pong" => Message::Pong,
+            "start" => Message::Start(num(0)?),
+            "time" => Message::Time(num(0)?, num(1)?, args.get(2)?.parse().ok()?),
+            "wait" => Message::Wait(num(0)? as u32, byte(1)?),
+            "cancel" => Message::Cancel(num(0)? as u32),
+            "data" => Message::Data(num(0)? as u32, byte(1)?, num(2)?),
+            "check" => Message::Check(num(0)? as u32, num(1)?, num(2)?, hex(3)?),
+            "agreed" => Message::Agreed(num(0)? as u32),
+            "missed" => Message::Missed(num(0)?),
+            "diverged" => Message::Diverged,
+            "resync" => Message::Resync(num(0)? as u32, num(1)?),
+            "resynced" => Message::Resynced,
+            "bye" => Message::Bye,
+            _ => return None
+        };
+        Some(msg)
+    }
+}
+
+// Our machine at some frame, to go back to if the link goes out of sync after it
+struct Checkpoint {
+    index: u32,
+    frame: u64,
+    time: u64,
+    transfers: u64,
+    transcript: u64,
+    state: Vec<u8>  // compressed
+}
+
+// where a resync goes back to, taken at the start of the next frame
+#[derive(Clone, Copy)]
+struct Rollback {
+    checkpoint: u32,
+    peer_checkpoint: Option<u32>,  // the host tells the joiner which of its own
+    peer_time: u64
+}
+
+// Everything both the frontend's NetLink and the cpu's NetPort look at
+struct Session {
+    stream: TcpStream,
+    messages: Receiver<Message>,
+    connected: bool,
+    host: bool,
+    lead: u64,  // how far ahead of the other side's last reported time this one may run
+    base: Option<u64>,  // our clock at the first frame, times are sent relative to it
+    now: u64,  // our time at the start of the running frame
+    peer_time: u64,
+    peer_transfers: u64,  // the other side's, with its time
+    peer_skew: i64,
+    peer_waiting: Option<(u32, u8)>,  // the other side waits for our clock, with its id and byte
+    wait_id: u32,  // ours, one per transfer
+    waiting: Option<u8>,  // announced for wait_id
+    wait_since: Option<u64>,  // our time at the start of the frame it was announced in
+    delivered: Option<(u8, u64)>,  // clocked in by the other side at its time, not yet picked up
+    skew: i64,  // how much later than the other side clocked it we picked up the last byte
+
+    // Both sides run their own machine, what they have to agree on is the link: every transfer's
+    // two bytes, the host's first, go into an FNV-1a transcript
+    frame: u64,  // run since linking
+    transfers: u64,
+    transcript: u64,
+    checkpoints: VecDeque<Checkpoint>,  // from the last one both sides agreed on
+    next_checkpoint: u32,
+    history: VecDeque<(u64, u64)>,  // host, transfers and transcript after each since the agreed checkpoint
+    reports: VecDeque<(u32, u64, u64, u64)>,  // host, the joiner's checkpoints it hasn't got as far as yet
+    counts: VecDeque<(u64, u64)>,  // host, ours and the joiner's transfers at our last few checkpoints
+    agreed: Option<Rollback>,  // host, the latest pair of checkpoints with the same transfers
+    rollback: Option<Rollback>,
+    awaiting: bool,  // host, the joiner's messages are from before the resync until it says Resynced
+    resynced: Option<u64>,  // frames the last resync went back, for the frontend
+    lost: bool  // out of sync with nothing to go back to
+}
+
+impl Session {
+    fn send(&mut self, msg: Message) {
+        if !self.connected {
+            return
+        }
+        if writeln!(self.stream, "{}", msg.encode()).is_err() {
+            self.connected = false;
+        }
+    }
+
+    fn handle(&mut self, msg: Message) {
+        if self.awaiting {
+            match msg {
+                Message::Resynced => self.awaiting = false,
+                Message::Bye => self.connected = false,
+                _ => ()
+            }
+            return
+        }
+        match msg {
+            Message::Time(t, transfers, skew) => {
+                self.peer_time = t;
+                self.peer_transfers = transfers;
+                self.peer_skew = skew;
+            },
+            Message::Wait(id, b) => self.peer_waiting = Some((id, b)),
+            Message::Cancel(id) => if self.peer_waiting.map(|w| w.0) == Some(id) { self.peer_waiting = None },
+            Message::Data(id, b, t) => if id == self.wait_id && self.waiting.is_some() { self.delivered = Some((b, t)) },
+            Message::Check(index, t, transfers, transcript) if self.host => {
+                self.reports.push_back((index, t, transfers, transcript));
+                self.verify();
+            },
+            Message::Agreed(index) => {
+                while self.checkpoints.front().map_or(false, |c| c.index < index) {
+                    self.checkpoints.pop_front();
+                }
+            },
+            // waiting since before the byte went out, whichever point of the frames both were at
+            Message::Missed(t) => if self.wait_since.map_or(false, |w| w as i64 - self.skew + 2 * FRAME_CLOCK as i64 <= t as i64) {
+                self.desync()
+            },
+            Message::Diverged if self.host => self.diverged(),
+            Message::Resync(index, t) if !self.host => {
+                self.rollback = Some(Rollback { checkpoint: index, peer_checkpoint: None, peer_time: t });
+            },
+            Message::Bye => self.connected = false,
+            _ => ()
+        }
+    }
+
+    // what comes after a Resync is from after the other side went back, it waits for us to go back too
+    fn poll(&mut self) {
+        while self.rollback.is_none() {
+            match self.messages.try_recv() {
+                Ok(msg) => self.handle(msg),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    return
+                }
+            }
+        }
+    }
+
+    // For a transfer on our clock, until the other side said it's waiting or ran past the frame this
+    // one is in, it was too late then. At most as long as the lead takes to play
+    fn wait_for_peer(&mut self) {
+        let start = Instant::now();
+        let max = Duration::from_secs_f32((self.lead / FRAME_CLOCK + 1) as f32 * FRAME_TIME);
+        while self.connected && self.rollback.is_none() && self.peer_waiting.is_none() && !self.peer_passed() {
+            let left = match max.checked_sub(start.elapsed()) {
+                Some(d) => d,
+                None => return
+            };
+            match self.messages.recv_timeout(left) {
+                Ok(msg) => self.handle(msg),
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => self.connected = false
+            }
+        }
+    }
+
+    // Not before it picked up our last byte, it couldn't wait again before that. After, it's as far
+    // behind where it would be as it picked the byte up late, give or take a frame on each side
+    fn peer_passed(&self) -> bool {
+        let passed = self.peer_time as i64 - self.peer_skew - FRAME_CLOCK as i64;
+        self.peer_transfers >= self.transfers && passed >= (self.now + FRAME_CLOCK) as i64
+    }
+
+    fn transferred(&mut self, ours: u8, theirs: u8) {
+        let (first, second) = if self.host { (ours, theirs) } else { (theirs, ours) };
+        for b in [first, second].iter() {
+            self.transcript = (self.transcript ^ *b as u64).wrapping_mul(FNV_PRIME);
+        }
+        self.transfers += 1;
+        if self.host {
+            self.history.push_back((self.transfers, self.transcript));
+        }
+    }
+
+    // One every frame, some games never stop transferring and only now and then does a frame end on
+    // one side line up with one on the other. The joiner's all go to the host, which keeps its own
+    // for a few leads back
+    fn checkpoint(&mut self, cpu: &mut CPU) {
+        let checkpoint = Checkpoint {
+            index: self.next_checkpoint,
+            frame: self.frame,
+            time: self.now,
+            transfers: self.transfers,
+            transcript: self.transcript,
+            state: cpu.save_state_compressed()
+        };
+        self.next_checkpoint += 1;
+        if !self.host {
+            self.send(Message::Check(checkpoint.index, checkpoint.time, checkpoint.transfers, checkpoint.transcript));
+            self.checkpoints.push_back(checkpoint);
+            if self.checkpoints.len() > MAX_CHECKPOINTS {
+                self.checkpoints.pop_front();
+            }
+            return
+        }
+
+        self.checkpoints.push_back(checkpoint);
+        let (now, keep) = (self.now, KEEP_LEADS * self.lead + CHECK_FRAMES * FRAME_CLOCK);
+        let agreed = self.agreed.map(|a| a.checkpoint);
+        self.checkpoints.retain(|c| Some(c.index) == agreed || c.time + keep >= now);
+
+        if self.frame % CHECK_FRAMES == 0 {
+            // a transfer only one side did, the other won't do it anymore
+            self.counts.push_back((self.transfers, self.peer_transfers));
+            if self.counts.len() > STUCK_CHECKS {
+                self.counts.pop_front();
+            }
+            let (ours, theirs) = self.counts[0];
+            if self.counts.len() == STUCK_CHECKS && ours != theirs && self.counts.iter().all(|&c| c == (ours, theirs)) {
+                return self.diverged()
+            }
+        }
+        self.verify();
+    }
+
+    // Host, the joiner's transcript has to be what ours was after as many transfers. Where both
+    // had done the same ones at checkpoints a few leads apart is somewhere to go back to. Picking up
+    // bytes late puts one side's behind the other's by more than the lead
+    fn verify(&mut self) {
+        while let Some(&(index, time, transfers, transcript)) = self.reports.front() {
+            if self.checkpoints.back().map_or(true, |c| c.transfers < transfers) {
+                return
+            }
+            self.reports.pop_front();
+            if self.history.iter().find(|h| h.0 == transfers).map(|h| h.1) != Some(transcript) {
+                return self.diverged()
+            }
+
+            let apart = |a: u64, b: u64| (a as i64 - b as i64).abs() as u64;
+            let ours = self.checkpoints.iter()
+                .filter(|c| c.transfers == transfers && apart(c.time, time) <= MATCH_LEADS * self.lead)
+                .min_by_key(|c| apart(c.time, time))
+                .map(|c| (c.index, c.transfers, apart(c.time, time)));
+            // Checks at the same count keep coming while one side waits, the pair only moves for a
+            // closer one. Whichever side is ahead after going back waits for the lead, one too far
+            // apart ends up with both waiting on each other
+            let current = self.agreed.and_then(|a| {
+                self.checkpoints.iter().find(|c| c.index == a.checkpoint).map(|c| (c.transfers, apart(c.time, a.peer_time)))
+            });
+            let ours = ours.filter(|o| current.map_or(true, |(t, d)| o.1 > t || o.2 < d));
+            if let Some((checkpoint, transfers, _)) = ours {
+                self.agreed = Some(Rollback { checkpoint: checkpoint, peer_checkpoint: Some(index), peer_time: time });
+                while self.checkpoints.front().map_or(false, |c| c.index < checkpoint) {
+                    self.checkpoints.pop_front();
+                }
+                while self.history.front().map_or(false, |h| h.0 < transfers) {
+                    self.history.pop_front();
+                }
+                self.send(Message::Agreed(index));
+            }
+        }
+    }
+
+    fn desync(&mut self) {
+        if self.host {
+            self.diverged();
+        } else {
+            self.send(Message::Diverged);
+        }
+    }
+
+    fn diverged(&mut self) {
+        if self.rollback.is_some() || self.awaiting {
+            return
+        }
+        match self.agreed {
+            Some(agreed) => self.rollback = Some(agreed),
+            None => self.lost = true
+        }
+    }
+
+    // Puts cpu back at the checkpoint and both sides' times where they were then, everything
+    // on the wire about transfers is gone
+    fn roll_back(&mut self, cpu: &mut CPU, rollback: Rollback) {
+        let i = match self.checkpoints.iter().position(|c| c.index == rollback.checkpoint) {
+            Some(i) => i,
+            None => {
+                self.lost = true;
+                return
+            }
+        };
+        if let Err(e) = cpu.load_state(&self.checkpoints[i].state) {
+            eprintln!("link: couldn't go back to the checkpoint: {}", e);
+            self.lost = true;
+            return
+        }
+        self.checkpoints.truncate(i + 1);
+        let c = &self.checkpoints[i];
+        let (time, frame, transfers, transcript) = (c.time, c.frame, c.transfers, c.transcript);
+
+        self.base = Some(cpu.memory.clock() - time);
+        self.now = time;
+        self.peer_time = rollback.peer_time;
+        self.peer_waiting = None;
+        self.waiting = None;
+        self.wait_since = None;
+        self.delivered = None;
+        self.wait_id += 1;
+        self.skew = 0;
+        self.peer_skew = 0;
+
+        self.resynced = Some(self.frame - frame);
+        self.frame = frame;
+        self.next_checkpoint = rollback.checkpoint + 1;
+        self.transfers = transfers;
+        self.transcript = transcript;
+        while self.history.back().map_or(false, |h| h.0 > transfers) {
+            self.history.pop_back();
+        }
+        self.reports.clear();
+        self.counts.clear();
+        // until the peer's first time from its checkpoint, which it sends after announcing any wait
+        // it went back into, so we don't clock a byte out past one it hasn't told us about yet
+        self.peer_transfers = 0;
+
+        match rollback.peer_checkpoint {
+            Some(peer) => {
+                self.send(Message::Resync(peer, time));
+                self.awaiting = true;
+            },
+            None => self.send(Message::Resynced)
+        }
+        // the wait we went back into was announced before the checkpoint, announce it again
+        let (control, out) = (cpu.memory.read(0xFF02), cpu.memory.read(0xFF01));
+        if control&0x81 == 0x80 {
+            self.waiting = Some(out);
+            self.wait_since = Some(time);
+            let id = self.wait_id;
+            self.send(Message::Wait(id, out));
+        }
+    }
+}
+
+// The link cable over TCP, --host waits for --join to connect. Whichever side starts a transfer on
+// its own clock sends the byte to the other, which picks it up if it's waiting on the external clock.
+// Waits are announced as they start, so a transfer usually finds out without a round trip.
+// Both sides report their emulated time every frame and neither runs further ahead of the other
+// than the lead, negotiated from the round trip time when they connect.
+// A byte that got in too late still goes through on one side only, so both keep checkpoints of their
+// machine and the joiner sends the host how many transfers it had done at each and their transcript.
+// When those stop matching both go back to the last checkpoints they agreed on
+pub struct NetLink {
+    session: Rc<RefCell<Session>>,
+    peer: String
+}
+
+impl NetLink {
+    pub fn host(port: u16) -> io::Result<NetLink> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        println!("Waiting for the other player on port {}", port);
+        let (stream, addr) = listener.accept()?;
+
+        let mut link = NetLink::open(stream, addr.to_string(), true)?;
+        link.expect(|m| m == Message::Hello(VERSION), "a different version on the other side")?;
+        link.send(Message::Hello(VERSION));
+
+        // the slowest of a few round trips in frames, with MIN_LEAD_FRAMES on top
+        let mut rtt = Duration::from_secs(0);
+        for _ in 0 .. PINGS {
+            let start = Instant::now();
+            link.send(Message::Ping);
+            link.expect(|m| m == Message::Pong, "no answer to ping")?;
+            rtt = rtt.max(start.elapsed());
+        }
+        let frames = (rtt.as_secs_f32() / FRAME_TIME).ceil() as u64 + MIN_LEAD_FRAMES;
+        let lead = frames.min(MAX_LEAD_FRAMES) * FRAME_CLOCK;
+        link.send(Message::Start(lead));
+        link.session.borrow_mut().lead = lead;
+        println!("Linked with {}, {}ms round trip", link.peer, rtt.as_millis());
+        Ok(link)
+    }
+
+    pub fn join(address: &str) -> io::Result<NetLink> {
+        let addr = address.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("no address for {}", address)))?;
+        let stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT)?;
+
+        let mut link = NetLink::open(stream, addr.to_string(), false)?;
+        link.send(Message::Hello(VERSION));
+        link.expect(|m| m == Message::Hello(VERSION), "a different version on the other side")?;
+        loop {
+            match link.recv()? {
+                Message::Ping => link.send(Message::Pong),
+                Message::Start(lead) => {
+                    link.session.borrow_mut().lead = lead.min(MAX_LEAD_FRAMES * FRAME_CLOCK);
+                    break;
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected handshake"))
+            }
+        }
+        println!("Linked with {}", link.peer);
+        Ok(link)
+    }
+
+    fn open(stream: TcpStream, peer: String, host: bool) -> io::Result<NetLink> {
+        stream.set_nodelay(true)?;
+        let reader = stream.try_clone()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break
+                };
+                match Message::decode(&line) {
+                    Some(msg) => if tx.send(msg).is_err() { return },
+                    None => eprintln!("link: unknown message `{}`", line)
+                }
+            }
+        });
+
+        Ok(NetLink {
+            session: Rc::new(RefCell::new(Session {
+                stream: stream,
+                messages: rx,
+                connected: true,
+                host: host,
+                lead: MIN_LEAD_FRAMES * FRAME_CLOCK,
+                base: None,
+                now: 0,
+                peer_time: 0,
+                peer_transfers: 0,
+                peer_skew: 0,
+                peer_waiting: None,
+                wait_id: 0,
+                waiting: None,
+                wait_since: None,
+                delivered: None,
+                skew: 0,
+                frame: 0,
+                transfers: 0,
+                transcript: FNV_OFFSET,
+                checkpoints: VecDeque::new(),
+                next_checkpoint: 0,
+                history: VecDeque::from(vec![(0, FNV_OFFSET)]),
+                reports: VecDeque::new(),
+                counts: VecDeque::new(),
+                agreed: None,
+                rollback: None,
+                awaiting: false,
+                resynced: None,
+                lost: false
+            })),
+            peer: peer
+        })
+    }
+
+    fn send(&mut self, msg: Message) {
+        self.session.borrow_mut().send(msg);
+    }
+
+    fn recv(&mut self) -> io::Result<Message> {
+        self.session.borrow().messages.recv_timeout(HANDSHAKE_TIMEOUT).map_err(|e| match e {
+            RecvTimeoutError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "the other side stopped answering"),
+            RecvTimeoutError::Disconnected => io::Error::new(io::ErrorKind::ConnectionAborted, "the other side hung up")
+        })
+    }
+
+    fn expect(&mut self, ok: impl Fn(Message) -> bool, err: &str) -> io::Result<()> {
+        if ok(self.recv()?) {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+        }
+    }
+
+    // for the cpu's serial port, one is enough
+    pub fn port(&self) -> NetPort {
+        NetPort { session: self.session.clone() }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.session.borrow().connected
+    }
+
+    // Before running a frame, false while we're too far ahead of the other side or the host waits
+    // for the joiner to go back to a checkpoint too. Runs freely once it's gone
+    pub fn may_run(&mut self, cpu: &mut CPU) -> bool {
+        let mut s = self.session.borrow_mut();
+        s.poll();
+        if let Some(rollback) = s.rollback.take() {
+            s.roll_back(cpu, rollback);
+            s.poll();
+        }
+        let clock = cpu.memory.clock();
+        if s.base.is_none() {
+            // where both sides start, nothing went over the link yet
+            s.base = Some(clock);
+            s.checkpoint(cpu);
+        }
+        s.now = clock - s.base.unwrap_or(clock);
+        !s.connected || (!s.awaiting && s.now <= s.peer_time.saturating_add(s.lead))
+    }
+
+    // after the frame, tells the other side how far we got and takes a checkpoint
+    pub fn ran(&mut self, cpu: &mut CPU) {
+        let mut s = self.session.borrow_mut();
+        let clock = cpu.memory.clock();
+        let base = *s.base.get_or_insert(clock);
+        s.now = clock - base;
+        let (now, transfers, skew) = (s.now, s.transfers, s.skew);
+        s.send(Message::Time(now, transfers, skew));
+        s.frame += 1;
+        if s.rollback.is_none() && !s.awaiting {
+            s.checkpoint(cpu);
+        }
+    }
+
+    // frames the link went back to get in sync again, once
+    pub fn take_resync(&mut self) -> Option<u64> {
+        self.session.borrow_mut().resynced.take()
+    }
+
+    // out of sync without a checkpoint both sides have, once
+    pub fn take_lost(&mut self) -> bool {
+        std::mem::replace(&mut self.session.borrow_mut().lost, false)
+    }
+}
+
+impl Drop for NetLink {
+    fn drop(&mut self) {
+        self.send(Message::Bye);
+    }
+}
+
+// The NetLink's end plugged into the cpu
+pub struct NetPort {
+    session: Rc<RefCell<Session>>
+}
+
+impl SerialDevice for NetPort {
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut s = self.session.borrow_mut();
+        s.poll();
+        s.wait_for_peer();
+        match s.peer_waiting.take() {
+            Some((id, reply)) => {
+                let now = s.now;
+                s.send(Message::Data(id, out, now));
+                s.transferred(out, reply);
+                reply
+            },
+            None => {
+                let now = s.now;
+                s.send(Message::Missed(now));
+                0xFF
+            }
+        }
+    }
+
+    fn external_clock(&mut self, out: u8) -> Option<u8> {
+        let mut s = self.session.borrow_mut();
+        s.poll();
+        if let Some((b, t)) = s.delivered.take() {
+            s.waiting = None;
+            s.wait_since = None;
+            s.wait_id += 1;
+            s.skew = s.now as i64 - t as i64;
+            s.transferred(out, b);
+            return Some(b)
+        }
+        if s.waiting != Some(out) {
+            s.waiting = Some(out);
+            let now = s.now;
+            s.wait_since.get_or_insert(now);
+            let id = s.wait_id;
+            s.send(Message::Wait(id, out));
+        }
+        None
+    }
+
+    fn transfer_cancelled(&mut self) {
+        let mut s = self.session.borrow_mut();
+        if s.waiting.take().is_some() {
+            let id = s.wait_id;
+            s.send(Message::Cancel(id));
+        }
+        s.delivered = None;
+        s.wait_since = None;
+        s.wait_id += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [Message::Hello(VERSION), Message::Ping, Message::Pong, Message::Start(2 * FRAME_CLOCK),
+            Message::Time(u64::MAX, 0, -1), Message::Wait(7, 0x00), Message::Cancel(u32::MAX), Message::Data(3, 0xFF, 0),
+            Message::Check(9, FRAME_CLOCK, 0, FNV_OFFSET), Message::Agreed(9), Message::Missed(0), Message::Diverged,
+            Message::Resync(0, u64::MAX), Message::Resynced, Message::Bye];
+        for msg in messages.iter() {
+            assert_eq!(Message::decode(&msg.encode()), Some(*msg));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        for line in ["", "hello", "time 1 0", "time -1 0 0", "time x 0 0", "wait 1", "wait 1 zz", "data 1 ff", "data 1 100 0", "check 1 2 3", "check 1 2 3 xyz", "resync 1", "what 1"].iter() {
+            assert_eq!(Message::decode(line), None, "{}", line);
+        }
+    }
+}
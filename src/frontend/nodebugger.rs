@@ -0,0 +1,48 @@
+use std::io;
+use std::path::Path;
+
+use raylib::prelude::*;
+
+use crate::emulator::{CPU, RunState};
+
+pub const WINDOW_WIDTH: i32 = 0;
+pub const WINDOW_HEIGHT: i32 = 0;
+
+// Stands in for the debugger when built without the "debugger" feature,
+// only pausing (F6) is left
+pub struct DebugUI {
+    pub visible: bool,
+    pub paused: bool
+}
+
+impl DebugUI {
+    pub fn new(_handle: &mut RaylibHandle, _thread: &RaylibThread) -> DebugUI {
+        DebugUI {
+            visible: false,
+            paused: false
+        }
+    }
+
+    pub fn handle_keys(&mut self, hl: &mut RaylibHandle, _cpu: &mut CPU) -> bool {
+        if hl.is_key_pressed(KeyboardKey::KEY_F6) {
+            self.paused = !self.paused;
+        }
+        false
+    }
+
+    pub fn load_textures(&mut self, _handle: &mut RaylibHandle, _thread: &RaylibThread) {}
+
+    pub fn unload_textures(&mut self) {}
+
+    pub fn stopped(&mut self, _state: RunState) {}
+
+    pub fn load_session(&mut self, _cpu: &mut CPU, _p: &Path) {}
+
+    pub fn save_session(&self, _cpu: &CPU, _p: &Path) -> io::Result<()> { Ok(()) }
+
+    pub fn frame_done(&mut self, _cpu: &mut CPU) {}
+
+    pub fn update_textures(&mut self, _cpu: &mut CPU) {}
+
+    pub fn draw(&mut self, _d: &mut RaylibDrawHandle, _cpu: &mut CPU) {}
+}
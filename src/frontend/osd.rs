@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+use raylib::prelude::*;
+
+const SHOW_FOR: Duration = Duration::from_secs(3);
+const MAX_MESSAGES: usize = 4;
+
+// short messages drawn over the bottom of the game screen
+pub struct Osd {
+    messages: Vec<(String, Instant)>
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd {
+            messages: vec![]
+        }
+    }
+
+    pub fn push(&mut self, msg: &str) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+        self.messages.push((msg.to_string(), Instant::now()));
+    }
+
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, x: i32, bottom: i32) {
+        self.messages.retain(|(_, t)| t.elapsed() < SHOW_FOR);
+
+        let mut y = bottom - 14 * self.messages.len() as i32;
+        for (msg, _) in self.messages.iter() {
+            let w = measure_text(msg, 10);
+            d.draw_rectangle(x, y, w + 6, 13, Color::new(0, 0, 0, 180));
+            d.draw_text(msg, x + 3, y + 2, 10, Color::WHITE);
+            y += 14;
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::emulator::{png, Color};
+
+const SWATCH: usize = 16;  // pixels per color in the png
+
+// picked by the file extension: .png swatch, .pal JASC palette, anything else a hex list
+pub fn export(palettes: &[[Color; 4]], p: &Path) -> io::Result<()> {
+    match p.extension().and_then(|e| e.to_str()) {
+        Some("png") => export_png(palettes, p),
+        Some("pal") => fs::write(p, jasc(palettes)),
+        _ => fs::write(p, hex_list(palettes))
+    }
+}
+
+// a palette per row
+fn export_png(palettes: &[[Color; 4]], p: &Path) -> io::Result<()> {
+    let width = 4*SWATCH;
+    let height = palettes.len()*SWATCH;
+    let mut rgb = vec![0; width*height*3];
+
+    for (y, row) in rgb.chunks_mut(width*3).enumerate() {
+        for (x, px) in row.chunks_mut(3).enumerate() {
+            let c = palettes[y / SWATCH][x / SWATCH];
+            px.copy_from_slice(&[c.r, c.g, c.b]);
+        }
+    }
+
+    png::write_rgb(p, width as u32, height as u32, &rgb)
+}
+
+fn jasc(palettes: &[[Color; 4]]) -> String {
+    let mut s = format!("JASC-PAL\r\n0100\r\n{}\r\n", palettes.len()*4);
+    for c in palettes.iter().flatten() {
+        s += &format!("{} {} {}\r\n", c.r, c.g, c.b);
+    }
+    s
+}
+
+fn hex_list(palettes: &[[Color; 4]]) -> String {
+    let mut s = String::new();
+    for row in palettes.iter() {
+        let colors: Vec<String> = row.iter().map(|c| format!("#{:02X}{:02X}{:02X}", c.r, c.g, c.b)).collect();
+        s += &colors.join(" ");
+        s.push('\n');
+    }
+    s
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use crate::emulator::CPU;
+use crate::emulator::compress::{compress, decompress};
+
+pub const DEFAULT_SECONDS: usize = 60;
+const FRAME_RATE: usize = 60;
+const SNAPSHOT_FRAMES: usize = 4;  // emulated frames between snapshots
+const GROUP_SNAPSHOTS: usize = 30;  // a keyframe and the deltas against it, two seconds
+
+// A compressed keyframe and the snapshots taken after it, each XORed with the keyframe
+// and compressed. Only the little that changed since the keyframe is left non-zero
+struct Group {
+    keyframe: Vec<u8>,
+    len: usize,
+    deltas: Vec<Vec<u8>>
+}
+
+fn xor(state: &[u8], keyframe: &[u8]) -> Vec<u8> {
+    state.iter().zip(keyframe.iter()).map(|(a, b)| a ^ b).collect()
+}
+
+// Save states taken every few frames while playing, held Backspace walks back through them.
+// The newest group's keyframe is also kept unpacked, that's the one deltas are made against
+pub struct Rewind {
+    capacity: usize,  // snapshots
+    groups: VecDeque<Group>,
+    snapshots: usize,
+    keyframe: Vec<u8>,
+    countdown: usize,
+    frame: u64,  // emulated frames seen
+    taken: VecDeque<u64>  // the frame each snapshot was taken at, oldest first
+}
+
+impl Rewind {
+    pub fn new(seconds: usize) -> Rewind {
+        Rewind {
+            capacity: seconds * FRAME_RATE / SNAPSHOT_FRAMES,
+            groups: VecDeque::new(),
+            snapshots: 0,
+            keyframe: vec![],
+            countdown: 0,
+            frame: 0,
+            taken: VecDeque::new()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // call after every emulated frame
+    pub fn frame(&mut self, cpu: &mut CPU) {
+        if !self.enabled() {
+            return
+        }
+        self.frame += 1;
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return
+        }
+        self.countdown = SNAPSHOT_FRAMES - 1;
+
+        let state = cpu.save_state();
+        match self.groups.back_mut() {
+            Some(g) if g.len == state.len() && g.deltas.len() + 1 < GROUP_SNAPSHOTS => {
+                g.deltas.push(compress(&xor(&state, &self.keyframe)));
+            },
+            _ => {
+                self.groups.push_back(Group {
+                    keyframe: compress(&state),
+                    len: state.len(),
+                    deltas: vec![]
+                });
+                self.keyframe = state;
+            }
+        }
+        self.snapshots += 1;
+        self.taken.push_back(self.frame);
+
+        // a whole group at a time, the oldest seconds go in steps
+        while let Some(g) = self.groups.front() {
+            if self.snapshots - (g.deltas.len() + 1) < self.capacity {
+                break;
+            }
+            self.snapshots -= g.deltas.len() + 1;
+            self.taken.drain(.. g.deltas.len() + 1);
+            self.groups.pop_front();
+        }
+    }
+
+    // takes the newest snapshot off and loads it, how many frames that went back.
+    // None once there's nothing left
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Option<usize> {
+        let state = match self.pop() {
+            Ok(Some(state)) => state,
+            Ok(None) => return None,
+            Err(e) => {
+                eprintln!("Couldn't rewind: {}", e);
+                self.clear();
+                return None
+            }
+        };
+        if let Err(e) = cpu.load_state(&state) {
+            eprintln!("Couldn't rewind: {}", e);
+            self.clear();
+            return None
+        }
+        self.countdown = SNAPSHOT_FRAMES - 1;
+        let taken = self.taken.pop_back().unwrap_or(self.frame);
+        let back = self.frame - taken;
+        self.frame = taken;
+        Some(back as usize)
+    }
+
+    fn pop(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        let g = match self.groups.back_mut() {
+            Some(g) => g,
+            None => return Ok(None)
+        };
+        self.snapshots -= 1;
+        if let Some(delta) = g.deltas.pop() {
+            return Ok(Some(xor(&decompress(&delta, g.len)?, &self.keyframe)))
+        }
+
+        self.groups.pop_back();
+        let state = std::mem::replace(&mut self.keyframe, vec![]);
+        if let Some(g) = self.groups.back() {
+            self.keyframe = decompress(&g.keyframe, g.len)?;
+        }
+        Ok(Some(state))
+    }
+
+    // after anything that replaces the running game's state
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.snapshots = 0;
+        self.keyframe = vec![];
+        self.countdown = 0;
+        self.taken.clear();
+    }
+
+    pub fn seconds(&self) -> f32 {
+        (self.snapshots * SNAPSHOT_FRAMES) as f32 / FRAME_RATE as f32
+    }
+
+    // bytes held, for the stats overlay
+    pub fn memory(&self) -> usize {
+        self.keyframe.len() + self.groups.iter()
+            .map(|g| g.keyframe.len() + g.deltas.iter().map(|d| d.len()).sum::<usize>())
+            .sum::<usize>()
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// what a push does with samples that don't fit
+#[derive(Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Block,  // wait for the reader, the audio paces the emulation
+    Drop    // throw away what doesn't fit, fast-forward runs ahead of it
+}
+
+// single producer single consumer, fixed size, no locks on either side
+struct Shared {
+    slots: Vec<AtomicI16>,
+    written: AtomicUsize,  // total pushed, only the writer stores it
+    read: AtomicUsize,  // total popped, only the reader stores it
+    closed: AtomicBool  // either side is gone
+}
+
+pub struct Writer(Arc<Shared>);
+pub struct Reader(Arc<Shared>);
+
+pub fn ring(capacity: usize) -> (Writer, Reader) {
+    let shared = Arc::new(Shared {
+        slots: (0 .. capacity).map(|_| AtomicI16::new(0)).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        closed: AtomicBool::new(false)
+    });
+    (Writer(shared.clone()), Reader(shared))
+}
+
+impl Shared {
+    fn queued(&self) -> usize {
+        self.written.load(Ordering::Acquire) - self.read.load(Ordering::Acquire)
+    }
+}
+
+impl Writer {
+    // returns how many samples were dropped, never more than the reader can't take
+    pub fn push(&mut self, samples: &[i16], overflow: Overflow) -> usize {
+        let s = &*self.0;
+        let mut done = 0;
+        while done < samples.len() {
+            let written = s.written.load(Ordering::Relaxed);
+            let free = s.slots.len() - (written - s.read.load(Ordering::Acquire));
+            if free == 0 {
+                if overflow == Overflow::Drop || s.closed.load(Ordering::Relaxed) {
+                    return samples.len() - done
+                }
+                thread::sleep(Duration::from_micros(500));
+                continue;
+            }
+
+            let n = free.min(samples.len() - done);
+            for i in 0 .. n {
+                s.slots[(written + i) % s.slots.len()].store(samples[done + i], Ordering::Relaxed);
+            }
+            s.written.store(written + n, Ordering::Release);
+            done += n;
+        }
+        0
+    }
+
+    pub fn queued(&self) -> usize {
+        self.0.queued()
+    }
+
+    pub fn close(&mut self) {
+        self.0.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Reader {
+    // all of out or nothing, a partial stream buffer would play as a gap anyway
+    pub fn pop_exact(&mut self, out: &mut [i16]) -> bool {
+        let s = &*self.0;
+        let read = s.read.load(Ordering::Relaxed);
+        if s.written.load(Ordering::Acquire) - read < out.len() {
+            return false
+        }
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = s.slots[(read + i) % s.slots.len()].load(Ordering::Relaxed);
+        }
+        s.read.store(read + out.len(), Ordering::Release);
+        true
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,82 @@
+use raylib::prelude::*;
+
+use crate::frontend::Lang;
+
+const HISTORY: usize = 300;
+const GRAPH_HEIGHT: i32 = 80;
+const GRAPH_MS: f32 = 33.4;  // two frames at 59.73Hz fill the graph
+
+#[derive(Clone, Copy, Default)]
+pub struct FrameTiming {
+    pub emulation: f32,  // ms spent in the core
+    pub present: f32,    // ms spent presenting, including audio and fps pacing
+    pub audio_fill: f32  // 0.0 - 1.0 of the audio buffer
+}
+
+// F2 toggles a graph of the last few hundred frames over the game screen
+pub struct FrameStats {
+    pub visible: bool,
+    pub rewind: (f32, usize),  // seconds held and bytes they take
+    history: Vec<FrameTiming>,
+    pos: usize
+}
+
+impl FrameStats {
+    pub fn new() -> FrameStats {
+        FrameStats {
+            visible: false,
+            rewind: (0., 0),
+            history: vec![FrameTiming::default(); HISTORY],
+            pos: 0
+        }
+    }
+
+    pub fn push(&mut self, timing: FrameTiming) {
+        self.history[self.pos] = timing;
+        self.pos = (self.pos + 1) % HISTORY;
+    }
+
+    fn iter(&self) -> impl Iterator<Item=&FrameTiming> {
+        self.history[self.pos ..].iter().chain(self.history[.. self.pos].iter())
+    }
+
+    fn summary<F: Fn(&FrameTiming) -> f32>(&self, f: F) -> (f32, f32) {
+        let mut max = 0f32;
+        let mut sum = 0f32;
+        for t in self.history.iter() {
+            max = max.max(f(t));
+            sum += f(t);
+        }
+        (sum / HISTORY as f32, max)
+    }
+
+    pub fn draw(&self, d: &mut RaylibDrawHandle, x: i32, y: i32, lang: &Lang) {
+        d.draw_rectangle(x, y, HISTORY as i32, GRAPH_HEIGHT + 47, Color::new(0, 0, 0, 180));
+
+        let base = y + GRAPH_HEIGHT;
+        let frame_line = base - (16.74 / GRAPH_MS * GRAPH_HEIGHT as f32) as i32;
+        d.draw_line(x, frame_line, x + HISTORY as i32, frame_line, Color::DARKGRAY);
+
+        let to_y = |v: f32| base - (v.min(1.) * GRAPH_HEIGHT as f32) as i32;
+        let mut last: Option<&FrameTiming> = None;
+        for (i, t) in self.iter().enumerate() {
+            if let Some(l) = last {
+                let x0 = x + i as i32 - 1;
+                let x1 = x + i as i32;
+                d.draw_line(x0, to_y(l.audio_fill), x1, to_y(t.audio_fill), Color::YELLOW);
+                d.draw_line(x0, to_y(l.present / GRAPH_MS), x1, to_y(t.present / GRAPH_MS), Color::SKYBLUE);
+                d.draw_line(x0, to_y(l.emulation / GRAPH_MS), x1, to_y(t.emulation / GRAPH_MS), Color::GREEN);
+            }
+            last = Some(t);
+        }
+
+        let (emu_avg, emu_max) = self.summary(|t| t.emulation);
+        let (pre_avg, pre_max) = self.summary(|t| t.present);
+        let (fill_avg, _) = self.summary(|t| t.audio_fill);
+        let ms = |v: f32| format!("{:.1}", v);
+        d.draw_text(&lang.text("stats_emulation", &[&ms(emu_avg), &ms(emu_max)]), x + 2, base + 2, 10, Color::GREEN);
+        d.draw_text(&lang.text("stats_present", &[&ms(pre_avg), &ms(pre_max)]), x + 2, base + 13, 10, Color::SKYBLUE);
+        d.draw_text(&lang.text("stats_audio", &[&format!("{:.0}", fill_avg*100.)]), x + 2, base + 24, 10, Color::YELLOW);
+        d.draw_text(&lang.text("stats_rewind", &[&format!("{:.0}", self.rewind.0), &(self.rewind.1 / 1024)]), x + 2, base + 35, 10, Color::LIGHTGRAY);
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use crate::emulator::png::ApngWriter;
+use crate::emulator::WavWriter;
+use crate::frontend::display::GB_REFRESH;
+
+const FFMPEG_SCALE: u32 = 4;  // nearest neighbour, so the encoder's chroma subsampling doesn't smear the pixels
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum VideoFormat {
+    Apng,   // written by us, lossless and silent
+    Ffmpeg  // frames and audio piped to an ffmpeg process, muxed into an mp4 at the end
+}
+
+enum Encoder {
+    Apng(ApngWriter),
+    Ffmpeg {
+        process: Child,
+        program: PathBuf,  // run again to mux
+        video: PathBuf,  // <name>.video.mkv and <name>.wav, muxed into <name>.mp4 by finish
+        audio: WavWriter
+    }
+}
+
+// Every emulated frame as it comes out of the PPU, fast-forwarded ones too, so the
+// recording plays back at the Game Boy's speed whatever the window did
+pub struct VideoCapture {
+    encoder: Option<Encoder>,  // taken by finish
+    path: PathBuf,
+    frames: u32
+}
+
+impl VideoCapture {
+    pub fn start(format: VideoFormat, p: &Path, ffmpeg: &Path, sample_rate: u32) -> io::Result<VideoCapture> {
+        let (path, encoder) = match format {
+            VideoFormat::Apng => {
+                let path = p.with_extension("apng");
+                let writer = ApngWriter::create(&path, 160, 144, GB_REFRESH as f64)?;
+                (path, Encoder::Apng(writer))
+            },
+            VideoFormat::Ffmpeg => {
+                let video = p.with_extension("video.mkv");
+                let process = Command::new(ffmpeg)
+                    .args(&["-y", "-loglevel", "error", "-f", "rawvideo", "-pixel_format", "rgb24", "-video_size", "160x144"])
+                    .args(&["-framerate", "4194304/70224", "-i", "-"])
+                    .arg("-vf").arg(format!("scale={}:{}:flags=neighbor", 160 * FFMPEG_SCALE, 144 * FFMPEG_SCALE))
+                    .args(&["-pix_fmt", "yuv420p"])
+                    .arg(&video)
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                let audio = WavWriter::create(&p.with_extension("wav"), sample_rate)?;
+                (p.with_extension("mp4"), Encoder::Ffmpeg { process: process, program: ffmpeg.to_path_buf(), video: video, audio: audio })
+            }
+        };
+
+        Ok(VideoCapture {
+            encoder: Some(encoder),
+            path: path,
+            frames: 0
+        })
+    }
+
+    // the 160x144 rgb frame
+    pub fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        match &mut self.encoder {
+            Some(Encoder::Apng(w)) => w.write_frame(rgb)?,
+            Some(Encoder::Ffmpeg { process, .. }) => {
+                // gone once ffmpeg quits, its own error went to stderr
+                let stdin = process.stdin.as_mut().ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "ffmpeg closed"))?;
+                stdin.write_all(rgb)?;
+            },
+            None => ()
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // the APU's samples for the frames so far, the apng has no sound
+    pub fn audio(&mut self, samples: &[i16]) {
+        if let Some(Encoder::Ffmpeg { audio, .. }) = &mut self.encoder {
+            audio.write(samples);
+        }
+    }
+
+    pub fn seconds(&self) -> u32 {
+        (self.frames as f32 / GB_REFRESH) as u32
+    }
+
+    // where the recording ends up
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        self.close()?;
+        Ok(self.path.clone())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.encoder.take() {
+            Some(Encoder::Apng(w)) => {
+                w.finish()?;
+            },
+            Some(Encoder::Ffmpeg { mut process, program, video, audio }) => {
+                drop(process.stdin.take());  // end of input
+                let encoded = process.wait()?;
+                let wav = self.path.with_extension("wav");
+                audio.finish()?;
+                if !encoded.success() {
+                    return Err(io::Error::new(io::ErrorKind::Other, format!("ffmpeg failed encoding {}", video.display())))
+                }
+
+                let muxed = Command::new(&program)
+                    .args(&["-y", "-loglevel", "error", "-i"]).arg(&video)
+                    .arg("-i").arg(&wav)
+                    .args(&["-c:v", "copy", "-c:a", "aac", "-b:a", "192k", "-shortest"])
+                    .arg(&self.path)
+                    .status()?;
+                if !muxed.success() {
+                    return Err(io::Error::new(io::ErrorKind::Other, format!("ffmpeg failed muxing, {} and {} are kept", video.display(), wav.display())))
+                }
+                let _ = fs::remove_file(&video);
+                let _ = fs::remove_file(&wav);
+            },
+            None => ()
+        }
+        Ok(())
+    }
+}
+
+// stopped without finish, like when the window closes mid recording
+impl Drop for VideoCapture {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            eprintln!("Couldn't finish video recording {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// "http://host[:port][/path]" -> (host:port, path)
+fn split_url(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[.. i], &rest[i ..]),
+        None => (rest, "/")
+    };
+    if host.is_empty() {
+        return None
+    }
+
+    let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    Some((host, path.to_string()))
+}
+
+fn post_blocking(url: &str, body: &str) -> io::Result<()> {
+    let (host, path) = split_url(url).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid url"))?;
+
+    let mut stream = TcpStream::connect(&host)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(stream,
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+        path, host, body.len(), body
+    )?;
+    stream.flush()
+}
+
+// fire and forget, plain http only
+pub fn post(url: &str, body: &str) {
+    let url = url.to_string();
+    let body = body.to_string();
+
+    thread::spawn(move || {
+        if let Err(e) = post_blocking(&url, &body) {
+            eprintln!("webhook {} failed: {}", url, e);
+        }
+    });
+}
@@ -0,0 +1,252 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::error::Error;
+
+use jgboy_core::emulator::{CPU, MODE, TestScript, Failure, StateDiff};
+
+use crate::cli::{RunArgs, DiffArgs, StateDiffArgs};
+use crate::frontend;
+
+// Runs frames without a window and writes <rom>.ch1.raw - ch4.raw with each channel's output
+// and <rom>.mix.raw with the stereo mix, all 16 bit little endian at the sample_rate setting.
+// Byte exact between builds, for diffing against golden captures.
+pub fn dump_audio(cpu: &mut CPU, rom: &Path, frames: u32) -> io::Result<()> {
+    let mut channels = Vec::new();
+    for i in 1 ..= 4 {
+        channels.push(BufWriter::new(File::create(rom.with_extension(format!("ch{}.raw", i)))?));
+    }
+    let mut mix = BufWriter::new(File::create(rom.with_extension("mix.raw"))?);
+
+    cpu.memory.apu.set_channel_dump(true);
+    for _ in 0 .. frames {
+        cpu.run_frame();
+
+        if let Some(dump) = cpu.memory.apu.channel_dump() {
+            for (file, samples) in channels.iter_mut().zip(dump.iter_mut()) {
+                write_samples(file, samples)?;
+            }
+        }
+        write_samples(&mut mix, &mut cpu.memory.apu.samples)?;
+    }
+
+    for file in channels.iter_mut() {
+        file.flush()?;
+    }
+    mix.flush()?;
+    println!("{} frames of audio written next to {}", frames, rom.display());
+    Ok(())
+}
+
+fn write_samples(w: &mut impl Write, samples: &mut Vec<i16>) -> io::Result<()> {
+    for s in samples.drain(..) {
+        w.write_all(&s.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// Runs a test script, or every .toml file in a directory, without a window. Games boot
+// without a bootrom or save and with the default accuracy, so results only depend on the script.
+// Returns whether everything passed
+pub fn run_tests(path: &Path) -> io::Result<bool> {
+    let mut scripts = vec![];
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let p = entry?.path();
+            if p.extension().map_or(false, |e| e == "toml") {
+                scripts.push(p);
+            }
+        }
+        scripts.sort();
+    } else {
+        scripts.push(path.to_path_buf());
+    }
+
+    let mut failed = 0;
+    for p in scripts.iter() {
+        match run_test(p) {
+            Ok(failures) if failures.is_empty() => println!("ok    {}", p.display()),
+            Ok(failures) => {
+                failed += 1;
+                println!("FAIL  {}", p.display());
+                for f in failures {
+                    println!("      line {}, frame {}: {}", f.line, f.frame, f.message);
+                }
+            },
+            Err(e) => {
+                failed += 1;
+                println!("ERROR {}: {}", p.display(), e);
+            }
+        }
+    }
+    println!("{} passed, {} failed", scripts.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+// `sponGB run`: boots the rom like the tests do, without a bootrom or save, feeds it the inputs
+// and checks the assertions as the frames go by, then dumps what was asked for.
+// Returns whether the assertions passed
+pub fn run_scripted(args: &RunArgs) -> Result<bool, Box<dyn Error>> {
+    let inputs = match &args.inputs {
+        Some(p) => parse_inputs(&fs::read_to_string(p)?).map_err(|e| format!("{}: {}", p.display(), e))?,
+        None => vec![]
+    };
+    let script = match &args.assert {
+        Some(p) => Some(TestScript::parse_assertions(&fs::read_to_string(p)?).map_err(|e| format!("{}:{}", p.display(), e))?),
+        None => None
+    };
+
+    let mut cpu = power_on(&args.rom, args.model.or_else(|| script.as_ref().and_then(|s| s.model)))?;
+
+    let frames = args.frames.unwrap_or(0)
+        .max(inputs.len() as u32)
+        .max(script.as_ref().map_or(0, |s| s.frames));
+    let mut failures = vec![];
+    for frame in 0 ..= frames {
+        if let Some(s) = &script {
+            s.check(&mut cpu, frame, &mut failures);
+        }
+        if frame == frames {
+            break;
+        }
+
+        let joypad = inputs.get(frame as usize).copied().unwrap_or(0xFF);
+        cpu.memory.set_joypad(joypad >> 4, joypad & 0xF);
+        cpu.run_frame();
+    }
+
+    if let Some(p) = &args.dump_frame {
+        frontend::write_png(p, &cpu.memory.ppu.frame)?;
+    }
+    if let Some(p) = &args.dump_ram {
+        let mut ram = cpu.memory.wram().to_vec();
+        ram.extend_from_slice(cpu.memory.cart.ram());
+        fs::write(p, ram)?;
+    }
+
+    for f in failures.iter() {
+        println!("FAIL  line {}, frame {}: {}", f.line, f.frame, f.message);
+    }
+    println!("{} frames run, {} assertions failed", frames, failures.len());
+    Ok(failures.is_empty())
+}
+
+// `sponGB diff`: runs the frames like `run` does and compares the last one with a golden
+// screenshot. Writes a diff image, mismatches red over the dimmed frame, and prints where they
+// are. Returns whether every pixel matched
+pub fn diff_frame(args: &DiffArgs) -> Result<bool, Box<dyn Error>> {
+    let golden = frontend::read_png(&args.against).map_err(|e| format!("{}: {}", args.against.display(), e))?;
+    let inputs = match &args.inputs {
+        Some(p) => parse_inputs(&fs::read_to_string(p)?).map_err(|e| format!("{}: {}", p.display(), e))?,
+        None => vec![]
+    };
+
+    let mut cpu = power_on(&args.rom, args.model)?;
+    for frame in 0 .. args.frames {
+        let joypad = inputs.get(frame as usize).copied().unwrap_or(0xFF);
+        cpu.memory.set_joypad(joypad >> 4, joypad & 0xF);
+        cpu.run_frame();
+    }
+
+    let frame = &cpu.memory.ppu.frame;
+    let mut diff = vec![0u8; 160 * 144 * 3];
+    let mut mismatches = vec![];
+    for i in 0 .. 160 * 144 {
+        let (ours, theirs) = (&frame[i*3 .. i*3 + 3], &golden[i*3 .. i*3 + 3]);
+        if ours == theirs {
+            for c in 0 .. 3 {
+                diff[i*3 + c] = ours[c] / 4;
+            }
+        } else {
+            diff[i*3] = 0xFF;
+            mismatches.push((i % 160, i / 160));
+        }
+    }
+
+    let out = args.out.clone().unwrap_or_else(|| args.against.with_extension("diff.png"));
+    frontend::write_png(&out, &diff)?;
+
+    if mismatches.is_empty() {
+        println!("frame {} matches {}", args.frames, args.against.display());
+        return Ok(true)
+    }
+    let (x0, x1) = (mismatches.iter().map(|m| m.0).min().unwrap(), mismatches.iter().map(|m| m.0).max().unwrap());
+    let (y0, y1) = (mismatches.iter().map(|m| m.1).min().unwrap(), mismatches.iter().map(|m| m.1).max().unwrap());
+    println!("frame {}: {} pixels differ, within x {}..={} y {}..={}, diff in {}",
+        args.frames, mismatches.len(), x0, x1, y0, y1, out.display());
+    for (x, y) in mismatches.iter().take(20) {
+        let i = (y * 160 + x) * 3;
+        println!("  {:3},{:3}  ours {:02X}{:02X}{:02X}  golden {:02X}{:02X}{:02X}", x, y,
+            frame[i], frame[i+1], frame[i+2], golden[i], golden[i+1], golden[i+2]);
+    }
+    if mismatches.len() > 20 {
+        println!("  and {} more", mismatches.len() - 20);
+    }
+    Ok(false)
+}
+
+// `sponGB statediff`: loads both states into their own machine and prints every cpu field, io
+// register and memory range that differs. Returns whether they were the same
+pub fn diff_states(args: &StateDiffArgs) -> Result<bool, Box<dyn Error>> {
+    let load = |p: &Path| -> Result<CPU, Box<dyn Error>> {
+        let mut cpu = power_on(&args.rom, args.model)?;
+        cpu.load_state(&fs::read(p)?).map_err(|e| format!("{}: {}", p.display(), e))?;
+        Ok(cpu)
+    };
+    let (mut old, mut new) = (load(&args.old)?, load(&args.new)?);
+    let diff = StateDiff::new(&mut old, &mut new);
+
+    for (name, a, b) in diff.cpu.iter() {
+        println!("cpu   {:<12} {:04X} -> {:04X}", name, a, b);
+    }
+    for (addr, name, a, b) in diff.io.iter() {
+        println!("io    {:04X} {:<7} {:02X} -> {:02X}", addr, name, a, b);
+    }
+    for r in diff.ranges.iter() {
+        let hex = |bytes: &[u8]| bytes.iter().take(16).map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+        let more = if r.old.len() > 16 { " .." } else { "" };
+        println!("{:<12} {} +{}", r.region(), r.location(), r.old.len());
+        println!("    {}{}", hex(&r.old), more);
+        println!("    {}{}", hex(&r.new), more);
+    }
+
+    let bytes: usize = diff.ranges.iter().map(|r| r.old.len()).sum();
+    println!("{} cpu fields, {} io registers, {} ranges over {} bytes differ",
+        diff.cpu.len(), diff.io.len(), diff.ranges.len(), bytes);
+    Ok(diff.is_empty())
+}
+
+// booted like the tests, without a bootrom or save
+fn power_on(rom: &Path, model: Option<MODE>) -> Result<CPU, Box<dyn Error>> {
+    let mut cpu = CPU::new();
+    cpu.memory.load_rom(rom).map_err(|e| format!("{}: {}", rom.display(), e))?;
+    if let Some(m) = model {
+        cpu.memory.set_model(m)?;
+    }
+    cpu.boot();
+    Ok(cpu)
+}
+
+// hex bytes separated by whitespace, the same as a macro but free to span lines
+fn parse_inputs(data: &str) -> Result<Vec<u8>, String> {
+    data.split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).map_err(|_| format!("invalid input `{}`", b)))
+        .collect()
+}
+
+fn run_test(p: &Path) -> Result<Vec<Failure>, Box<dyn Error>> {
+    let script = TestScript::parse(&fs::read_to_string(p)?)?;
+    let rom = p.parent().map_or_else(|| PathBuf::from(&script.rom), |dir| dir.join(&script.rom));
+
+    let mut power_on = || -> Result<CPU, String> {
+        let mut cpu = CPU::new();
+        cpu.memory.load_rom(&rom).map_err(|e| format!("{}: {}", rom.display(), e))?;
+        if let Some(m) = script.model {
+            cpu.memory.set_model(m)?;
+        }
+        cpu.boot();
+        Ok(cpu)
+    };
+    let mut cpu = power_on()?;
+    Ok(script.run(&mut cpu, &mut power_on))
+}
@@ -0,0 +1,7 @@
+// Emulator core, builds without std (alloc only) when the "std" feature is off
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+
+pub mod emulator;
@@ -1,20 +1,346 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 
-mod emulator;
+use jgboy_core::emulator::{self, CPU, MODE, EmulatorError};
+
+mod frontend;
+mod config;
+mod cli;
+mod headless;
+mod selftest;
+
+use config::{Config, ConfigWatch};
+use cli::Args;
+use frontend::LinkedInstance;
+
+// a fresh cpu with the rom and its save loaded, booted. model forces DMG, DMG0 or CGB, dmg carts run on a CGB in compatibility mode
+fn power_on(rom: &Path, save: &Path, config: &Config, model: Option<MODE>) -> Result<CPU, Box<dyn Error>> {
+    let mut c = CPU::new();
+    c.memory.cart.lenient = config.lenient_header;
+    c.memory.cart.verify_global_checksum = config.verify_global_checksum;
+    c.memory.load_rom(rom)?;
+    for w in c.memory.cart.warnings.iter() {
+        eprintln!("{}: {}, loading anyway", rom.display(), w);
+    }
+    c.memory.cart.load_save(save)?;
+    c.memory.init_ram(config.ram_init);
+    if let Some(m) = model {
+        c.memory.set_model(m)?;
+    }
+    if let Some(p) = config.dmg_palette {
+        c.memory.ppu.set_dmg_palette(p);
+    }
+    let mut accuracy = emulator::Accuracy::new(config.accuracy);
+    accuracy.bus.echo_ram = config.echo_ram.unwrap_or(accuracy.bus.echo_ram);
+    accuracy.bus.open_bus = config.open_bus.unwrap_or(accuracy.bus.open_bus);
+    accuracy.bus.data_bus = config.data_bus.unwrap_or(accuracy.bus.data_bus);
+    accuracy.bus.dma_conflicts = config.dma_conflicts.unwrap_or(accuracy.bus.dma_conflicts);
+    c.set_accuracy(accuracy);
+    c.memory.apu.set_audio_config(config.audio());
+    for (ch, v) in config.channel_volume.iter().enumerate() {
+        c.memory.apu.set_channel_volume(ch, *v);
+    }
+    c.set_overclock(config.overclock);
+    #[cfg(feature = "debugger")]
+    if let Some(filter) = &config.io_log {
+        c.memory.io_log.set_filter(filter);
+        c.memory.io_log.enabled = true;
+    }
+    c.memory.cart.set_rtc_clock(emulator::RtcClock::new(config.rtc, config.rtc_offset));
+    c.memory.cart.power_loss = config.power_loss.map(emulator::PowerLoss::new);
+    if let Some(p) = &config.camera_image {
+        match fs::read(p).map_err(|e| e.to_string()).and_then(|d| Ok(emulator::StillImage::from_pgm(&d)?)) {
+            Ok(image) => c.memory.cart.set_camera_source(Box::new(image)),
+            Err(e) => eprintln!("{}: {}, the camera sees its test image", p.display(), e)
+        }
+    }
+    if let Some(l) = &config.link_loopback {
+        c.memory.set_serial_device(Box::new(l.clone()));
+    }
+    // without a usable dump the registers are set up as the bootrom would leave them, in the cartridge's model
+    let fallback = if cfg!(feature = "free_bootrom") { "using the built-in one" } else { "skipping boot" };
+    #[cfg_attr(not(feature = "free_bootrom"), allow(unused_variables))]
+    let dumped = match &config.bootrom_dir {
+        Some(dir) => match c.memory.load_bootrom_from_dir(dir) {
+            Ok(true) => true,
+            Ok(false) => {
+                eprintln!("No bootrom for this model in {}, {}", dir.display(), fallback);
+                false
+            },
+            Err(e @ EmulatorError::BootromMismatch(_)) => {
+                eprintln!("{}: {}, {}", dir.display(), e, fallback);
+                false
+            },
+            Err(e) => return Err(e.into())
+        },
+        None => false
+    };
+    #[cfg(feature = "free_bootrom")]
+    if !dumped {
+        c.memory.load_free_bootrom()?;
+    }
+    c.boot();
+    if config.fast_boot && c.memory.cart.bootrom_enable && !c.run_bootrom() {
+        eprintln!("The bootrom didn't finish, showing the rest of it");
+    }
+    Ok(c)
+}
+
+// the global config with the game's on top and the command line on top of that
+fn load_config(rom: &Path, args: &Args) -> Config {
+    let mut config = Config::load(Path::new(config::CONFIG_PATH));
+    config.load_game(rom);
+    config.lenient_header |= args.force_load;
+    config.model = args.model.or(config.model);
+    config
+}
+
+fn set_title(f: &mut frontend::Frontend, c: &CPU, config: &Config) {
+    if config.overclock > 1. {
+        f.set_title(&format!("{} (overclocked {}x)", c.memory.cart.title, config.overclock), config.title_play_time);
+    } else {
+        f.set_title(&c.memory.cart.title, config.title_play_time);
+    }
+}
+
+// Settings a running game can pick up, vsync is left to the caller since it needs a new window.
+// Everything else from the new config waits for the next restart
+fn apply_live(f: &mut frontend::Frontend, c: &mut CPU, old: &Config, new: &Config) {
+    if new.dmg_palette != old.dmg_palette {
+        if let Some(p) = new.dmg_palette {
+            c.memory.ppu.set_dmg_palette(p);
+        }
+    }
+    if new.overclock != old.overclock {
+        c.set_overclock(new.overclock);
+    }
+    if new.frame_skip != old.frame_skip {
+        f.set_frame_skip(new.frame_skip);
+    }
+    if new.video_sync != old.video_sync {
+        f.set_video_sync(new.video_sync);
+    }
+    if new.channel_volume != old.channel_volume {
+        for (ch, v) in new.channel_volume.iter().enumerate() {
+            c.memory.apu.set_channel_volume(ch, *v);
+        }
+    }
+    if new.sync != old.sync {
+        f.set_sync(new.sync);
+    }
+    if new.audio() != old.audio() {
+        f.set_audio_config(c, new.audio());
+    }
+    if new.rewind != old.rewind {
+        f.set_rewind(new.rewind);
+    }
+    f.set_rumble_shake(new.rumble_shake);
+    f.set_bess_states(new.bess_states);
+    f.set_screenshot_window(new.screenshot_window);
+    f.set_video_capture(new.video_format, &new.ffmpeg);
+    if new.language != old.language {
+        f.set_lang(load_lang(new));
+    }
+    set_title(f, c, new);
+}
+
+fn load_lang(config: &Config) -> frontend::Lang {
+    match &config.language {
+        Some(p) => frontend::Lang::load(p).unwrap_or_else(|e| {
+            eprintln!("{}: {}, keeping English", p.display(), e);
+            frontend::Lang::english()
+        }),
+        None => frontend::Lang::english()
+    }
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut c = emulator::CPU::new();
-    let p = Path::new(&"gbc_bootrom.gbc");
-    let r = Path::new(&"pksilver.gbc");
-    c.memory.load_rom(&r)?;
-    c.memory.load_bootrom(&p)?;
-    
-    {
-        let h = &c.memory.ppu.d.thread;
-        c.memory.ppu.d.handle.set_window_title(h, &c.memory.cart.title);
-    }
-    println!("{}", c.memory.cart.title);
-    c.run();
+    let args = Args::parse()?;
+    if let Some(p) = &args.test {
+        if !headless::run_tests(p)? {
+            std::process::exit(1);
+        }
+        return Ok(())
+    }
+    if let Some(run) = &args.run {
+        if !headless::run_scripted(run)? {
+            std::process::exit(1);
+        }
+        return Ok(())
+    }
+    if let Some(diff) = &args.diff {
+        if !headless::diff_frame(diff)? {
+            std::process::exit(1);
+        }
+        return Ok(())
+    }
+    if args.selftest {
+        selftest::run();
+        return Ok(())
+    }
+    if let Some(diff) = &args.state_diff {
+        if !headless::diff_states(diff)? {
+            std::process::exit(1);
+        }
+        return Ok(())
+    }
+    if args.info {
+        let mut c = CPU::new();
+        c.memory.cart.lenient = true;  // a bad header is worth seeing too
+        let integrity = c.memory.load_rom(&args.rom)?;
+        println!("{}", c.memory.cart.info());
+        println!("  integrity {}", integrity);
+        return Ok(())
+    }
+    let r = args.rom.as_path();
+    // the second linked instance is another player, keep its save apart
+    let save = if args.link_child { r.with_extension("2.sav") } else { r.with_extension("sav") };
+    let mut config = load_config(r, &args);
+    #[cfg(not(feature = "debugger"))]
+    if config.io_log.is_some() {
+        eprintln!("io_log needs the debugger feature, ignoring it");
+    }
+    let mut c = power_on(r, &save, &config, config.model)?;
+    // the hardware, a dmg game on a CGB runs in DMG mode once booted
+    let mut model = config.model.unwrap_or(c.memory.cart.gb_cart_type);
+    if let Some(frames) = args.dump_audio {
+        headless::dump_audio(&mut c, r, frames)?;
+        return Ok(())
+    }
+
+    let mut report = emulator::CompatReport::new(&c.memory.cart);
+    if let Some(db) = &config.compat_db {
+        report.known_issue = config::known_issue(db, report.header_hash);
+    }
+    println!("{}", report);
+
+    // connected before the window opens, it would hang meanwhile
+    let net = match (args.host, &args.join) {
+        (Some(port), _) => Some(frontend::NetLink::host(port)?),
+        (None, Some(address)) => Some(frontend::NetLink::join(address)?),
+        (None, None) => None
+    };
+
+    let mut f = frontend::Frontend::new(config.vsync, config.audio());
+    f.set_frame_skip(config.frame_skip);
+    f.set_video_sync(config.video_sync);
+    f.set_sync(config.sync);
+    f.set_rewind(config.rewind);
+    f.set_bess_states(config.bess_states);
+    f.set_screenshot_window(config.screenshot_window);
+    f.set_video_capture(config.video_format, &config.ffmpeg);
+    f.set_rumble_shake(config.rumble_shake);
+    f.set_lang(load_lang(&config));
+    for p in report.problems() {
+        f.notify(&p);
+    }
+    if let Some(p) = &config.state_hash_log {
+        f.set_hash_log(emulator::StateHashLog::create(p)?);
+    }
+
+    if let Ok(data) = fs::read_to_string(r.with_extension("triggers")) {
+        match emulator::Triggers::parse(&data) {
+            Ok(t) => f.set_triggers(t),
+            Err(e) => eprintln!("{}:{}", r.with_extension("triggers").display(), e)
+        }
+    }
+
+    #[cfg(feature = "debugger")]
+    if let Ok(data) = fs::read_to_string(r.with_extension("sym")) {
+        f.set_symbols(emulator::Symbols::parse(&data));
+    }
+
+    f.load_session(&mut c);
+    f.set_play_time(config.play_time);
+    if config.overclock > 1. {
+        eprintln!("Warning: cpu overclocked {}x, timing sensitive games may break", config.overclock);
+    }
+    set_title(&mut f, &c, &config);
+    println!("{} - played {}", c.memory.cart.title, config::format_time(config.play_time));
+    if let emulator::RamInit::Random(seed) = config.ram_init {
+        println!("Ram filled from seed {}, ram_init = random:{} repeats it", seed, seed);
+    }
+
+    // the second player's game, a save of its own when it's the same one
+    let peer_save = args.link.as_ref().map(|p| if p == r { p.with_extension("2.sav") } else { p.with_extension("sav") });
+    if let (Some(p), Some(save)) = (&args.link, &peer_save) {
+        let peer_config = load_config(p, &args);
+        let peer = power_on(p, save, &peer_config, peer_config.model)?;
+        println!("Linked with {} on the gamepad", peer.memory.cart.title);
+        f.set_peer(&mut c, peer);
+    }
+
+    if let Some(net) = net {
+        f.set_net_link(&mut c, net);
+    }
+
+    if args.link_child {
+        f.set_link(LinkedInstance::child());
+    } else if args.link_local {
+        f.set_link(LinkedInstance::spawn(r)?);
+    }
+
+    let mut watch = ConfigWatch::new(&[PathBuf::from(config::CONFIG_PATH), r.with_extension("cfg")]);
+    while !f.should_close() {
+        f.frame(&mut c);
+
+        // saving a config file or Shift+F2
+        if watch.changed() | f.take_reload() {
+            let new = load_config(r, &args);
+            apply_live(&mut f, &mut c, &config, &new);
+            if new.vsync != config.vsync {
+                f = f.reopen_display(new.vsync);
+            }
+            config = new;
+            f.say("config_reloaded", &[]);
+        }
+
+        if let Some(vsync) = f.take_reopen() {
+            f = f.reopen_display(vsync);
+            f.say(if vsync { "vsync_on" } else { "vsync_off" }, &[]);
+        }
+
+        // same rom as the other model, battery ram goes through the save file.
+        // Pulling the power restarts as the same model, minus the writes that didn't make it
+        if let Some(pull) = f.take_restart() {
+            let next = match (pull, model) {
+                (true, m) => m,
+                (false, MODE::CGB) => MODE::DMG,
+                (false, _) => MODE::CGB
+            };
+            if pull {
+                let lost = c.memory.cart.pull_power();
+                f.say("power_pulled", &[&lost]);
+            }
+            c.memory.cart.write_save(&save)?;
+            match power_on(r, &save, &config, Some(next)) {
+                Ok(new) => {
+                    model = next;
+                    if !args.link_child {
+                        f.save_session(&c);
+                    }
+                    c = new;
+                    f.relink(&mut c);
+                    f.load_session(&mut c);
+                    if !pull {
+                        f.say("restarted", &[&format!("{:?}", model)]);
+                    }
+                },
+                Err(e) => f.say("restart_failed", &[&format!("{:?}", next), &e])
+            }
+        }
+    }
+
+    if !args.link_child {
+        f.save_session(&c);
+        if let Err(e) = config::save_play_time(r, f.play_time()) {
+            eprintln!("Couldn't save play time to {}: {}", r.with_extension("cfg").display(), e);
+        }
+    }
+    c.memory.cart.write_save(&save)?;
+    if let (Some(peer), Some(save)) = (f.peer(), &peer_save) {
+        peer.memory.cart.write_save(save)?;
+    }
     Ok(())
 }
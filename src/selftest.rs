@@ -0,0 +1,281 @@
+use jgboy_core::emulator::{CPU, Accuracy, AccuracyPreset, RunState};
+
+// `sponGB selftest`: tiny generated roms, each checking one documented piece of hardware
+// behaviour, run under every accuracy preset. The scorecard goes into bug reports. A failure
+// is something the emulator gets wrong, not a broken build, so the exit status stays 0
+
+const MAX_FRAMES: u32 = 30;
+const RESULT: usize = 0x000;  // C000, where a test leaves its bytes
+const DONE: usize = 0x0FF;  // C0FF, set to 1 when it has finished
+
+// the vblank and timer handlers count into C0F0 and C0F1
+const VBLANK_HANDLER: [u8; 7] = [0xE5, 0x21, 0xF0, 0xC0, 0x34, 0xE1, 0xD9];
+const TIMER_HANDLER: [u8; 7] = [0xE5, 0x21, 0xF1, 0xC0, 0x34, 0xE1, 0xD9];
+const FINISH: [u8; 7] = [0x3E, 0x01, 0xEA, 0xFF, 0xC0, 0x18, 0xFE];  // ld (C0FF),1 and spin
+
+// ldh a,(44); cp 91; jr nz, back to the ldh
+const WAIT_VBLANK: [u8; 6] = [0xF0, 0x44, 0xFE, 0x91, 0x20, 0xFA];
+
+struct MicroTest {
+    name: &'static str,
+    cgb: bool,
+    cart: (u8, u8, u8),  // cartridge type, rom size and ram size header bytes
+    code: &'static [&'static [u8]],  // runs from 0150
+    expect: &'static [u8]
+}
+
+const NO_MBC: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+static TESTS: &[MicroTest] = &[
+    MicroTest {
+        name: "alu flags and daa",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[
+            &[0x3E, 0x15, 0xC6, 0x27, 0x27, 0xF5, 0xE1],  // 15 + 27, daa
+            &[0x7C, 0xEA, 0x00, 0xC0, 0x7D, 0xEA, 0x01, 0xC0],
+            &[0x3E, 0x10, 0xD6, 0x20, 0xF5, 0xE1, 0x7D, 0xEA, 0x02, 0xC0],  // borrow
+            &[0x3E, 0x0F, 0xC6, 0x01, 0xF5, 0xE1, 0x7D, 0xEA, 0x03, 0xC0]  // half carry
+        ],
+        expect: &[0x42, 0x00, 0x50, 0x20]
+    },
+    MicroTest {
+        name: "echo ram",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[&[0x3E, 0x5A, 0xEA, 0x34, 0xC1, 0xFA, 0x34, 0xE1, 0xEA, 0x00, 0xC0]],
+        expect: &[0x5A]
+    },
+    MicroTest {
+        name: "div reset",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[&[0xE0, 0x04, 0xF0, 0x04, 0xEA, 0x00, 0xC0]],
+        expect: &[0x00]
+    },
+    MicroTest {
+        name: "joypad unselected",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[&[0x3E, 0x30, 0xE0, 0x00, 0xF0, 0x00, 0xEA, 0x00, 0xC0]],
+        expect: &[0xFF]
+    },
+    MicroTest {
+        name: "cgb registers on dmg",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[&[0xF0, 0x4F, 0xEA, 0x00, 0xC0, 0xF0, 0x70, 0xEA, 0x01, 0xC0, 0xF0, 0x4D, 0xEA, 0x02, 0xC0]],
+        expect: &[0xFF, 0xFF, 0xFF]
+    },
+    MicroTest {
+        name: "vblank interrupt",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[
+            &[0xF3, 0x3E, 0x01, 0xE0, 0xFF, 0xAF, 0xE0, 0x0F],  // IE vblank, IF clear
+            &[0xFB, 0x76, 0x00, 0xF3, 0xFA, 0xF0, 0xC0, 0xEA, 0x00, 0xC0]
+        ],
+        expect: &[0x01]
+    },
+    MicroTest {
+        name: "timer interrupt",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[
+            &[0xF3, 0x3E, 0x04, 0xE0, 0xFF, 0xAF, 0xE0, 0x0F],  // IE timer, IF clear
+            &[0xAF, 0xE0, 0x06, 0x3E, 0xF0, 0xE0, 0x05, 0x3E, 0x05, 0xE0, 0x07],  // TMA 0, TIMA F0, TAC 16 cycles
+            &[0xFB, 0x76, 0x00, 0xF3, 0xFA, 0xF1, 0xC0, 0xEA, 0x00, 0xC0]
+        ],
+        expect: &[0x01]
+    },
+    MicroTest {
+        name: "halt bug",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[&[0xF3, 0x3E, 0x01, 0xE0, 0xFF, 0xE0, 0x0F, 0xAF, 0x76, 0x3C, 0xEA, 0x00, 0xC0]],
+        expect: &[0x02]
+    },
+    MicroTest {
+        name: "ei delay",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[
+            &[0xF3, 0x3E, 0x01, 0xE0, 0xFF, 0xE0, 0x0F],  // vblank pending
+            &[0xFB, 0xF3, 0xFA, 0xF0, 0xC0, 0xEA, 0x00, 0xC0],  // ei; di takes nothing
+            &[0xFB, 0x00, 0xF3, 0xFA, 0xF0, 0xC0, 0xEA, 0x01, 0xC0]  // ei; nop; di does
+        ],
+        expect: &[0x00, 0x01]
+    },
+    MicroTest {
+        name: "stat mode in vblank",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[&WAIT_VBLANK, &[0xF0, 0x41, 0xE6, 0x03, 0xEA, 0x00, 0xC0]],
+        expect: &[0x01]
+    },
+    MicroTest {
+        name: "oam dma",
+        cgb: false,
+        cart: NO_MBC,
+        code: &[
+            &WAIT_VBLANK,
+            &[0xAF, 0xE0, 0x40],  // lcd off, oam stays readable
+            &[0x21, 0x00, 0xC1, 0x06, 0xA0, 0x78, 0x22, 0x05, 0x20, 0xFB],  // C100-C19F = A0 down to 01
+            // ld a,C1; ldh (46),a; ld a,28; dec a; jr nz; ret copied to FF80
+            &[0x21, 0x80, 0xFF, 0x3E, 0x3E, 0x22, 0x3E, 0xC1, 0x22, 0x3E, 0xE0, 0x22, 0x3E, 0x46, 0x22, 0x3E, 0x3E, 0x22],
+            &[0x3E, 0x28, 0x22, 0x3E, 0x3D, 0x22, 0x3E, 0x20, 0x22, 0x3E, 0xFD, 0x22, 0x3E, 0xC9, 0x22],
+            &[0xCD, 0x80, 0xFF, 0xFA, 0x00, 0xFE, 0xEA, 0x00, 0xC0, 0xFA, 0x9F, 0xFE, 0xEA, 0x01, 0xC0]
+        ],
+        expect: &[0xA0, 0x01]
+    },
+    MicroTest {
+        name: "mbc1 rom banks",
+        cgb: false,
+        cart: (0x01, 0x01, 0x00),
+        code: &[
+            &[0xFA, 0x00, 0x40, 0xEA, 0x00, 0xC0],
+            &[0x3E, 0x02, 0xEA, 0x00, 0x20, 0xFA, 0x00, 0x40, 0xEA, 0x01, 0xC0],
+            &[0xAF, 0xEA, 0x00, 0x20, 0xFA, 0x00, 0x40, 0xEA, 0x02, 0xC0]  // bank 0 selects 1
+        ],
+        expect: &[0xB1, 0xB2, 0xB1]
+    },
+    MicroTest {
+        name: "mbc1 cart ram",
+        cgb: false,
+        cart: (0x03, 0x00, 0x02),
+        code: &[
+            &[0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x77, 0xEA, 0x00, 0xA0, 0xFA, 0x00, 0xA0, 0xEA, 0x00, 0xC0],
+            &[0xAF, 0xEA, 0x00, 0x00, 0x3E, 0x0A, 0xEA, 0x00, 0x00, 0xFA, 0x00, 0xA0, 0xEA, 0x01, 0xC0]
+        ],
+        expect: &[0x77, 0x77]
+    },
+    MicroTest {
+        name: "cgb wram banks",
+        cgb: true,
+        cart: NO_MBC,
+        code: &[
+            &[0x3E, 0x02, 0xE0, 0x70, 0x3E, 0x22, 0xEA, 0x00, 0xD0],
+            &[0x3E, 0x03, 0xE0, 0x70, 0x3E, 0x33, 0xEA, 0x00, 0xD0],
+            &[0x3E, 0x02, 0xE0, 0x70, 0xFA, 0x00, 0xD0, 0xEA, 0x00, 0xC0]
+        ],
+        expect: &[0x22]
+    },
+    MicroTest {
+        name: "cgb vram banks",
+        cgb: true,
+        cart: NO_MBC,
+        code: &[
+            &WAIT_VBLANK,
+            &[0xAF, 0xE0, 0x40],
+            &[0x3E, 0x01, 0xE0, 0x4F, 0x3E, 0x11, 0xEA, 0x00, 0x80],
+            &[0xAF, 0xE0, 0x4F, 0x3E, 0x22, 0xEA, 0x00, 0x80],
+            &[0x3E, 0x01, 0xE0, 0x4F, 0xFA, 0x00, 0x80, 0xEA, 0x00, 0xC0],
+            &[0xAF, 0xE0, 0x4F, 0xFA, 0x00, 0x80, 0xEA, 0x01, 0xC0, 0xF0, 0x4F, 0xEA, 0x02, 0xC0]
+        ],
+        expect: &[0x11, 0x22, 0xFE]
+    },
+    MicroTest {
+        name: "cgb speed switch",
+        cgb: true,
+        cart: NO_MBC,
+        code: &[&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0xF0, 0x4D, 0xEA, 0x00, 0xC0]],
+        expect: &[0xFE]
+    }
+];
+
+impl MicroTest {
+    // each switchable bank starts with B0 + its number
+    fn rom(&self) -> Vec<u8> {
+        let (kind, size, ram) = self.cart;
+        let mut rom = vec![0u8; 0x8000 << size];
+        for bank in 1 .. rom.len() / 0x4000 {
+            rom[bank * 0x4000] = 0xB0 + bank as u8;
+        }
+
+        rom[0x40 .. 0x47].copy_from_slice(&VBLANK_HANDLER);
+        rom[0x50 .. 0x57].copy_from_slice(&TIMER_HANDLER);
+        rom[0x100 .. 0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x134 .. 0x13C].copy_from_slice(b"SELFTEST");
+        rom[0x143] = if self.cgb { 0x80 } else { 0x00 };
+        rom[0x147] = kind;
+        rom[0x148] = size;
+        rom[0x149] = ram;
+        rom[0x14D] = rom[0x134 ..= 0x14C].iter().fold(0u8, |sum, b| sum.wrapping_sub(*b).wrapping_sub(1));
+
+        let mut pc = 0x150;
+        for part in self.code.iter().chain([&FINISH[..]].iter()) {
+            rom[pc .. pc + part.len()].copy_from_slice(part);
+            pc += part.len();
+        }
+        rom
+    }
+
+    // what it left in C000, None when it never finished
+    fn run(&self, preset: AccuracyPreset) -> Result<Option<Vec<u8>>, String> {
+        let mut cpu = CPU::new();
+        cpu.memory.cart.lenient = true;  // no logo
+        cpu.memory.load_rom_from_bytes(self.rom()).map_err(|e| e.to_string())?;
+        cpu.set_accuracy(Accuracy::new(preset));
+        cpu.boot();
+
+        for _ in 0 .. MAX_FRAMES {
+            if cpu.run_frame() != RunState::FrameDone {
+                break;
+            }
+            if cpu.memory.wram()[DONE] == 1 {
+                return Ok(Some(cpu.memory.wram()[RESULT .. RESULT + self.expect.len()].to_vec()))
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+pub fn run() {
+    const PRESETS: [(AccuracyPreset, &str); 3] = [
+        (AccuracyPreset::Fast, "fast"),
+        (AccuracyPreset::Balanced, "balanced"),
+        (AccuracyPreset::Accurate, "accurate")
+    ];
+
+    println!("sponGB {} selftest, {} tests", env!("CARGO_PKG_VERSION"), TESTS.len());
+    println!("{:<22} {:<9} {:<9} {:<9}", "", "fast", "balanced", "accurate");
+    let mut passed = [0; 3];
+    let mut failures = vec![];
+    for test in TESTS.iter() {
+        let mut row = format!("{:<22}", test.name);
+        for (i, (preset, preset_name)) in PRESETS.iter().enumerate() {
+            let result = match test.run(*preset) {
+                Ok(Some(got)) if got == test.expect => {
+                    passed[i] += 1;
+                    "pass"
+                },
+                Ok(Some(got)) => {
+                    failures.push(format!("{} ({}): got {}, expected {}", test.name, preset_name, hex(&got), hex(test.expect)));
+                    "FAIL"
+                },
+                Ok(None) => {
+                    failures.push(format!("{} ({}): didn't finish in {} frames", test.name, preset_name, MAX_FRAMES));
+                    "FAIL"
+                },
+                Err(e) => {
+                    failures.push(format!("{} ({}): {}", test.name, preset_name, e));
+                    "FAIL"
+                }
+            };
+            row += &format!(" {:<9}", result);
+        }
+        println!("{}", row);
+    }
+
+    println!("{:<22} {:<9} {:<9} {:<9}", "score",
+        format!("{}/{}", passed[0], TESTS.len()),
+        format!("{}/{}", passed[1], TESTS.len()),
+        format!("{}/{}", passed[2], TESTS.len()));
+    for f in failures.iter() {
+        println!("  {}", f);
+    }
+}